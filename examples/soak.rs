@@ -0,0 +1,369 @@
+//! Soak test with synthetic native clients, each sending fixed size messages at a fixed rate to
+//! a server which echoes them back, reporting throughput, loss, round trip latency and CPU use.
+//!
+//! Without `--target` the echo server runs in this process on the loopback interface.  To load a
+//! server on another host, run the echo server there with
+//!
+//!   soak --serve 0.0.0.0:5000 --public 203.0.113.7:5000 --token-key SECRET
+//!
+//! and point the clients at it with the fingerprint it prints:
+//!
+//!   soak --target 203.0.113.7:5000 --fingerprint AB:CD:... --token-key SECRET --clients 1000
+//!
+//! The clients answer their own offers with stateless session tokens signed by the shared key,
+//! so the two sides need no signalling channel between them.
+
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use clap::{Arg, ArgMatches, Command};
+use futures::future::{select, Either};
+use unreliablertc::{
+  LoopbackClient, MessageType, Server, ServerConfig, SessionEndpoint, SessionEndpointConfig,
+  TokenKey,
+};
+
+// Every message starts with the time it was sent, in nanoseconds since the test started.
+const TIMESTAMP_LEN: usize = 8;
+
+#[derive(Default)]
+struct Totals {
+  connected: AtomicU64,
+  connect_failures: AtomicU64,
+  sent: AtomicU64,
+  received: AtomicU64,
+  errors: AtomicU64,
+  // Round trip times in microseconds since the last report.
+  latencies: Mutex<Vec<u32>>,
+}
+
+#[tokio::main]
+async fn main() {
+  let matches = Command::new("soak")
+    .arg(number_arg("clients", "number of synthetic clients", "100"))
+    .arg(number_arg(
+      "rate",
+      "messages per second sent by each client",
+      "10",
+    ))
+    .arg(number_arg("size", "size of each message in bytes", "64"))
+    .arg(number_arg(
+      "duration",
+      "seconds to send for once connected",
+      "30",
+    ))
+    .arg(number_arg(
+      "ramp-ms",
+      "milliseconds between client connections",
+      "5",
+    ))
+    .arg(number_arg("report", "seconds between reports", "5"))
+    .arg(
+      Arg::new("serve")
+        .long("serve")
+        .takes_value(true)
+        .help("only run an echo server listening on this address"),
+    )
+    .arg(
+      Arg::new("public")
+        .long("public")
+        .takes_value(true)
+        .help("public address of the echo server, defaults to its listen address"),
+    )
+    .arg(
+      Arg::new("target")
+        .long("target")
+        .takes_value(true)
+        .requires_all(&["fingerprint", "token-key"])
+        .help("address of a remote echo server started with --serve"),
+    )
+    .arg(
+      Arg::new("fingerprint")
+        .long("fingerprint")
+        .takes_value(true)
+        .help("certificate fingerprint printed by the remote echo server"),
+    )
+    .arg(
+      Arg::new("token-key")
+        .long("token-key")
+        .takes_value(true)
+        .help("session token key shared by the clients and the echo server"),
+    )
+    .get_matches();
+
+  if let Some(listen_addr) = matches.value_of("serve") {
+    let listen_addr: SocketAddr = listen_addr.parse().expect("could not parse --serve");
+    let public_addr = match matches.value_of("public") {
+      Some(public_addr) => public_addr.parse().expect("could not parse --public"),
+      None => listen_addr,
+    };
+    let token_key = matches
+      .value_of("token-key")
+      .expect("--serve requires --token-key");
+    let server = Server::with_config(
+      [(listen_addr, public_addr)],
+      ServerConfig {
+        stun_token_key: Some(TokenKey::new(token_key)),
+        ..Default::default()
+      },
+      None,
+    )
+    .expect("could not start echo server");
+    println!(
+      "echo server on {}, fingerprint {}",
+      public_addr,
+      server.session_endpoint().cert_fingerprint()
+    );
+    serve(server).await;
+    return;
+  }
+
+  let (session_endpoint, server_addr) = match matches.value_of("target") {
+    Some(target) => {
+      let target: SocketAddr = target.parse().expect("could not parse --target");
+      let config = SessionEndpointConfig::default().stateless_tokens(
+        TokenKey::new(matches.value_of("token-key").unwrap()),
+        Duration::from_secs(60),
+      );
+      let fingerprint = matches.value_of("fingerprint").unwrap();
+      (
+        SessionEndpoint::standalone(vec![target], fingerprint, config),
+        target,
+      )
+    }
+    None => {
+      let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+      let server = Server::with_config([(addr, addr)], ServerConfig::default(), None)
+        .expect("could not start echo server");
+      let session_endpoint = server.session_endpoint();
+      let server_addr = server.local_addrs().unwrap()[0];
+      tokio::spawn(serve(server));
+      (session_endpoint, server_addr)
+    }
+  };
+
+  let clients = number(&matches, "clients");
+  let rate = number(&matches, "rate").max(1);
+  let size = number(&matches, "size").max(TIMESTAMP_LEN as u64) as usize;
+  let duration = Duration::from_secs(number(&matches, "duration"));
+  let ramp = Duration::from_millis(number(&matches, "ramp-ms"));
+  let report_interval = Duration::from_secs(number(&matches, "report").max(1));
+
+  println!(
+    "{} clients sending {} byte messages {} times a second to {} for {:?}",
+    clients, size, rate, server_addr, duration
+  );
+  let started = Instant::now();
+  let totals = Arc::new(Totals::default());
+  let reporter = tokio::spawn(report(totals.clone(), report_interval));
+  let mut tasks = Vec::new();
+  for _ in 0..clients {
+    tasks.push(tokio::spawn(run_client(
+      session_endpoint.clone(),
+      server_addr,
+      Duration::from_secs(1) / rate as u32,
+      size,
+      duration,
+      started,
+      totals.clone(),
+    )));
+    Timer::after(ramp).await;
+  }
+
+  for task in tasks {
+    let _ = task.await;
+  }
+  reporter.abort();
+
+  let sent = totals.sent.load(Ordering::Relaxed);
+  let received = totals.received.load(Ordering::Relaxed);
+  println!(
+    "done: {} connected, {} failed to connect, {} sent, {} received, {:.2}% lost, {} errors",
+    totals.connected.load(Ordering::Relaxed),
+    totals.connect_failures.load(Ordering::Relaxed),
+    sent,
+    received,
+    loss_percent(sent, received),
+    totals.errors.load(Ordering::Relaxed),
+  );
+}
+
+// Echo every message back to the client it came from.
+async fn serve(server: Server) {
+  let (handle, driver) = server.run();
+  tokio::spawn(driver);
+  while let Ok(received) = handle.recv().await {
+    let _ = handle
+      .send(
+        received.message,
+        received.message_type,
+        received.remote_addr,
+      )
+      .await;
+  }
+}
+
+async fn run_client(
+  mut session_endpoint: SessionEndpoint,
+  server_addr: SocketAddr,
+  interval: Duration,
+  size: usize,
+  duration: Duration,
+  started: Instant,
+  totals: Arc<Totals>,
+) {
+  let mut client = match LoopbackClient::connect(&mut session_endpoint, server_addr).await {
+    Ok(client) => client,
+    Err(err) => {
+      eprintln!("failed to connect: {}", err);
+      totals.connect_failures.fetch_add(1, Ordering::Relaxed);
+      return;
+    }
+  };
+  totals.connected.fetch_add(1, Ordering::Relaxed);
+
+  // Echoes still in flight when sending stops are waited for this long before counting as lost.
+  let stop_sending = Instant::now() + duration;
+  let stop = stop_sending + Duration::from_secs(1);
+  let mut message = vec![0; size];
+  let mut next_send = Instant::now();
+  loop {
+    let now = Instant::now();
+    if now >= stop {
+      break;
+    }
+    if now >= next_send && now < stop_sending {
+      let timestamp = started.elapsed().as_nanos() as u64;
+      message[..TIMESTAMP_LEN].copy_from_slice(&timestamp.to_be_bytes());
+      match client.send(&message, MessageType::Binary).await {
+        Ok(()) => totals.sent.fetch_add(1, Ordering::Relaxed),
+        Err(_) => totals.errors.fetch_add(1, Ordering::Relaxed),
+      };
+      next_send += interval;
+      continue;
+    }
+
+    let wake_at = if now < stop_sending { next_send } else { stop };
+    let received = match select(Box::pin(client.recv()), Timer::at(wake_at)).await {
+      Either::Left((received, _)) => received,
+      Either::Right(_) => continue,
+    };
+    match received {
+      Ok((echo, _)) if echo.len() >= TIMESTAMP_LEN => {
+        let mut timestamp = [0; TIMESTAMP_LEN];
+        timestamp.copy_from_slice(&echo[..TIMESTAMP_LEN]);
+        let sent_at = Duration::from_nanos(u64::from_be_bytes(timestamp));
+        let round_trip = started.elapsed().saturating_sub(sent_at);
+        totals.received.fetch_add(1, Ordering::Relaxed);
+        totals
+          .latencies
+          .lock()
+          .unwrap()
+          .push(round_trip.as_micros().min(u32::MAX as u128) as u32);
+      }
+      Ok(_) => {}
+      Err(err) => {
+        eprintln!("client stopped: {}", err);
+        totals.errors.fetch_add(1, Ordering::Relaxed);
+        return;
+      }
+    }
+  }
+}
+
+// Print what happened since the previous report, every `interval`.
+async fn report(totals: Arc<Totals>, interval: Duration) {
+  let mut last_sent = 0;
+  let mut last_received = 0;
+  let mut last_cpu = cpu_time();
+  let mut last_report = Instant::now();
+  loop {
+    Timer::after(interval).await;
+    let sent = totals.sent.load(Ordering::Relaxed);
+    let received = totals.received.load(Ordering::Relaxed);
+    let mut latencies = std::mem::take(&mut *totals.latencies.lock().unwrap());
+    latencies.sort_unstable();
+    let cpu = cpu_time();
+    let elapsed = last_report.elapsed();
+    let cpu_percent = match (cpu, last_cpu) {
+      (Some(cpu), Some(last_cpu)) => format!(
+        "{:.1}%",
+        (cpu - last_cpu).as_secs_f64() / elapsed.as_secs_f64() * 100.0
+      ),
+      _ => "n/a".to_owned(),
+    };
+    println!(
+      "{} clients, {:.0} sent/s, {:.0} received/s, {:.2}% lost, rtt p50 {} p99 {} max {}, cpu {}",
+      totals.connected.load(Ordering::Relaxed),
+      (sent - last_sent) as f64 / elapsed.as_secs_f64(),
+      (received - last_received) as f64 / elapsed.as_secs_f64(),
+      loss_percent(sent - last_sent, received - last_received),
+      percentile(&latencies, 50),
+      percentile(&latencies, 99),
+      percentile(&latencies, 100),
+      cpu_percent,
+    );
+    last_sent = sent;
+    last_received = received;
+    last_cpu = cpu;
+    last_report = Instant::now();
+  }
+}
+
+fn percentile(sorted_micros: &[u32], percent: usize) -> String {
+  if sorted_micros.is_empty() {
+    return "-".to_owned();
+  }
+  let index = ((sorted_micros.len() - 1) * percent) / 100;
+  format!("{:.2}ms", sorted_micros[index] as f64 / 1000.0)
+}
+
+fn loss_percent(sent: u64, received: u64) -> f64 {
+  if sent == 0 {
+    0.0
+  } else {
+    sent.saturating_sub(received) as f64 / sent as f64 * 100.0
+  }
+}
+
+// User and system CPU time of the whole process so far.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+  let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+  if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+    return None;
+  }
+  let usage = unsafe { usage.assume_init() };
+  let to_duration = |time: libc::timeval| {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+  };
+  Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+  None
+}
+
+fn number_arg(name: &'static str, help: &'static str, default: &'static str) -> Arg<'static> {
+  Arg::new(name)
+    .long(name)
+    .takes_value(true)
+    .default_value(default)
+    .help(help)
+}
+
+fn number(matches: &ArgMatches, name: &str) -> u64 {
+  matches
+    .value_of(name)
+    .unwrap()
+    .parse()
+    .unwrap_or_else(|_| panic!("--{} must be a number", name))
+}
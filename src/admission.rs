@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+/// Limits how quickly a `Server` accepts new clients, set with `ServerConfig::admission_rate`.
+///
+/// When many browsers connect at once, for example right after a restart, the DTLS handshakes
+/// they start compete with established clients for the server's time.  New clients beyond the
+/// rate have their first STUN request held back and answered once the rate allows, rather than
+/// dropped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AdmissionRate {
+  /// New clients accepted per second on average.
+  pub per_second: u32,
+  /// New clients that may be accepted at once after a quiet period.
+  pub burst: u32,
+  /// STUN requests held back at most, further requests from new clients are dropped until there
+  /// is room (browsers retransmit them).
+  pub max_deferred: usize,
+}
+
+// Token bucket refilled at `AdmissionRate::per_second` up to `AdmissionRate::burst` tokens.
+pub struct TokenBucket {
+  rate: AdmissionRate,
+  tokens: f64,
+  last_refill: Instant,
+}
+
+impl TokenBucket {
+  pub fn new(rate: AdmissionRate) -> TokenBucket {
+    TokenBucket {
+      rate,
+      tokens: rate.burst as f64,
+      last_refill: Instant::now(),
+    }
+  }
+
+  pub fn has_token(&mut self) -> bool {
+    self.refill();
+    self.tokens >= 1.0
+  }
+
+  pub fn try_take(&mut self) -> bool {
+    self.refill();
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn refill(&mut self) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+    self.last_refill = now;
+    let burst = self.rate.burst.max(1) as f64;
+    self.tokens = (self.tokens + elapsed * self.rate.per_second as f64).min(burst);
+  }
+}
@@ -0,0 +1,129 @@
+use std::{
+  net::SocketAddr,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Receives the security relevant events of a `Server`, set with `Server::set_audit_sink`.
+///
+/// This is kept apart from `ServerEvent` and from logging so that deployments with compliance
+/// requirements can ship exactly these events to a SIEM, for example as the JSON of
+/// `AuditEvent::to_json`.  Any `FnMut(&AuditEvent) + Send` closure is an audit sink.
+pub trait AuditSink: Send {
+  /// Called on the thread driving the server, in the middle of handling packets, so a sink
+  /// should hand events off rather than block on I/O.
+  fn record(&mut self, event: &AuditEvent);
+}
+
+impl<F: FnMut(&AuditEvent) + Send> AuditSink for F {
+  fn record(&mut self, event: &AuditEvent) {
+    self(event)
+  }
+}
+
+/// A single security relevant event, passed to an `AuditSink`.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEvent {
+  /// Wall clock time of the event, in milliseconds since the Unix epoch.
+  pub unix_ms: u64,
+  /// The client the event is about, if any.
+  pub remote_addr: Option<SocketAddr>,
+  #[serde(flatten)]
+  pub kind: AuditKind,
+}
+
+impl AuditEvent {
+  /// The event as a JSON object with the fields of this struct, and those of its `kind` next to
+  /// an `"event"` field naming it, such as `"integrity_failure"`.
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).expect("audit event is always serializable")
+  }
+}
+
+/// What happened in an `AuditEvent`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditKind {
+  /// A STUN binding request named a session the server does not know about, or a session token
+  /// which did not verify.
+  UnknownUser { username: String },
+  /// A STUN binding request for a known session failed its MESSAGE-INTEGRITY check, so it was
+  /// not sent by the client the session was answered for.
+  IntegrityFailure { username: String },
+  /// A client moved to another address was made to authenticate again, under
+  /// `ServerConfig::reauthenticate_on_rebind`.
+  RebindRefused { username: String },
+  /// A DTLS handshake was refused for offering less than the server's `DtlsFloor`.
+  DowngradeRefused { reason: String },
+  /// New clients were turned away or held back over the last second, by `max_clients`,
+  /// `max_pending_handshakes` or `admission_rate` of `ServerConfig`.
+  Flood {
+    refused_clients: u64,
+    deferred_handshakes: u64,
+  },
+  /// The server disconnected a client, with `Server::kick` when there is a `reason_code`, or with
+  /// `Server::disconnect` or a middleware verdict.
+  Kicked {
+    reason_code: Option<u16>,
+    reason: String,
+  },
+  /// The configuration of the running server was changed.
+  ConfigChanged { setting: String, value: String },
+}
+
+// The server's audit sink, along with the counters summarized in `AuditKind::Flood`.
+pub(crate) struct AuditLog {
+  sink: Option<Box<dyn AuditSink>>,
+  refused_clients: u64,
+  deferred_handshakes: u64,
+}
+
+impl AuditLog {
+  pub fn new() -> AuditLog {
+    AuditLog {
+      sink: None,
+      refused_clients: 0,
+      deferred_handshakes: 0,
+    }
+  }
+
+  pub fn set_sink(&mut self, sink: Box<dyn AuditSink>) {
+    self.sink = Some(sink);
+  }
+
+  // Pass an event to the sink, `kind` is only called if there is one.
+  pub fn record(&mut self, remote_addr: Option<SocketAddr>, kind: impl FnOnce() -> AuditKind) {
+    if let Some(sink) = &mut self.sink {
+      sink.record(&AuditEvent {
+        unix_ms: SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .unwrap_or_default()
+          .as_millis() as u64,
+        remote_addr,
+        kind: kind(),
+      });
+    }
+  }
+
+  pub fn refused_client(&mut self) {
+    self.refused_clients += 1;
+  }
+
+  pub fn deferred_handshake(&mut self) {
+    self.deferred_handshakes += 1;
+  }
+
+  // Report the clients turned away since the last call, if there were any.
+  pub fn report_floods(&mut self) {
+    if self.refused_clients == 0 && self.deferred_handshakes == 0 {
+      return;
+    }
+    let refused_clients = std::mem::take(&mut self.refused_clients);
+    let deferred_handshakes = std::mem::take(&mut self.deferred_handshakes);
+    self.record(None, || AuditKind::Flood {
+      refused_clients,
+      deferred_handshakes,
+    });
+  }
+}
@@ -3,9 +3,12 @@ use std::{
   sync::{Arc, Mutex},
 };
 
+use bytes::Bytes;
+
 /// Shared pool of reusable Vec<u8> buffers.
 ///
-/// Send and lock-free, but will panic if accessed from multiple threads at one time.
+/// Only the thread driving the server acquires buffers, but buffers frozen into `Bytes` may be
+/// returned from any thread, so the pool is guarded by an uncontended mutex.
 #[derive(Clone, Debug)]
 pub struct BufferPool(Arc<Mutex<Vec<OwnedBuffer>>>);
 
@@ -20,7 +23,7 @@ impl BufferPool {
   /// The buffer will be returned to the pool when the handle is dropped, unless it is converted
   /// to an `OwnedBuffer`.
   pub fn acquire(&self) -> BufferHandle {
-    let mut buffer = self.0.try_lock().unwrap().pop().unwrap_or_default();
+    let mut buffer = self.0.lock().unwrap().pop().unwrap_or_default();
     buffer.0.clear();
     BufferHandle(self, Some(buffer))
   }
@@ -31,8 +34,48 @@ impl BufferPool {
     BufferHandle(self, Some(buffer))
   }
 
+  /// Turn an owned buffer into immutable, cheaply cloneable `Bytes`, which return the buffer to
+  /// the pool once the last clone is dropped.
+  pub fn freeze(&self, buffer: OwnedBuffer) -> Bytes {
+    Bytes::from_owner(FrozenBuffer(self.clone(), Some(buffer)))
+  }
+
+  /// Take an owned buffer out of the server's hands, returning a handle which does not borrow the
+  /// pool and returns the buffer to it on drop, from any thread.
+  pub fn detach(&self, buffer: OwnedBuffer) -> PooledBuffer {
+    PooledBuffer(self.clone(), Some(buffer))
+  }
+
+  /// Total capacity of the buffers currently waiting in the pool, in bytes.
+  pub fn pooled_bytes(&self) -> usize {
+    self
+      .0
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|buffer| buffer.0.capacity())
+      .sum()
+  }
+
   fn release(&self, buffer: OwnedBuffer) {
-    self.0.try_lock().unwrap().push(buffer);
+    self.0.lock().unwrap().push(buffer);
+  }
+}
+
+// The owner of the memory behind `Bytes` made by `BufferPool::freeze`.
+struct FrozenBuffer(BufferPool, Option<OwnedBuffer>);
+
+impl AsRef<[u8]> for FrozenBuffer {
+  fn as_ref(&self) -> &[u8] {
+    &self.1.as_ref().unwrap().0
+  }
+}
+
+impl Drop for FrozenBuffer {
+  fn drop(&mut self) {
+    if let Some(owned) = self.1.take() {
+      self.0.release(owned);
+    }
   }
 }
 
@@ -69,6 +112,39 @@ impl<'a> Drop for BufferHandle<'a> {
   }
 }
 
+/// A buffer made by `BufferPool::detach`, which will return the buffer to the pool on drop.
+#[derive(Debug)]
+pub struct PooledBuffer(BufferPool, Option<OwnedBuffer>);
+
+impl PooledBuffer {
+  /// Take the buffer for good, it is never returned to the pool.
+  pub fn into_vec(mut self) -> Vec<u8> {
+    self.1.take().unwrap().0
+  }
+}
+
+impl Deref for PooledBuffer {
+  type Target = Vec<u8>;
+
+  fn deref(&self) -> &Vec<u8> {
+    &self.1.as_ref().unwrap().0
+  }
+}
+
+impl DerefMut for PooledBuffer {
+  fn deref_mut(&mut self) -> &mut Vec<u8> {
+    &mut self.1.as_mut().unwrap().0
+  }
+}
+
+impl Drop for PooledBuffer {
+  fn drop(&mut self) {
+    if let Some(owned) = self.1.take() {
+      self.0.release(owned);
+    }
+  }
+}
+
 /// An buffer that has been taken out of a `BufferPool` and is no longer owned by it.
 ///
 /// It is an opaque type for transferring ownership of buffers, in order to access the inner buffer
@@ -77,3 +153,17 @@ impl<'a> Drop for BufferHandle<'a> {
 /// dropped without being returned to the pool.
 #[derive(Debug, Default)]
 pub struct OwnedBuffer(Vec<u8>);
+
+impl OwnedBuffer {
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.0.capacity()
+  }
+
+  pub fn as_slice(&self) -> &[u8] {
+    &self.0
+  }
+}
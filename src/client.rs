@@ -1,4 +1,5 @@
 use atone::Vc as VecDeque;
+use byteorder::{ByteOrder, NetworkEndian};
 use openssl::{
   error::ErrorStack as OpenSslErrorStack,
   ssl::{
@@ -8,27 +9,62 @@ use openssl::{
 };
 use rand::{thread_rng, Rng};
 use std::{
+  any::Any,
+  convert::TryFrom,
   error::Error,
   fmt,
   io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write},
   iter::Iterator,
   mem,
   net::SocketAddr,
-  time::{Duration, Instant},
+  sync::Arc,
+  time::{Duration, Instant, SystemTime},
 };
 
 use crate::{
   buffer_pool::{BufferPool, OwnedBuffer},
+  client_table::ClientState as PublicClientState,
+  clock::count_gap,
+  config::NegotiatedChannel,
+  event::{DisconnectReason, HandshakeDiagnostics},
+  ffi_event::FfiEventQueue,
+  pktinfo::LocalSocket,
+  relay::RelayTransport,
+  ring_queue::{OverflowPolicy, RingQueue},
   sctp::{
-    read_sctp_packet, write_sctp_packet, SctpChunk, SctpPacket, SctpWriteError,
-    SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_COMPLETE_UNRELIABLE, SCTP_FLAG_END_FRAGMENT,
+    read_sctp_packet, write_sctp_packet, SctpChunk, SctpPacket, SctpWriteError, StreamResetRequest,
+    RECONFIG_RESULT_PERFORMED, SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_COMPLETE_UNRELIABLE,
+    SCTP_FLAG_END_FRAGMENT, SCTP_FLAG_UNRELIABLE,
   },
+  sdp::DataDirection,
+  stats::{QueueDepth, ReassemblyStats, TrafficCounters},
+  trace::{describe_dtls_datagram, TraceDirection, TraceLayer, TraceLog},
 };
 
+#[cfg(test)]
+mod channels;
+#[cfg(test)]
+mod reassembly;
+#[cfg(test)]
+mod reliability;
+#[cfg(test)]
+mod reliable_receive;
+#[cfg(test)]
+mod transcripts;
+
 /// Heartbeat packets will be generated at a maximum of this rate (if the connection is otherwise
 /// idle).
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
 
+// Unacknowledged reliable chunks are retransmitted this long after they were first sent, and
+// twice as long after each retransmission, up to 64 times as long.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_RETRANSMIT_BACKOFF: u32 = 6;
+
+/// Most bytes of reliable and partially reliable messages a client may have unacknowledged,
+/// beyond which `Server::send_with` fails with `SendError::Backpressure`.
+pub const MAX_UNACKED_BYTES: usize = 1024 * 1024;
+
 // Maximum theoretical UDP payload size
 pub const MAX_UDP_PAYLOAD_SIZE: usize = 65507;
 
@@ -43,16 +79,21 @@ pub const SCTP_MESSAGE_OVERHEAD: usize = 28;
 /// Maximum supported theoretical size of a single WebRTC message, based on DTLS and SCTP packet
 /// size limits.
 ///
-/// WebRTC makes no attempt at packet fragmentation and re-assembly or to support fragmented
-/// received messages, all sent and received unreliable messages must fit into a single SCTP packet.
-/// As such, this maximum size is almost certainly too large for browsers to actually support.
-/// Start with a much lower MTU (around 1200) and test it.
+/// Unless `ServerConfig::max_fragment_len` is set, all sent unreliable messages must fit into a
+/// single SCTP packet.  As such, this maximum size is almost certainly too large for browsers to
+/// actually support.  Start with a much lower MTU (around 1200) and test it.
+///
+/// This is only the upper bound, the limit for each client also takes the `a=max-message-size` of
+/// its offer into account and is returned by `Server::max_message_len`.
 pub const MAX_MESSAGE_LEN: usize = MAX_SCTP_PACKET_SIZE - SCTP_MESSAGE_OVERHEAD;
 
+/// Maximum size of a message sent in fragments under `ServerConfig::max_fragment_len`, which is
+/// the largest message browsers accept by default.
+pub const MAX_FRAGMENTED_MESSAGE_LEN: usize = 256 * 1024;
+
 pub const DATA_CHANNEL_OPEN_FAILED: u16 = 5000;
 pub const DATA_CHANNEL_ERROR_NEGOTIATION_FAILED: u8 = 2;
 
-use crate::server::EVENT_CB;
 #[derive(Debug)]
 pub enum ClientError {
   TlsError(SslError),
@@ -61,6 +102,10 @@ pub enum ClientError {
   NotEstablished,
   IncompletePacketRead,
   IncompletePacketWrite,
+  /// More than `MAX_UNACKED_BYTES` of reliable messages are waiting to be acknowledged.
+  SendWindowFull,
+  /// The client has no open data channel with the given id.
+  UnknownChannel(u16),
   SCTPError(String),
 }
 
@@ -79,6 +124,8 @@ impl fmt::Display for ClientError {
       ClientError::IncompletePacketWrite => {
         write!(f, "WebRTC connection packet not completely written")
       }
+      ClientError::SendWindowFull => write!(f, "too many unacknowledged messages"),
+      ClientError::UnknownChannel(channel_id) => write!(f, "no open data channel {}", channel_id),
       ClientError::SCTPError(err) => write!(f, "SCTP error: {}", err),
     }
   }
@@ -92,9 +139,148 @@ pub enum MessageType {
   Binary,
 }
 
+/// How a sent message is delivered, passed to `Server::send_with` or negotiated by the browser for
+/// each data channel it opens.
+///
+/// Every mode but `Unreliable` keeps a copy of the message until the client acknowledges it, and
+/// retransmits it a second after it was last sent, doubling the wait on each retransmission.
+/// Ordered messages reach the browser in the order they were sent, so one being retransmitted
+/// holds back those sent after it.  Abandoned partially reliable messages are skipped with the
+/// FORWARD TSN of RFC 3758.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Delivery {
+  /// Sent once, unordered, which is how data channel messages are always sent by `Server::send`.
+  #[default]
+  Unreliable,
+  ReliableOrdered,
+  ReliableUnordered,
+  /// Retransmitted at most `retransmits` times, like the `maxRetransmits` of a browser's data
+  /// channel.
+  MaxRetransmits {
+    retransmits: u16,
+    ordered: bool,
+  },
+  /// Retransmitted until `lifetime` after it was first sent, like the `maxPacketLifeTime` of a
+  /// browser's data channel.
+  MaxPacketLifetime {
+    lifetime: Duration,
+    ordered: bool,
+  },
+}
+
+impl Delivery {
+  pub fn is_ordered(&self) -> bool {
+    match *self {
+      Delivery::Unreliable | Delivery::ReliableUnordered => false,
+      Delivery::ReliableOrdered => true,
+      Delivery::MaxRetransmits { ordered, .. } | Delivery::MaxPacketLifetime { ordered, .. } => {
+        ordered
+      }
+    }
+  }
+
+  fn max_retransmits(&self) -> Option<u32> {
+    match *self {
+      Delivery::MaxRetransmits { retransmits, .. } => Some(retransmits.into()),
+      _ => None,
+    }
+  }
+
+  fn lifetime(&self) -> Option<Duration> {
+    match *self {
+      Delivery::MaxPacketLifetime { lifetime, .. } => Some(lifetime),
+      _ => None,
+    }
+  }
+
+  // The delivery of a data channel with the given channel type and reliability parameter of its
+  // DATA_CHANNEL_OPEN message (RFC 8832 section 5.1).
+  fn from_channel_type(channel_type: u8, reliability: u32) -> Delivery {
+    let ordered = channel_type & 0x80 == 0;
+    match channel_type & 0x7f {
+      0x01 if reliability == 0 && !ordered => Delivery::Unreliable,
+      0x01 => Delivery::MaxRetransmits {
+        retransmits: reliability.min(u16::MAX.into()) as u16,
+        ordered,
+      },
+      0x02 => Delivery::MaxPacketLifetime {
+        lifetime: Duration::from_millis(reliability.into()),
+        ordered,
+      },
+      _ if ordered => Delivery::ReliableOrdered,
+      _ => Delivery::ReliableUnordered,
+    }
+  }
+
+  // The channel type and reliability parameter of a DATA_CHANNEL_OPEN message for a data channel
+  // with this delivery.
+  fn channel_type(&self) -> (u8, u32) {
+    let unordered = if self.is_ordered() { 0 } else { 0x80 };
+    match *self {
+      Delivery::Unreliable => (0x81, 0),
+      Delivery::ReliableOrdered | Delivery::ReliableUnordered => (unordered, 0),
+      Delivery::MaxRetransmits { retransmits, .. } => (0x01 | unordered, retransmits.into()),
+      Delivery::MaxPacketLifetime { lifetime, .. } => (
+        0x02 | unordered,
+        lifetime.as_millis().min(u32::MAX.into()) as u32,
+      ),
+    }
+  }
+}
+
+/// A data channel opened or closed by the client, opened with `Client::open_channel` and
+/// acknowledged by the client, or negotiated in advance, taken with
+/// `Client::take_channel_events`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChannelEvent {
+  Opened {
+    channel_id: u16,
+    label: String,
+    delivery: Delivery,
+  },
+  Closed {
+    channel_id: u16,
+  },
+}
+
 pub struct Client {
   buffer_pool: BufferPool,
   pub _remote_addr: SocketAddr,
+  /// The server socket this client's traffic most recently arrived on, and the local address it
+  /// was sent to.
+  pub local_socket: LocalSocket,
+  /// The client has nominated a candidate pair with USE-CANDIDATE.
+  pub nominated: bool,
+  /// Time of the last authenticated STUN binding request from the client's address.
+  pub last_validated: Instant,
+  /// Time of the last packet of any kind the server took in from the client's address.
+  pub last_heard: Instant,
+  /// The server's and the client's ICE username fragments, from the binding request which
+  /// created the client, used to recognize the client when its NAT rebinds it to another port.
+  pub ice_users: Option<(String, String)>,
+  /// The client is waiting in the server's round-robin egress schedule.
+  pub egress_scheduled: bool,
+  /// Index into `ServerConfig::protocol_versions` of the version named by the client's first
+  /// message.
+  pub protocol_version: Option<usize>,
+  /// Longest message the client accepts, see `Client::set_max_message_size`.
+  pub max_message_len: usize,
+  // Messages longer than this are sent in fragments, see `Client::set_max_fragment_len`.
+  max_fragment_len: Option<usize>,
+  /// Direction of the client's offer, see `Server::data_direction`.
+  pub data_direction: DataDirection,
+  /// Where the client's messages go instead of `Server::recv`, see `Server::relay`.
+  pub relay: Option<RelayTransport>,
+  /// When the client first became established.
+  pub established_at: Option<SystemTime>,
+  /// When the client's session runs out, see `SessionEndpoint::session_request_with_lifetime`.
+  pub expires_at: Option<Instant>,
+  // A DTLS packet has been received from the client.
+  dtls_started: bool,
+  /// Whatever the application attached with `Server::set_client_data`.
+  pub user_data: Option<Box<dyn Any + Send>>,
+  // Outgoing packets, with the time each was queued.
+  egress: RingQueue<(OwnedBuffer, Instant)>,
   ssl_state: ClientSslState,
   pub client_state: ClientState,
 }
@@ -104,13 +290,11 @@ impl Client {
     ssl_acceptor: &SslAcceptor,
     buffer_pool: BufferPool,
     remote_addr: SocketAddr,
-    cb: Option<extern "C" fn(u32, *mut u8, u32)>,
+    local_socket: LocalSocket,
+    egress_capacity: usize,
+    egress_overflow: OverflowPolicy,
+    ffi_events: FfiEventQueue,
   ) -> Result<Client, OpenSslErrorStack> {
-    if cb.is_some() {
-      unsafe {
-        EVENT_CB = cb;
-      }
-    }
     match ssl_acceptor.accept(ClientSslPackets {
       buffer_pool: buffer_pool.clone(),
       incoming_udp: VecDeque::new(),
@@ -125,21 +309,24 @@ impl Client {
         Client {
           buffer_pool,
           _remote_addr: remote_addr,
+          local_socket,
+          nominated: false,
+          last_validated: Instant::now(),
+          last_heard: Instant::now(),
+          ice_users: None,
+          egress_scheduled: false,
+          protocol_version: None,
+          max_message_len: MAX_MESSAGE_LEN,
+          max_fragment_len: None,
+          data_direction: DataDirection::default(),
+          relay: None,
+          user_data: None,
+          established_at: None,
+          expires_at: None,
+          dtls_started: false,
+          egress: RingQueue::new(egress_capacity, egress_overflow),
           ssl_state: ClientSslState::Handshake(mid_handshake),
-          client_state: ClientState {
-            sctp_remote_address: remote_addr,
-            last_activity: Instant::now(),
-            last_sent: Instant::now(),
-            last_received: Instant::now(),
-            received_messages: Vec::new(),
-            sctp_state: SctpState::Shutdown,
-            sctp_local_port: 0,
-            sctp_remote_port: 0,
-            sctp_local_verification_tag: 0,
-            sctp_remote_verification_tag: 0,
-            sctp_local_tsn: 0,
-            sctp_remote_tsn: 0,
-          },
+          client_state: ClientState::new(remote_addr, ffi_events),
         }
       }),
     }
@@ -153,13 +340,98 @@ impl Client {
     }
   }
 
+  /// How far along its connection this client is.
+  pub fn state(&self) -> PublicClientState {
+    match (&self.ssl_state, self.client_state.sctp_state) {
+      (ClientSslState::Handshake(_), _) if !self.dtls_started => PublicClientState::StunBound,
+      (ClientSslState::Handshake(_), _) => PublicClientState::DtlsHandshaking,
+      (ClientSslState::Established(_), SctpState::Established) => PublicClientState::Established,
+      // An association which was never established has no reason to be going away.
+      (ClientSslState::Established(_), _)
+        if self.established_at.is_none() && self.client_state.disconnect_reason.is_none() =>
+      {
+        PublicClientState::SctpConnecting
+      }
+      (ClientSslState::Established(_), _) => PublicClientState::ShuttingDown,
+      (ClientSslState::ShuttingDown(_, ShutdownResult::Received), _) => PublicClientState::Closed,
+      (ClientSslState::ShuttingDown(_, _), _) => PublicClientState::ShuttingDown,
+      (ClientSslState::Shutdown, _) => PublicClientState::Closed,
+    }
+  }
+
+  /// Parameters negotiated by the DTLS handshake, once it has completed.
+  pub fn handshake_diagnostics(&self) -> Option<HandshakeDiagnostics> {
+    let ssl = match &self.ssl_state {
+      ClientSslState::Established(ssl_stream) | ClientSslState::ShuttingDown(ssl_stream, _) => {
+        ssl_stream.ssl()
+      }
+      _ => return None,
+    };
+    let cipher = ssl.current_cipher();
+    Some(HandshakeDiagnostics {
+      dtls_version: ssl.version_str(),
+      cipher: cipher.map(|c| c.name()),
+      cipher_bits: cipher.map(|c| c.bits().secret),
+      authentication: cipher.and_then(|c| {
+        c.description()
+          .split_whitespace()
+          .find_map(|field| field.strip_prefix("Au="))
+          .map(str::to_owned)
+      }),
+      srtp_profile: ssl.selected_srtp_profile().map(|p| p.name()),
+      extended_master_secret: ssl.extms_support(),
+      session_reused: ssl.session_reused(),
+    })
+  }
+
+  // Derive keying material from the established DTLS session with the exporter of RFC 5705.
+  pub fn export_keying_material(
+    &self,
+    label: &str,
+    context: Option<&[u8]>,
+    out: &mut [u8],
+  ) -> Result<(), ClientError> {
+    match &self.ssl_state {
+      ClientSslState::Established(ssl_stream) => ssl_stream
+        .ssl()
+        .export_keying_material(out, label, context)
+        .map_err(ClientError::OpenSslError),
+      _ => Err(ClientError::NotEstablished),
+    }
+  }
+
   /// Time of last activity that indicates a working connection
   pub fn last_activity(&self) -> Instant {
     self.client_state.last_activity
   }
 
-  /// Request SCTP and DTLS shutdown, connection immediately becomes un-established
-  pub fn start_shutdown(&mut self) -> Result<bool, ClientError> {
+  /// The client was authenticated with `server_user` and `remote_user`.
+  pub fn has_ice_users(&self, server_user: &str, remote_user: &str) -> bool {
+    match &self.ice_users {
+      Some((server, remote)) => server == server_user && remote == remote_user,
+      None => false,
+    }
+  }
+
+  /// Address the client's traffic comes from now that its NAT has moved it.
+  pub fn rebind(&mut self, remote_addr: SocketAddr) {
+    self._remote_addr = remote_addr;
+    self.client_state.sctp_remote_address = remote_addr;
+  }
+
+  // Add a clock jump of `gap` to the time since the last activity, so that it counts towards the
+  // connection timeout.
+  pub fn count_gap(&mut self, gap: Duration) {
+    count_gap(&mut self.client_state.last_activity, gap);
+    count_gap(&mut self.client_state.last_received, gap);
+    count_gap(&mut self.last_validated, gap);
+    count_gap(&mut self.last_heard, gap);
+  }
+
+  /// Request SCTP and DTLS shutdown, connection immediately becomes un-established.  `reason` is
+  /// remembered unless the client already has a `Client::disconnect_reason`.
+  pub fn start_shutdown(&mut self, reason: DisconnectReason) -> Result<bool, ClientError> {
+    self.client_state.disconnect_reason.get_or_insert(reason);
     let started;
     self.ssl_state = match mem::replace(&mut self.ssl_state, ClientSslState::Shutdown) {
       ClientSslState::Established(mut ssl_stream) => {
@@ -178,6 +450,10 @@ impl Client {
           )?;
           self.client_state.last_sent = Instant::now();
           self.client_state.sctp_state = SctpState::Shutdown;
+          self
+            .client_state
+            .trace
+            .record(TraceLayer::Sctp, TraceDirection::Sent, "ABORT");
         }
         match ssl_stream.shutdown() {
           Err(err) => {
@@ -188,15 +464,20 @@ impl Client {
             }
           }
           Ok(res) => {
-            unsafe {
-              // let mut msg = "client_datachannel_close".to_string();
-              let mut msg = format!(
+            self.client_state.trace.record(
+              TraceLayer::Dtls,
+              TraceDirection::Sent,
+              "Alert(close_notify)",
+            );
+            // "client_datachannel_close"
+            self.client_state.ffi_events.emit(
+              1003,
+              format!(
                 "{}:{}",
                 self.client_state.sctp_remote_address.ip(),
                 self.client_state.sctp_remote_address.port()
-              );
-              EVENT_CB.unwrap()(1003, msg.as_mut_ptr(), msg.len() as u32);
-            }
+              ),
+            );
             ClientSslState::ShuttingDown(ssl_stream, res)
           }
         }
@@ -209,6 +490,27 @@ impl Client {
     Ok(started)
   }
 
+  /// The DTLS handshake has neither completed nor been given up on.
+  pub fn is_handshaking(&self) -> bool {
+    matches!(self.ssl_state, ClientSslState::Handshake(_))
+  }
+
+  /// Give up on the DTLS handshake without answering, after which the client counts as shut down.
+  pub fn refuse_handshake(&mut self, reason: impl fmt::Display) {
+    if let ClientSslState::Handshake(_) = self.ssl_state {
+      self.ssl_state = ClientSslState::Shutdown;
+      self.client_state.trace.record(
+        TraceLayer::Dtls,
+        TraceDirection::Local,
+        format!("handshake refused: {}", reason),
+      );
+      self
+        .client_state
+        .disconnect_reason
+        .get_or_insert(DisconnectReason::DtlsError);
+    }
+  }
+
   /// Returns true if the shutdown process has been started or has already finished.
   pub fn shutdown_started(&self) -> bool {
     match &self.ssl_state {
@@ -217,6 +519,44 @@ impl Client {
     }
   }
 
+  /// Begin a graceful SCTP shutdown of an established association, optionally preceded by a final
+  /// text message carrying `reason`.  A following `Client::start_shutdown` then only has to close
+  /// the DTLS stream.
+  pub fn send_goodbye(&mut self, reason: Option<&str>) -> Result<(), ClientError> {
+    if let Some(reason) = reason {
+      self.send_message(MessageType::Text, reason.as_bytes())?;
+    }
+
+    let ssl_stream = match &mut self.ssl_state {
+      ClientSslState::Established(ssl_stream) => ssl_stream,
+      _ => return Err(ClientError::NotConnected),
+    };
+    if self.client_state.sctp_state != SctpState::Established {
+      return Err(ClientError::NotEstablished);
+    }
+
+    send_sctp_packet(
+      &self.buffer_pool,
+      ssl_stream,
+      SctpPacket {
+        source_port: self.client_state.sctp_local_port,
+        dest_port: self.client_state.sctp_remote_port,
+        verification_tag: self.client_state.sctp_remote_verification_tag,
+        chunks: &[SctpChunk::Shutdown {
+          cumulative_tsn_ack: self.client_state.sctp_remote_tsn,
+        }],
+      },
+    )?;
+    self.client_state.last_sent = Instant::now();
+    self.client_state.sctp_state = SctpState::Shutdown;
+    self
+      .client_state
+      .trace
+      .record(TraceLayer::Sctp, TraceDirection::Sent, "SHUTDOWN");
+
+    Ok(())
+  }
+
   /// Connection has finished shutting down.
   pub fn is_shutdown(&self) -> bool {
     match &self.ssl_state {
@@ -225,30 +565,32 @@ impl Client {
     }
   }
 
-  /// Generate any periodic packets, currently only heartbeat packets.
+  /// Generate any periodic packets, which are retransmissions of unacknowledged reliable chunks
+  /// and heartbeat packets.
   pub fn generate_periodic(&mut self) -> Result<(), ClientError> {
-    // We send heartbeat packets if the last sent packet was more than HEARTBEAT_INTERVAL ago
-    if self.client_state.last_sent.elapsed() > HEARTBEAT_INTERVAL {
-      match &mut self.ssl_state {
-        ClientSslState::Established(ssl_stream) => {
-          if self.client_state.sctp_state == SctpState::Established {
-            send_sctp_packet(
-              &self.buffer_pool,
-              ssl_stream,
-              SctpPacket {
-                source_port: self.client_state.sctp_local_port,
-                dest_port: self.client_state.sctp_remote_port,
-                verification_tag: self.client_state.sctp_remote_verification_tag,
-                chunks: &[SctpChunk::Heartbeat {
-                  heartbeat_info: Some(SCTP_HEARTBEAT),
-                }],
-              },
-            )?;
-            self.client_state.last_sent = Instant::now();
-          }
+    match &mut self.ssl_state {
+      ClientSslState::Established(ssl_stream)
+        if self.client_state.sctp_state == SctpState::Established =>
+      {
+        retransmit(&self.buffer_pool, ssl_stream, &mut self.client_state)?;
+        // We send heartbeat packets if the last sent packet was more than HEARTBEAT_INTERVAL ago
+        if self.client_state.last_sent.elapsed() > HEARTBEAT_INTERVAL {
+          send_sctp_packet(
+            &self.buffer_pool,
+            ssl_stream,
+            SctpPacket {
+              source_port: self.client_state.sctp_local_port,
+              dest_port: self.client_state.sctp_remote_port,
+              verification_tag: self.client_state.sctp_remote_verification_tag,
+              chunks: &[SctpChunk::Heartbeat {
+                heartbeat_info: Some(SCTP_HEARTBEAT),
+              }],
+            },
+          )?;
+          self.client_state.last_sent = Instant::now();
         }
-        _ => {}
       }
+      _ => {}
     }
     Ok(())
   }
@@ -258,14 +600,56 @@ impl Client {
   pub fn receive_incoming_packet(&mut self, udp_packet: OwnedBuffer) -> Result<(), ClientError> {
     self.ssl_state = match mem::replace(&mut self.ssl_state, ClientSslState::Shutdown) {
       ClientSslState::Handshake(mut mid_handshake) => {
-        mid_handshake.get_mut().incoming_udp.push_back(udp_packet);
+        self.dtls_started = true;
+        let udp_packet = self.buffer_pool.adopt(udp_packet);
+        self.client_state.trace.record(
+          TraceLayer::Dtls,
+          TraceDirection::Received,
+          describe_dtls_datagram(&udp_packet),
+        );
+        mid_handshake
+          .get_mut()
+          .incoming_udp
+          .push_back(udp_packet.into_owned());
         match mid_handshake.handshake() {
-          Ok(ssl_stream) => ClientSslState::Established(ssl_stream),
+          Ok(mut ssl_stream) => {
+            // The final flight is queued as the handshake completes, after which outgoing
+            // packets are no longer looked into.
+            let outgoing = &mut ssl_stream.get_mut().outgoing_udp;
+            for _ in 0..outgoing.len() {
+              let packet = self.buffer_pool.adopt(outgoing.pop_front().unwrap());
+              self.client_state.trace.record(
+                TraceLayer::Dtls,
+                TraceDirection::Sent,
+                describe_dtls_datagram(&packet),
+              );
+              outgoing.push_back(packet.into_owned());
+            }
+            self.client_state.trace.record(
+              TraceLayer::Dtls,
+              TraceDirection::Local,
+              "handshake complete",
+            );
+            ClientSslState::Established(ssl_stream)
+          }
           Err(handshake_error) => match handshake_error {
             HandshakeError::SetupFailure(err) => {
               return Err(ClientError::OpenSslError(err));
             }
-            HandshakeError::Failure(mid_handshake) => ClientSslState::Handshake(mid_handshake),
+            HandshakeError::Failure(mid_handshake) => {
+              let error = mid_handshake.error().to_string();
+              self.client_state.trace.record(
+                TraceLayer::Dtls,
+                TraceDirection::Local,
+                format!("handshake failed: {}", error),
+              );
+              self.client_state.handshake_failure = Some(error);
+              self
+                .client_state
+                .disconnect_reason
+                .get_or_insert(DisconnectReason::DtlsError);
+              ClientSslState::Handshake(mid_handshake)
+            }
             HandshakeError::WouldBlock(mid_handshake) => ClientSslState::Handshake(mid_handshake),
           },
         }
@@ -294,20 +678,26 @@ impl Client {
 
     while let ClientSslState::Established(ssl_stream) = &mut self.ssl_state {
       let mut ssl_buffer = self.buffer_pool.acquire();
-      ssl_buffer.resize(MAX_SCTP_PACKET_SIZE, 0);
-      match ssl_stream.ssl_read(&mut ssl_buffer) {
+      ssl_buffer.reserve(MAX_SCTP_PACKET_SIZE);
+      match ssl_stream.ssl_read_uninit(&mut ssl_buffer.spare_capacity_mut()[..MAX_SCTP_PACKET_SIZE])
+      {
         Ok(size) => {
+          // OpenSSL initialized the first `size` bytes of the buffer, which was empty.
+          unsafe {
+            ssl_buffer.set_len(size);
+          }
           let mut sctp_chunks = [SctpChunk::Abort; SCTP_MAX_CHUNKS];
-          match read_sctp_packet(&ssl_buffer[0..size], false, &mut sctp_chunks) {
+          match read_sctp_packet(&ssl_buffer, false, &mut sctp_chunks) {
             Ok(sctp_packet) => {
               if !receive_sctp_packet(
                 &self.buffer_pool,
                 ssl_stream,
+                &mut thread_rng(),
                 &mut self.client_state,
                 &sctp_packet,
               )? {
                 drop(ssl_buffer);
-                self.start_shutdown()?;
+                self.start_shutdown(DisconnectReason::SctpAbort)?;
               }
             }
             Err(_err) => {}
@@ -318,7 +708,12 @@ impl Client {
             break;
           } else if err.code() == ErrorCode::ZERO_RETURN {
             drop(ssl_buffer);
-            self.start_shutdown()?;
+            self.client_state.trace.record(
+              TraceLayer::Dtls,
+              TraceDirection::Received,
+              "Alert(close_notify)",
+            );
+            self.start_shutdown(DisconnectReason::PeerClose)?;
           } else {
             return Err(ssl_err_to_client_err(err));
           }
@@ -329,24 +724,258 @@ impl Client {
     Ok(())
   }
 
-  pub fn take_outgoing_packets<'a>(&'a mut self) -> impl Iterator<Item = OwnedBuffer> + 'a {
-    (match &mut self.ssl_state {
+  /// Move any packets written by the DTLS stream into this client's egress queue.
+  pub fn queue_outgoing_packets(&mut self) {
+    let egress = &mut self.egress;
+    match &mut self.ssl_state {
       ClientSslState::Handshake(mid_handshake) => {
-        Some(mid_handshake.get_mut().outgoing_udp.drain(..))
+        for packet in mid_handshake.get_mut().outgoing_udp.drain(..) {
+          let packet = self.buffer_pool.adopt(packet);
+          self.client_state.trace.record(
+            TraceLayer::Dtls,
+            TraceDirection::Sent,
+            describe_dtls_datagram(&packet),
+          );
+          egress.push_back((packet.into_owned(), Instant::now()));
+        }
       }
       ClientSslState::Established(ssl_stream) | ClientSslState::ShuttingDown(ssl_stream, _) => {
-        Some(ssl_stream.get_mut().outgoing_udp.drain(..))
+        let now = Instant::now();
+        egress.extend(
+          ssl_stream
+            .get_mut()
+            .outgoing_udp
+            .drain(..)
+            .map(|packet| (packet, now)),
+        )
       }
-      ClientSslState::Shutdown => None,
-    })
-    .into_iter()
-    .flatten()
+      ClientSslState::Shutdown => {}
+    }
+  }
+
+  pub fn pop_egress(&mut self) -> Option<OwnedBuffer> {
+    self.egress.pop_front().map(|(packet, _)| packet)
+  }
+
+  /// Number of packets waiting in this client's egress queue.
+  pub fn egress_len(&self) -> usize {
+    self.egress.len()
+  }
+
+  /// Packets and bytes waiting in this client's egress queue, and how long the oldest has waited.
+  pub fn egress_depth(&self) -> QueueDepth {
+    QueueDepth {
+      packets: self.egress.len(),
+      bytes: self.egress.iter().map(|(packet, _)| packet.len()).sum(),
+      oldest_age: self
+        .egress
+        .front()
+        .map(|(_, queued_at)| queued_at.elapsed()),
+    }
+  }
+
+  /// Memory held by the packets in this client's egress queue, and by the chunks of reliable
+  /// messages kept until they are acknowledged.
+  pub fn egress_bytes(&self) -> usize {
+    let unacked: usize = self
+      .client_state
+      .outstanding
+      .iter()
+      .map(|chunk| chunk.data.capacity())
+      .sum();
+    self
+      .egress
+      .iter()
+      .map(|(packet, _)| packet.capacity())
+      .sum::<usize>()
+      + unacked
+  }
+
+  /// Memory held by received messages and fragments waiting to be taken, such as while paused.
+  pub fn received_bytes(&self) -> usize {
+    let client_state = &self.client_state;
+    let messages: usize = client_state
+      .received_messages
+      .iter()
+      .map(|(_, _, _, message)| message.capacity())
+      .sum();
+    let fragments: usize = client_state
+      .received_fragments
+      .iter()
+      .map(|(_, _, _, _, fragment)| fragment.capacity())
+      .sum();
+    messages + fragments
+  }
+
+  /// Memory held to reassemble fragmented messages, which is only the position of each partial
+  /// message when fragments are streamed, and their data so far otherwise.
+  pub fn reassembly_bytes(&self) -> usize {
+    let data: usize = self
+      .client_state
+      .partial_messages
+      .iter()
+      .filter_map(|partial| partial.data.as_ref())
+      .map(|data| data.capacity())
+      .sum();
+    self.client_state.partial_messages.capacity() * mem::size_of::<PartialMessage>() + data
+  }
+
+  pub fn has_egress(&self) -> bool {
+    !self.egress.is_empty()
+  }
+
+  /// Drop every packet still waiting in this client's egress queue.
+  pub fn clear_egress(&mut self) {
+    self.egress.clear();
+  }
+
+  /// Number of packets dropped because this client's egress queue was full.
+  pub fn egress_dropped(&self) -> u64 {
+    self.egress.dropped()
+  }
+
+  /// Hold received messages and fragments in the client, up to `max_held` of them, rather than
+  /// have them taken by `receive_messages` and `receive_fragments` until `resume`.  Beyond the
+  /// limit, messages on reliable channels are left unacknowledged rather than dropped.
+  pub fn pause(&mut self, max_held: usize) {
+    self.client_state.max_held = Some(max_held);
+  }
+
+  pub fn resume(&mut self) {
+    self.client_state.max_held = None;
+    self.client_state.refused_tsn = None;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.client_state.max_held.is_some()
+  }
+
+  /// The first reason this client was found to be going away for, if any.
+  pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+    self.client_state.disconnect_reason
+  }
+
+  /// The error the DTLS handshake failed with, if it has since the last call.
+  pub fn take_handshake_failure(&mut self) -> Option<String> {
+    self.client_state.handshake_failure.take()
+  }
+
+  /// The data channels the client opened and closed since the last call, in order.
+  pub fn take_channel_events(&mut self) -> Vec<ChannelEvent> {
+    mem::take(&mut self.client_state.channel_events)
+  }
+
+  /// Number of received messages and fragments dropped because too many were held while paused.
+  pub fn held_dropped(&self) -> u64 {
+    self.client_state.held_dropped
   }
 
   pub fn send_message(
     &mut self,
     message_type: MessageType,
     message: &[u8],
+  ) -> Result<(), ClientError> {
+    self.send_message_with(message_type, message, Delivery::Unreliable)
+  }
+
+  /// Send a message as with `send_message`, delivered according to `delivery`.
+  pub fn send_message_with(
+    &mut self,
+    message_type: MessageType,
+    message: &[u8],
+    delivery: Delivery,
+  ) -> Result<(), ClientError> {
+    self.send_on_stream(0, message_type, message, delivery)
+  }
+
+  /// Send a message on the data channel the client opened with the given id, delivered as the
+  /// channel was negotiated.
+  pub fn send_message_on(
+    &mut self,
+    channel_id: u16,
+    message_type: MessageType,
+    message: &[u8],
+  ) -> Result<(), ClientError> {
+    let delivery = self
+      .client_state
+      .channel_deliveries
+      .iter()
+      .find(|(id, _)| *id == channel_id)
+      .map(|&(_, delivery)| delivery)
+      .ok_or(ClientError::UnknownChannel(channel_id))?;
+    self.send_on_stream(channel_id, message_type, message, delivery)
+  }
+
+  /// Open a data channel called `label` towards the client, delivered as `delivery`, returning its
+  /// id.  The channel is open once the client acknowledges it, see `Client::is_channel_open`.
+  pub fn open_channel(&mut self, label: &str, delivery: Delivery) -> Result<u16, ClientError> {
+    if self.client_state.sctp_state != SctpState::Established {
+      return Err(ClientError::NotEstablished);
+    }
+    let channel_id = free_channel_id(&self.client_state)
+      .ok_or_else(|| ClientError::SCTPError("no free stream for a data channel".to_owned()))?;
+    // DCEP messages are always sent reliably and in order.
+    self.send_data(
+      channel_id,
+      DATA_CHANNEL_PROTO_CONTROL,
+      &data_channel_open_message(label, delivery),
+      Delivery::ReliableOrdered,
+    )?;
+    self
+      .client_state
+      .pending_channels
+      .push((channel_id, label.to_owned(), delivery));
+    self.client_state.trace.record(
+      TraceLayer::DataChannel,
+      TraceDirection::Sent,
+      format!("DATA_CHANNEL_OPEN stream {} label {:?}", channel_id, label),
+    );
+    Ok(channel_id)
+  }
+
+  /// Whether the data channel `channel_id` is open, whichever side opened it.
+  pub fn is_channel_open(&self, channel_id: u16) -> bool {
+    self
+      .client_state
+      .channel_deliveries
+      .iter()
+      .any(|&(id, _)| id == channel_id)
+  }
+
+  fn send_on_stream(
+    &mut self,
+    stream_id: u16,
+    message_type: MessageType,
+    message: &[u8],
+    delivery: Delivery,
+  ) -> Result<(), ClientError> {
+    let proto_id = if message_type == MessageType::Text {
+      DATA_CHANNEL_PROTO_STRING
+    } else {
+      DATA_CHANNEL_PROTO_BINARY
+    };
+    self.send_data(stream_id, proto_id, message, delivery)?;
+
+    let label = self
+      .client_state
+      .channel_labels
+      .iter()
+      .find(|(id, _)| *id == stream_id)
+      .map_or("", |(_, label)| label.as_str());
+    self
+      .client_state
+      .traffic
+      .record_sent(label, message_type, message.len());
+
+    Ok(())
+  }
+
+  fn send_data(
+    &mut self,
+    stream_id: u16,
+    proto_id: u32,
+    message: &[u8],
+    delivery: Delivery,
   ) -> Result<(), ClientError> {
     let ssl_stream = match &mut self.ssl_state {
       ClientSslState::Established(ssl_stream) => ssl_stream,
@@ -358,52 +987,291 @@ impl Client {
     if self.client_state.sctp_state != SctpState::Established {
       return Err(ClientError::NotEstablished);
     }
+    if message.len() > self.max_message_len {
+      return Err(ClientError::IncompletePacketWrite);
+    }
+    check_send_window(&self.client_state, delivery, message.len())?;
+    // Losing any fragment loses the whole message, so a fragmented message is only sent if all
+    // of its fragments fit in the egress queue.
+    let fragment_len = self.max_fragment_len.unwrap_or(MAX_MESSAGE_LEN);
+    let fragments = message.len().div_ceil(fragment_len).max(1);
+    if let Some(capacity) = self.egress.bounded_capacity() {
+      if fragments > 1 && fragments > capacity.saturating_sub(self.egress.len()) {
+        return Err(ClientError::SendWindowFull);
+      }
+    }
 
-    let proto_id = if message_type == MessageType::Text {
-      DATA_CHANNEL_PROTO_STRING
+    // Ordered messages are numbered within their stream, and all of a message's fragments carry its
+    // number.
+    let stream_seq = if delivery.is_ordered() {
+      next_stream_seq(&mut self.client_state, stream_id)
     } else {
-      DATA_CHANNEL_PROTO_BINARY
+      0
     };
-
-    send_sctp_packet(
-      &self.buffer_pool,
-      ssl_stream,
-      SctpPacket {
-        source_port: self.client_state.sctp_local_port,
-        dest_port: self.client_state.sctp_remote_port,
-        verification_tag: self.client_state.sctp_remote_verification_tag,
-        chunks: &[SctpChunk::Data {
-          chunk_flags: SCTP_FLAG_COMPLETE_UNRELIABLE,
-          tsn: self.client_state.sctp_local_tsn,
-          stream_id: 0,
-          stream_seq: 0,
+    let message_id = self.client_state.sctp_local_tsn;
+    let now = Instant::now();
+
+    // Each fragment goes in its own packet, with consecutive TSNs, the first marked as beginning
+    // the message and the last as ending it.
+    for index in 0..fragments {
+      let start = index * fragment_len;
+      let end = (start + fragment_len).min(message.len());
+      let mut chunk_flags = if delivery.is_ordered() {
+        0
+      } else {
+        SCTP_FLAG_UNRELIABLE
+      };
+      if index == 0 {
+        chunk_flags |= SCTP_FLAG_BEGIN_FRAGMENT;
+      }
+      if index + 1 == fragments {
+        chunk_flags |= SCTP_FLAG_END_FRAGMENT;
+      }
+      let tsn = self.client_state.sctp_local_tsn;
+      send_sctp_packet(
+        &self.buffer_pool,
+        ssl_stream,
+        SctpPacket {
+          source_port: self.client_state.sctp_local_port,
+          dest_port: self.client_state.sctp_remote_port,
+          verification_tag: self.client_state.sctp_remote_verification_tag,
+          chunks: &[SctpChunk::Data {
+            chunk_flags,
+            tsn,
+            stream_id,
+            stream_seq,
+            proto_id,
+            user_data: &message[start..end],
+          }],
+        },
+      )?;
+      self.client_state.sctp_local_tsn = tsn.wrapping_add(1);
+
+      if delivery != Delivery::Unreliable {
+        let mut data = self.buffer_pool.acquire();
+        data.extend_from_slice(&message[start..end]);
+        self.client_state.unacked_bytes += data.len();
+        self.client_state.outstanding.push(OutstandingChunk {
+          message_id,
+          tsn,
+          chunk_flags,
+          stream_id,
+          stream_seq,
           proto_id,
-          user_data: message,
-        }],
-      },
-    )?;
-    self.client_state.sctp_local_tsn = self.client_state.sctp_local_tsn.wrapping_add(1);
+          data: data.into_owned(),
+          last_sent: now,
+          retransmits: 0,
+          max_retransmits: delivery.max_retransmits(),
+          expires_at: delivery.lifetime().map(|lifetime| now + lifetime),
+        });
+      }
+    }
 
     Ok(())
   }
 
+  /// Record a step of the connection's lifecycle which happened outside the client itself.
+  pub fn record_trace(
+    &mut self,
+    layer: TraceLayer,
+    direction: TraceDirection,
+    event: impl Into<String>,
+  ) {
+    self.client_state.trace.record(layer, direction, event);
+  }
+
+  /// The connection's lifecycle so far, see `Server::connection_trace`.
+  pub fn trace(&self) -> &TraceLog {
+    &self.client_state.trace
+  }
+
+  /// Record the removal of the client, and hand over its trace.
+  pub fn finish_trace(&mut self, event: impl Into<String>) -> TraceLog {
+    self.client_state.trace.finish(event);
+    mem::replace(&mut self.client_state.trace, TraceLog::new())
+  }
+
+  /// Messages and bytes this client has sent and received on each of its data channels.
+  pub fn traffic(&self) -> &TrafficCounters {
+    &self.client_state.traffic
+  }
+
+  /// Data channel subprotocols this client's channels may be tagged with, by their index.
+  pub fn set_subprotocols(&mut self, subprotocols: Arc<[String]>) {
+    self.client_state.subprotocols = subprotocols;
+  }
+
+  /// Data channels which are open as soon as the SCTP association is established.
+  pub fn set_negotiated_channels(&mut self, negotiated_channels: Arc<[NegotiatedChannel]>) {
+    self.client_state.negotiated_channels = negotiated_channels;
+  }
+
   pub fn receive_messages<'a>(
     &'a mut self,
-  ) -> impl Iterator<Item = (MessageType, OwnedBuffer)> + 'a {
+  ) -> impl Iterator<Item = (MessageType, Option<u8>, u16, OwnedBuffer)> + 'a {
     self.client_state.received_messages.drain(..)
   }
+
+  /// Pass on the fragments of messages too large for a single DATA chunk as they arrive, instead
+  /// of discarding them.
+  pub fn set_stream_fragments(&mut self, stream_fragments: bool) {
+    self.client_state.stream_fragments = stream_fragments;
+  }
+
+  /// Reassemble fragmented messages of up to `max_reassembled_len` bytes and receive them whole
+  /// with `receive_messages`, instead of discarding or streaming their fragments.
+  pub fn set_max_reassembled_len(&mut self, max_reassembled_len: Option<usize>) {
+    self.client_state.max_reassembled_len = max_reassembled_len;
+  }
+
+  /// Send messages longer than `max_fragment_len` in fragments of at most that many bytes, which
+  /// allows messages of up to `MAX_FRAGMENTED_MESSAGE_LEN`.  Fragments are never longer than
+  /// `MAX_MESSAGE_LEN`, which is all a single packet holds.  Must be set before
+  /// `set_max_message_size`.
+  pub fn set_max_fragment_len(&mut self, max_fragment_len: Option<usize>) {
+    self.max_fragment_len = max_fragment_len.map(|len| len.clamp(1, MAX_MESSAGE_LEN));
+  }
+
+  /// Limit sent messages to the `a=max-message-size` of the client's offer, where 0 or a missing
+  /// attribute means the client accepts messages of any size (RFC 8841 section 6).
+  ///
+  /// When the egress queue drops packets once full, fragmented messages are also limited to as
+  /// many fragments as the queue holds, as a message which overflows it is lost.
+  pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+    let max_len = match (self.max_fragment_len, self.egress.bounded_capacity()) {
+      (Some(fragment_len), Some(capacity)) => {
+        MAX_FRAGMENTED_MESSAGE_LEN.min(fragment_len * capacity.max(1))
+      }
+      (Some(_), None) => MAX_FRAGMENTED_MESSAGE_LEN,
+      (None, _) => MAX_MESSAGE_LEN,
+    };
+    self.max_message_len = match max_message_size {
+      Some(size) if size > 0 => size.min(max_len),
+      _ => max_len,
+    };
+  }
+
+  /// Abandon the oldest partial message on a new fragmented message beyond `max_partial_messages`,
+  /// which is at least 1, and any partial message growing beyond `max_partial_message_len`.
+  pub fn set_reassembly_limits(
+    &mut self,
+    max_partial_messages: usize,
+    max_partial_message_len: Option<usize>,
+  ) {
+    self.client_state.max_partial_messages = max_partial_messages.max(1);
+    self.client_state.max_partial_message_len = max_partial_message_len;
+  }
+
+  pub fn reassembly(&self) -> ReassemblyStats {
+    ReassemblyStats {
+      partial_messages: self.client_state.partial_messages.len(),
+      ..self.client_state.reassembly
+    }
+  }
+
+  pub fn receive_fragments<'a>(
+    &'a mut self,
+  ) -> impl Iterator<Item = (MessageType, Option<u8>, u16, FragmentPosition, OwnedBuffer)> + 'a {
+    self.client_state.received_fragments.drain(..)
+  }
+}
+
+/// Where a received fragment belongs within its message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FragmentPosition {
+  /// The TSN of the message's first fragment.
+  pub message_id: u32,
+  pub offset: usize,
+  pub last: bool,
+}
+
+// A DATA chunk of a reliable or partially reliable message, kept until it is acknowledged.
+struct OutstandingChunk {
+  // The TSN of the message's first chunk, as partial reliability abandons whole messages.
+  message_id: u32,
+  tsn: u32,
+  chunk_flags: u8,
+  stream_id: u16,
+  stream_seq: u16,
+  proto_id: u32,
+  data: OwnedBuffer,
+  last_sent: Instant,
+  retransmits: u32,
+  max_retransmits: Option<u32>,
+  expires_at: Option<Instant>,
+}
+
+// A DATA chunk received ahead of a missing TSN.
+struct EarlyChunk {
+  tsn: u32,
+  chunk_flags: u8,
+  stream_id: u16,
+  proto_id: u32,
+  data: OwnedBuffer,
+}
+
+// A fragmented message whose final fragment has not arrived yet.
+struct PartialMessage {
+  stream_id: u16,
+  message_id: u32,
+  // The TSN the next fragment must have, and its offset.
+  next_tsn: u32,
+  offset: usize,
+  // The message so far, when it is reassembled rather than streamed.
+  data: Option<OwnedBuffer>,
 }
 
 pub struct ClientState {
   pub last_activity: Instant,
   pub last_sent: Instant,
   pub last_received: Instant,
-  received_messages: Vec<(MessageType, OwnedBuffer)>,
+  // Received messages with the subprotocol tag and id of the data channel they arrived on.
+  received_messages: Vec<(MessageType, Option<u8>, u16, OwnedBuffer)>,
+  // While paused, received messages and fragments are held here up to this many in total, and
+  // the ones beyond are dropped.
+  max_held: Option<usize>,
+  held_dropped: u64,
+  // The first TSN left unacknowledged because the hold was full when it arrived on a reliable
+  // channel.  It and every chunk after it are refused until `Client::resume`.
+  refused_tsn: Option<u32>,
+  // Set when the DTLS handshake fails, until taken by `Client::take_handshake_failure`.
+  handshake_failure: Option<String>,
+  disconnect_reason: Option<DisconnectReason>,
+
+  stream_fragments: bool,
+  received_fragments: Vec<(MessageType, Option<u8>, u16, FragmentPosition, OwnedBuffer)>,
+  // The fragmented messages being received, at most one per stream, oldest first.
+  partial_messages: Vec<PartialMessage>,
+  max_partial_messages: usize,
+  max_partial_message_len: Option<usize>,
+  // Reassemble fragmented messages up to this long, taking precedence over `stream_fragments`.
+  max_reassembled_len: Option<usize>,
+  reassembly: ReassemblyStats,
+
+  subprotocols: Arc<[String]>,
+  // The subprotocol tag of each data channel which negotiated a known subprotocol, by stream id.
+  channel_subprotocols: Vec<(u16, u8)>,
+  // The label of each data channel opened with DATA_CHANNEL_OPEN, by stream id.
+  channel_labels: Vec<(u16, String)>,
+  // The negotiated delivery of each open data channel, by stream id.
+  channel_deliveries: Vec<(u16, Delivery)>,
+  // Data channels opened with `Client::open_channel` which the client has not acknowledged yet.
+  pending_channels: Vec<(u16, String, Delivery)>,
+  // Data channels opened without DCEP once the association is established.
+  negotiated_channels: Arc<[NegotiatedChannel]>,
+  // Data channels opened and closed since the last `Client::take_channel_events`.
+  channel_events: Vec<ChannelEvent>,
+  traffic: TrafficCounters,
+  trace: TraceLog,
+  ffi_events: FfiEventQueue,
 
   sctp_state: SctpState,
 
   sctp_local_port: u16,
   sctp_remote_port: u16,
+  // How many streams the association has in each direction, the lesser of the client's outbound
+  // and inbound counts in its INIT, which the INIT ACK mirrors.
+  sctp_streams: u16,
 
   sctp_remote_address: SocketAddr,
 
@@ -412,6 +1280,74 @@ pub struct ClientState {
 
   sctp_local_tsn: u32,
   sctp_remote_tsn: u32,
+
+  // TSNs received past `sctp_remote_tsn`, in TSN order, which SACKs report in gap ack blocks.
+  received_tsns: Vec<u32>,
+  // Chunks of streams other than unreliable channels received past a missing TSN, held until the
+  // chunks before them arrive or are skipped, and the total length of their data.
+  early_chunks: Vec<EarlyChunk>,
+  early_bytes: usize,
+
+  // Chunks sent with a `Delivery` other than `Unreliable` and not yet acknowledged, by TSN.
+  outstanding: Vec<OutstandingChunk>,
+  unacked_bytes: usize,
+  // The next stream sequence number of each stream ordered messages have been sent on.
+  stream_seqs: Vec<(u16, u16)>,
+  // The stream sequence number of the last abandoned ordered message of each stream, which
+  // FORWARD TSN chunks tell the client to skip until it acknowledges the message's last TSN.
+  skipped_streams: Vec<(u16, u16, u32)>,
+  // The sequence number of the next stream reset request sent to the client (RFC 6525).
+  reconfig_seq: u32,
+}
+
+impl ClientState {
+  fn new(remote_addr: SocketAddr, ffi_events: FfiEventQueue) -> ClientState {
+    ClientState {
+      sctp_remote_address: remote_addr,
+      last_activity: Instant::now(),
+      last_sent: Instant::now(),
+      last_received: Instant::now(),
+      received_messages: Vec::new(),
+      max_held: None,
+      held_dropped: 0,
+      refused_tsn: None,
+      handshake_failure: None,
+      disconnect_reason: None,
+      stream_fragments: false,
+      received_fragments: Vec::new(),
+      partial_messages: Vec::new(),
+      max_partial_messages: usize::MAX,
+      max_partial_message_len: None,
+      max_reassembled_len: None,
+      reassembly: ReassemblyStats::default(),
+      subprotocols: Arc::new([]),
+      channel_subprotocols: Vec::new(),
+      channel_labels: Vec::new(),
+      channel_deliveries: Vec::new(),
+      pending_channels: Vec::new(),
+      negotiated_channels: Arc::new([]),
+      channel_events: Vec::new(),
+      traffic: TrafficCounters::default(),
+      trace: TraceLog::new(),
+      ffi_events,
+      sctp_state: SctpState::Shutdown,
+      sctp_local_port: 0,
+      sctp_remote_port: 0,
+      sctp_streams: 0,
+      sctp_local_verification_tag: 0,
+      sctp_remote_verification_tag: 0,
+      sctp_local_tsn: 0,
+      sctp_remote_tsn: 0,
+      received_tsns: Vec::new(),
+      early_chunks: Vec::new(),
+      early_bytes: 0,
+      outstanding: Vec::new(),
+      unacked_bytes: 0,
+      stream_seqs: Vec::new(),
+      skipped_streams: Vec::new(),
+      reconfig_seq: 0,
+    }
+  }
 }
 
 enum ClientSslState {
@@ -422,10 +1358,12 @@ enum ClientSslState {
 }
 
 #[derive(Debug)]
-struct ClientSslPackets {
-  buffer_pool: BufferPool,
-  incoming_udp: VecDeque<OwnedBuffer>,
-  outgoing_udp: VecDeque<OwnedBuffer>,
+// Datagram transport for an `SslStream`, reading from queued incoming packets and queueing every
+// write as a separate outgoing packet.
+pub struct ClientSslPackets {
+  pub buffer_pool: BufferPool,
+  pub incoming_udp: VecDeque<OwnedBuffer>,
+  pub outgoing_udp: VecDeque<OwnedBuffer>,
 }
 
 impl Read for ClientSslPackets {
@@ -461,15 +1399,19 @@ impl Write for ClientSslPackets {
 
 const SCTP_COOKIE: &[u8] = b"WEBRTC-UNRELIABLE-COOKIE";
 const SCTP_HEARTBEAT: &[u8] = b"WEBRTC-UNRELIABLE-HEARTBEAT";
-const SCTP_MAX_CHUNKS: usize = 16;
-const SCTP_BUFFER_SIZE: u32 = 0x40000;
+pub const SCTP_MAX_CHUNKS: usize = 16;
+pub const SCTP_BUFFER_SIZE: u32 = 0x40000;
+// DATA chunks further than this past the cumulative TSN are dropped rather than tracked.
+const MAX_TSN_AHEAD: u32 = 4096;
+// SACKs report at most this many gap ack blocks, the ones nearest the cumulative TSN.
+const MAX_GAP_ACK_BLOCKS: usize = 64;
 
-const DATA_CHANNEL_PROTO_CONTROL: u32 = 50;
-const DATA_CHANNEL_PROTO_STRING: u32 = 51;
-const DATA_CHANNEL_PROTO_BINARY: u32 = 53;
+pub const DATA_CHANNEL_PROTO_CONTROL: u32 = 50;
+pub const DATA_CHANNEL_PROTO_STRING: u32 = 51;
+pub const DATA_CHANNEL_PROTO_BINARY: u32 = 53;
 
-const DATA_CHANNEL_MESSAGE_ACK: u8 = 2;
-const DATA_CHANNEL_MESSAGE_OPEN: u8 = 3;
+pub const DATA_CHANNEL_MESSAGE_ACK: u8 = 2;
+pub const DATA_CHANNEL_MESSAGE_OPEN: u8 = 3;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 enum SctpState {
@@ -496,6 +1438,850 @@ fn ssl_err_to_client_err(err: SslError) -> ClientError {
   ClientError::TlsError(err)
 }
 
+// The protocol field of a DATA_CHANNEL_OPEN message (RFC 8832 section 5.1).
+fn data_channel_open_protocol(message: &[u8]) -> Option<&[u8]> {
+  if message.len() < 12 {
+    return None;
+  }
+  let label_len = NetworkEndian::read_u16(&message[8..10]) as usize;
+  let protocol_len = NetworkEndian::read_u16(&message[10..12]) as usize;
+  message.get(12 + label_len..12 + label_len + protocol_len)
+}
+
+// A DATA_CHANNEL_OPEN message opening a data channel called `label` with the given delivery.
+pub fn data_channel_open_message(label: &str, delivery: Delivery) -> Vec<u8> {
+  let (channel_type, reliability) = delivery.channel_type();
+  let mut message = vec![DATA_CHANNEL_MESSAGE_OPEN, channel_type, 0, 0];
+  message.extend_from_slice(&reliability.to_be_bytes());
+  message.extend_from_slice(&(label.len() as u16).to_be_bytes());
+  message.extend_from_slice(&[0; 2]);
+  message.extend_from_slice(label.as_bytes());
+  message
+}
+
+// The label field of a DATA_CHANNEL_OPEN message.
+fn data_channel_open_label(message: &[u8]) -> Option<&[u8]> {
+  if message.len() < 12 {
+    return None;
+  }
+  let label_len = NetworkEndian::read_u16(&message[8..10]) as usize;
+  message.get(12..12 + label_len)
+}
+
+// Count a message, or a fragment of one, received on the data channel on `stream_id`.
+fn record_received(
+  client_state: &mut ClientState,
+  stream_id: u16,
+  message_type: MessageType,
+  bytes: usize,
+  complete: bool,
+) {
+  let label = client_state
+    .channel_labels
+    .iter()
+    .find(|(id, _)| *id == stream_id)
+    .map_or("", |(_, label)| label.as_str());
+  client_state
+    .traffic
+    .record_received(label, message_type, bytes, complete);
+}
+
+// The subprotocol tag of the data channel on `stream_id`, if it negotiated a known subprotocol.
+fn channel_subprotocol(client_state: &ClientState, stream_id: u16) -> Option<u8> {
+  client_state
+    .channel_subprotocols
+    .iter()
+    .find(|&&(id, _)| id == stream_id)
+    .map(|&(_, tag)| tag)
+}
+
+// Pass on one fragment of a message too large for a single DATA chunk, or add it to the message
+// being reassembled with `max_reassembled_len`.  The fragments of a message have consecutive
+// TSNs and arrive in TSN order, so after a gap, left by a fragment the client abandoned, the rest
+// of the message can never be placed and is dropped.
+fn receive_fragment(
+  buffer_pool: &BufferPool,
+  client_state: &mut ClientState,
+  chunk_flags: u8,
+  tsn: u32,
+  stream_id: u16,
+  proto_id: u32,
+  user_data: &[u8],
+) {
+  let message_type = match proto_id {
+    DATA_CHANNEL_PROTO_STRING => MessageType::Text,
+    DATA_CHANNEL_PROTO_BINARY => MessageType::Binary,
+    _ => return,
+  };
+
+  let reassemble = client_state.max_reassembled_len.is_some();
+  let partial_messages = &mut client_state.partial_messages;
+  let existing = partial_messages
+    .iter()
+    .position(|partial| partial.stream_id == stream_id);
+  let (message_id, offset, data) = if chunk_flags & SCTP_FLAG_BEGIN_FRAGMENT != 0 {
+    // A new message means the client abandoned any earlier one on its stream.
+    if let Some(index) = existing {
+      abandon(buffer_pool, partial_messages.remove(index));
+    }
+    if partial_messages.len() >= client_state.max_partial_messages {
+      let oldest = partial_messages.remove(0);
+      let oldest_stream_id = oldest.stream_id;
+      abandon(buffer_pool, oldest);
+      reset_reassembly(client_state, oldest_stream_id, "too many partial messages");
+    }
+    let data = reassemble.then(|| buffer_pool.acquire().into_owned());
+    (tsn, 0, data)
+  } else {
+    match existing {
+      Some(index) if partial_messages[index].next_tsn == tsn => {
+        let partial = partial_messages.remove(index);
+        (partial.message_id, partial.offset, partial.data)
+      }
+      Some(index) => {
+        // A fragment went missing, so the rest of the message cannot be delivered.
+        abandon(buffer_pool, partial_messages.remove(index));
+        client_state.reassembly.holes += 1;
+        return;
+      }
+      None => return,
+    }
+  };
+
+  let len = offset + user_data.len();
+  let max_len = if reassemble {
+    client_state.max_reassembled_len
+  } else {
+    client_state.max_partial_message_len
+  };
+  if max_len.is_some_and(|max_len| len > max_len) {
+    if let Some(data) = data {
+      buffer_pool.adopt(data);
+    }
+    reset_reassembly(client_state, stream_id, "partial message too long");
+    return;
+  }
+  let last = chunk_flags & SCTP_FLAG_END_FRAGMENT != 0;
+  let subprotocol = channel_subprotocol(client_state, stream_id);
+  if let Some(data) = data {
+    let mut data = buffer_pool.adopt(data);
+    data.extend(user_data);
+    if !last {
+      client_state.partial_messages.push(PartialMessage {
+        stream_id,
+        message_id,
+        next_tsn: tsn.wrapping_add(1),
+        offset: len,
+        data: Some(data.into_owned()),
+      });
+      return;
+    }
+    record_received(client_state, stream_id, message_type, len, true);
+    client_state.last_received = Instant::now();
+    if !hold_full(client_state) {
+      client_state.received_messages.push((
+        message_type,
+        subprotocol,
+        stream_id,
+        data.into_owned(),
+      ));
+    }
+    return;
+  }
+
+  record_received(client_state, stream_id, message_type, user_data.len(), last);
+  client_state.last_received = Instant::now();
+  // Dropping a fragment leaves a hole in its message, so the rest of the message goes too.
+  if hold_full(client_state) {
+    reset_reassembly(client_state, stream_id, "too many held fragments");
+    return;
+  }
+  if !last {
+    client_state.partial_messages.push(PartialMessage {
+      stream_id,
+      message_id,
+      next_tsn: tsn.wrapping_add(1),
+      offset: len,
+      data: None,
+    });
+  }
+  let mut fragment = buffer_pool.acquire();
+  fragment.extend(user_data);
+  client_state.received_fragments.push((
+    message_type,
+    subprotocol,
+    stream_id,
+    FragmentPosition {
+      message_id,
+      offset,
+      last,
+    },
+    fragment.into_owned(),
+  ));
+}
+
+// Drop a partial message, returning its data to the pool.
+fn abandon(buffer_pool: &BufferPool, partial: PartialMessage) {
+  if let Some(data) = partial.data {
+    buffer_pool.adopt(data);
+  }
+}
+
+// Whether a paused client already holds as many received messages and fragments as it may,
+// counting the one about to be dropped if so.
+fn hold_full(client_state: &mut ClientState) -> bool {
+  match client_state.max_held {
+    Some(max_held)
+      if client_state.received_messages.len() + client_state.received_fragments.len()
+        >= max_held =>
+    {
+      client_state.held_dropped += 1;
+      true
+    }
+    _ => false,
+  }
+}
+
+// Take in a DATA chunk.  Chunks of unreliable channels are delivered as soon as they arrive,
+// while those of any other stream are delivered in TSN order, held until every chunk before them
+// has arrived or been skipped with FORWARD TSN, so that a reliable message is never acknowledged
+// before it arrives and its fragments are always reassembled in order.
+#[allow(clippy::too_many_arguments)]
+fn receive_data(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+  chunk_flags: u8,
+  tsn: u32,
+  stream_id: u16,
+  proto_id: u32,
+  user_data: &[u8],
+) -> Result<(), ClientError> {
+  let ahead = tsn.wrapping_sub(client_state.sctp_remote_tsn);
+  if ahead == 0 || ahead > MAX_TSN_AHEAD || client_state.received_tsns.contains(&tsn) {
+    // Already received, or too far ahead to be tracked, in which case the client sends it again
+    // once the chunks before it are acknowledged.
+    return Ok(());
+  }
+  let unreliable = client_state
+    .channel_deliveries
+    .iter()
+    .any(|&(id, delivery)| id == stream_id && delivery == Delivery::Unreliable);
+  if unreliable || ahead == 1 {
+    deliver_chunk(
+      buffer_pool,
+      ssl_stream,
+      client_state,
+      chunk_flags,
+      tsn,
+      stream_id,
+      proto_id,
+      user_data,
+    )?;
+  } else if client_state.early_bytes + user_data.len() <= SCTP_BUFFER_SIZE as usize {
+    let mut data = buffer_pool.acquire();
+    data.extend(user_data);
+    client_state.early_bytes += user_data.len();
+    client_state.early_chunks.push(EarlyChunk {
+      tsn,
+      chunk_flags,
+      stream_id,
+      proto_id,
+      data: data.into_owned(),
+    });
+  } else {
+    // Past the receive window, so it is left for the client to send again.
+    return Ok(());
+  }
+
+  let cumulative_tsn = client_state.sctp_remote_tsn;
+  let index = client_state
+    .received_tsns
+    .partition_point(|&received| received.wrapping_sub(cumulative_tsn) < ahead);
+  client_state.received_tsns.insert(index, tsn);
+  advance_cumulative_tsn(buffer_pool, ssl_stream, client_state)
+}
+
+// Move the cumulative TSN over the chunks received right after it, delivering those held for it.
+fn advance_cumulative_tsn(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+) -> Result<(), ClientError> {
+  loop {
+    let next_tsn = client_state.sctp_remote_tsn.wrapping_add(1);
+    if client_state.received_tsns.first() != Some(&next_tsn) {
+      break;
+    }
+    client_state.received_tsns.remove(0);
+    client_state.sctp_remote_tsn = next_tsn;
+    if let Some(index) = client_state
+      .early_chunks
+      .iter()
+      .position(|chunk| chunk.tsn == next_tsn)
+    {
+      let chunk = client_state.early_chunks.remove(index);
+      deliver_early_chunk(buffer_pool, ssl_stream, client_state, chunk)?;
+    }
+  }
+
+  // When every channel is unreliable nothing missing is ever sent again, so everything up to the
+  // highest TSN received is acknowledged rather than waiting for the client's FORWARD TSN.
+  let all_unreliable = !client_state.channel_deliveries.is_empty()
+    && client_state.pending_channels.is_empty()
+    && client_state
+      .channel_deliveries
+      .iter()
+      .all(|&(_, delivery)| delivery == Delivery::Unreliable);
+  if all_unreliable && client_state.early_chunks.is_empty() {
+    if let Some(&highest_tsn) = client_state.received_tsns.last() {
+      client_state.sctp_remote_tsn = highest_tsn;
+      client_state.received_tsns.clear();
+    }
+  }
+  Ok(())
+}
+
+// Move the cumulative TSN up to `new_cumulative_tsn` from a FORWARD TSN chunk, past the chunks
+// the client gave up on, delivering the chunks held up to there in order.
+fn skip_to_tsn(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+  new_cumulative_tsn: u32,
+) -> Result<(), ClientError> {
+  let cumulative_tsn = client_state.sctp_remote_tsn;
+  let skipped = new_cumulative_tsn.wrapping_sub(cumulative_tsn);
+  if skipped == 0 || skipped >= 1 << 31 {
+    return Ok(());
+  }
+  let (mut released, held): (Vec<_>, Vec<_>) = mem::take(&mut client_state.early_chunks)
+    .into_iter()
+    .partition(|chunk| chunk.tsn.wrapping_sub(cumulative_tsn) <= skipped);
+  client_state.early_chunks = held;
+  released.sort_by_key(|chunk| chunk.tsn.wrapping_sub(cumulative_tsn));
+  client_state
+    .received_tsns
+    .retain(|&received| received.wrapping_sub(cumulative_tsn) > skipped);
+  client_state.sctp_remote_tsn = new_cumulative_tsn;
+  for chunk in released {
+    deliver_early_chunk(buffer_pool, ssl_stream, client_state, chunk)?;
+  }
+  advance_cumulative_tsn(buffer_pool, ssl_stream, client_state)
+}
+
+fn deliver_early_chunk(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+  chunk: EarlyChunk,
+) -> Result<(), ClientError> {
+  client_state.early_bytes -= chunk.data.len();
+  let data = buffer_pool.adopt(chunk.data);
+  deliver_chunk(
+    buffer_pool,
+    ssl_stream,
+    client_state,
+    chunk.chunk_flags,
+    chunk.tsn,
+    chunk.stream_id,
+    chunk.proto_id,
+    &data,
+  )
+}
+
+// Acknowledge the chunks received so far, reporting those past the cumulative TSN in gap ack
+// blocks so that the client only sends the missing ones again.
+fn send_sack(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+) -> Result<(), ClientError> {
+  let cumulative_tsn = client_state.sctp_remote_tsn;
+  let mut blocks: Vec<(u16, u16)> = Vec::new();
+  for &tsn in &client_state.received_tsns {
+    // Received TSNs are never more than `MAX_TSN_AHEAD` past the cumulative TSN.
+    let offset = tsn.wrapping_sub(cumulative_tsn) as u16;
+    match blocks.last_mut() {
+      Some((_, end)) if *end + 1 == offset => *end = offset,
+      _ => blocks.push((offset, offset)),
+    }
+  }
+  let mut gap_ack_blocks = buffer_pool.acquire();
+  for (start, end) in blocks.into_iter().take(MAX_GAP_ACK_BLOCKS) {
+    gap_ack_blocks.extend_from_slice(&start.to_be_bytes());
+    gap_ack_blocks.extend_from_slice(&end.to_be_bytes());
+  }
+
+  send_sctp_packet(
+    buffer_pool,
+    ssl_stream,
+    SctpPacket {
+      source_port: client_state.sctp_local_port,
+      dest_port: client_state.sctp_remote_port,
+      verification_tag: client_state.sctp_remote_verification_tag,
+      chunks: &[SctpChunk::SAck {
+        cumulative_tsn_ack: cumulative_tsn,
+        adv_recv_window: SCTP_BUFFER_SIZE.saturating_sub(client_state.early_bytes as u32),
+        gap_ack_blocks: &gap_ack_blocks,
+        num_dup_tsn: 0,
+      }],
+    },
+  )?;
+  client_state.last_sent = Instant::now();
+  Ok(())
+}
+
+// Deliver a DATA chunk's message or fragment, or handle its DCEP message.
+#[allow(clippy::too_many_arguments)]
+fn deliver_chunk(
+  buffer_pool: &BufferPool,
+  ssl_stream: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+  chunk_flags: u8,
+  tsn: u32,
+  stream_id: u16,
+  proto_id: u32,
+  user_data: &[u8],
+) -> Result<(), ClientError> {
+  let complete =
+    chunk_flags & SCTP_FLAG_BEGIN_FRAGMENT != 0 && chunk_flags & SCTP_FLAG_END_FRAGMENT != 0;
+  if !complete {
+    // Fragments are dropped unless they are streamed or reassembled.
+    if !client_state.stream_fragments && client_state.max_reassembled_len.is_none() {
+      return Ok(());
+    }
+    receive_fragment(
+      buffer_pool,
+      client_state,
+      chunk_flags,
+      tsn,
+      stream_id,
+      proto_id,
+      user_data,
+    );
+  } else if proto_id == DATA_CHANNEL_PROTO_CONTROL {
+    if !user_data.is_empty() {
+      if user_data[0] == DATA_CHANNEL_MESSAGE_OPEN {
+        client_state
+          .channel_subprotocols
+          .retain(|&(id, _)| id != stream_id);
+        let tag = data_channel_open_protocol(user_data)
+          .and_then(|protocol| {
+            client_state
+              .subprotocols
+              .iter()
+              .position(|p| p.as_bytes() == protocol)
+          })
+          .and_then(|index| u8::try_from(index).ok());
+        if let Some(tag) = tag {
+          client_state.channel_subprotocols.push((stream_id, tag));
+        }
+        client_state
+          .channel_labels
+          .retain(|(id, _)| *id != stream_id);
+        let label = data_channel_open_label(user_data).map(String::from_utf8_lossy);
+        client_state.trace.record(
+          TraceLayer::DataChannel,
+          TraceDirection::Received,
+          format!(
+            "DATA_CHANNEL_OPEN stream {} label {:?}",
+            stream_id,
+            label.as_deref().unwrap_or("")
+          ),
+        );
+        let label = label.map(|label| label.into_owned()).unwrap_or_default();
+        client_state.channel_labels.push((stream_id, label.clone()));
+        let delivery = Delivery::from_channel_type(
+          user_data.get(1).copied().unwrap_or(0),
+          user_data.get(4..8).map_or(0, NetworkEndian::read_u32),
+        );
+        client_state
+          .channel_deliveries
+          .retain(|(id, _)| *id != stream_id);
+        client_state.channel_deliveries.push((stream_id, delivery));
+        client_state.channel_events.push(ChannelEvent::Opened {
+          channel_id: stream_id,
+          label,
+          delivery,
+        });
+        client_state.ffi_events.emit(
+          1001,
+          format!(
+            "{}:{}",
+            client_state.sctp_remote_address.ip(),
+            client_state.sctp_remote_address.port()
+          ),
+        );
+        send_sctp_packet(
+          &buffer_pool,
+          ssl_stream,
+          SctpPacket {
+            source_port: client_state.sctp_local_port,
+            dest_port: client_state.sctp_remote_port,
+            verification_tag: client_state.sctp_remote_verification_tag,
+            chunks: &[SctpChunk::Data {
+              chunk_flags: SCTP_FLAG_COMPLETE_UNRELIABLE,
+              tsn: client_state.sctp_local_tsn,
+              stream_id,
+              stream_seq: 0,
+              proto_id: DATA_CHANNEL_PROTO_CONTROL,
+              user_data: &[DATA_CHANNEL_MESSAGE_ACK],
+            }],
+          },
+        )?;
+        client_state.trace.record(
+          TraceLayer::DataChannel,
+          TraceDirection::Sent,
+          format!("DATA_CHANNEL_ACK stream {}", stream_id),
+        );
+        client_state.sctp_local_tsn = client_state.sctp_local_tsn.wrapping_add(1);
+      } else if user_data[0] == DATA_CHANNEL_MESSAGE_ACK {
+        if let Some(index) = client_state
+          .pending_channels
+          .iter()
+          .position(|(id, _, _)| *id == stream_id)
+        {
+          let (_, label, delivery) = client_state.pending_channels.remove(index);
+          client_state.trace.record(
+            TraceLayer::DataChannel,
+            TraceDirection::Received,
+            format!("DATA_CHANNEL_ACK stream {}", stream_id),
+          );
+          client_state.channel_labels.push((stream_id, label.clone()));
+          client_state.channel_deliveries.push((stream_id, delivery));
+          client_state.channel_events.push(ChannelEvent::Opened {
+            channel_id: stream_id,
+            label,
+            delivery,
+          });
+        }
+      }
+    }
+  } else if proto_id == DATA_CHANNEL_PROTO_STRING || proto_id == DATA_CHANNEL_PROTO_BINARY {
+    let message_type = if proto_id == DATA_CHANNEL_PROTO_STRING {
+      MessageType::Text
+    } else {
+      MessageType::Binary
+    };
+    let subprotocol = channel_subprotocol(client_state, stream_id);
+    record_received(client_state, stream_id, message_type, user_data.len(), true);
+    if !hold_full(client_state) {
+      let mut msg_buffer = buffer_pool.acquire();
+      msg_buffer.extend(user_data);
+      client_state.received_messages.push((
+        message_type,
+        subprotocol,
+        stream_id,
+        msg_buffer.into_owned(),
+      ));
+    }
+    client_state.last_received = Instant::now();
+  }
+  Ok(())
+}
+
+// Whether a paused client whose hold is full refuses a DATA chunk by not acknowledging it, which
+// it does for the chunks of reliable channels, as dropping them would lose them for good, and for
+// every chunk after the first it refused, since acknowledgements are cumulative.
+fn hold_refuses(client_state: &mut ClientState, stream_id: u16, tsn: u32) -> bool {
+  let max_held = match client_state.max_held {
+    Some(max_held) => max_held,
+    None => return false,
+  };
+  if let Some(refused_tsn) = client_state.refused_tsn {
+    return max_tsn(refused_tsn, tsn) == tsn;
+  }
+  let reliable = client_state
+    .channel_deliveries
+    .iter()
+    .any(|&(id, delivery)| id == stream_id && delivery != Delivery::Unreliable);
+  let held = client_state.received_messages.len() + client_state.received_fragments.len();
+  if reliable && held >= max_held {
+    client_state.refused_tsn = Some(tsn);
+    return true;
+  }
+  false
+}
+
+// Give up on the partial message of a stream because it went past the configured limits.  The
+// stream's remaining fragments are discarded until its next message begins.
+fn reset_reassembly(client_state: &mut ClientState, stream_id: u16, reason: &str) {
+  client_state.reassembly.resets += 1;
+  client_state.trace.record(
+    TraceLayer::DataChannel,
+    TraceDirection::Local,
+    format!("reset reassembly on stream {}: {}", stream_id, reason),
+  );
+}
+
+// The first stream free for a data channel opened from this side.  As the DTLS server, those take
+// odd stream ids (RFC 8832 section 6), below the association's stream count, which also keeps
+// them clear of the reserved stream 65535.
+fn free_channel_id(client_state: &ClientState) -> Option<u16> {
+  (1..client_state.sctp_streams).step_by(2).find(|&id| {
+    !client_state
+      .channel_deliveries
+      .iter()
+      .any(|&(open_id, _)| open_id == id)
+      && !client_state
+        .pending_channels
+        .iter()
+        .any(|(pending_id, _, _)| *pending_id == id)
+  })
+}
+
+// The stream sequence number for the next ordered message sent on `stream_id`.
+fn next_stream_seq(client_state: &mut ClientState, stream_id: u16) -> u16 {
+  let seqs = &mut client_state.stream_seqs;
+  let index = match seqs.iter().position(|&(id, _)| id == stream_id) {
+    Some(index) => index,
+    None => {
+      seqs.push((stream_id, 0));
+      seqs.len() - 1
+    }
+  };
+  let stream_seq = seqs[index].1;
+  seqs[index].1 = stream_seq.wrapping_add(1);
+  stream_seq
+}
+
+// Reliable messages are only sent while no more than `MAX_UNACKED_BYTES` are waiting to be
+// acknowledged, counting the message itself.
+fn check_send_window(
+  client_state: &ClientState,
+  delivery: Delivery,
+  len: usize,
+) -> Result<(), ClientError> {
+  if delivery != Delivery::Unreliable && client_state.unacked_bytes + len > MAX_UNACKED_BYTES {
+    return Err(ClientError::SendWindowFull);
+  }
+  Ok(())
+}
+
+// Forget the chunks the client has acknowledged with `cumulative_tsn_ack`, and the skipped
+// messages it has moved past.
+fn acknowledge(buffer_pool: &BufferPool, client_state: &mut ClientState, cumulative_tsn_ack: u32) {
+  let acked = client_state
+    .outstanding
+    .iter()
+    .take_while(|chunk| max_tsn(chunk.tsn, cumulative_tsn_ack) == cumulative_tsn_ack)
+    .count();
+  for chunk in client_state.outstanding.drain(..acked) {
+    client_state.unacked_bytes -= chunk.data.len();
+    buffer_pool.adopt(chunk.data);
+  }
+  client_state
+    .skipped_streams
+    .retain(|&(_, _, tsn)| max_tsn(tsn, cumulative_tsn_ack) != cumulative_tsn_ack);
+}
+
+// Retransmit the outstanding chunks which are due, after abandoning the messages whose partial
+// reliability has run out and telling the client to skip them.
+fn retransmit(
+  buffer_pool: &BufferPool,
+  transport: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+) -> Result<(), ClientError> {
+  let now = Instant::now();
+  let is_due = |chunk: &OutstandingChunk| {
+    now
+      >= chunk.last_sent + RETRANSMIT_TIMEOUT * (1 << chunk.retransmits.min(MAX_RETRANSMIT_BACKOFF))
+  };
+
+  let mut abandoned: Vec<u32> = Vec::new();
+  for chunk in &client_state.outstanding {
+    let expired = chunk.expires_at.is_some_and(|expires_at| now >= expires_at);
+    let exhausted = chunk
+      .max_retransmits
+      .is_some_and(|max_retransmits| chunk.retransmits >= max_retransmits && is_due(chunk));
+    if (expired || exhausted) && !abandoned.contains(&chunk.message_id) {
+      abandoned.push(chunk.message_id);
+    }
+  }
+  if !abandoned.is_empty() {
+    let mut kept = Vec::with_capacity(client_state.outstanding.len());
+    for chunk in mem::take(&mut client_state.outstanding) {
+      if !abandoned.contains(&chunk.message_id) {
+        kept.push(chunk);
+        continue;
+      }
+      if chunk.chunk_flags & SCTP_FLAG_UNRELIABLE == 0 {
+        let skipped = &mut client_state.skipped_streams;
+        match skipped.iter_mut().find(|(id, _, _)| *id == chunk.stream_id) {
+          Some((_, stream_seq, tsn)) => {
+            *stream_seq = chunk.stream_seq;
+            *tsn = chunk.tsn;
+          }
+          None => skipped.push((chunk.stream_id, chunk.stream_seq, chunk.tsn)),
+        }
+      }
+      client_state.unacked_bytes -= chunk.data.len();
+      buffer_pool.adopt(chunk.data);
+    }
+    client_state.outstanding = kept;
+    client_state.trace.record(
+      TraceLayer::Sctp,
+      TraceDirection::Local,
+      format!("abandoned {} partially reliable messages", abandoned.len()),
+    );
+    send_forward_tsn(buffer_pool, transport, client_state)?;
+  }
+
+  for index in 0..client_state.outstanding.len() {
+    let chunk = &client_state.outstanding[index];
+    if !is_due(chunk) {
+      continue;
+    }
+    send_sctp_packet(
+      buffer_pool,
+      transport,
+      SctpPacket {
+        source_port: client_state.sctp_local_port,
+        dest_port: client_state.sctp_remote_port,
+        verification_tag: client_state.sctp_remote_verification_tag,
+        chunks: &[SctpChunk::Data {
+          chunk_flags: chunk.chunk_flags,
+          tsn: chunk.tsn,
+          stream_id: chunk.stream_id,
+          stream_seq: chunk.stream_seq,
+          proto_id: chunk.proto_id,
+          user_data: chunk.data.as_slice(),
+        }],
+      },
+    )?;
+    let chunk = &mut client_state.outstanding[index];
+    chunk.retransmits += 1;
+    chunk.last_sent = now;
+    client_state.last_sent = now;
+  }
+  Ok(())
+}
+
+// Move the client's cumulative TSN past every chunk which is never going to be retransmitted,
+// which is everything sent before the oldest outstanding chunk, and past the abandoned ordered
+// messages of each stream.
+fn send_forward_tsn(
+  buffer_pool: &BufferPool,
+  transport: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+) -> Result<(), ClientError> {
+  let new_cumulative_tsn = client_state
+    .outstanding
+    .first()
+    .map_or(client_state.sctp_local_tsn, |chunk| chunk.tsn)
+    .wrapping_sub(1);
+  let mut streams = Vec::with_capacity(client_state.skipped_streams.len() * 4);
+  for &(stream_id, stream_seq, _) in &client_state.skipped_streams {
+    streams.extend_from_slice(&stream_id.to_be_bytes());
+    streams.extend_from_slice(&stream_seq.to_be_bytes());
+  }
+  send_sctp_packet(
+    buffer_pool,
+    transport,
+    SctpPacket {
+      source_port: client_state.sctp_local_port,
+      dest_port: client_state.sctp_remote_port,
+      verification_tag: client_state.sctp_remote_verification_tag,
+      chunks: &[SctpChunk::ForwardTsn {
+        new_cumulative_tsn,
+        streams: &streams,
+      }],
+    },
+  )?;
+  client_state.last_sent = Instant::now();
+  Ok(())
+}
+
+// Close the data channels on the streams the client reset, which it does to close them, and reset
+// the same streams towards the client as RFC 8831 section 6.7 requires.  A request for streams
+// which are already closed, such as a retransmitted one, is only answered.
+fn reset_streams(
+  buffer_pool: &BufferPool,
+  transport: &mut impl SctpTransport,
+  client_state: &mut ClientState,
+  request: StreamResetRequest,
+) -> Result<(), ClientError> {
+  let mut stream_ids: Vec<u16> = request
+    .streams
+    .chunks_exact(2)
+    .map(NetworkEndian::read_u16)
+    .collect();
+  if stream_ids.is_empty() {
+    stream_ids = client_state
+      .channel_deliveries
+      .iter()
+      .map(|&(id, _)| id)
+      .collect();
+  }
+  stream_ids.retain(|&stream_id| {
+    client_state
+      .channel_deliveries
+      .iter()
+      .any(|&(id, _)| id == stream_id)
+  });
+
+  for &stream_id in &stream_ids {
+    client_state
+      .channel_labels
+      .retain(|(id, _)| *id != stream_id);
+    client_state
+      .channel_subprotocols
+      .retain(|&(id, _)| id != stream_id);
+    client_state
+      .channel_deliveries
+      .retain(|&(id, _)| id != stream_id);
+    client_state.stream_seqs.retain(|&(id, _)| id != stream_id);
+    client_state
+      .skipped_streams
+      .retain(|&(id, _, _)| id != stream_id);
+    if let Some(index) = client_state
+      .partial_messages
+      .iter()
+      .position(|partial| partial.stream_id == stream_id)
+    {
+      abandon(buffer_pool, client_state.partial_messages.remove(index));
+    }
+    client_state.trace.record(
+      TraceLayer::DataChannel,
+      TraceDirection::Received,
+      format!("stream {} reset", stream_id),
+    );
+    client_state.channel_events.push(ChannelEvent::Closed {
+      channel_id: stream_id,
+    });
+  }
+
+  let mut streams = Vec::with_capacity(stream_ids.len() * 2);
+  for stream_id in &stream_ids {
+    streams.extend_from_slice(&stream_id.to_be_bytes());
+  }
+  let reset_request = (!stream_ids.is_empty()).then(|| StreamResetRequest {
+    request_seq: client_state.reconfig_seq,
+    response_seq: request.request_seq,
+    last_tsn: client_state.sctp_local_tsn.wrapping_sub(1),
+    streams: &streams,
+  });
+  send_sctp_packet(
+    buffer_pool,
+    transport,
+    SctpPacket {
+      source_port: client_state.sctp_local_port,
+      dest_port: client_state.sctp_remote_port,
+      verification_tag: client_state.sctp_remote_verification_tag,
+      chunks: &[SctpChunk::ReConfig {
+        reset_request,
+        response: Some((request.request_seq, RECONFIG_RESULT_PERFORMED)),
+      }],
+    },
+  )?;
+  if reset_request.is_some() {
+    client_state.reconfig_seq = client_state.reconfig_seq.wrapping_add(1);
+  }
+  client_state.last_sent = Instant::now();
+  Ok(())
+}
+
 fn max_tsn(a: u32, b: u32) -> u32 {
   if a > b {
     if a - b < (1 << 31) {
@@ -512,9 +2298,25 @@ fn max_tsn(a: u32, b: u32) -> u32 {
   }
 }
 
-fn send_sctp_packet(
+// Destination of the SCTP packets written by the association state machine, which is the
+// client's DTLS stream outside of tests.
+pub trait SctpTransport {
+  fn send_sctp(&mut self, packet: &[u8]) -> Result<(), ClientError>;
+}
+
+impl SctpTransport for SslStream<ClientSslPackets> {
+  fn send_sctp(&mut self, packet: &[u8]) -> Result<(), ClientError> {
+    assert_eq!(
+      self.ssl_write(packet).map_err(ssl_err_to_client_err)?,
+      packet.len()
+    );
+    Ok(())
+  }
+}
+
+pub fn send_sctp_packet(
   buffer_pool: &BufferPool,
-  ssl_stream: &mut SslStream<ClientSslPackets>,
+  transport: &mut impl SctpTransport,
   sctp_packet: SctpPacket,
 ) -> Result<(), ClientError> {
   let mut sctp_buffer = buffer_pool.acquire();
@@ -530,19 +2332,15 @@ fn send_sctp_packet(
     }
   };
 
-  assert_eq!(
-    ssl_stream
-      .ssl_write(&sctp_buffer[0..packet_len])
-      .map_err(ssl_err_to_client_err)?,
-    packet_len
-  );
-
-  Ok(())
+  transport.send_sctp(&sctp_buffer[0..packet_len])
 }
 
+// Advance the SCTP association by one received packet, returning false if the association has
+// been closed.
 fn receive_sctp_packet(
   buffer_pool: &BufferPool,
-  ssl_stream: &mut SslStream<ClientSslPackets>,
+  ssl_stream: &mut impl SctpTransport,
+  rng: &mut impl Rng,
   client_state: &mut ClientState,
   sctp_packet: &SctpPacket,
 ) -> Result<bool, ClientError> {
@@ -556,22 +2354,34 @@ fn receive_sctp_packet(
         initial_tsn,
         support_unreliable,
       } => {
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Received, "INIT");
         if !support_unreliable {
           //   log::warn!("peer does not support selective unreliability, abort connection");
+          client_state.trace.record(
+            TraceLayer::Sctp,
+            TraceDirection::Local,
+            "peer does not support partial reliability",
+          );
           client_state.sctp_state = SctpState::Shutdown;
           return Ok(false);
         }
 
-        let mut rng = thread_rng();
-
         client_state.sctp_local_port = sctp_packet.dest_port;
         client_state.sctp_remote_port = sctp_packet.source_port;
+        client_state.sctp_streams = num_outbound_streams.min(num_inbound_streams);
 
         client_state.sctp_local_verification_tag = rng.gen();
         client_state.sctp_remote_verification_tag = initiate_tag;
 
         client_state.sctp_local_tsn = rng.gen();
-        client_state.sctp_remote_tsn = initial_tsn;
+        // Nothing is received yet, so the cumulative TSN is the one before the client's first.
+        client_state.sctp_remote_tsn = initial_tsn.wrapping_sub(1);
+        client_state.received_tsns.clear();
+        client_state.early_chunks.clear();
+        client_state.early_bytes = 0;
+        client_state.reconfig_seq = client_state.sctp_local_tsn;
 
         send_sctp_packet(
           &buffer_pool,
@@ -591,11 +2401,17 @@ fn receive_sctp_packet(
           },
         )?;
 
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Sent, "INIT ACK");
         client_state.sctp_state = SctpState::InitAck;
         client_state.last_activity = Instant::now();
         client_state.last_sent = Instant::now();
       }
       SctpChunk::CookieEcho { state_cookie } => {
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Received, "COOKIE ECHO");
         if state_cookie == SCTP_COOKIE && client_state.sctp_state != SctpState::Shutdown {
           send_sctp_packet(
             &buffer_pool,
@@ -608,10 +2424,37 @@ fn receive_sctp_packet(
             },
           )?;
           client_state.last_sent = Instant::now();
+          client_state
+            .trace
+            .record(TraceLayer::Sctp, TraceDirection::Sent, "COOKIE ACK");
 
           if client_state.sctp_state == SctpState::InitAck {
             client_state.sctp_state = SctpState::Established;
             client_state.last_activity = Instant::now();
+            client_state.trace.record(
+              TraceLayer::Sctp,
+              TraceDirection::Local,
+              "association established",
+            );
+            for channel in client_state.negotiated_channels.iter() {
+              client_state
+                .channel_labels
+                .retain(|(id, _)| *id != channel.id);
+              client_state
+                .channel_labels
+                .push((channel.id, channel.label.clone()));
+              client_state
+                .channel_deliveries
+                .retain(|(id, _)| *id != channel.id);
+              client_state
+                .channel_deliveries
+                .push((channel.id, channel.delivery));
+              client_state.channel_events.push(ChannelEvent::Opened {
+                channel_id: channel.id,
+                label: channel.label.clone(),
+                delivery: channel.delivery,
+              });
+            }
           }
         }
       }
@@ -623,77 +2466,22 @@ fn receive_sctp_packet(
         proto_id,
         user_data,
       } => {
-        if chunk_flags & SCTP_FLAG_BEGIN_FRAGMENT == 0 || chunk_flags & SCTP_FLAG_END_FRAGMENT == 0
-        {
+        if hold_refuses(client_state, stream_id, tsn) {
+          // Left out of the acknowledgement below, so the client sends it again.
         } else {
-          client_state.sctp_remote_tsn = max_tsn(client_state.sctp_remote_tsn, tsn);
-
-          if proto_id == DATA_CHANNEL_PROTO_CONTROL {
-            if !user_data.is_empty() {
-              if user_data[0] == DATA_CHANNEL_MESSAGE_OPEN {
-                unsafe {
-                  let mut msg = format!(
-                    "{}:{}",
-                    client_state.sctp_remote_address.ip(),
-                    client_state.sctp_remote_address.port()
-                  );
-                  EVENT_CB.unwrap()(1001, msg.as_mut_ptr(), msg.len() as u32)
-                }
-                send_sctp_packet(
-                  &buffer_pool,
-                  ssl_stream,
-                  SctpPacket {
-                    source_port: client_state.sctp_local_port,
-                    dest_port: client_state.sctp_remote_port,
-                    verification_tag: client_state.sctp_remote_verification_tag,
-                    chunks: &[SctpChunk::Data {
-                      chunk_flags: SCTP_FLAG_COMPLETE_UNRELIABLE,
-                      tsn: client_state.sctp_local_tsn,
-                      stream_id,
-                      stream_seq: 0,
-                      proto_id: DATA_CHANNEL_PROTO_CONTROL,
-                      user_data: &[DATA_CHANNEL_MESSAGE_ACK],
-                    }],
-                  },
-                )?;
-                client_state.sctp_local_tsn = client_state.sctp_local_tsn.wrapping_add(1);
-              }
-            }
-          } else if proto_id == DATA_CHANNEL_PROTO_STRING {
-            let mut msg_buffer = buffer_pool.acquire();
-            msg_buffer.extend(user_data);
-            client_state
-              .received_messages
-              .push((MessageType::Text, msg_buffer.into_owned()));
-            client_state.last_received = Instant::now();
-          } else if proto_id == DATA_CHANNEL_PROTO_BINARY {
-            let mut msg_buffer = buffer_pool.acquire();
-            msg_buffer.extend(user_data);
-            client_state
-              .received_messages
-              .push((MessageType::Binary, msg_buffer.into_owned()));
-            client_state.last_received = Instant::now();
-          }
-
-          send_sctp_packet(
-            &buffer_pool,
+          receive_data(
+            buffer_pool,
             ssl_stream,
-            SctpPacket {
-              source_port: client_state.sctp_local_port,
-              dest_port: client_state.sctp_remote_port,
-              verification_tag: client_state.sctp_remote_verification_tag,
-              chunks: &[SctpChunk::SAck {
-                cumulative_tsn_ack: client_state.sctp_remote_tsn,
-                adv_recv_window: SCTP_BUFFER_SIZE,
-                num_gap_ack_blocks: 0,
-                num_dup_tsn: 0,
-              }],
-            },
+            client_state,
+            chunk_flags,
+            tsn,
+            stream_id,
+            proto_id,
+            user_data,
           )?;
-
-          client_state.last_activity = Instant::now();
-          client_state.last_sent = Instant::now();
         }
+        send_sack(buffer_pool, ssl_stream, client_state)?;
+        client_state.last_activity = Instant::now();
       }
       SctpChunk::Heartbeat { heartbeat_info } => {
         send_sctp_packet(
@@ -713,29 +2501,25 @@ fn receive_sctp_packet(
         client_state.last_activity = Instant::now();
       }
       SctpChunk::SAck {
-        cumulative_tsn_ack: _,
+        cumulative_tsn_ack,
         adv_recv_window: _,
-        num_gap_ack_blocks,
+        gap_ack_blocks,
         num_dup_tsn: _,
       } => {
-        if num_gap_ack_blocks > 0 {
-          send_sctp_packet(
-            &buffer_pool,
-            ssl_stream,
-            SctpPacket {
-              source_port: client_state.sctp_local_port,
-              dest_port: client_state.sctp_remote_port,
-              verification_tag: client_state.sctp_remote_verification_tag,
-              chunks: &[SctpChunk::ForwardTsn {
-                new_cumulative_tsn: client_state.sctp_local_tsn,
-              }],
-            },
-          )?;
-          client_state.last_sent = Instant::now();
+        acknowledge(buffer_pool, client_state, cumulative_tsn_ack);
+        // Unreliable chunks are never retransmitted, so a gap is skipped rather than filled.
+        if !gap_ack_blocks.is_empty() {
+          send_forward_tsn(buffer_pool, ssl_stream, client_state)?;
         }
         client_state.last_activity = Instant::now();
       }
       SctpChunk::Shutdown { .. } => {
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Received, "SHUTDOWN");
+        client_state
+          .disconnect_reason
+          .get_or_insert(DisconnectReason::PeerClose);
         send_sctp_packet(
           &buffer_pool,
           ssl_stream,
@@ -746,13 +2530,36 @@ fn receive_sctp_packet(
             chunks: &[SctpChunk::ShutdownAck],
           },
         )?;
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Sent, "SHUTDOWN ACK");
       }
-      SctpChunk::ShutdownAck { .. } | SctpChunk::Abort => {
+      SctpChunk::ShutdownAck { .. } => {
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Received, "SHUTDOWN ACK");
         client_state.sctp_state = SctpState::Shutdown;
         return Ok(false);
       }
-      SctpChunk::ForwardTsn { new_cumulative_tsn } => {
-        client_state.sctp_remote_tsn = new_cumulative_tsn;
+      SctpChunk::Abort => {
+        client_state
+          .trace
+          .record(TraceLayer::Sctp, TraceDirection::Received, "ABORT");
+        client_state.sctp_state = SctpState::Shutdown;
+        return Ok(false);
+      }
+      SctpChunk::ForwardTsn {
+        new_cumulative_tsn, ..
+      } => {
+        skip_to_tsn(buffer_pool, ssl_stream, client_state, new_cumulative_tsn)?;
+        send_sack(buffer_pool, ssl_stream, client_state)?;
+        client_state.last_activity = Instant::now();
+      }
+      SctpChunk::ReConfig {
+        reset_request: Some(request),
+        ..
+      } => {
+        reset_streams(buffer_pool, ssl_stream, client_state, request)?;
       }
       SctpChunk::InitAck { .. } | SctpChunk::CookieAck => {}
       SctpChunk::Error {
@@ -761,6 +2568,11 @@ fn receive_sctp_packet(
       } => {
         if first_param_type == DATA_CHANNEL_OPEN_FAILED {
           if first_param_data[0] == DATA_CHANNEL_ERROR_NEGOTIATION_FAILED {
+            client_state.trace.record(
+              TraceLayer::DataChannel,
+              TraceDirection::Received,
+              "ERROR data channel negotiation failed",
+            );
             client_state.sctp_state = SctpState::Shutdown;
             return Ok(false);
           }
@@ -0,0 +1,39 @@
+//! The stream ids of data channels opened from the server's side.
+
+use super::{free_channel_id, ClientState, Delivery};
+use crate::ffi_event::{FfiEventDelivery, FfiEventQueue};
+
+fn client(sctp_streams: u16) -> ClientState {
+  let ffi_events = FfiEventQueue::new(None, FfiEventDelivery::Direct).unwrap();
+  let mut client_state = ClientState::new(([127, 0, 0, 1], 5000).into(), ffi_events);
+  client_state.sctp_streams = sctp_streams;
+  client_state
+}
+
+#[test]
+fn channel_ids_are_odd_and_free() {
+  let mut client_state = client(1024);
+  assert_eq!(free_channel_id(&client_state), Some(1));
+  client_state.channel_deliveries = vec![(0, Delivery::Unreliable), (1, Delivery::Unreliable)];
+  client_state
+    .pending_channels
+    .push((3, "state".to_owned(), Delivery::ReliableOrdered));
+  assert_eq!(free_channel_id(&client_state), Some(5));
+}
+
+#[test]
+fn channel_ids_stay_below_stream_count() {
+  // Streams 0 to 3, of which 1 and 3 are ours.
+  let mut client_state = client(4);
+  client_state.channel_deliveries = vec![(1, Delivery::Unreliable)];
+  assert_eq!(free_channel_id(&client_state), Some(3));
+  client_state
+    .channel_deliveries
+    .push((3, Delivery::Unreliable));
+  assert_eq!(free_channel_id(&client_state), None);
+}
+
+#[test]
+fn no_channel_ids_before_init() {
+  assert_eq!(free_channel_id(&client(0)), None);
+}
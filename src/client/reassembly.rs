@@ -0,0 +1,153 @@
+//! Limits on the fragmented messages a client may leave partly received, whether streamed or
+//! reassembled.
+
+use super::{
+  receive_fragment, ClientState, FragmentPosition, DATA_CHANNEL_PROTO_BINARY,
+  SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_END_FRAGMENT,
+};
+use crate::{
+  buffer_pool::BufferPool,
+  ffi_event::{FfiEventDelivery, FfiEventQueue},
+};
+
+const BEGIN: u8 = SCTP_FLAG_BEGIN_FRAGMENT;
+const MIDDLE: u8 = 0;
+const END: u8 = SCTP_FLAG_END_FRAGMENT;
+
+fn client(max_partial_messages: usize, max_partial_message_len: Option<usize>) -> ClientState {
+  let ffi_events = FfiEventQueue::new(None, FfiEventDelivery::Direct).unwrap();
+  let mut client_state = ClientState::new(([127, 0, 0, 1], 5000).into(), ffi_events);
+  client_state.stream_fragments = true;
+  client_state.max_partial_messages = max_partial_messages;
+  client_state.max_partial_message_len = max_partial_message_len;
+  client_state
+}
+
+fn receive(client_state: &mut ClientState, flags: u8, tsn: u32, stream_id: u16, len: usize) {
+  let buffer_pool = BufferPool::new();
+  receive_fragment(
+    &buffer_pool,
+    client_state,
+    flags,
+    tsn,
+    stream_id,
+    DATA_CHANNEL_PROTO_BINARY,
+    &vec![0; len],
+  );
+}
+
+fn positions(client_state: &mut ClientState) -> Vec<FragmentPosition> {
+  client_state
+    .received_fragments
+    .drain(..)
+    .map(|(_, _, _, position, _)| position)
+    .collect()
+}
+
+fn position(message_id: u32, offset: usize, last: bool) -> FragmentPosition {
+  FragmentPosition {
+    message_id,
+    offset,
+    last,
+  }
+}
+
+#[test]
+fn lost_fragment() {
+  let mut client_state = client(4, None);
+  receive(&mut client_state, BEGIN, 10, 1, 100);
+  // TSN 11 is lost, and a message on another stream follows.
+  receive(&mut client_state, BEGIN, 12, 2, 100);
+  receive(&mut client_state, END, 13, 2, 50);
+  assert_eq!(client_state.partial_messages.len(), 1);
+  receive(&mut client_state, END, 14, 1, 100);
+  assert_eq!(client_state.reassembly.holes, 1);
+  assert!(client_state.partial_messages.is_empty());
+  assert_eq!(
+    positions(&mut client_state),
+    [
+      position(10, 0, false),
+      position(12, 0, false),
+      position(12, 100, true),
+    ]
+  );
+}
+
+#[test]
+fn too_many_partial_messages() {
+  let mut client_state = client(2, None);
+  receive(&mut client_state, BEGIN, 1, 1, 100);
+  receive(&mut client_state, BEGIN, 2, 2, 100);
+  receive(&mut client_state, BEGIN, 3, 3, 100);
+  assert_eq!(client_state.reassembly.resets, 1);
+  assert_eq!(client_state.partial_messages.len(), 2);
+
+  // The oldest message was abandoned, so its stream's next fragment is discarded.
+  receive(&mut client_state, END, 2, 1, 100);
+  receive(&mut client_state, END, 3, 2, 100);
+  assert_eq!(
+    positions(&mut client_state),
+    [
+      position(1, 0, false),
+      position(2, 0, false),
+      position(3, 0, false),
+      position(2, 100, true),
+    ]
+  );
+}
+
+#[test]
+fn partial_message_too_long() {
+  let mut client_state = client(4, Some(250));
+  receive(&mut client_state, BEGIN, 1, 1, 100);
+  receive(&mut client_state, MIDDLE, 2, 1, 100);
+  receive(&mut client_state, MIDDLE, 3, 1, 100);
+  receive(&mut client_state, END, 4, 1, 100);
+  assert_eq!(client_state.reassembly.resets, 1);
+  assert!(client_state.partial_messages.is_empty());
+  assert_eq!(
+    positions(&mut client_state),
+    [position(1, 0, false), position(1, 100, false)]
+  );
+}
+
+#[test]
+fn reassembled_message() {
+  let mut client_state = client(4, None);
+  client_state.max_reassembled_len = Some(250);
+  receive(&mut client_state, BEGIN, 1, 1, 100);
+  receive(&mut client_state, BEGIN, 2, 2, 100);
+  receive(&mut client_state, MIDDLE, 3, 2, 100);
+  receive(&mut client_state, END, 4, 2, 50);
+  // Too long once its last fragment arrives.
+  receive(&mut client_state, MIDDLE, 2, 1, 100);
+  receive(&mut client_state, END, 3, 1, 100);
+  assert_eq!(client_state.reassembly.resets, 1);
+  assert!(client_state.partial_messages.is_empty());
+  assert!(client_state.received_fragments.is_empty());
+  let lens: Vec<usize> = client_state
+    .received_messages
+    .iter()
+    .map(|(_, _, _, message)| message.len())
+    .collect();
+  assert_eq!(lens, [250]);
+}
+
+#[test]
+fn held_fragments_full() {
+  let mut client_state = client(4, None);
+  client_state.max_held = Some(2);
+  receive(&mut client_state, BEGIN, 1, 1, 100);
+  receive(&mut client_state, MIDDLE, 2, 1, 100);
+  // Dropped, so the rest of its message is dropped rather than delivered with a hole.
+  receive(&mut client_state, MIDDLE, 3, 1, 100);
+  client_state.max_held = None;
+  receive(&mut client_state, END, 4, 1, 100);
+  assert_eq!(client_state.held_dropped, 1);
+  assert_eq!(client_state.reassembly.resets, 1);
+  assert!(client_state.partial_messages.is_empty());
+  assert_eq!(
+    positions(&mut client_state),
+    [position(1, 0, false), position(1, 100, false)]
+  );
+}
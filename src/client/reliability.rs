@@ -0,0 +1,278 @@
+//! Retransmission of reliable and partially reliable messages, the FORWARD TSN chunks which tell
+//! the client to skip the abandoned ones, and the reliable messages a paused client refuses.
+
+use std::time::{Duration, Instant};
+
+use super::{
+  acknowledge, check_send_window, hold_refuses, retransmit, ClientError, ClientState, Delivery,
+  OutstandingChunk, SctpTransport, DATA_CHANNEL_PROTO_BINARY, MAX_UNACKED_BYTES,
+  RETRANSMIT_TIMEOUT, SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_END_FRAGMENT, SCTP_FLAG_UNRELIABLE,
+};
+use crate::{
+  buffer_pool::BufferPool,
+  ffi_event::{FfiEventDelivery, FfiEventQueue},
+  sctp::{read_sctp_packet, SctpChunk},
+};
+
+// The SCTP packets sent to the client.
+#[derive(Default)]
+struct Packets(Vec<Vec<u8>>);
+
+impl SctpTransport for Packets {
+  fn send_sctp(&mut self, packet: &[u8]) -> Result<(), ClientError> {
+    self.0.push(packet.to_vec());
+    Ok(())
+  }
+}
+
+// What was sent, as the TSN of each DATA chunk and the new cumulative TSN and stream list of each
+// FORWARD TSN chunk.
+#[derive(Debug, PartialEq)]
+enum Sent {
+  Data(u32),
+  ForwardTsn(u32, Vec<(u16, u16)>),
+}
+
+impl Packets {
+  fn take(&mut self) -> Vec<Sent> {
+    let mut sent = Vec::new();
+    for packet in self.0.drain(..) {
+      let mut chunk_space = [SctpChunk::Pad; 4];
+      let packet = read_sctp_packet(&packet, true, &mut chunk_space).unwrap();
+      for chunk in packet.chunks {
+        match *chunk {
+          SctpChunk::Data { tsn, .. } => sent.push(Sent::Data(tsn)),
+          SctpChunk::ForwardTsn {
+            new_cumulative_tsn,
+            streams,
+          } => sent.push(Sent::ForwardTsn(
+            new_cumulative_tsn,
+            streams
+              .chunks_exact(4)
+              .map(|entry| {
+                (
+                  u16::from_be_bytes([entry[0], entry[1]]),
+                  u16::from_be_bytes([entry[2], entry[3]]),
+                )
+              })
+              .collect(),
+          )),
+          chunk => panic!("unexpected chunk {:?}", chunk),
+        }
+      }
+    }
+    sent
+  }
+}
+
+// A client's sending side, with the packets it has sent.
+struct Sender {
+  buffer_pool: BufferPool,
+  client_state: ClientState,
+  packets: Packets,
+}
+
+impl Sender {
+  fn new() -> Sender {
+    let ffi_events = FfiEventQueue::new(None, FfiEventDelivery::Direct).unwrap();
+    let mut client_state = ClientState::new(([127, 0, 0, 1], 5000).into(), ffi_events);
+    client_state.sctp_local_tsn = 100;
+    Sender {
+      buffer_pool: BufferPool::new(),
+      client_state,
+      packets: Packets::default(),
+    }
+  }
+
+  // Record a single chunk message of 100 bytes as first sent `age` ago and retransmitted
+  // `retransmits` times since, the last time `age` ago as well, as `Client::send_data` does.
+  fn sent(
+    &mut self,
+    stream: (u16, u16),
+    delivery: Delivery,
+    age: Duration,
+    retransmits: u32,
+  ) -> u32 {
+    let tsn = self.client_state.sctp_local_tsn;
+    self.client_state.sctp_local_tsn = tsn.wrapping_add(1);
+    let mut chunk_flags = SCTP_FLAG_BEGIN_FRAGMENT | SCTP_FLAG_END_FRAGMENT;
+    if !delivery.is_ordered() {
+      chunk_flags |= SCTP_FLAG_UNRELIABLE;
+    }
+    let sent_at = Instant::now() - age;
+    let mut data = self.buffer_pool.acquire();
+    data.extend_from_slice(&[0; 100]);
+    self.client_state.unacked_bytes += data.len();
+    self.client_state.outstanding.push(OutstandingChunk {
+      message_id: tsn,
+      tsn,
+      chunk_flags,
+      stream_id: stream.0,
+      stream_seq: stream.1,
+      proto_id: DATA_CHANNEL_PROTO_BINARY,
+      data: data.into_owned(),
+      last_sent: sent_at,
+      retransmits,
+      max_retransmits: delivery.max_retransmits(),
+      expires_at: delivery.lifetime().map(|lifetime| sent_at + lifetime),
+    });
+    tsn
+  }
+
+  fn retransmit(&mut self) -> Vec<Sent> {
+    retransmit(&self.buffer_pool, &mut self.packets, &mut self.client_state).unwrap();
+    self.packets.take()
+  }
+
+  fn acknowledge(&mut self, cumulative_tsn_ack: u32) {
+    acknowledge(
+      &self.buffer_pool,
+      &mut self.client_state,
+      cumulative_tsn_ack,
+    );
+  }
+
+  fn outstanding(&self) -> Vec<(u32, u32)> {
+    self
+      .client_state
+      .outstanding
+      .iter()
+      .map(|chunk| (chunk.tsn, chunk.retransmits))
+      .collect()
+  }
+}
+
+#[test]
+fn retransmission_backoff() {
+  let mut sender = Sender::new();
+  let ordered = Delivery::ReliableOrdered;
+  // Not yet due, due after the first timeout, resent once and not due until twice the timeout,
+  // and resent once and due.
+  let recent = sender.sent((1, 0), ordered, Duration::ZERO, 0);
+  let due = sender.sent((1, 1), ordered, RETRANSMIT_TIMEOUT, 0);
+  let resent = sender.sent((1, 2), ordered, RETRANSMIT_TIMEOUT, 1);
+  let backed_off = sender.sent((1, 3), ordered, RETRANSMIT_TIMEOUT * 2, 1);
+
+  assert_eq!(
+    sender.retransmit(),
+    vec![Sent::Data(due), Sent::Data(backed_off)]
+  );
+  assert_eq!(
+    sender.outstanding(),
+    vec![(recent, 0), (due, 1), (resent, 1), (backed_off, 2)]
+  );
+  // The chunks just resent wait for their next, longer timeout.
+  assert_eq!(sender.retransmit(), vec![]);
+}
+
+#[test]
+fn max_retransmits() {
+  let mut sender = Sender::new();
+  let delivery = Delivery::MaxRetransmits {
+    retransmits: 2,
+    ordered: true,
+  };
+  let resent = sender.sent((1, 0), delivery, RETRANSMIT_TIMEOUT * 2, 1);
+  sender.sent((2, 0), delivery, RETRANSMIT_TIMEOUT * 4, 2);
+  // Out of retransmissions, but the client may still acknowledge its last one.
+  let waiting = sender.sent((3, 0), delivery, Duration::ZERO, 2);
+
+  assert_eq!(
+    sender.retransmit(),
+    vec![
+      Sent::ForwardTsn(resent - 1, vec![(2, 0)]),
+      Sent::Data(resent)
+    ]
+  );
+  assert_eq!(sender.outstanding(), vec![(resent, 2), (waiting, 2)]);
+  assert_eq!(sender.client_state.unacked_bytes, 200);
+}
+
+#[test]
+fn max_packet_lifetime() {
+  let mut sender = Sender::new();
+  let lifetime = |ordered| Delivery::MaxPacketLifetime {
+    lifetime: Duration::from_millis(500),
+    ordered,
+  };
+  sender.sent((1, 4), lifetime(true), RETRANSMIT_TIMEOUT, 0);
+  sender.sent((2, 0), lifetime(false), RETRANSMIT_TIMEOUT, 0);
+  let live = sender.sent((3, 0), lifetime(true), Duration::ZERO, 0);
+
+  // Both expired messages are skipped, but only the ordered one needs its stream listed.
+  assert_eq!(
+    sender.retransmit(),
+    vec![Sent::ForwardTsn(live - 1, vec![(1, 4)])]
+  );
+  assert_eq!(sender.outstanding(), vec![(live, 0)]);
+  assert_eq!(sender.client_state.unacked_bytes, 100);
+}
+
+#[test]
+fn forward_tsn_streams() {
+  let mut sender = Sender::new();
+  let delivery = Delivery::MaxRetransmits {
+    retransmits: 0,
+    ordered: true,
+  };
+  sender.sent((1, 0), delivery, RETRANSMIT_TIMEOUT, 0);
+  let skipped = sender.sent((1, 1), delivery, RETRANSMIT_TIMEOUT, 0);
+  let pending = sender.sent((1, 2), delivery, Duration::ZERO, 0);
+
+  // Only the last abandoned message of a stream is listed.
+  assert_eq!(
+    sender.retransmit(),
+    vec![Sent::ForwardTsn(pending - 1, vec![(1, 1)])]
+  );
+
+  // A message abandoned later on another stream still lists the first stream, until the client
+  // acknowledges moving past its skipped message.
+  let other = sender.sent((2, 0), delivery, RETRANSMIT_TIMEOUT, 0);
+  assert_eq!(
+    sender.retransmit(),
+    vec![Sent::ForwardTsn(pending - 1, vec![(1, 1), (2, 0)])]
+  );
+  sender.acknowledge(skipped);
+  assert_eq!(sender.client_state.skipped_streams, vec![(2, 0, other)]);
+  sender.acknowledge(other);
+  assert_eq!(sender.client_state.skipped_streams, vec![]);
+  assert_eq!(sender.outstanding(), vec![]);
+  assert_eq!(sender.client_state.unacked_bytes, 0);
+}
+
+#[test]
+fn send_window_full() {
+  let mut sender = Sender::new();
+  let ordered = Delivery::ReliableOrdered;
+  assert!(check_send_window(&sender.client_state, ordered, MAX_UNACKED_BYTES).is_ok());
+
+  let tsn = sender.sent((1, 0), ordered, Duration::ZERO, 0);
+  let left = MAX_UNACKED_BYTES - 100;
+  assert!(check_send_window(&sender.client_state, ordered, left).is_ok());
+  assert!(matches!(
+    check_send_window(&sender.client_state, Delivery::ReliableUnordered, left + 1),
+    Err(ClientError::SendWindowFull)
+  ));
+  // Unreliable messages are never held for acknowledgement.
+  assert!(check_send_window(&sender.client_state, Delivery::Unreliable, left + 1).is_ok());
+
+  sender.acknowledge(tsn);
+  assert!(check_send_window(&sender.client_state, ordered, MAX_UNACKED_BYTES).is_ok());
+}
+
+#[test]
+fn hold_refuses_reliable_messages() {
+  let mut client_state = Sender::new().client_state;
+  client_state.channel_deliveries = vec![(1, Delivery::ReliableOrdered), (2, Delivery::Unreliable)];
+  client_state.max_held = Some(0);
+  // Messages on unreliable channels are left to be dropped.
+  assert!(!hold_refuses(&mut client_state, 2, 10));
+  assert!(hold_refuses(&mut client_state, 1, 11));
+  // Acknowledgements are cumulative, so nothing after the refused message can be accepted.
+  assert!(hold_refuses(&mut client_state, 2, 12));
+  assert!(!hold_refuses(&mut client_state, 2, 10));
+  // The client sends them again once resumed.
+  client_state.max_held = None;
+  client_state.refused_tsn = None;
+  assert!(!hold_refuses(&mut client_state, 1, 11));
+}
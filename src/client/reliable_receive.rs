@@ -0,0 +1,217 @@
+//! Receiving DATA chunks in TSN order on every stream but those of unreliable channels, with the
+//! gap ack blocks which tell the client which chunks to send again.
+
+use super::{
+  receive_data, send_sack, skip_to_tsn, ClientError, ClientState, Delivery, SctpTransport,
+  DATA_CHANNEL_PROTO_BINARY, SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_END_FRAGMENT,
+};
+use crate::{
+  buffer_pool::BufferPool,
+  ffi_event::{FfiEventDelivery, FfiEventQueue},
+  sctp::{read_sctp_packet, SctpChunk},
+};
+
+const BEGIN: u8 = SCTP_FLAG_BEGIN_FRAGMENT;
+const MIDDLE: u8 = 0;
+const END: u8 = SCTP_FLAG_END_FRAGMENT;
+const WHOLE: u8 = SCTP_FLAG_BEGIN_FRAGMENT | SCTP_FLAG_END_FRAGMENT;
+
+const RELIABLE: u16 = 1;
+const UNRELIABLE: u16 = 3;
+
+// The SCTP packets sent to the client.
+#[derive(Default)]
+struct Packets(Vec<Vec<u8>>);
+
+impl SctpTransport for Packets {
+  fn send_sctp(&mut self, packet: &[u8]) -> Result<(), ClientError> {
+    self.0.push(packet.to_vec());
+    Ok(())
+  }
+}
+
+// A client's receiving side, with the packets it has sent in answer.
+struct Receiver {
+  buffer_pool: BufferPool,
+  client_state: ClientState,
+  packets: Packets,
+}
+
+impl Receiver {
+  // A client whose next TSN is 11, with a reliable and an unreliable channel open.
+  fn new() -> Receiver {
+    let ffi_events = FfiEventQueue::new(None, FfiEventDelivery::Direct).unwrap();
+    let mut client_state = ClientState::new(([127, 0, 0, 1], 5000).into(), ffi_events);
+    client_state.sctp_remote_tsn = 10;
+    client_state.channel_deliveries = vec![
+      (RELIABLE, Delivery::ReliableOrdered),
+      (UNRELIABLE, Delivery::Unreliable),
+    ];
+    Receiver {
+      buffer_pool: BufferPool::new(),
+      client_state,
+      packets: Packets::default(),
+    }
+  }
+
+  // Receive a DATA chunk whose data is its TSN's low byte, as `receive_sctp_packet` does.
+  fn data(&mut self, chunk_flags: u8, tsn: u32, stream_id: u16) {
+    receive_data(
+      &self.buffer_pool,
+      &mut self.packets,
+      &mut self.client_state,
+      chunk_flags,
+      tsn,
+      stream_id,
+      DATA_CHANNEL_PROTO_BINARY,
+      &[tsn as u8],
+    )
+    .unwrap();
+    send_sack(&self.buffer_pool, &mut self.packets, &mut self.client_state).unwrap();
+  }
+
+  fn forward_tsn(&mut self, new_cumulative_tsn: u32) {
+    skip_to_tsn(
+      &self.buffer_pool,
+      &mut self.packets,
+      &mut self.client_state,
+      new_cumulative_tsn,
+    )
+    .unwrap();
+    send_sack(&self.buffer_pool, &mut self.packets, &mut self.client_state).unwrap();
+  }
+
+  // The cumulative TSN and gap ack blocks of the last SACK sent.
+  fn sack(&mut self) -> (u32, Vec<(u16, u16)>) {
+    let packet = self.packets.0.pop().unwrap();
+    self.packets.0.clear();
+    let mut chunk_space = [SctpChunk::Pad; 1];
+    let packet = read_sctp_packet(&packet, true, &mut chunk_space).unwrap();
+    match packet.chunks[0] {
+      SctpChunk::SAck {
+        cumulative_tsn_ack,
+        gap_ack_blocks,
+        ..
+      } => (
+        cumulative_tsn_ack,
+        gap_ack_blocks
+          .chunks_exact(4)
+          .map(|block| {
+            (
+              u16::from_be_bytes([block[0], block[1]]),
+              u16::from_be_bytes([block[2], block[3]]),
+            )
+          })
+          .collect(),
+      ),
+      ref chunk => panic!("unexpected chunk {:?}", chunk),
+    }
+  }
+
+  // The data of the messages delivered so far.
+  fn messages(&mut self) -> Vec<Vec<u8>> {
+    let buffer_pool = &self.buffer_pool;
+    self
+      .client_state
+      .received_messages
+      .drain(..)
+      .map(|(_, _, _, message)| buffer_pool.adopt(message).to_vec())
+      .collect()
+  }
+}
+
+#[test]
+fn gap_held_until_filled() {
+  let mut receiver = Receiver::new();
+  receiver.data(WHOLE, 11, RELIABLE);
+  receiver.data(WHOLE, 13, RELIABLE);
+  receiver.data(WHOLE, 14, RELIABLE);
+  receiver.data(WHOLE, 16, RELIABLE);
+  assert_eq!(receiver.sack(), (11, vec![(2, 3), (5, 5)]));
+  assert_eq!(receiver.messages(), [vec![11]]);
+  assert_eq!(receiver.client_state.early_bytes, 3);
+
+  receiver.data(WHOLE, 12, RELIABLE);
+  assert_eq!(receiver.sack(), (14, vec![(2, 2)]));
+  assert_eq!(receiver.messages(), [vec![12], vec![13], vec![14]]);
+
+  receiver.data(WHOLE, 15, RELIABLE);
+  assert_eq!(receiver.sack(), (16, vec![]));
+  assert_eq!(receiver.messages(), [vec![15], vec![16]]);
+  assert_eq!(receiver.client_state.early_bytes, 0);
+}
+
+#[test]
+fn duplicates_delivered_once() {
+  let mut receiver = Receiver::new();
+  receiver.data(WHOLE, 11, RELIABLE);
+  receiver.data(WHOLE, 13, RELIABLE);
+  receiver.data(WHOLE, 11, RELIABLE);
+  receiver.data(WHOLE, 13, RELIABLE);
+  assert_eq!(receiver.sack(), (11, vec![(2, 2)]));
+  receiver.data(WHOLE, 12, RELIABLE);
+  assert_eq!(receiver.sack(), (13, vec![]));
+  assert_eq!(receiver.messages(), [vec![11], vec![12], vec![13]]);
+}
+
+#[test]
+fn unreliable_delivered_at_once() {
+  let mut receiver = Receiver::new();
+  receiver.data(WHOLE, 12, UNRELIABLE);
+  receiver.data(WHOLE, 13, RELIABLE);
+  assert_eq!(receiver.messages(), [vec![12]]);
+  // The gap is still reported while a reliable channel is open.
+  assert_eq!(receiver.sack(), (10, vec![(2, 3)]));
+  receiver.data(WHOLE, 11, UNRELIABLE);
+  assert_eq!(receiver.sack(), (13, vec![]));
+  assert_eq!(receiver.messages(), [vec![11], vec![13]]);
+}
+
+#[test]
+fn unreliable_only_acknowledges_everything() {
+  let mut receiver = Receiver::new();
+  receiver
+    .client_state
+    .channel_deliveries
+    .retain(|&(id, _)| id == UNRELIABLE);
+  receiver.data(WHOLE, 12, UNRELIABLE);
+  receiver.data(WHOLE, 14, UNRELIABLE);
+  assert_eq!(receiver.sack(), (14, vec![]));
+  assert_eq!(receiver.messages(), [vec![12], vec![14]]);
+}
+
+#[test]
+fn fragments_reassembled_out_of_order() {
+  let mut receiver = Receiver::new();
+  receiver.client_state.max_reassembled_len = Some(1000);
+  receiver.data(BEGIN, 11, RELIABLE);
+  receiver.data(END, 14, RELIABLE);
+  receiver.data(MIDDLE, 13, RELIABLE);
+  assert_eq!(receiver.sack(), (11, vec![(2, 3)]));
+  assert!(receiver.messages().is_empty());
+  receiver.data(MIDDLE, 12, RELIABLE);
+  assert_eq!(receiver.sack(), (14, vec![]));
+  assert_eq!(receiver.messages(), [vec![11, 12, 13, 14]]);
+  assert_eq!(receiver.client_state.reassembly.holes, 0);
+}
+
+#[test]
+fn forward_tsn_releases_held_chunks() {
+  let mut receiver = Receiver::new();
+  receiver.data(WHOLE, 11, RELIABLE);
+  receiver.data(WHOLE, 13, RELIABLE);
+  receiver.data(WHOLE, 14, RELIABLE);
+  receiver.data(WHOLE, 17, RELIABLE);
+  assert_eq!(receiver.messages(), [vec![11]]);
+  // The client abandoned TSN 12.
+  receiver.forward_tsn(12);
+  assert_eq!(receiver.sack(), (14, vec![(3, 3)]));
+  assert_eq!(receiver.messages(), [vec![13], vec![14]]);
+  // And then TSNs 15 and 16, past which 17 is delivered.
+  receiver.forward_tsn(16);
+  assert_eq!(receiver.sack(), (17, vec![]));
+  assert_eq!(receiver.messages(), [vec![17]]);
+  // A stale FORWARD TSN changes nothing.
+  receiver.forward_tsn(15);
+  assert_eq!(receiver.sack(), (17, vec![]));
+}
@@ -0,0 +1,248 @@
+//! Golden transcripts for the SCTP association state machine.
+//!
+//! Each transcript in `transcripts/` is a sequence of SCTP packets as a browser sends them, after
+//! DTLS decryption, each followed by the exact packets the state machine must answer with.
+//! Every transcript not named `synthetic_` is a recording of a real browser connected to this
+//! server, named after the browser and its SCTP stack, such as `chrome_dcsctp_chat.txt` or
+//! `firefox_usrsctp_chat.txt`.  To record one, start the browser with `SSLKEYLOGFILE` set, capture
+//! its traffic to the server, let Wireshark decrypt the DTLS records with the logged keys, and
+//! copy out the SCTP packets in both directions.
+//!
+//! The `synthetic_` transcripts are not captures: their browser packets were written by hand after
+//! the chunks and parameters each browser is known to send, and their responses were blessed from
+//! this state machine.  They catch changes in behaviour, not disagreements with real browsers.
+//! Lines are:
+//!
+//! - `% rng <hex> ...`: the random 32 bit values the server drew, in order, which for a recording
+//!   are the initiate tag and initial TSN of its INIT ACK
+//! - `> <hex>`: a packet from the browser, fed to `receive_sctp_packet`
+//! - `< <hex>`: a packet the server must emit in response, in order
+//! - `= closed`: the association must report itself closed after the previous packet
+//! - `# ...`: comments, also allowed after the hex of a packet
+//!
+//! Random values beyond those listed are drawn from a fixed `StepRng`, so responses are
+//! reproducible.  After an intended change in behaviour, run the tests with `BLESS_TRANSCRIPTS=1`
+//! to rewrite the expected responses, and review the diff.
+
+use std::{collections::VecDeque, env, fmt::Write as _, fs, path::Path};
+
+use rand::{rngs::mock::StepRng, RngCore};
+
+use super::{receive_sctp_packet, ClientError, ClientState, SctpTransport, SCTP_MAX_CHUNKS};
+use crate::{
+  buffer_pool::BufferPool,
+  ffi_event::{FfiEventDelivery, FfiEventQueue},
+  sctp::{read_sctp_packet, SctpChunk},
+};
+
+#[test]
+fn synthetic_chrome() {
+  check_transcript(
+    "synthetic_chrome.txt",
+    include_str!("transcripts/synthetic_chrome.txt"),
+  );
+}
+
+#[test]
+fn synthetic_firefox() {
+  check_transcript(
+    "synthetic_firefox.txt",
+    include_str!("transcripts/synthetic_firefox.txt"),
+  );
+}
+
+#[test]
+fn recorded() {
+  let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/client/transcripts");
+  let mut names: Vec<String> = fs::read_dir(dir.as_path())
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+    .filter(|name| name.ends_with(".txt") && !name.starts_with("synthetic_"))
+    .collect();
+  names.sort();
+  for name in names {
+    check_transcript(&name, &fs::read_to_string(dir.join(&name)).unwrap());
+  }
+}
+
+#[derive(Default)]
+struct Recorder(Vec<Vec<u8>>);
+
+// The random values listed by a transcript, then those of a fixed `StepRng`.
+struct Draws(VecDeque<u32>, StepRng);
+
+impl RngCore for Draws {
+  fn next_u32(&mut self) -> u32 {
+    self.0.pop_front().unwrap_or_else(|| self.1.next_u32())
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    u64::from(self.next_u32()) << 32 | u64::from(self.next_u32())
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(4) {
+      chunk.copy_from_slice(&self.next_u32().to_be_bytes()[..chunk.len()]);
+    }
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+    self.fill_bytes(dest);
+    Ok(())
+  }
+}
+
+impl SctpTransport for Recorder {
+  fn send_sctp(&mut self, packet: &[u8]) -> Result<(), ClientError> {
+    self.0.push(packet.to_vec());
+    Ok(())
+  }
+}
+
+struct Step {
+  input: Vec<u8>,
+  responses: Vec<Vec<u8>>,
+  closed: bool,
+}
+
+fn check_transcript(name: &str, transcript: &str) {
+  let buffer_pool = BufferPool::new();
+  let (draws, steps) = parse_transcript(transcript);
+  let mut rng = Draws(draws, StepRng::new(0x1111_1111, 0x1111_1111));
+  let ffi_events = FfiEventQueue::new(None, FfiEventDelivery::Direct).unwrap();
+  let mut client_state = ClientState::new(([127, 0, 0, 1], 5000).into(), ffi_events);
+
+  let mut actual = Vec::new();
+  for (index, step) in steps.into_iter().enumerate() {
+    let mut recorder = Recorder::default();
+    let mut chunks = [SctpChunk::Abort; SCTP_MAX_CHUNKS];
+    let packet = read_sctp_packet(&step.input, false, &mut chunks)
+      .unwrap_or_else(|err| panic!("{} packet {}: {}", name, index, err));
+    let open = receive_sctp_packet(
+      &buffer_pool,
+      &mut recorder,
+      &mut rng,
+      &mut client_state,
+      &packet,
+    )
+    .unwrap_or_else(|err| panic!("{} packet {}: {}", name, index, err));
+
+    actual.push(Step {
+      input: step.input.clone(),
+      responses: recorder.0,
+      closed: !open,
+    });
+    if env::var_os("BLESS_TRANSCRIPTS").is_none() {
+      let actual = actual.last().unwrap();
+      assert_eq!(
+        describe_all(&actual.responses),
+        describe_all(&step.responses),
+        "{} packet {}: responses differ",
+        name,
+        index
+      );
+      assert_eq!(
+        actual.responses, step.responses,
+        "{} packet {}: response bytes differ",
+        name, index
+      );
+      assert_eq!(
+        actual.closed, step.closed,
+        "{} packet {}: association closed state differs",
+        name, index
+      );
+    }
+  }
+
+  if env::var_os("BLESS_TRANSCRIPTS").is_some() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("src/client/transcripts")
+      .join(name);
+    fs::write(path, bless_transcript(transcript, &actual)).unwrap();
+  }
+}
+
+fn parse_transcript(transcript: &str) -> (VecDeque<u32>, Vec<Step>) {
+  let mut draws = VecDeque::new();
+  let mut steps: Vec<Step> = Vec::new();
+  for line in transcript.lines() {
+    let line = line.split('#').next().unwrap().trim();
+    if let Some(values) = line.strip_prefix("% rng") {
+      for value in values.split_whitespace() {
+        draws.push_back(u32::from_str_radix(value, 16).expect("invalid random value"));
+      }
+    } else if let Some(hex) = line.strip_prefix('>') {
+      steps.push(Step {
+        input: from_hex(hex),
+        responses: Vec::new(),
+        closed: false,
+      });
+    } else if let Some(hex) = line.strip_prefix('<') {
+      let step = steps.last_mut().expect("response before any input");
+      step.responses.push(from_hex(hex));
+    } else if line == "= closed" {
+      steps.last_mut().expect("closed before any input").closed = true;
+    } else if !line.is_empty() {
+      panic!("unrecognized transcript line {:?}", line);
+    }
+  }
+  (draws, steps)
+}
+
+// Rewrite a transcript with the given responses, keeping its inputs and comments in place.
+fn bless_transcript(transcript: &str, steps: &[Step]) -> String {
+  let mut steps = steps.iter();
+  let mut blessed = String::new();
+  for line in transcript.lines() {
+    if line.starts_with('<') || line.starts_with('=') {
+      continue;
+    }
+    blessed.push_str(line);
+    blessed.push('\n');
+    if line.starts_with('>') {
+      let step = steps.next().unwrap();
+      for response in &step.responses {
+        writeln!(blessed, "< {}  # {}", to_hex(response), describe(response)).unwrap();
+      }
+      if step.closed {
+        blessed.push_str("= closed\n");
+      }
+    }
+  }
+  blessed
+}
+
+fn describe_all(packets: &[Vec<u8>]) -> Vec<String> {
+  packets.iter().map(|p| describe(p)).collect()
+}
+
+fn describe(packet: &[u8]) -> String {
+  let mut chunks = [SctpChunk::Abort; SCTP_MAX_CHUNKS];
+  match read_sctp_packet(packet, true, &mut chunks) {
+    Ok(packet) => packet
+      .chunks
+      .iter()
+      .map(|chunk| format!("{:?}", chunk))
+      .collect::<Vec<_>>()
+      .join(", "),
+    Err(err) => format!("unreadable packet: {}", err),
+  }
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+  let digits: Vec<u8> = hex
+    .bytes()
+    .filter(|b| !b.is_ascii_whitespace())
+    .map(|b| (b as char).to_digit(16).expect("invalid hex digit") as u8)
+    .collect();
+  assert!(digits.len().is_multiple_of(2), "odd number of hex digits");
+  digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  let mut hex = String::new();
+  for b in bytes {
+    write!(hex, "{:02x}", b).unwrap();
+  }
+  hex
+}
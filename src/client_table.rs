@@ -0,0 +1,135 @@
+use std::{fmt, net::SocketAddr};
+
+use hashbrown::HashMap;
+
+use crate::client::Client;
+
+/// Opaque handle of a client, which stays the same for as long as the server knows the client,
+/// whichever address it moves to, returned by `Server::client_id`.
+///
+/// Ids are never reused by the same `Server`, so one can safely outlive its client.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClientId(u64);
+
+impl fmt::Display for ClientId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "client-{}", self.0)
+  }
+}
+
+/// How far along its connection a client is, returned by `Server::client_state`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientState {
+  /// A STUN binding request from the client was answered, and nothing else has arrived yet.
+  StunBound,
+  /// The DTLS handshake has started, and has not completed.  A client whose handshake failed
+  /// stays here until it times out.
+  DtlsHandshaking,
+  /// The DTLS handshake has completed, and the SCTP association and data channel are being set
+  /// up.
+  SctpConnecting,
+  /// Messages may be sent back and forth, as with `Server::is_connected`.
+  Established,
+  /// The SCTP association or the DTLS session is being closed, by either side.
+  ShuttingDown,
+  /// The connection is over, and the client is about to be or has been removed.
+  Closed,
+}
+
+// Every client of a server, keyed by its `ClientId` and found by its current address.
+pub struct ClientTable {
+  clients: HashMap<ClientId, Client>,
+  ids: HashMap<SocketAddr, ClientId>,
+  next_id: u64,
+}
+
+impl ClientTable {
+  pub fn new() -> ClientTable {
+    ClientTable {
+      clients: HashMap::new(),
+      ids: HashMap::new(),
+      next_id: 0,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.clients.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.clients.is_empty()
+  }
+
+  pub fn contains_key(&self, remote_addr: &SocketAddr) -> bool {
+    self.ids.contains_key(remote_addr)
+  }
+
+  pub fn id(&self, remote_addr: &SocketAddr) -> Option<ClientId> {
+    self.ids.get(remote_addr).copied()
+  }
+
+  pub fn addr(&self, id: ClientId) -> Option<SocketAddr> {
+    self.clients.get(&id).map(|client| client._remote_addr)
+  }
+
+  pub fn get(&self, remote_addr: &SocketAddr) -> Option<&Client> {
+    self.clients.get(self.ids.get(remote_addr)?)
+  }
+
+  pub fn get_mut(&mut self, remote_addr: &SocketAddr) -> Option<&mut Client> {
+    self.clients.get_mut(self.ids.get(remote_addr)?)
+  }
+
+  // Add a client at the address it was created for, giving it a new id.
+  pub fn insert(&mut self, client: Client) -> &mut Client {
+    let id = ClientId(self.next_id);
+    self.next_id += 1;
+    self.ids.insert(client._remote_addr, id);
+    self.clients.entry(id).or_insert(client)
+  }
+
+  // Move a client to a new address, returning it if there was one at `old_addr`.
+  pub fn rebind(&mut self, old_addr: &SocketAddr, new_addr: SocketAddr) -> Option<&mut Client> {
+    let id = self.ids.remove(old_addr)?;
+    self.ids.insert(new_addr, id);
+    let client = self.clients.get_mut(&id)?;
+    client.rebind(new_addr);
+    Some(client)
+  }
+
+  pub fn remove(&mut self, remote_addr: &SocketAddr) -> Option<Client> {
+    let id = self.ids.remove(remote_addr)?;
+    self.clients.remove(&id)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&SocketAddr, &Client)> {
+    self
+      .clients
+      .values()
+      .map(|client| (&client._remote_addr, client))
+  }
+
+  pub fn iter_ids(&self) -> impl Iterator<Item = (ClientId, &Client)> {
+    self.clients.iter().map(|(id, client)| (*id, client))
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (SocketAddr, &mut Client)> {
+    self
+      .clients
+      .values_mut()
+      .map(|client| (client._remote_addr, client))
+  }
+
+  pub fn values(&self) -> impl Iterator<Item = &Client> {
+    self.clients.values()
+  }
+
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+    self.clients.values_mut()
+  }
+
+  pub fn clear(&mut self) {
+    self.clients.clear();
+    self.ids.clear();
+  }
+}
@@ -0,0 +1,85 @@
+use std::{
+  mem,
+  time::{Duration, Instant},
+};
+
+/// What a `Server` does about time that passes while it is not running at all, such as while
+/// the host is suspended or a VM is paused for migration, set with
+/// `ServerConfig::clock_jump_policy`.
+///
+/// Such a gap is noticed as time which a clock that keeps counting through suspension moves on
+/// by while `Instant` does not: `CLOCK_BOOTTIME` on Linux, and the system's wall clock elsewhere,
+/// where setting the system clock forward also counts.  Time `Instant` counts anyway, such as
+/// while the application stops driving the server, is not a jump.  Either way the jump is
+/// reported with `ServerEvent::ClockJump`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClockJumpPolicy {
+  /// Leave the gap out of client and session timeouts, as if the server's clock had stopped
+  /// along with it.  Clients which are still there carry on after resuming, while clients which
+  /// went away in the meantime time out as usual once the server runs again.
+  Freeze,
+  /// Count the gap towards timeouts like any other time, which disconnects every client and
+  /// expires every session after a long enough pause.
+  Ignore,
+}
+
+// Notices time which passes without `Instant` counting it, by comparing it with a reference
+// clock which keeps counting while the host is suspended.
+pub struct ClockMonitor {
+  reference: fn() -> Duration,
+  last_reading: (Instant, Duration),
+}
+
+impl ClockMonitor {
+  pub fn new() -> ClockMonitor {
+    ClockMonitor::with_reference(suspend_aware_now)
+  }
+
+  pub fn with_reference(reference: fn() -> Duration) -> ClockMonitor {
+    ClockMonitor {
+      reference,
+      last_reading: (Instant::now(), reference()),
+    }
+  }
+
+  // Read both clocks at `now`, returning how much further the reference clock has moved than
+  // `Instant` since the last reading, if that is at least `threshold`.
+  pub fn check(&mut self, now: Instant, threshold: Duration) -> Option<Duration> {
+    let reading = (self.reference)();
+    let (last_now, last_reading) = mem::replace(&mut self.last_reading, (now, reading));
+    reading
+      .saturating_sub(last_reading)
+      .checked_sub(now.saturating_duration_since(last_now))
+      .filter(|&gap| gap >= threshold)
+  }
+}
+
+// `CLOCK_BOOTTIME` is the `CLOCK_MONOTONIC` which `Instant` reads, plus the time spent suspended.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn suspend_aware_now() -> Duration {
+  let mut now = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+  };
+  if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut now) } != 0 {
+    return Duration::ZERO;
+  }
+  Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn suspend_aware_now() -> Duration {
+  use std::time::{SystemTime, UNIX_EPOCH};
+
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+}
+
+// Move an instant back by a gap `Instant` did not count, so that time elapsed since it includes
+// the gap.
+pub fn count_gap(instant: &mut Instant, gap: Duration) {
+  if let Some(shifted) = instant.checked_sub(gap) {
+    *instant = shifted;
+  }
+}
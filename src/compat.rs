@@ -0,0 +1,275 @@
+//! The public API of upstream `webrtc-unreliable`, for projects moving over from it.
+//!
+//! Everything here has the same names and signatures as in `webrtc-unreliable` 0.5, so a project
+//! built against it only needs to depend on this crate with the `compat` feature and import this
+//! module under the old name:
+//!
+//! ```ignore
+//! use unreliablertc::compat as webrtc_unreliable;
+//! ```
+//!
+//! The wrapped server registers no FFI event callback.  `Server::into_inner` gives access to the
+//! rest of this crate's API once a project is ready to use it.
+
+use std::{
+  error::Error,
+  fmt,
+  io::{Error as IoError, ErrorKind as IoErrorKind},
+  marker::PhantomData,
+  net::SocketAddr,
+  ops::Deref,
+};
+
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use http::{header, Response};
+
+pub use crate::client::{MessageType, MAX_MESSAGE_LEN};
+
+/// A reference to an internal buffer containing a received message.
+pub struct MessageBuffer<'a>(crate::server::MessageBuffer, PhantomData<&'a ()>);
+
+impl<'a> Deref for MessageBuffer<'a> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl<'a> AsRef<[u8]> for MessageBuffer<'a> {
+  fn as_ref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+pub struct MessageResult<'a> {
+  pub message: MessageBuffer<'a>,
+  pub message_type: MessageType,
+  pub remote_addr: SocketAddr,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+  /// Non-fatal error trying to send a message to an unknown, disconnected, or not fully
+  /// established client.
+  ClientNotConnected,
+  /// Non-fatal error writing a WebRTC Data Channel message that is too large to fit in the
+  /// maximum message length.
+  IncompleteMessageWrite,
+  /// I/O error on the underlying socket.  May or may not be fatal, depending on the specific
+  /// error.
+  Io(IoError),
+}
+
+impl fmt::Display for SendError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      SendError::ClientNotConnected => write!(f, "client is not connected"),
+      SendError::IncompleteMessageWrite => {
+        write!(f, "incomplete write of WebRTC Data Channel message")
+      }
+      SendError::Io(err) => fmt::Display::fmt(err, f),
+    }
+  }
+}
+
+impl Error for SendError {}
+
+impl From<IoError> for SendError {
+  fn from(err: IoError) -> SendError {
+    SendError::Io(err)
+  }
+}
+
+impl From<crate::server::SendError> for SendError {
+  fn from(err: crate::server::SendError) -> SendError {
+    match err {
+      crate::server::SendError::ClientNotConnected => SendError::ClientNotConnected,
+      crate::server::SendError::IncompleteMessageWrite => SendError::IncompleteMessageWrite,
+      crate::server::SendError::ClientError(err) => SendError::Io(IoError::other(err)),
+      crate::server::SendError::Io(err) => SendError::Io(err),
+      err @ crate::server::SendError::Backpressure => {
+        SendError::Io(IoError::new(IoErrorKind::WouldBlock, err.to_string()))
+      }
+      err @ crate::server::SendError::UnknownChannel(_) => {
+        SendError::Io(IoError::new(IoErrorKind::InvalidInput, err.to_string()))
+      }
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+  /// `SessionEndpoint` has beeen disconnected from its `Server` (the `Server` has been dropped).
+  Disconnected,
+  /// An error streaming the SDP descriptor
+  StreamError(Box<dyn Error + Send + Sync + 'static>),
+  /// SessionEndpoint has failed to parse a SDP descriptor
+  ParseError(Box<dyn Error + Send + Sync + 'static>),
+}
+
+impl fmt::Display for SessionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      SessionError::Disconnected => write!(f, "`SessionEndpoint` disconnected from `Server`"),
+      SessionError::StreamError(e) => {
+        write!(f, "error streaming the incoming SDP descriptor: {}", e)
+      }
+      SessionError::ParseError(e) => write!(f, "error parsing incoming SDP descriptor: {}", e),
+    }
+  }
+}
+
+impl Error for SessionError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      SessionError::Disconnected => None,
+      SessionError::StreamError(e) => Some(e.as_ref()),
+      SessionError::ParseError(e) => Some(e.as_ref()),
+    }
+  }
+}
+
+impl From<crate::server::SessionError> for SessionError {
+  fn from(err: crate::server::SessionError) -> SessionError {
+    match err {
+      crate::server::SessionError::Disconnected => SessionError::Disconnected,
+      crate::server::SessionError::ParseError(e) => SessionError::ParseError(e.to_string().into()),
+      err => SessionError::StreamError(err.to_string().into()),
+    }
+  }
+}
+
+/// A clone-able handle to a `Server` which answers incoming WebRTC sessions.
+#[derive(Clone)]
+pub struct SessionEndpoint(crate::server::SessionEndpoint);
+
+impl SessionEndpoint {
+  /// Receives an incoming SDP descriptor of an `RTCSessionDescription` from a browser, informs
+  /// the corresponding `Server` of the new WebRTC session, and returns a JSON object containing
+  /// two fields:
+  ///   1) An `answer` field which contains the `RTCSessionDescription` answer.
+  ///   2) A `candidate` field which contains the `RTCIceCandidate` of the server.
+  pub async fn session_request<I, E, S>(
+    &mut self,
+    sdp_descriptor: S,
+  ) -> Result<String, SessionError>
+  where
+    I: AsRef<[u8]>,
+    E: Error + Send + Sync + 'static,
+    S: Stream<Item = Result<I, E>>,
+  {
+    let max_len = self.0.max_offer_len();
+    let mut sdp = Vec::new();
+    pin_mut!(sdp_descriptor);
+    while let Some(chunk) = sdp_descriptor.next().await {
+      sdp.extend_from_slice(
+        chunk
+          .map_err(|e| SessionError::StreamError(e.into()))?
+          .as_ref(),
+      );
+      if sdp.len() > max_len {
+        return Err(SessionError::StreamError(
+          "incoming SDP descriptor is too large".into(),
+        ));
+      }
+    }
+    let sdp = String::from_utf8(sdp).map_err(|e| SessionError::ParseError(e.into()))?;
+    Ok(self.0.session_request(&sdp)?)
+  }
+
+  /// Convenience method which returns an `http::Response` rather than a JSON string.
+  pub async fn http_session_request<I, E, S>(
+    &mut self,
+    sdp_descriptor: S,
+  ) -> Result<Response<String>, SessionError>
+  where
+    I: AsRef<[u8]>,
+    E: Error + Send + Sync + 'static,
+    S: Stream<Item = Result<I, E>>,
+  {
+    let response = self.session_request(sdp_descriptor).await?;
+    Ok(
+      Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(response)
+        .expect("could not construct session response"),
+    )
+  }
+}
+
+pub struct Server(crate::server::Server);
+
+impl Server {
+  /// Start a new WebRTC data channel server listening on `listen_addr` and advertising its
+  /// publicly available address as `public_addr`.
+  ///
+  /// WebRTC connections must be started via an external communication channel from a browser via
+  /// the `SessionEndpoint`, after which a WebRTC data channel can be opened.
+  pub async fn new(listen_addr: SocketAddr, public_addr: SocketAddr) -> Result<Server, IoError> {
+    crate::server::Server::new(listen_addr, public_addr, None).map(Server)
+  }
+
+  /// Returns a `SessionEndpoint` which can be used to start new WebRTC sessions.
+  ///
+  /// WebRTC connections must be started via an external communication channel from a browser via
+  /// the returned `SessionEndpoint`, and this communication channel will be used to exchange
+  /// session descriptions in SDP format.
+  pub fn session_endpoint(&self) -> SessionEndpoint {
+    SessionEndpoint(self.0.session_endpoint())
+  }
+
+  /// The total count of clients in any active state, whether still starting up, fully
+  /// established, or still shutting down.
+  pub fn active_clients(&self) -> usize {
+    self.0.active_clients()
+  }
+
+  /// List all the currently fully established client connections.
+  pub fn connected_clients(&self) -> impl Iterator<Item = &SocketAddr> + '_ {
+    self.0.connected_client_addrs()
+  }
+
+  /// Returns true if the client has a completely established WebRTC data channel connection and
+  /// can send messages back and forth.  Returns false for disconnected clients as well as those
+  /// that are still starting up or are in the process of shutting down.
+  pub fn is_connected(&self, remote_addr: &SocketAddr) -> bool {
+    self.0.is_connected(remote_addr)
+  }
+
+  /// Disconect the given client, does nothing if the client is not currently connected.
+  pub async fn disconnect(&mut self, remote_addr: &SocketAddr) -> Result<(), IoError> {
+    self.0.disconnect(remote_addr).await
+  }
+
+  /// Send the given message to the given remote client, if they are connected.
+  ///
+  /// The given message must be less than `MAX_MESSAGE_LEN`.
+  pub async fn send(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    remote_addr: &SocketAddr,
+  ) -> Result<(), SendError> {
+    Ok(self.0.send(message, message_type, remote_addr).await?)
+  }
+
+  /// Receive a WebRTC data channel message from any connected client.
+  ///
+  /// `Server::recv` *must* be called for proper operation of the server, as it also handles
+  /// background tasks such as responding to STUN packets and timing out existing sessions.
+  pub async fn recv(&mut self) -> Result<MessageResult<'_>, IoError> {
+    let received = self.0.recv().await?;
+    Ok(MessageResult {
+      message: MessageBuffer(received.message, PhantomData),
+      message_type: received.message_type,
+      remote_addr: received.remote_addr,
+    })
+  }
+
+  /// The server this wraps, with the full API of this crate.
+  pub fn into_inner(self) -> crate::server::Server {
+    self.0
+  }
+}
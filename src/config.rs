@@ -0,0 +1,493 @@
+use std::{
+  net::{IpAddr, SocketAddr},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+use crate::{
+  admission::AdmissionRate,
+  client::Delivery,
+  clock::ClockJumpPolicy,
+  ffi_event::FfiEventDelivery,
+  ring_queue::OverflowPolicy,
+  sdp::{CandidateKind, IceCandidate, SdpAnswer, SdpAnswerFormat},
+  token::TokenKey,
+};
+
+pub(crate) type AnswerMutator = Arc<dyn Fn(&mut SdpAnswer) + Send + Sync>;
+pub(crate) type RateLimiter = Arc<dyn Fn(IpAddr) -> bool + Send + Sync>;
+pub(crate) type SharedRng = Arc<Mutex<dyn RngCore + Send>>;
+
+/// Tunable behaviour of a `Server`, passed to `Server::with_config`.
+///
+/// All fields have sensible defaults, so the usual way to build one is to override only what is
+/// needed, for example `ServerConfig { stun_silent_unknown_users: true, ..Default::default() }`.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+  /// Do not answer STUN binding requests naming a session the server does not know about.
+  ///
+  /// By default such requests are rejected with `401 Unauthorized` so that browsers fail fast,
+  /// but a public server may prefer to stay silent so that it cannot be discovered by scanning.
+  /// Defaults to true under the `paranoid` feature.
+  pub stun_silent_unknown_users: bool,
+  /// Discard STUN binding requests without a valid FINGERPRINT attribute, defaults to true under
+  /// the `paranoid` feature.
+  ///
+  /// Browsers always send FINGERPRINT, so this only turns away traffic that merely looks like
+  /// STUN.
+  pub stun_require_fingerprint: bool,
+  /// Number of STUN responses the server's own send queue holds without reallocating.
+  pub outgoing_queue_capacity: usize,
+  /// Number of outgoing packets each client's egress queue holds without reallocating.
+  pub client_egress_capacity: usize,
+  /// Number of received messages the receive queue holds without reallocating.
+  pub incoming_queue_capacity: usize,
+  /// What happens to packets and messages queued beyond their queue's capacity.
+  ///
+  /// The default, `OverflowPolicy::Grow`, never drops anything but may reallocate on the packet
+  /// path on very busy servers.
+  pub queue_overflow: OverflowPolicy,
+  /// How often public addresses given as `PublicAddr::Host` are resolved again.  The system
+  /// resolver does not report record TTLs, so this should be set close to the TTL of the
+  /// records being served.
+  pub dns_refresh_interval: Duration,
+  /// Run the thread started by `Server::spawn_driver` with the `SCHED_FIFO` realtime scheduling
+  /// policy at this priority, from 1 (lowest) to 99 on Linux.
+  ///
+  /// OS scheduling jitter on a busy host turns directly into latency for every client, and a
+  /// realtime priority keeps the driver from waiting behind ordinary threads.  This needs
+  /// `CAP_SYS_NICE` or a suitable `RLIMIT_RTPRIO` on Linux, and is only supported on Unix.
+  pub driver_realtime_priority: Option<i32>,
+  /// Pin the thread started by `Server::spawn_driver` to these CPU indices, only supported on
+  /// Linux.
+  pub driver_cpu_affinity: Option<Vec<usize>>,
+  /// Accept STUN binding requests for session tokens minted with this key by a
+  /// `SessionEndpoint` configured with `SessionEndpointConfig::stateless_tokens`, even if this
+  /// server has never seen the session.
+  ///
+  /// This lets a separate web tier answer offers on behalf of any number of servers sharing the
+  /// key.  Sessions known to the server are still accepted as usual.
+  pub stun_token_key: Option<TokenKey>,
+  /// Accept new clients no faster than this, holding back the STUN answers of the others, to
+  /// keep latency stable for established clients during reconnect storms.
+  ///
+  /// Held back requests are only revisited as packets arrive and on the server's one second
+  /// timer, so on a quiet server admission may lag slightly behind the configured rate.
+  pub admission_rate: Option<AdmissionRate>,
+  /// Application subprotocols the server dispatches on, tagged by their index in this list.
+  ///
+  /// A data channel opened with a `protocol` equal to one of these, for example with
+  /// `createDataChannel("game", { protocol: "chat" })`, has its messages tagged with that index
+  /// in `MessageResult::subprotocol`, so multiplexed protocols can be told apart without peeking
+  /// at the payload.  Only the first 256 entries can be used.
+  pub subprotocols: Vec<String>,
+  /// Data channels every client has as soon as its SCTP association is established, without
+  /// either side opening them, for browsers which create them with `negotiated: true`.
+  ///
+  /// Each is reported with a `ServerEvent::ChannelOpened` and used with `Server::send_on` as if
+  /// the client had opened it, which saves the round trip of opening a channel.
+  pub negotiated_channels: Vec<NegotiatedChannel>,
+  /// What to do about time that passes while the server is not running at all, defaults to
+  /// `ClockJumpPolicy::Freeze`.
+  ///
+  /// Without this, resuming a suspended laptop or a paused VM would find every client and session
+  /// past its timeout and disconnect them all at once.
+  pub clock_jump_policy: ClockJumpPolicy,
+  /// Pass on the fragments of received messages too large for a single SCTP DATA chunk through
+  /// `Server::recv_stream` as they arrive, rather than discarding such messages.
+  ///
+  /// Fragments bypass inbound middleware, and enforcing a limit on the size of such messages is
+  /// up to the application.
+  pub stream_fragments: bool,
+  /// Number of fragmented messages each client may have partly received at once with
+  /// `stream_fragments`, at most one per data channel, defaults to 4.
+  ///
+  /// Beginning another one abandons the oldest, whose remaining fragments are then discarded, so
+  /// that a client leaving many messages unfinished cannot make the server track them all.
+  pub max_partial_messages: usize,
+  /// Abandon a fragmented message received with `stream_fragments` once it grows beyond this
+  /// many bytes, discarding the rest of its fragments.  Unlimited by default.
+  pub max_partial_message_len: Option<usize>,
+  /// Reassemble received messages too large for a single SCTP DATA chunk, of up to this many
+  /// bytes, and deliver them whole through `Server::recv` like any other message.  This takes
+  /// precedence over `stream_fragments`, and is advertised as the `a=max-message-size` of session
+  /// answers so that browsers send messages this large.
+  ///
+  /// Unset by default, which advertises a maximum of 1160 bytes.  Partial messages are limited
+  /// by `max_partial_messages` as with streamed fragments.
+  pub max_reassembled_len: Option<usize>,
+  /// Send messages longer than this many bytes as several SCTP DATA chunks of at most this size,
+  /// each in its own packet, so that messages of up to `MAX_FRAGMENTED_MESSAGE_LEN` can be sent
+  /// without exceeding the path MTU.  Around 1160 suits most paths.
+  ///
+  /// Unset by default, which sends every message as a single chunk of at most `MAX_MESSAGE_LEN`,
+  /// which is also the most a fragment holds whatever this is set to.  Either way each client's
+  /// `Server::max_message_len` also respects the `a=max-message-size` of its offer.  Losing any
+  /// fragment loses the whole message, so unless `queue_overflow` is `OverflowPolicy::Grow`
+  /// messages are limited to `client_egress_capacity` fragments, and a message whose fragments
+  /// do not fit in what is left of its client's queue fails with `SendError::Backpressure`.
+  pub max_fragment_len: Option<usize>,
+  /// Versions of the application's own protocol the server speaks, advertised to browsers in
+  /// session answers as `protocolVersions`.
+  ///
+  /// When not empty, the first message each client sends must be exactly one of these strings.
+  /// That message is not delivered to `Server::recv`, and the version it names can be looked up
+  /// with `Server::protocol_version`.  A client naming any other version is sent a text message
+  /// carrying `VERSION_MISMATCH_CLOSE_CODE` and the supported versions, then disconnected.
+  pub protocol_versions: Vec<String>,
+  /// How much time must pass without `Instant` counting it before this counts as a clock jump,
+  /// defaults to 5 seconds.
+  pub clock_jump_threshold: Duration,
+  /// Produce a `ServerEvent::Stats` this often, rounded up to the next second, so that metrics
+  /// can be collected by subscribing to `EventKind::Stats`.  Disabled by default.
+  pub stats_event_interval: Option<Duration>,
+  /// Watch the server from a separate thread, and produce a `ServerEvent::DriverStalled` when it
+  /// has been busy with one thing for longer than this, or the application has not called
+  /// `Server::recv` again for that long.  Disabled by default.
+  ///
+  /// This helps find what keeps a frozen server from running, such as an FFI event callback that
+  /// blocks.
+  pub watchdog_timeout: Option<Duration>,
+  /// How events reach the FFI event callback, defaults to `FfiEventDelivery::Direct`.
+  ///
+  /// With either of the queued modes the server never calls foreign code while handling packets,
+  /// so a slow consumer cannot hold up other clients.
+  pub ffi_event_delivery: FfiEventDelivery,
+  /// Discard DTLS packets from a client whose address has not sent an authenticated STUN binding
+  /// request for this long, counting them in `ServerStats::stale_source_packets`.  Disabled by
+  /// default.
+  ///
+  /// Without this, once a client exists every packet from its address is handed to DTLS, so an
+  /// attacker spoofing that address can keep injecting records long after the real peer has gone.
+  /// Browsers renew consent (RFC 7675) with a binding request about every 5 seconds, so 30
+  /// seconds leaves plenty of room for lost requests.  Native clients must keep sending binding
+  /// requests for as long as they are connected, which `LoopbackClient` does not.
+  pub stun_validation_timeout: Option<Duration>,
+  /// Number of received messages and fragments held for each client paused with
+  /// `Server::pause`, beyond which they are dropped, or left unacknowledged on reliable channels,
+  /// defaults to 1024.
+  pub max_held_messages: usize,
+  /// Answer a client which shows up at a new port of the same IP address, with the ICE
+  /// credentials of a client already connected, with 401 Unauthorized rather than moving the
+  /// client to its new address, so that it has to negotiate a new session.  Defaults to true
+  /// under the `paranoid` feature.
+  ///
+  /// Otherwise such a client, most likely rebound by its NAT, keeps its connection and
+  /// `ServerEvent::ClientRebound` is produced.  Either way a binding request from another address
+  /// only counts as a rebind once it carries USE-CANDIDATE or the client's address has gone
+  /// quiet, and is otherwise answered as a check of another candidate pair.
+  pub reauthenticate_on_rebind: bool,
+  /// Move a client to a new IP address, not just a new port, when a binding request from there
+  /// carries its ICE credentials, as after the device switches networks, so that its DTLS and
+  /// SCTP association carries on.  False by default, as only ICE then stands between a client's
+  /// traffic and whoever learns its credentials.
+  ///
+  /// As with a new port, the client only moves once a request from the new address carries
+  /// USE-CANDIDATE or its current address has gone quiet, so that the checks a browser with
+  /// several network interfaces sends from each of its host candidates do not move it back and
+  /// forth.
+  pub migrate_across_ips: bool,
+  /// Number of outgoing packets queued for a single client beyond which sending it another
+  /// message fails with `SendError::Backpressure`, unlimited by default.
+  ///
+  /// Unlike `queue_overflow`, which silently drops packets once a queue is full, this lets the
+  /// application notice a client it is sending to faster than the socket takes its packets, and
+  /// skip or coalesce messages for it.
+  pub max_client_egress: Option<usize>,
+  /// Number of clients beyond which new ones are refused, unlimited by default.
+  ///
+  /// The STUN binding requests of refused clients go unanswered, so browsers keep retrying them
+  /// for a while and get in if a place frees up in time.  Refusals are counted in
+  /// `ServerStats::refused_clients`.
+  pub max_clients: Option<usize>,
+  /// Number of clients which have not completed their DTLS handshake and SCTP association
+  /// beyond which new ones are refused as with `max_clients`, unlimited by default.
+  ///
+  /// Each handshake costs the server a DTLS state and some asymmetric cryptography, so this
+  /// bounds what a flood of handshakes can take away from established clients.
+  pub max_pending_handshakes: Option<usize>,
+  /// Name the server in a SOFTWARE attribute of every STUN response, such as
+  /// `"unreliablertc 0.1"`, of which only the first 127 characters are sent.
+  pub stun_software: Option<String>,
+  /// Send 300 Try Alternate STUN error responses with an ALTERNATE-SERVER attribute naming this
+  /// address, so that clients turned away can be redirected to another server at the ICE layer.
+  pub stun_alternate_server: Option<SocketAddr>,
+  /// Number of clients beyond which new ones are answered with 300 Try Alternate, sending them
+  /// on to `stun_alternate_server`, unlimited by default and ignored unless
+  /// `stun_alternate_server` is set.
+  ///
+  /// This spreads clients over a fleet of servers which name each other as alternates without
+  /// any balancer in front of them.  Redirected clients are counted in
+  /// `ServerStats::redirected_clients`.
+  pub try_alternate_beyond: Option<usize>,
+  /// Send STUN success responses with a RESPONSE-ORIGIN attribute holding the public address of
+  /// the socket they are sent from, which lets clients check that the response came from the
+  /// address they sent their request to.
+  pub stun_response_origin: bool,
+}
+
+impl Default for ServerConfig {
+  fn default() -> ServerConfig {
+    ServerConfig {
+      stun_silent_unknown_users: cfg!(feature = "paranoid"),
+      stun_require_fingerprint: cfg!(feature = "paranoid"),
+      outgoing_queue_capacity: 1024,
+      client_egress_capacity: 64,
+      incoming_queue_capacity: 1024,
+      queue_overflow: OverflowPolicy::Grow,
+      dns_refresh_interval: Duration::from_secs(60),
+      driver_realtime_priority: None,
+      driver_cpu_affinity: None,
+      stun_token_key: None,
+      admission_rate: None,
+      subprotocols: Vec::new(),
+      negotiated_channels: Vec::new(),
+      clock_jump_policy: ClockJumpPolicy::Freeze,
+      stream_fragments: false,
+      max_partial_messages: 4,
+      max_partial_message_len: None,
+      max_reassembled_len: None,
+      max_fragment_len: None,
+      protocol_versions: Vec::new(),
+      clock_jump_threshold: Duration::from_secs(5),
+      stats_event_interval: None,
+      watchdog_timeout: None,
+      ffi_event_delivery: FfiEventDelivery::Direct,
+      stun_validation_timeout: None,
+      max_held_messages: 1024,
+      reauthenticate_on_rebind: cfg!(feature = "paranoid"),
+      migrate_across_ips: false,
+      max_client_egress: None,
+      max_clients: None,
+      max_pending_handshakes: None,
+      stun_software: None,
+      stun_alternate_server: None,
+      try_alternate_beyond: None,
+      stun_response_origin: false,
+    }
+  }
+}
+
+/// A data channel agreed on out of band, set with `ServerConfig::negotiated_channels`.
+///
+/// The browser creates its side with `createDataChannel(label, { negotiated: true, id })`, with
+/// the same `id` and options matching `delivery`, such as `ordered: false, maxRetransmits: 0` for
+/// `Delivery::Unreliable`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NegotiatedChannel {
+  /// The channel's id, which is the SCTP stream it is sent on.  Ids must be unique and below
+  /// 65535, or `Server::with_config` fails.
+  pub id: u16,
+  pub label: String,
+  /// How the server sends on the channel.
+  pub delivery: Delivery,
+}
+
+/// Configuration for a single `SessionEndpoint`, passed to `Server::session_endpoint_with_config`.
+#[derive(Clone)]
+pub struct SessionEndpointConfig {
+  pub(crate) answer_mutator: Option<AnswerMutator>,
+  pub(crate) max_offer_len: usize,
+  pub(crate) rate_limiter: Option<RateLimiter>,
+  pub(crate) stateless_tokens: Option<(TokenKey, Duration)>,
+  pub(crate) rng: Option<SharedRng>,
+  pub(crate) idempotent_offers: bool,
+  pub(crate) protocol_versions: Option<Arc<[String]>>,
+  pub(crate) candidate_filter: CandidateFilter,
+  pub(crate) answer_format: SdpAnswerFormat,
+  pub(crate) duplicate_ufrag: DuplicateUfragPolicy,
+  pub(crate) tcp_candidate_stub: bool,
+}
+
+impl Default for SessionEndpointConfig {
+  fn default() -> SessionEndpointConfig {
+    SessionEndpointConfig {
+      answer_mutator: None,
+      max_offer_len: 64 * 1024,
+      rate_limiter: None,
+      stateless_tokens: None,
+      rng: None,
+      idempotent_offers: false,
+      protocol_versions: None,
+      candidate_filter: CandidateFilter::default(),
+      answer_format: SdpAnswerFormat::default(),
+      duplicate_ufrag: DuplicateUfragPolicy::default(),
+      tcp_candidate_stub: false,
+    }
+  }
+}
+
+impl SessionEndpointConfig {
+  /// Adjust every generated SDP answer before it is returned from
+  /// `SessionEndpoint::session_request`.
+  ///
+  /// This allows adding, overriding or removing attributes of the answer (such as an `a=extmap`
+  /// or `a=tool` line) for client stacks which need something other than the default answer.  It
+  /// runs after the attributes of `SessionEndpointConfig::answer_format` are added.
+  pub fn answer_mutator(
+    mut self,
+    mutator: impl Fn(&mut SdpAnswer) + Send + Sync + 'static,
+  ) -> Self {
+    self.answer_mutator = Some(Arc::new(mutator));
+    self
+  }
+
+  /// Reject SDP offers longer than `len` bytes before parsing them, defaults to 64 KiB.
+  pub fn max_offer_len(mut self, len: usize) -> Self {
+    self.max_offer_len = len;
+    self
+  }
+
+  /// Consult `limiter` with the source address of every offer passed to
+  /// `SessionEndpoint::session_request_from`, rejecting the offer with
+  /// `SessionError::RateLimited` if it returns false.
+  ///
+  /// The limiter runs before any parsing, so it is the place to enforce per-source request rates
+  /// on a public signaling endpoint.
+  pub fn rate_limiter(mut self, limiter: impl Fn(IpAddr) -> bool + Send + Sync + 'static) -> Self {
+    self.rate_limiter = Some(Arc::new(limiter));
+    self
+  }
+
+  /// Answer offers with an HMAC-signed session token as the ICE username fragment instead of
+  /// registering a session with the server, so that no server state is needed until the
+  /// browser's first STUN request.
+  ///
+  /// Tokens expire `ttl` after the answer, and are accepted by any `Server` whose
+  /// `ServerConfig::stun_token_key` is the same `key`.  This is how an endpoint made with
+  /// `SessionEndpoint::standalone` answers offers.
+  pub fn stateless_tokens(mut self, key: TokenKey, ttl: Duration) -> Self {
+    self.stateless_tokens = Some((key, ttl));
+    self
+  }
+
+  /// Generate ICE credentials and SDP session ids with `rng` instead of the thread local RNG,
+  /// for example to use an RNG approved for a FIPS-constrained deployment.
+  ///
+  /// The RNG is shared by every clone of the endpoint, and locked for the duration of each
+  /// session request.
+  pub fn rng(mut self, rng: impl RngCore + Send + 'static) -> Self {
+    self.rng = Some(Arc::new(Mutex::new(rng)));
+    self
+  }
+
+  /// Generate ICE credentials and SDP session ids from a fixed `seed`, so that tests and
+  /// simulations get the same answers on every run.
+  ///
+  /// The generated credentials are entirely predictable, so this must never be used to accept
+  /// real clients.
+  pub fn seeded_rng(self, seed: u64) -> Self {
+    self.rng(StdRng::seed_from_u64(seed))
+  }
+
+  /// Answer an offer identical to one answered in the last 30 seconds with the same answer and
+  /// credentials, rather than starting a second session for the same browser.
+  ///
+  /// Offers are matched by their `a=ice-ufrag` and must otherwise be byte for byte the same, which
+  /// is the case when a browser's signaling request is retried over a flaky network.  Answers are
+  /// remembered by the endpoint this configures and all its clones.
+  pub fn idempotent_offers(mut self, enabled: bool) -> Self {
+    self.idempotent_offers = enabled;
+    self
+  }
+
+  /// Advertise these application protocol versions in answers, instead of the
+  /// `ServerConfig::protocol_versions` of the server the endpoint belongs to.
+  ///
+  /// This is mainly for endpoints made with `SessionEndpoint::standalone`, which have no server to
+  /// take them from, and should list the same versions as the servers they answer for.
+  pub fn protocol_versions(mut self, versions: Vec<String>) -> Self {
+    self.protocol_versions = Some(versions.into());
+    self
+  }
+
+  /// Ignore the candidates of offers matched by `filter`, so that they are left out of
+  /// `SessionInfo::remote_candidates`.
+  ///
+  /// When an offer lists candidates but none of them pass, the server produces a
+  /// `ServerEvent::NoUsableCandidates` for the session, which is likely to fail to connect.
+  pub fn candidate_filter(mut self, filter: CandidateFilter) -> Self {
+    self.candidate_filter = filter;
+    self
+  }
+
+  /// Describe the SCTP association in answers with the attributes of `format`, defaults to
+  /// `SdpAnswerFormat::Modern`.
+  pub fn answer_format(mut self, format: SdpAnswerFormat) -> Self {
+    self.answer_format = format;
+    self
+  }
+
+  /// Handle offers reusing the `a=ice-ufrag` of an earlier offer according to `policy`, defaults
+  /// to `DuplicateUfragPolicy::Allow`.
+  ///
+  /// Takes precedence over `SessionEndpointConfig::idempotent_offers` for offers which are not
+  /// byte for byte the same as the earlier one.
+  pub fn duplicate_ufrag_policy(mut self, policy: DuplicateUfragPolicy) -> Self {
+    self.duplicate_ufrag = policy;
+    self
+  }
+
+  /// Also list a passive TCP host candidate (RFC 6544) on the port of each public address in
+  /// answers, ranked below every UDP candidate.
+  ///
+  /// The server does not accept TCP, so this is only a diagnostic: together with the
+  /// `ServerEvent::TcpOnlyOffer` produced for offers whose candidates are all TCP, it shows how
+  /// many users would need a TCP or TURN fallback before deploying one.
+  pub fn tcp_candidate_stub(mut self, enabled: bool) -> Self {
+    self.tcp_candidate_stub = enabled;
+    self
+  }
+}
+
+/// What a `SessionEndpoint` does with an offer whose `a=ice-ufrag` is the same as that of an
+/// earlier one, set with `SessionEndpointConfig::duplicate_ufrag_policy`.
+///
+/// Browsers pick a new ufrag for every offer, so a duplicate is either a retried signaling
+/// request or an intercepted offer being replayed.  `Reject` and `KeepFirst` recognize the offers
+/// answered by the same endpoint and its clones in the last 30 seconds, while `Replace` applies
+/// to every session the server knows about.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateUfragPolicy {
+  /// Answer it with a session of its own, so that either session may connect.  This is the
+  /// default.
+  #[default]
+  Allow,
+  /// Fail it with `SessionError::DuplicateUfrag`, leaving the earlier session alone.
+  Reject,
+  /// Answer it with a new session which replaces the earlier ones on the server, producing a
+  /// `ServerEvent::SessionReplaced` for each.  Clients which have already connected are
+  /// unaffected.
+  Replace,
+  /// Answer it with the earlier answer, so that the earlier session is the only one.
+  KeepFirst,
+}
+
+/// Which of the ICE candidates offered by clients to ignore, set with
+/// `SessionEndpointConfig::candidate_filter`.  Nothing is ignored by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CandidateFilter {
+  /// Ignore candidates on link-local addresses, `169.254.0.0/16` and `fe80::/10`, which cannot
+  /// reach a server on another link.
+  pub skip_link_local: bool,
+  /// Ignore mDNS candidates, whose address is a `.local` name hiding a browser's host address.
+  /// The names are never resolved, as they only resolve on the browser's own link.
+  pub skip_mdns: bool,
+  /// Ignore relay candidates, for servers which are not meant to be reached through TURN.
+  pub skip_relay: bool,
+}
+
+impl CandidateFilter {
+  pub(crate) fn keeps(&self, candidate: &IceCandidate) -> bool {
+    if self.skip_relay && candidate.kind == CandidateKind::Relay {
+      return false;
+    }
+    match candidate.address.parse::<IpAddr>() {
+      Ok(IpAddr::V4(ip)) => !(self.skip_link_local && ip.is_link_local()),
+      Ok(IpAddr::V6(ip)) => !(self.skip_link_local && ip.segments()[0] & 0xffc0 == 0xfe80),
+      Err(_) => !(self.skip_mdns && candidate.address.to_ascii_lowercase().ends_with(".local")),
+    }
+  }
+}
@@ -0,0 +1,95 @@
+use std::convert::TryInto;
+
+use crc::{Crc, CRC_32_ISCSI};
+
+#[cfg(test)]
+mod vectors;
+
+// CRC32c (Castagnoli) as used by SCTP, computed with the CRC32 instructions of SSE 4.2 on x86-64
+// or of ARMv8 on AArch64 where the CPU has them, and with a lookup table elsewhere.  At high
+// packet rates the checksum over every SCTP packet is a measurable part of the server's CPU time,
+// and the instructions are several times faster than the table.
+pub struct Crc32c {
+  // The running CRC register, before the final inversion.
+  state: u32,
+}
+
+impl Crc32c {
+  pub fn new() -> Crc32c {
+    Crc32c { state: !0 }
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    self.state = update(self.state, data);
+  }
+
+  pub fn finalize(self) -> u32 {
+    !self.state
+  }
+}
+
+pub fn crc32c(data: &[u8]) -> u32 {
+  let mut digest = Crc32c::new();
+  digest.update(data);
+  digest.finalize()
+}
+
+// The CPU features are detected once and cached by the standard library, so checking them on
+// every call is only a load and a branch.
+fn update(state: u32, data: &[u8]) -> u32 {
+  #[cfg(target_arch = "x86_64")]
+  {
+    if is_x86_feature_detected!("sse4.2") {
+      return unsafe { update_sse42(state, data) };
+    }
+  }
+  #[cfg(target_arch = "aarch64")]
+  {
+    if std::arch::is_aarch64_feature_detected!("crc") {
+      return unsafe { update_armv8(state, data) };
+    }
+  }
+  update_table(state, data)
+}
+
+fn update_table(state: u32, data: &[u8]) -> u32 {
+  const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+  // The algorithm is reflected, so the crate takes its initial register bit reversed.
+  let mut digest = CRC.digest_with_initial(state.reverse_bits());
+  digest.update(data);
+  !digest.finalize()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn update_sse42(state: u32, data: &[u8]) -> u32 {
+  use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+  let mut words = data.chunks_exact(8);
+  let mut state = u64::from(state);
+  for word in &mut words {
+    state = _mm_crc32_u64(state, u64::from_le_bytes(word.try_into().unwrap()));
+  }
+  let mut state = state as u32;
+  for &byte in words.remainder() {
+    state = _mm_crc32_u8(state, byte);
+  }
+  state
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn update_armv8(state: u32, data: &[u8]) -> u32 {
+  use std::arch::aarch64::{__crc32cb, __crc32cd};
+
+  let mut words = data.chunks_exact(8);
+  let mut state = state;
+  for word in &mut words {
+    state = __crc32cd(state, u64::from_le_bytes(word.try_into().unwrap()));
+  }
+  for &byte in words.remainder() {
+    state = __crc32cb(state, byte);
+  }
+  state
+}
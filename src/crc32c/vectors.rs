@@ -0,0 +1,83 @@
+//! Checks of the accelerated CRC32c implementations against the test vectors of RFC 3720
+//! appendix B.4 and against the table implementation, plus a throughput comparison which is
+//! ignored by default:
+//!
+//! `cargo test --release crc32c -- --ignored --nocapture`
+
+use std::time::{Duration, Instant};
+
+use super::*;
+
+// RFC 3720 appendix B.4
+#[test]
+fn rfc3720_vectors() {
+  let ascending: Vec<u8> = (0..32).collect();
+  let descending: Vec<u8> = (0..32).rev().collect();
+  for update in [update_table, update] {
+    let checksum = |data: &[u8]| !update(!0, data);
+    assert_eq!(checksum(&[0; 32]), 0x8a9136aa);
+    assert_eq!(checksum(&[0xff; 32]), 0x62a8ab43);
+    assert_eq!(checksum(&ascending), 0x46dd794e);
+    assert_eq!(checksum(&descending), 0x113fdb5c);
+  }
+}
+
+#[test]
+fn matches_table_at_every_length_and_alignment() {
+  let data: Vec<u8> = (0..1500u32)
+    .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+    .collect();
+  for start in 0..8 {
+    for end in start..start + 300 {
+      let data = &data[start..end];
+      assert_eq!(
+        update(!0, data),
+        update_table(!0, data),
+        "{}..{}",
+        start,
+        end
+      );
+    }
+  }
+}
+
+#[test]
+fn incremental_updates_match_one_shot() {
+  let data: Vec<u8> = (0..200u8).collect();
+  for split in 0..data.len() {
+    let mut digest = Crc32c::new();
+    digest.update(&data[..split]);
+    digest.update(&data[split..]);
+    assert_eq!(digest.finalize(), crc32c(&data));
+  }
+}
+
+#[test]
+#[ignore]
+fn bench_crc32c() {
+  const ROUNDS: usize = 200_000;
+
+  // The size of a full SCTP packet as sent by the server.
+  let packet = vec![0x5a; 1200];
+  let time = |update: fn(u32, &[u8]) -> u32| -> Duration {
+    let start = Instant::now();
+    let mut state = !0;
+    for _ in 0..ROUNDS {
+      state = update(state, std::hint::black_box(&packet));
+    }
+    std::hint::black_box(state);
+    start.elapsed()
+  };
+
+  let table = time(update_table);
+  let accelerated = time(update);
+  let throughput = |elapsed: Duration| {
+    (packet.len() * ROUNDS) as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0 * 1024.0)
+  };
+  println!(
+    "table: {:.2} GiB/s, accelerated: {:.2} GiB/s ({:.1}x)",
+    throughput(table),
+    throughput(accelerated),
+    table.as_secs_f64() / accelerated.as_secs_f64()
+  );
+}
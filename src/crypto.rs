@@ -7,10 +7,14 @@ use openssl::{
   nid::Nid,
   pkey::{PKey, Private},
   rsa::Rsa,
-  ssl::{SslAcceptor, SslMethod, SslVerifyMode},
+  ssl::{SslAcceptor, SslMethod, SslVerifyMode, SslVersion},
   x509::{X509NameBuilder, X509},
 };
 
+use crate::dtls_floor::DTLS_FLOOR;
+
+// OpenSSL wipes private keys and DTLS session secrets itself when they are freed, so unlike ICE
+// credentials they need no help from the `paranoid` feature.
 pub struct Crypto {
   pub key: PKey<Private>,
   pub x509: X509,
@@ -72,10 +76,12 @@ impl Crypto {
 
     ssl_acceptor_builder.set_private_key(&key)?;
     ssl_acceptor_builder.set_certificate(&x509)?;
+    // The Mozilla intermediate profile still allows DTLS 1.0 and CBC cipher suites, which no
+    // browser needs.
+    ssl_acceptor_builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+    ssl_acceptor_builder.set_cipher_list(DTLS_FLOOR.cipher_list)?;
     ssl_acceptor_builder
-      .set_tlsext_use_srtp(
-        "SRTP_AES128_CM_SHA1_80:SRTP_AES128_CM_SHA1_32:SRTP_AEAD_AES_128_GCM:SRTP_AEAD_AES_256_GCM",
-      )
+      .set_tlsext_use_srtp(DTLS_FLOOR.srtp_profiles)
       .unwrap();
     let ssl_acceptor = ssl_acceptor_builder.build();
 
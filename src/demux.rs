@@ -0,0 +1,27 @@
+/// What a received datagram is, going by its first byte as in RFC 7983, with the QUIC ranges of
+/// RFC 9443.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PacketClass {
+  Stun,
+  Zrtp,
+  Dtls,
+  /// QUIC, which takes the range RFC 7983 gives to TURN channels since the server never uses
+  /// TURN.
+  Quic,
+  Rtp,
+  /// Empty datagrams and those starting with an unassigned byte.
+  Unknown,
+}
+
+impl PacketClass {
+  pub fn of(packet: &[u8]) -> PacketClass {
+    match packet.first() {
+      Some(0..=3) => PacketClass::Stun,
+      Some(16..=19) => PacketClass::Zrtp,
+      Some(20..=63) => PacketClass::Dtls,
+      Some(64..=127) | Some(192..=255) => PacketClass::Quic,
+      Some(128..=191) => PacketClass::Rtp,
+      _ => PacketClass::Unknown,
+    }
+  }
+}
@@ -0,0 +1,561 @@
+use std::{
+  collections::VecDeque,
+  error::Error,
+  fmt,
+  future::Future,
+  io::{Error as IoError, ErrorKind as IoErrorKind},
+  net::SocketAddr,
+  pin::Pin,
+  task::{Context, Poll},
+  thread::{self, JoinHandle},
+  time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures_core::{ready, Stream};
+use futures_util::{pin_mut, select, FutureExt, Sink};
+
+use crate::{
+  client::MessageType,
+  event::ServerEvent,
+  server::{wait_connected, OwnedMessage, SendError, Server},
+  thread_tuning::tune_current_thread,
+};
+
+// Messages and events are dropped rather than stalling the driver thread when the application
+// falls this far behind.
+const DRIVER_CHANNEL_CAPACITY: usize = 1024;
+
+// Number of messages a `MessageSink` may have sent without having heard back from the server.
+const MAX_SINK_IN_FLIGHT: usize = 64;
+
+/// A message received by a `Server` running on a driver thread.
+#[derive(Clone, Debug)]
+pub struct ReceivedMessage {
+  pub message: Vec<u8>,
+  pub message_type: MessageType,
+  /// Tag of the application subprotocol of the data channel the message arrived on, see
+  /// `ServerConfig::subprotocols`.
+  pub subprotocol: Option<u8>,
+  pub remote_addr: SocketAddr,
+}
+
+impl From<OwnedMessage> for ReceivedMessage {
+  fn from(message: OwnedMessage) -> ReceivedMessage {
+    ReceivedMessage {
+      message_type: message.message_type,
+      subprotocol: message.subprotocol,
+      remote_addr: message.remote_addr,
+      message: message.into_vec(),
+    }
+  }
+}
+
+/// Error returned by the driver handles once the driver thread has stopped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DriverStopped;
+
+impl fmt::Display for DriverStopped {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    write!(f, "server driver thread has stopped")
+  }
+}
+
+impl Error for DriverStopped {}
+
+impl From<DriverStopped> for IoError {
+  fn from(err: DriverStopped) -> IoError {
+    IoError::new(IoErrorKind::NotConnected, err)
+  }
+}
+
+enum Command {
+  Send(Vec<u8>, MessageType, SocketAddr),
+  SendReporting(
+    Vec<u8>,
+    MessageType,
+    SocketAddr,
+    flume::Sender<Result<(), SendError>>,
+  ),
+  DisconnectReporting(SocketAddr, flume::Sender<Result<(), IoError>>),
+  WaitConnected(SocketAddr, flume::Sender<()>),
+  SendAt(Vec<u8>, MessageType, SocketAddr, Instant),
+  Disconnect(SocketAddr),
+  ExpireSession(String),
+  Shutdown(Option<String>),
+}
+
+/// Queues outgoing messages for a `Server` running on a driver thread.
+///
+/// Sending does not wait for the message to be written, messages to clients which are not
+/// connected are silently dropped.
+#[derive(Clone)]
+pub struct SenderHandle(flume::Sender<Command>);
+
+impl SenderHandle {
+  pub fn send(
+    &self,
+    message: impl Into<Vec<u8>>,
+    message_type: MessageType,
+    remote_addr: SocketAddr,
+  ) -> Result<(), DriverStopped> {
+    self
+      .0
+      .send(Command::Send(message.into(), message_type, remote_addr))
+      .map_err(|_| DriverStopped)
+  }
+
+  /// Queue a message to be sent at `at`, as with `Server::send_at`.
+  pub fn send_at(
+    &self,
+    message: impl Into<Vec<u8>>,
+    message_type: MessageType,
+    remote_addr: SocketAddr,
+    at: Instant,
+  ) -> Result<(), DriverStopped> {
+    self
+      .0
+      .send(Command::SendAt(
+        message.into(),
+        message_type,
+        remote_addr,
+        at,
+      ))
+      .map_err(|_| DriverStopped)
+  }
+}
+
+/// Messages received by a `Server` running on a driver thread, iterating blocks until the next
+/// message arrives and ends once the driver has stopped.
+pub struct ReceiverStream(flume::Receiver<OwnedMessage>);
+
+impl ReceiverStream {
+  /// Take the next received message without blocking, if there is one.
+  pub fn try_recv(&self) -> Option<ReceivedMessage> {
+    self.0.try_recv().ok().map(ReceivedMessage::from)
+  }
+}
+
+impl Iterator for ReceiverStream {
+  type Item = ReceivedMessage;
+
+  fn next(&mut self) -> Option<ReceivedMessage> {
+    self.0.recv().ok().map(ReceivedMessage::from)
+  }
+}
+
+/// `ServerEvent`s produced by a `Server` running on a driver thread or passed to a subscriber
+/// added with `Server::subscribe`, iterating blocks until the next event arrives and ends once
+/// the server is gone.
+pub struct EventStream(pub(crate) flume::Receiver<ServerEvent>);
+
+impl EventStream {
+  /// Take the next event without blocking, if there is one.
+  pub fn try_recv(&self) -> Option<ServerEvent> {
+    self.0.try_recv().ok()
+  }
+
+  /// Wait for the next event, returning `None` once the server is gone.
+  pub async fn recv(&self) -> Option<ServerEvent> {
+    self.0.recv_async().await.ok()
+  }
+}
+
+impl Iterator for EventStream {
+  type Item = ServerEvent;
+
+  fn next(&mut self) -> Option<ServerEvent> {
+    self.0.recv().ok()
+  }
+}
+
+/// Controls a `Server` running on a driver thread.
+///
+/// The driver keeps running until `ControlHandle::shutdown` is called, or until this and every
+/// `SenderHandle` have been dropped.
+pub struct ControlHandle {
+  commands: flume::Sender<Command>,
+  thread: JoinHandle<()>,
+}
+
+impl ControlHandle {
+  /// Disconnect the given client, does nothing if the client is not currently connected.
+  pub fn disconnect(&self, remote_addr: SocketAddr) -> Result<(), DriverStopped> {
+    self
+      .commands
+      .send(Command::Disconnect(remote_addr))
+      .map_err(|_| DriverStopped)
+  }
+
+  /// Expire a pending session, as with `Server::expire_session`.
+  pub fn expire_session(&self, id: impl Into<String>) -> Result<(), DriverStopped> {
+    self
+      .commands
+      .send(Command::ExpireSession(id.into()))
+      .map_err(|_| DriverStopped)
+  }
+
+  /// Shut the server down as with `Server::shutdown` and wait for the driver thread to exit.
+  pub fn shutdown(self) {
+    self.stop(None);
+  }
+
+  /// Shut the server down as with `Server::shutdown_with_reason` and wait for the driver thread
+  /// to exit.
+  pub fn shutdown_with_reason(self, reason: impl Into<String>) {
+    self.stop(Some(reason.into()));
+  }
+
+  fn stop(self, reason: Option<String>) {
+    let _ = self.commands.send(Command::Shutdown(reason));
+    let _ = self.thread.join();
+  }
+}
+
+/// A handle to a `Server` driven by the future returned from `Server::run`, through which any
+/// number of tasks may send and receive concurrently.
+///
+/// Handles are cheap to clone.  Every received message goes to exactly one of the handles waiting
+/// in `ServerHandle::recv`, so several tasks receiving share the work rather than each seeing
+/// every message.  Once the server is shut down, or every handle has been dropped, the future
+/// driving it completes.
+#[derive(Clone)]
+pub struct ServerHandle {
+  commands: flume::Sender<Command>,
+  messages: flume::Receiver<OwnedMessage>,
+}
+
+impl ServerHandle {
+  /// Send a message as with `Server::send`, waiting until the server has written it.
+  ///
+  /// Fails with `SendError::Io` once the server has stopped.
+  pub async fn send(
+    &self,
+    message: impl Into<Vec<u8>>,
+    message_type: MessageType,
+    remote_addr: SocketAddr,
+  ) -> Result<(), SendError> {
+    let (reply, result) = flume::bounded(1);
+    self
+      .commands
+      .send(Command::SendReporting(
+        message.into(),
+        message_type,
+        remote_addr,
+        reply,
+      ))
+      .map_err(|_| SendError::Io(DriverStopped.into()))?;
+    result
+      .recv_async()
+      .await
+      .unwrap_or_else(|_| Err(SendError::Io(DriverStopped.into())))
+  }
+
+  /// Receive the next message from any client, as with `Server::recv`.
+  ///
+  /// Messages are dropped rather than stalling the server when 1024 of them are waiting to be
+  /// received.
+  pub async fn recv(&self) -> Result<ReceivedMessage, DriverStopped> {
+    self
+      .messages
+      .recv_async()
+      .await
+      .map(ReceivedMessage::from)
+      .map_err(|_| DriverStopped)
+  }
+
+  /// Disconnect the given client as with `Server::disconnect`, waiting until the server has
+  /// started closing the connection.
+  pub async fn disconnect(&self, remote_addr: SocketAddr) -> Result<(), IoError> {
+    let (reply, result) = flume::bounded(1);
+    self
+      .commands
+      .send(Command::DisconnectReporting(remote_addr, reply))
+      .map_err(|_| DriverStopped)?;
+    result
+      .recv_async()
+      .await
+      .unwrap_or_else(|_| Err(DriverStopped.into()))
+  }
+
+  /// Wait until the given client is established, as with `Server::wait_connected`, returning
+  /// false if `timeout` passes first or the server stops.
+  pub async fn wait_connected(&self, remote_addr: SocketAddr, timeout: Duration) -> bool {
+    let (sender, receiver) = flume::bounded(1);
+    if self
+      .commands
+      .send(Command::WaitConnected(remote_addr, sender))
+      .is_err()
+    {
+      return false;
+    }
+    wait_connected(receiver, timeout).await
+  }
+
+  /// Shut the server down as with `Server::shutdown`, after which the future driving it
+  /// completes.
+  pub fn shutdown(&self) -> Result<(), DriverStopped> {
+    self
+      .commands
+      .send(Command::Shutdown(None))
+      .map_err(|_| DriverStopped)
+  }
+
+  /// Received messages as a `Stream` of each message along with the client it came from, for use
+  /// with `StreamExt` combinators.
+  pub fn stream(&self) -> MessageStream {
+    MessageStream(self.messages.clone().into_stream())
+  }
+
+  /// A `Sink` sending messages as with `ServerHandle::send`, for use with `SinkExt` combinators.
+  pub fn sink(&self) -> MessageSink {
+    MessageSink {
+      commands: self.commands.clone(),
+      sending: VecDeque::new(),
+    }
+  }
+
+  /// Split the handle into a sending and a receiving half, like other async network types.
+  pub fn split(self) -> (MessageSink, MessageStream) {
+    (self.sink(), self.stream())
+  }
+}
+
+/// Messages received by a `Server` driven by the future returned from `Server::run`, returned by
+/// `ServerHandle::stream`, which ends once the server has stopped.
+///
+/// As with `ServerHandle::recv`, every message goes to only one of the streams and handles
+/// receiving from the same server.  Messages keep the server's buffers, as with
+/// `Server::recv_owned`.
+pub struct MessageStream(flume::r#async::RecvStream<'static, OwnedMessage>);
+
+impl Stream for MessageStream {
+  type Item = (OwnedMessage, SocketAddr);
+
+  fn poll_next(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Option<(OwnedMessage, SocketAddr)>> {
+    let message = ready!(Pin::new(&mut self.0).poll_next(cx));
+    Poll::Ready(message.map(|message| {
+      let remote_addr = message.remote_addr;
+      (message, remote_addr)
+    }))
+  }
+}
+
+/// Sends messages to the clients of a `Server` driven by the future returned from `Server::run`,
+/// returned by `ServerHandle::sink`.
+///
+/// Up to 64 messages may be on their way to the server at once, beyond which `poll_ready` waits
+/// until the server has sent the oldest.  A message which could not be sent is reported by the
+/// next `poll_ready`, `poll_flush` or `poll_close` with its `SendError`, and the sink may still be
+/// used afterwards.
+pub struct MessageSink {
+  commands: flume::Sender<Command>,
+  // The replies to the messages being sent, in the order they were sent.
+  sending: VecDeque<flume::r#async::RecvFut<'static, Result<(), SendError>>>,
+}
+
+impl MessageSink {
+  // Take the replies which have arrived, oldest first, returning the first error among them, and
+  // wait until no more than `in_flight` messages are left without one.
+  fn poll_replies(&mut self, cx: &mut Context, in_flight: usize) -> Poll<Result<(), SendError>> {
+    while let Some(sending) = self.sending.front_mut() {
+      let result = match Pin::new(sending).poll(cx) {
+        Poll::Ready(result) => result,
+        Poll::Pending if self.sending.len() <= in_flight => break,
+        Poll::Pending => return Poll::Pending,
+      };
+      self.sending.pop_front();
+      if let Err(err) = result.unwrap_or_else(|_| Err(SendError::Io(DriverStopped.into()))) {
+        return Poll::Ready(Err(err));
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl Sink<(Bytes, MessageType, SocketAddr)> for MessageSink {
+  type Error = SendError;
+
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), SendError>> {
+    self.get_mut().poll_replies(cx, MAX_SINK_IN_FLIGHT - 1)
+  }
+
+  fn start_send(
+    mut self: Pin<&mut Self>,
+    (message, message_type, remote_addr): (Bytes, MessageType, SocketAddr),
+  ) -> Result<(), SendError> {
+    let (reply, result) = flume::bounded(1);
+    self
+      .commands
+      .send(Command::SendReporting(
+        message.into(),
+        message_type,
+        remote_addr,
+        reply,
+      ))
+      .map_err(|_| SendError::Io(DriverStopped.into()))?;
+    self.sending.push_back(result.into_recv_async());
+    Ok(())
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), SendError>> {
+    self.get_mut().poll_replies(cx, 0)
+  }
+
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), SendError>> {
+    self.get_mut().poll_replies(cx, 0)
+  }
+}
+
+pub fn run(server: Server) -> (ServerHandle, impl Future<Output = ()> + Send) {
+  let (command_sender, command_receiver) = flume::unbounded();
+  let (message_sender, message_receiver) = flume::bounded(DRIVER_CHANNEL_CAPACITY);
+  let handle = ServerHandle {
+    commands: command_sender,
+    messages: message_receiver,
+  };
+  (
+    handle,
+    drive(server, command_receiver, Some(message_sender), None),
+  )
+}
+
+pub fn spawn_driver(
+  server: Server,
+) -> Result<(SenderHandle, ReceiverStream, EventStream, ControlHandle), IoError> {
+  let (message_sender, message_receiver) = flume::bounded(DRIVER_CHANNEL_CAPACITY);
+  let (sender, events, control) = spawn(server, Some(message_sender))?;
+  Ok((sender, ReceiverStream(message_receiver), events, control))
+}
+
+pub fn spawn_headless(
+  server: Server,
+) -> Result<(SenderHandle, EventStream, ControlHandle), IoError> {
+  spawn(server, None)
+}
+
+// Start the driver thread, passing received messages on to `messages` or discarding them if
+// there is nobody to receive them.
+fn spawn(
+  server: Server,
+  messages: Option<flume::Sender<OwnedMessage>>,
+) -> Result<(SenderHandle, EventStream, ControlHandle), IoError> {
+  let (command_sender, command_receiver) = flume::unbounded();
+  let (event_sender, event_receiver) = flume::bounded(DRIVER_CHANNEL_CAPACITY);
+  let (tuned_sender, tuned_receiver) = flume::bounded(1);
+
+  let thread = thread::Builder::new()
+    .name("unreliablertc-driver".to_owned())
+    .spawn(move || {
+      let config = server.config();
+      let tuned = tune_current_thread(
+        config.driver_realtime_priority,
+        config.driver_cpu_affinity.as_deref(),
+      );
+      let failed = tuned.is_err();
+      let _ = tuned_sender.send(tuned);
+      if failed {
+        return;
+      }
+
+      async_io::block_on(drive(
+        server,
+        command_receiver,
+        messages,
+        Some(event_sender),
+      ))
+    })?;
+
+  // Scheduling options which cannot be applied fail the whole spawn, rather than leaving the
+  // server running without the latency guarantees it was configured for.
+  if let Err(err) = tuned_receiver
+    .recv()
+    .expect("driver thread reports its tuning")
+  {
+    let _ = thread.join();
+    return Err(err);
+  }
+
+  Ok((
+    SenderHandle(command_sender.clone()),
+    EventStream(event_receiver),
+    ControlHandle {
+      commands: command_sender,
+      thread,
+    },
+  ))
+}
+
+// Drive `server` until it is shut down, passing its events on to `events` or discarding them if
+// there is nobody to receive them.
+async fn drive(
+  mut server: Server,
+  commands: flume::Receiver<Command>,
+  messages: Option<flume::Sender<OwnedMessage>>,
+  events: Option<flume::Sender<ServerEvent>>,
+) {
+  let forward_events = |server: &mut Server| {
+    while let Some(event) = server.poll_event() {
+      if let Some(events) = &events {
+        let _ = events.try_send(event);
+      }
+    }
+  };
+
+  loop {
+    let command = {
+      let recv = server.recv_owned().fuse();
+      pin_mut!(recv);
+      select! {
+        received = recv => {
+          if let (Ok(received), Some(messages)) = (received, &messages) {
+            let _ = messages.try_send(received);
+          }
+          None
+        }
+        command = commands.recv_async().fuse() => {
+          // Every handle has been dropped, so nobody can talk to the server anymore.
+          Some(command.unwrap_or(Command::Shutdown(None)))
+        }
+      }
+    };
+
+    match command {
+      Some(Command::Send(message, message_type, remote_addr)) => {
+        let _ = server.send(&message, message_type, &remote_addr).await;
+      }
+      Some(Command::SendReporting(message, message_type, remote_addr, reply)) => {
+        let _ = reply.send(server.send(&message, message_type, &remote_addr).await);
+      }
+      Some(Command::DisconnectReporting(remote_addr, reply)) => {
+        let _ = reply.send(server.disconnect(&remote_addr).await);
+      }
+      Some(Command::WaitConnected(remote_addr, connected)) => {
+        server.add_connect_waiter(remote_addr, connected);
+      }
+      Some(Command::SendAt(message, message_type, remote_addr, at)) => {
+        let _ = server.send_at(&message, message_type, &remote_addr, at);
+      }
+      Some(Command::Disconnect(remote_addr)) => {
+        let _ = server.disconnect(&remote_addr).await;
+      }
+      Some(Command::ExpireSession(id)) => {
+        server.expire_session(&id);
+      }
+      Some(Command::Shutdown(reason)) => {
+        match reason {
+          Some(reason) => server.shutdown_with_reason(&reason),
+          None => server.shutdown(),
+        }
+        forward_events(&mut server);
+        return;
+      }
+      None => {}
+    }
+
+    forward_events(&mut server);
+  }
+}
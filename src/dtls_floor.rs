@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// The weakest DTLS a `Server` accepts, returned by `Server::dtls_floor`.
+///
+/// Clients are held to it twice over: the DTLS stack is configured with it, and ClientHellos
+/// asking for anything weaker are refused before the DTLS stack sees them, with
+/// `ServerEvent::DowngradeRefused`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DtlsFloor {
+  /// Oldest protocol version accepted, `"DTLSv1.2"`.
+  pub min_version: &'static str,
+  /// Cipher suites accepted, in OpenSSL's cipher list format.  All of them have forward secrecy
+  /// and authenticated encryption.
+  pub cipher_list: &'static str,
+  /// SRTP protection profiles accepted in the use_srtp extension, in OpenSSL's format.
+  pub srtp_profiles: &'static str,
+}
+
+pub const DTLS_FLOOR: DtlsFloor = DtlsFloor {
+  min_version: "DTLSv1.2",
+  cipher_list: "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+                ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:\
+                ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-CHACHA20-POLY1305",
+  srtp_profiles: "SRTP_AEAD_AES_128_GCM:SRTP_AEAD_AES_256_GCM:SRTP_AES128_CM_SHA1_80",
+};
+
+// IANA numbers of the cipher suites in `DTLS_FLOOR.cipher_list`.
+const STRONG_CIPHER_SUITES: [u16; 6] = [0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8];
+
+// DTLS versions count down, 1.0 is 0xfeff and 1.2 is 0xfefd.
+const DTLS_1_2: u16 = 0xfefd;
+
+/// Why a ClientHello was refused, part of `ServerEvent::DowngradeRefused`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DowngradeReason {
+  /// The client asked for a protocol version older than DTLS 1.2, given as sent on the wire,
+  /// such as `0xfeff` for DTLS 1.0.
+  ObsoleteVersion(u16),
+  /// None of the cipher suites the client offered is in `DtlsFloor::cipher_list`.
+  NoStrongCipherSuite,
+}
+
+impl fmt::Display for DowngradeReason {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DowngradeReason::ObsoleteVersion(version) => {
+        write!(f, "obsolete DTLS version {:#06x}", version)
+      }
+      DowngradeReason::NoStrongCipherSuite => f.write_str("no strong cipher suite offered"),
+    }
+  }
+}
+
+// Look for a ClientHello in a datagram and check it against `DTLS_FLOOR`.  Anything which is not
+// a ClientHello, or is too short to tell, passes and is left to the DTLS stack.
+pub fn check_client_hello(mut datagram: &[u8]) -> Result<(), DowngradeReason> {
+  const RECORD_HEADER_LEN: usize = 13;
+  const HANDSHAKE_HEADER_LEN: usize = 12;
+
+  while datagram.len() >= RECORD_HEADER_LEN {
+    let content_type = datagram[0];
+    let epoch = u16::from_be_bytes([datagram[3], datagram[4]]);
+    let len = usize::from(u16::from_be_bytes([datagram[11], datagram[12]]));
+    let record = &datagram[RECORD_HEADER_LEN..(RECORD_HEADER_LEN + len).min(datagram.len())];
+    datagram = &datagram[(RECORD_HEADER_LEN + len).min(datagram.len())..];
+
+    // Only the first fragment of a ClientHello holds its version and cipher suites.
+    if content_type != 22
+      || epoch != 0
+      || record.len() < HANDSHAKE_HEADER_LEN + 2
+      || record[0] != 1
+      || record[6..9] != [0, 0, 0]
+    {
+      continue;
+    }
+    let body = &record[HANDSHAKE_HEADER_LEN..];
+    let version = u16::from_be_bytes([body[0], body[1]]);
+    if body[0] != 0xfe || version > DTLS_1_2 {
+      return Err(DowngradeReason::ObsoleteVersion(version));
+    }
+
+    let suites = match offered_cipher_suites(body) {
+      Some(suites) => suites,
+      None => continue,
+    };
+    let strong = suites
+      .chunks_exact(2)
+      .any(|suite| STRONG_CIPHER_SUITES.contains(&u16::from_be_bytes([suite[0], suite[1]])));
+    if !strong {
+      return Err(DowngradeReason::NoStrongCipherSuite);
+    }
+  }
+  Ok(())
+}
+
+// The cipher suite list of a ClientHello body, if the fragment reaches that far.
+fn offered_cipher_suites(body: &[u8]) -> Option<&[u8]> {
+  const RANDOM_LEN: usize = 32;
+
+  // The session id and the cookie come first, each after a length byte.
+  let mut rest = body.get(2 + RANDOM_LEN..)?;
+  for _ in 0..2 {
+    rest = rest.get(1 + usize::from(*rest.first()?)..)?;
+  }
+  let suites_len = usize::from(u16::from_be_bytes([*rest.first()?, *rest.get(1)?]));
+  rest.get(2..2 + suites_len)
+}
+
+#[cfg(test)]
+mod client_hellos;
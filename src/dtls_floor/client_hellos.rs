@@ -0,0 +1,83 @@
+//! ClientHellos generated by OpenSSL as a DTLS client, checked against `DTLS_FLOOR`.
+
+use atone::Vc as VecDeque;
+use openssl::ssl::{HandshakeError, Ssl, SslContext, SslContextBuilder, SslMethod, SslVersion};
+
+use super::*;
+use crate::{buffer_pool::BufferPool, client::ClientSslPackets};
+
+// The first datagram of a handshake from a client configured by `configure`.
+fn client_hello(configure: impl FnOnce(&mut SslContextBuilder)) -> Vec<u8> {
+  let buffer_pool = BufferPool::new();
+  let mut context = SslContext::builder(SslMethod::dtls()).unwrap();
+  configure(&mut context);
+  let ssl = Ssl::new(&context.build()).unwrap();
+  let packets = ClientSslPackets {
+    buffer_pool: buffer_pool.clone(),
+    incoming_udp: VecDeque::new(),
+    outgoing_udp: VecDeque::new(),
+  };
+  match ssl.connect(packets) {
+    Err(HandshakeError::WouldBlock(mut mid_handshake)) => {
+      let datagram = mid_handshake.get_mut().outgoing_udp.pop_front().unwrap();
+      buffer_pool.adopt(datagram).to_vec()
+    }
+    _ => panic!("handshake did not wait for the server"),
+  }
+}
+
+#[test]
+fn default_client_passes() {
+  assert_eq!(check_client_hello(&client_hello(|_| {})), Ok(()));
+}
+
+#[test]
+fn dtls_1_0_is_refused() {
+  let hello = client_hello(|context| {
+    context.set_security_level(0);
+    context
+      .set_max_proto_version(Some(SslVersion::DTLS1))
+      .unwrap();
+  });
+  assert_eq!(
+    check_client_hello(&hello),
+    Err(DowngradeReason::ObsoleteVersion(0xfeff))
+  );
+}
+
+#[test]
+fn weak_cipher_suites_are_refused() {
+  let hello = client_hello(|context| {
+    context.set_cipher_list("AES128-SHA:AES256-SHA").unwrap();
+  });
+  assert_eq!(
+    check_client_hello(&hello),
+    Err(DowngradeReason::NoStrongCipherSuite)
+  );
+}
+
+#[test]
+fn one_strong_cipher_suite_passes() {
+  let hello = client_hello(|context| {
+    context
+      .set_cipher_list("AES128-SHA:ECDHE-RSA-CHACHA20-POLY1305")
+      .unwrap();
+  });
+  assert_eq!(check_client_hello(&hello), Ok(()));
+}
+
+#[test]
+fn truncated_hello_passes() {
+  let hello = client_hello(|context| {
+    context.set_cipher_list("AES128-SHA").unwrap();
+  });
+  // Cut off inside the cipher suites, which cannot be judged.
+  let record_len = 13 + 12 + 2 + 32 + 1;
+  assert_eq!(check_client_hello(&hello[..record_len + 4]), Ok(()));
+}
+
+#[test]
+fn other_packets_pass() {
+  assert_eq!(check_client_hello(&[]), Ok(()));
+  assert_eq!(check_client_hello(&[23, 0xfe, 0xfd, 0, 1]), Ok(()));
+}
@@ -0,0 +1,213 @@
+use std::{fmt, io::Error as IoError};
+
+use openssl::error::ErrorStack as OpenSslErrorStack;
+
+use crate::{
+  client::ClientError,
+  sctp::{SctpReadError, SctpWriteError},
+  sdp::OfferDecodeError,
+  server::{SendError, SessionError},
+};
+
+/// What kind of error happened, with a stable numeric value, so that errors can be told apart
+/// without parsing their messages.
+///
+/// The same codes appear in `ServerEvent::ClientError`, in the inspector's list of recent errors,
+/// and as the event code of error events passed to the FFI event callback, which never uses codes
+/// from 1000 up for errors:
+///
+/// | FFI event code | meaning                                                      |
+/// |----------------|--------------------------------------------------------------|
+/// | 0..=999        | an error, the `ErrorCode` with that value and its message    |
+/// | 1001           | a client opened a data channel, with its `ip:port`           |
+/// | 1002           | a client the server had not closed was removed, `ip:port`    |
+/// | 1003           | the server closed a client's connection, with `ip:port`      |
+///
+/// Every error type of this crate converts into an `ErrorCode` with `From`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(u32)]
+pub enum ErrorCode {
+  /// An error none of the other codes describe.
+  Other = 0,
+  /// The client is not connected, or its data channel is not established yet.
+  ClientNotConnected = 1,
+  /// The message is longer than the client accepts.
+  MessageTooLong = 2,
+  /// Too much is already waiting to be sent to or acknowledged by the client.
+  Backpressure = 3,
+  /// The client has no open data channel with the given id.
+  UnknownChannel = 4,
+  /// Reading from or writing to a socket failed.
+  Io = 10,
+  /// Starting, running or reading the DTLS session failed.
+  Dtls = 11,
+  /// An SCTP packet could not be read or written.
+  Sctp = 12,
+  /// The client's first message named none of `ServerConfig::protocol_versions`.
+  UnsupportedProtocolVersion = 13,
+  /// A message could not be passed to the server the client is relayed to.
+  Relay = 14,
+  /// The `SessionEndpoint` has been disconnected from its `Server`.
+  SessionDisconnected = 20,
+  /// The offer could not be parsed or decoded.
+  InvalidOffer = 21,
+  /// The offer was longer than the endpoint allows.
+  OfferTooLarge = 22,
+  /// The session request was rate limited.
+  RateLimited = 23,
+  /// The server or the endpoint accepts no new sessions.
+  NotAccepting = 24,
+  /// The offer reused the ice-ufrag of a recent offer.
+  DuplicateUfrag = 25,
+}
+
+impl ErrorCode {
+  /// The stable numeric value of the code.
+  pub fn code(self) -> u32 {
+    self as u32
+  }
+
+  /// The code with the given numeric value, if there is one.
+  pub fn from_code(code: u32) -> Option<ErrorCode> {
+    Some(match code {
+      0 => ErrorCode::Other,
+      1 => ErrorCode::ClientNotConnected,
+      2 => ErrorCode::MessageTooLong,
+      3 => ErrorCode::Backpressure,
+      4 => ErrorCode::UnknownChannel,
+      10 => ErrorCode::Io,
+      11 => ErrorCode::Dtls,
+      12 => ErrorCode::Sctp,
+      13 => ErrorCode::UnsupportedProtocolVersion,
+      14 => ErrorCode::Relay,
+      20 => ErrorCode::SessionDisconnected,
+      21 => ErrorCode::InvalidOffer,
+      22 => ErrorCode::OfferTooLarge,
+      23 => ErrorCode::RateLimited,
+      24 => ErrorCode::NotAccepting,
+      25 => ErrorCode::DuplicateUfrag,
+      _ => return None,
+    })
+  }
+
+  /// The code's name in snake case, such as `"client_not_connected"`, as listed by the inspector.
+  pub fn name(self) -> &'static str {
+    match self {
+      ErrorCode::Other => "other",
+      ErrorCode::ClientNotConnected => "client_not_connected",
+      ErrorCode::MessageTooLong => "message_too_long",
+      ErrorCode::Backpressure => "backpressure",
+      ErrorCode::UnknownChannel => "unknown_channel",
+      ErrorCode::Io => "io",
+      ErrorCode::Dtls => "dtls",
+      ErrorCode::Sctp => "sctp",
+      ErrorCode::UnsupportedProtocolVersion => "unsupported_protocol_version",
+      ErrorCode::Relay => "relay",
+      ErrorCode::SessionDisconnected => "session_disconnected",
+      ErrorCode::InvalidOffer => "invalid_offer",
+      ErrorCode::OfferTooLarge => "offer_too_large",
+      ErrorCode::RateLimited => "rate_limited",
+      ErrorCode::NotAccepting => "not_accepting",
+      ErrorCode::DuplicateUfrag => "duplicate_ufrag",
+    }
+  }
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    f.write_str(self.name())
+  }
+}
+
+impl From<&SendError> for ErrorCode {
+  fn from(err: &SendError) -> ErrorCode {
+    match err {
+      SendError::ClientNotConnected => ErrorCode::ClientNotConnected,
+      SendError::IncompleteMessageWrite => ErrorCode::MessageTooLong,
+      SendError::ClientError(_) => ErrorCode::Dtls,
+      SendError::Io(_) => ErrorCode::Io,
+      SendError::Backpressure => ErrorCode::Backpressure,
+      SendError::UnknownChannel(_) => ErrorCode::UnknownChannel,
+    }
+  }
+}
+
+impl From<&SessionError> for ErrorCode {
+  fn from(err: &SessionError) -> ErrorCode {
+    match err {
+      SessionError::Disconnected => ErrorCode::SessionDisconnected,
+      SessionError::ParseError(_) | SessionError::Decode(_) => ErrorCode::InvalidOffer,
+      SessionError::OfferTooLarge => ErrorCode::OfferTooLarge,
+      SessionError::RateLimited => ErrorCode::RateLimited,
+      SessionError::Draining | SessionError::NotAccepting => ErrorCode::NotAccepting,
+      SessionError::DuplicateUfrag => ErrorCode::DuplicateUfrag,
+    }
+  }
+}
+
+impl From<&ClientError> for ErrorCode {
+  fn from(err: &ClientError) -> ErrorCode {
+    match err {
+      ClientError::TlsError(_)
+      | ClientError::OpenSslError(_)
+      | ClientError::IncompletePacketRead => ErrorCode::Dtls,
+      ClientError::NotConnected | ClientError::NotEstablished => ErrorCode::ClientNotConnected,
+      ClientError::IncompletePacketWrite => ErrorCode::MessageTooLong,
+      ClientError::SendWindowFull => ErrorCode::Backpressure,
+      ClientError::UnknownChannel(_) => ErrorCode::UnknownChannel,
+      ClientError::SCTPError(_) => ErrorCode::Sctp,
+    }
+  }
+}
+
+impl From<&SctpReadError> for ErrorCode {
+  fn from(_: &SctpReadError) -> ErrorCode {
+    ErrorCode::Sctp
+  }
+}
+
+impl From<&SctpWriteError> for ErrorCode {
+  fn from(_: &SctpWriteError) -> ErrorCode {
+    ErrorCode::Sctp
+  }
+}
+
+impl From<&OfferDecodeError> for ErrorCode {
+  fn from(_: &OfferDecodeError) -> ErrorCode {
+    ErrorCode::InvalidOffer
+  }
+}
+
+impl From<&OpenSslErrorStack> for ErrorCode {
+  fn from(_: &OpenSslErrorStack) -> ErrorCode {
+    ErrorCode::Dtls
+  }
+}
+
+impl From<&IoError> for ErrorCode {
+  fn from(_: &IoError) -> ErrorCode {
+    ErrorCode::Io
+  }
+}
+
+/// An error as its `ErrorCode` and message, for bindings which hand errors over as a number and a
+/// string.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorMessage {
+  pub code: ErrorCode,
+  pub message: String,
+}
+
+impl ErrorMessage {
+  /// The code and message of any error of this crate.
+  pub fn new<'a, E>(err: &'a E) -> ErrorMessage
+  where
+    E: fmt::Display,
+    ErrorCode: From<&'a E>,
+  {
+    ErrorMessage {
+      code: ErrorCode::from(err),
+      message: err.to_string(),
+    }
+  }
+}
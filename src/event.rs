@@ -0,0 +1,289 @@
+use std::{
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use crate::{
+  client::Delivery,
+  dtls_floor::DowngradeReason,
+  error_code::ErrorCode,
+  ring_queue::{OverflowPolicy, RingQueue},
+  server::MessageResult,
+  stats::ServerStats,
+  watchdog::DriverPhase,
+};
+
+/// Notable changes in the state of a `Server` and its clients, retrieved with `Server::poll_event`
+/// or, along with received messages, with `Server::next_event`.
+#[derive(Clone, Debug)]
+pub enum ServerEvent {
+  /// The client at `remote_addr` completed its DTLS handshake and SCTP association, and messages
+  /// may now be sent to it.
+  ClientConnected {
+    remote_addr: SocketAddr,
+    diagnostics: HandshakeDiagnostics,
+  },
+  /// The client at `remote_addr` was removed, after which it is no longer known to the server.
+  ClientDisconnected {
+    remote_addr: SocketAddr,
+    reason: DisconnectReason,
+  },
+  /// The DTLS handshake with the client at `remote_addr` failed, for example because it offered
+  /// no acceptable cipher suite.  The client is removed once it times out.
+  HandshakeFailed {
+    remote_addr: SocketAddr,
+    error: String,
+  },
+  /// The client at `remote_addr` sent a ClientHello asking for weaker DTLS than
+  /// `Server::dtls_floor` allows, which was refused without starting the handshake.  The client
+  /// is removed, with `DisconnectReason::DtlsError`.
+  DowngradeRefused {
+    remote_addr: SocketAddr,
+    reason: DowngradeReason,
+  },
+  /// The offer of the session `session_id` listed `offered` ICE candidates, none of which passed
+  /// the endpoint's `SessionEndpointConfig::candidate_filter`, so the client is unlikely to
+  /// connect.
+  NoUsableCandidates { session_id: String, offered: usize },
+  /// The offer of the session `session_id` listed `offered` ICE candidates, all of them TCP, so
+  /// the browser found no UDP path to gather from and needs a TCP or TURN fallback to connect.
+  /// See `SessionEndpointConfig::tcp_candidate_stub`.
+  TcpOnlyOffer { session_id: String, offered: usize },
+  /// The session `old_session_id` was dropped for `new_session_id`, answering an offer with the
+  /// same ICE ufrag under `DuplicateUfragPolicy::Replace`.  A client which had already connected
+  /// with the old session stays connected.
+  SessionReplaced {
+    old_session_id: String,
+    new_session_id: String,
+  },
+  /// The client at `remote_addr` opened the data channel `channel_id` called `label`, which
+  /// `Server::send_on` sends to as `delivery`, the reliability the browser negotiated for it.  Also
+  /// returned once the client acknowledges a channel opened with `Server::open_channel`, and for
+  /// each of `ServerConfig::negotiated_channels` once the client's association is established.
+  ChannelOpened {
+    remote_addr: SocketAddr,
+    channel_id: u16,
+    label: String,
+    delivery: Delivery,
+  },
+  /// The client at `remote_addr` closed the data channel `channel_id`, by resetting its stream.
+  ChannelClosed {
+    remote_addr: SocketAddr,
+    channel_id: u16,
+  },
+  /// A message received from a client, only returned by `Server::next_event` in place of
+  /// `Server::recv`, and never by `Server::poll_event` or to subscribers.
+  Message(MessageResult),
+  /// The client at `old_addr` sent an authenticated binding request from `new_addr`, a new port
+  /// of the same IP address, most likely because its NAT rebound it, and has been moved there.
+  /// This only happens once the request nominates the new address with USE-CANDIDATE, or nothing
+  /// has been heard from `old_addr` for a few seconds, as browsers also check other pairs.
+  /// Under `ServerConfig::migrate_across_ips` the new address may be on another IP address too.
+  ///
+  /// Anything keyed by the client's address should be moved to `new_addr`, which is where the
+  /// client's messages come from and where it must be sent to from now on, while its
+  /// `Server::client_id` stays the same.  Not produced under
+  /// `ServerConfig::reauthenticate_on_rebind`.
+  ClientRebound {
+    old_addr: SocketAddr,
+    new_addr: SocketAddr,
+  },
+  /// The client at `remote_addr` outlived the lifetime of its session, set with
+  /// `SessionEndpoint::session_request_with_lifetime`, and is being disconnected after being sent
+  /// `SESSION_EXPIRED_CLOSE_CODE`.
+  SessionLifetimeExpired { remote_addr: SocketAddr },
+  /// The client's ICE agent nominated the candidate pair ending at `remote_addr` by sending
+  /// USE-CANDIDATE, so ICE has completed from the client's point of view.
+  PairNominated { remote_addr: SocketAddr },
+  /// A `PublicAddr::Host` re-resolved to a different address, which will be advertised to new
+  /// sessions from now on.
+  PublicAddrChanged { old: SocketAddr, new: SocketAddr },
+  /// Time passed without `Instant` counting it, `gap` of at least
+  /// `ServerConfig::clock_jump_threshold`, most likely because the host was suspended.  The gap
+  /// was handled according to `ServerConfig::clock_jump_policy`, see `ClockJumpPolicy` for how
+  /// it is noticed.
+  ///
+  /// An application which stops driving the server for a while does not cause one.
+  ClockJump { gap: Duration },
+  /// Something went wrong on the connection of the client at `remote_addr`, usually leading to it
+  /// being disconnected.  The same errors are listed by the inspector.
+  ClientError {
+    remote_addr: SocketAddr,
+    code: ErrorCode,
+    error: String,
+  },
+  /// A snapshot of `Server::stats`, produced every `ServerConfig::stats_event_interval`.
+  Stats(ServerStats),
+  /// The server has spent `stalled_for`, at least `ServerConfig::watchdog_timeout`, in `phase`
+  /// without getting back to waiting for packets, for example because the FFI event callback
+  /// blocked.
+  ///
+  /// This is produced by a watchdog thread while the server is still stuck, so it only reaches
+  /// subscribers from `Server::subscribe` and never `Server::poll_event`.  It is produced once
+  /// per stall.
+  DriverStalled {
+    phase: DriverPhase,
+    stalled_for: Duration,
+  },
+}
+
+impl ServerEvent {
+  /// Which kind of event this is, as filtered on by `Server::subscribe`.
+  pub fn kind(&self) -> EventKind {
+    match self {
+      ServerEvent::ClientConnected { .. }
+      | ServerEvent::ClientDisconnected { .. }
+      | ServerEvent::HandshakeFailed { .. }
+      | ServerEvent::DowngradeRefused { .. }
+      | ServerEvent::ClientRebound { .. }
+      | ServerEvent::SessionLifetimeExpired { .. }
+      | ServerEvent::SessionReplaced { .. }
+      | ServerEvent::TcpOnlyOffer { .. }
+      | ServerEvent::ChannelOpened { .. }
+      | ServerEvent::ChannelClosed { .. }
+      | ServerEvent::PairNominated { .. } => EventKind::Lifecycle,
+      ServerEvent::Message(_) => EventKind::Message,
+      ServerEvent::ClientError { .. } | ServerEvent::NoUsableCandidates { .. } => EventKind::Error,
+      ServerEvent::Stats(_) => EventKind::Stats,
+      ServerEvent::PublicAddrChanged { .. }
+      | ServerEvent::ClockJump { .. }
+      | ServerEvent::DriverStalled { .. } => EventKind::Host,
+    }
+  }
+}
+
+/// Broad categories of `ServerEvent`, for subscribing to only some of them with
+/// `Server::subscribe`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventKind {
+  /// Clients connecting, failing to, disconnecting, nominating candidate pairs, and opening and
+  /// closing data channels.
+  Lifecycle,
+  /// `ServerEvent::Message`, which is never delivered to subscribers.
+  Message,
+  /// `ServerEvent::ClientError` and `ServerEvent::NoUsableCandidates`.
+  Error,
+  /// `ServerEvent::Stats`.
+  Stats,
+  /// Changes around the server itself, such as to its public addresses or its clock, and stalls
+  /// of the server's own loop.
+  Host,
+}
+
+/// Why a client was removed, part of `ServerEvent::ClientDisconnected` and returned by
+/// `Server::disconnect_reason`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DisconnectReason {
+  /// Nothing was heard from the client for too long.
+  Timeout,
+  /// The DTLS handshake failed, or reading or writing the DTLS stream failed.
+  DtlsError,
+  /// The SCTP association was aborted, by an ABORT from the client or because data channel
+  /// negotiation failed.
+  SctpAbort,
+  /// The server closed the connection, because of `Server::disconnect`, a middleware verdict, an
+  /// unsupported protocol version, or the server shutting down.
+  LocalShutdown,
+  /// The client closed the connection, with an SCTP SHUTDOWN or a DTLS close_notify alert.
+  PeerClose,
+}
+
+// Subscribers to events, each with the kinds of event it is interested in.  Shared with the
+// watchdog thread, which reports stalls straight to them.
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<Subscriber>>>);
+
+type Subscriber = (Vec<EventKind>, flume::Sender<ServerEvent>);
+
+impl Subscribers {
+  pub fn publish(&self, event: &ServerEvent) {
+    let kind = event.kind();
+    // Subscribers whose streams have been dropped are forgotten.
+    self.0.lock().unwrap().retain(|(kinds, sender)| {
+      if kinds.contains(&kind) {
+        !matches!(
+          sender.try_send(event.clone()),
+          Err(flume::TrySendError::Disconnected(_))
+        )
+      } else {
+        !sender.is_disconnected()
+      }
+    });
+  }
+}
+
+// Events waiting for `Server::poll_event`, which are also passed on to every subscriber
+// interested in them as they happen.
+pub struct EventQueue {
+  queue: RingQueue<ServerEvent>,
+  subscribers: Subscribers,
+}
+
+impl EventQueue {
+  pub fn new(capacity: usize) -> EventQueue {
+    EventQueue {
+      queue: RingQueue::new(capacity, OverflowPolicy::DropOldest),
+      subscribers: Subscribers::default(),
+    }
+  }
+
+  // Add a subscriber to events of the given kinds, which are dropped for it whenever it has
+  // `capacity` events it has not taken yet.
+  pub fn subscribe(
+    &mut self,
+    kinds: &[EventKind],
+    capacity: usize,
+  ) -> flume::Receiver<ServerEvent> {
+    let (sender, receiver) = flume::bounded(capacity);
+    self
+      .subscribers
+      .0
+      .lock()
+      .unwrap()
+      .push((kinds.to_vec(), sender));
+    receiver
+  }
+
+  pub fn push_back(&mut self, event: ServerEvent) {
+    self.subscribers.publish(&event);
+    self.queue.push_back(event);
+  }
+
+  pub fn pop_front(&mut self) -> Option<ServerEvent> {
+    self.queue.pop_front()
+  }
+
+  pub fn len(&self) -> usize {
+    self.queue.len()
+  }
+
+  pub fn subscribers(&self) -> usize {
+    self.subscribers.0.lock().unwrap().len()
+  }
+
+  pub fn shared_subscribers(&self) -> Subscribers {
+    self.subscribers.clone()
+  }
+}
+
+/// Security parameters negotiated during a client's DTLS handshake, useful for finding which
+/// browsers or devices end up on weaker settings before tightening the accepted configuration.
+#[derive(Clone, Debug)]
+pub struct HandshakeDiagnostics {
+  /// Negotiated protocol version, such as `"DTLSv1.2"`.
+  pub dtls_version: &'static str,
+  /// OpenSSL name of the negotiated cipher suite, such as `"ECDHE-RSA-AES128-GCM-SHA256"`.
+  pub cipher: Option<&'static str>,
+  /// Number of secret bits used by the negotiated cipher.
+  pub cipher_bits: Option<i32>,
+  /// Authentication algorithm of the cipher suite, `"RSA"` or `"ECDSA"`, which is the kind of
+  /// signature the client had to verify.
+  pub authentication: Option<String>,
+  /// SRTP protection profile negotiated through the `use_srtp` extension, if any.
+  pub srtp_profile: Option<&'static str>,
+  /// Whether the extended master secret extension (RFC 7627) was negotiated.
+  pub extended_master_secret: Option<bool>,
+  /// The session was resumed rather than fully negotiated.
+  pub session_reused: bool,
+}
@@ -0,0 +1,140 @@
+use std::{
+  io::Error as IoError,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, OnceLock, Weak,
+  },
+  thread::{self, Thread},
+  time::Duration,
+};
+
+use crossbeam_queue::ArrayQueue;
+
+/// The FFI event callback passed to `Server::new`, called with an event code and a message which
+/// is only valid for the duration of the call.  Event codes below 1000 are errors, as listed by
+/// `ErrorCode`.
+pub type EventCallback = extern "C" fn(u32, *mut u8, u32);
+
+/// How events reach the FFI event callback passed to `Server::new`, set with
+/// `ServerConfig::ffi_event_delivery`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FfiEventDelivery {
+  /// Call the callback right where the event happens, in the middle of handling packets, so a
+  /// slow callback holds up every client.
+  Direct,
+  /// Queue events for a dedicated notifier thread which calls the callback, so the server never
+  /// waits for foreign code.
+  NotifierThread,
+  /// Queue events until the application calls `FfiEventQueue::dispatch`, on whichever thread
+  /// suits the foreign side.
+  Poll,
+}
+
+// Events queued for delivery beyond this many are dropped, and counted by
+// `FfiEventQueue::dropped`.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// The FFI events of one `Server`, returned by `Server::ffi_event_queue`.
+///
+/// Handles may be cloned and sent to other threads, and keep working after the server is
+/// dropped, delivering whatever it left queued.
+#[derive(Clone)]
+pub struct FfiEventQueue(Arc<Sink>);
+
+struct Sink {
+  cb: Option<EventCallback>,
+  delivery: FfiEventDelivery,
+  queue: ArrayQueue<(u32, String)>,
+  dropped: AtomicU64,
+  notifier: OnceLock<Thread>,
+}
+
+impl FfiEventQueue {
+  pub(crate) fn new(
+    cb: Option<EventCallback>,
+    delivery: FfiEventDelivery,
+  ) -> Result<FfiEventQueue, IoError> {
+    let sink = Arc::new(Sink {
+      cb,
+      delivery,
+      queue: ArrayQueue::new(if delivery == FfiEventDelivery::Direct {
+        1
+      } else {
+        QUEUE_CAPACITY
+      }),
+      dropped: AtomicU64::new(0),
+      notifier: OnceLock::new(),
+    });
+    if cb.is_some() && delivery == FfiEventDelivery::NotifierThread {
+      let weak = Arc::downgrade(&sink);
+      let handle = thread::Builder::new()
+        .name("unreliablertc-ffi-events".to_owned())
+        .spawn(move || notify(weak))?;
+      let _ = sink.notifier.set(handle.thread().clone());
+    }
+    Ok(FfiEventQueue(sink))
+  }
+
+  // Pass an event on to the FFI callback, if there is one, according to the configured
+  // `FfiEventDelivery`.
+  pub(crate) fn emit(&self, code: u32, message: String) {
+    let sink = &*self.0;
+    if sink.cb.is_none() {
+      return;
+    }
+    if sink.delivery == FfiEventDelivery::Direct {
+      sink.call(code, message);
+      return;
+    }
+    if sink.queue.push((code, message)).is_err() {
+      sink.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(notifier) = sink.notifier.get() {
+      notifier.unpark();
+    }
+  }
+
+  /// Call the FFI event callback with up to `max` events queued under `FfiEventDelivery::Poll`,
+  /// on the calling thread, returning how many were delivered.
+  ///
+  /// This may be called from any thread, while the server keeps running elsewhere.
+  pub fn dispatch(&self, max: usize) -> usize {
+    let sink = &*self.0;
+    let mut delivered = 0;
+    while delivered < max {
+      match sink.queue.pop() {
+        Some((code, message)) => sink.call(code, message),
+        None => break,
+      }
+      delivered += 1;
+    }
+    delivered
+  }
+
+  /// Number of FFI events dropped because more than 4096 were waiting to be delivered.
+  pub fn dropped(&self) -> u64 {
+    self.0.dropped.load(Ordering::Relaxed)
+  }
+
+  // Number of FFI events waiting to be delivered.
+  pub(crate) fn queued(&self) -> usize {
+    self.0.queue.len()
+  }
+}
+
+impl Sink {
+  fn call(&self, code: u32, mut message: String) {
+    if let Some(cb) = self.cb {
+      cb(code, message.as_mut_ptr(), message.len() as u32);
+    }
+  }
+}
+
+// Body of a server's notifier thread, which stops once every handle to its events is dropped.
+fn notify(sink: Weak<Sink>) {
+  while let Some(events) = sink.upgrade().map(FfiEventQueue) {
+    events.dispatch(usize::MAX);
+    drop(events);
+    thread::park_timeout(Duration::from_millis(100));
+  }
+}
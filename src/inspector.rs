@@ -0,0 +1,213 @@
+use std::{collections::VecDeque, fmt, time::Instant};
+
+use crate::error_code::ErrorCode;
+
+#[cfg(feature = "inspector")]
+pub use listener::{spawn_inspector, InspectorAddr};
+
+// Errors kept for the inspector to show, older ones are forgotten.
+const MAX_RECENT_ERRORS: usize = 64;
+
+// A request from the inspector for a snapshot of the server's state.
+pub type InspectorRequest = flume::Sender<serde_json::Value>;
+
+// The most recent client errors of a `Server`.
+#[derive(Default)]
+pub struct ErrorLog {
+  entries: VecDeque<(Instant, ErrorCode, String)>,
+}
+
+impl ErrorLog {
+  pub fn record(&mut self, code: ErrorCode, error: fmt::Arguments) {
+    if self.entries.len() == MAX_RECENT_ERRORS {
+      self.entries.pop_front();
+    }
+    self
+      .entries
+      .push_back((Instant::now(), code, error.to_string()));
+  }
+
+  pub fn to_json(&self) -> serde_json::Value {
+    self
+      .entries
+      .iter()
+      .map(|(at, code, message)| {
+        serde_json::json!({
+          "age_ms": at.elapsed().as_millis() as u64,
+          "code": code.code(),
+          "kind": code.name(),
+          "message": message,
+        })
+      })
+      .collect()
+  }
+}
+
+#[cfg(feature = "inspector")]
+mod listener {
+  use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread,
+    time::Duration,
+  };
+
+  use super::InspectorRequest;
+
+  // How long a connection may take to send its request, and how long to wait for the server to
+  // produce a snapshot.
+  const TIMEOUT: Duration = Duration::from_secs(5);
+  // How often the listener checks whether its server has been dropped while nobody connects.
+  const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+  const MAX_REQUEST_LEN: usize = 8192;
+
+  /// Where `Server::enable_inspector` listens.
+  #[derive(Clone, Debug, Eq, PartialEq)]
+  pub enum InspectorAddr {
+    /// A TCP port, which must be on a loopback address.
+    Tcp(SocketAddr),
+    /// A Unix domain socket at this path, which is removed once the server is dropped.
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+  }
+
+  pub fn spawn_inspector(
+    addr: InspectorAddr,
+    requests: flume::Sender<InspectorRequest>,
+  ) -> Result<(), IoError> {
+    match addr {
+      InspectorAddr::Tcp(addr) => {
+        if !addr.ip().is_loopback() {
+          return Err(IoError::new(
+            IoErrorKind::InvalidInput,
+            "the inspector may only listen on a loopback address",
+          ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        spawn(move || {
+          serve(&requests, || match listener.accept() {
+            Ok((stream, _)) => stream.set_nonblocking(false).map(|_| Some(stream)),
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+          })
+        })
+      }
+      #[cfg(unix)]
+      InspectorAddr::Unix(path) => {
+        use std::os::unix::net::UnixListener;
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        spawn(move || {
+          serve(&requests, || match listener.accept() {
+            Ok((stream, _)) => stream.set_nonblocking(false).map(|_| Some(stream)),
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+          });
+          let _ = std::fs::remove_file(&path);
+        })
+      }
+    }
+  }
+
+  fn spawn(f: impl FnOnce() + Send + 'static) -> Result<(), IoError> {
+    thread::Builder::new()
+      .name("unreliablertc-inspector".to_owned())
+      .spawn(f)
+      .map(|_| ())
+  }
+
+  trait Connection: Read + Write {
+    fn set_timeout(&self, timeout: Duration) -> Result<(), IoError>;
+  }
+
+  impl Connection for std::net::TcpStream {
+    fn set_timeout(&self, timeout: Duration) -> Result<(), IoError> {
+      self.set_read_timeout(Some(timeout))?;
+      self.set_write_timeout(Some(timeout))
+    }
+  }
+
+  #[cfg(unix)]
+  impl Connection for std::os::unix::net::UnixStream {
+    fn set_timeout(&self, timeout: Duration) -> Result<(), IoError> {
+      self.set_read_timeout(Some(timeout))?;
+      self.set_write_timeout(Some(timeout))
+    }
+  }
+
+  // Answer connections one at a time until the server is dropped.
+  fn serve<C: Connection>(
+    requests: &flume::Sender<InspectorRequest>,
+    mut accept: impl FnMut() -> Result<Option<C>, IoError>,
+  ) {
+    while !requests.is_disconnected() {
+      match accept() {
+        Ok(Some(mut conn)) => {
+          let _ = conn
+            .set_timeout(TIMEOUT)
+            .and_then(|_| handle(requests, &mut conn));
+        }
+        Ok(None) => thread::sleep(ACCEPT_POLL_INTERVAL),
+        Err(_) => thread::sleep(ACCEPT_POLL_INTERVAL),
+      }
+    }
+  }
+
+  fn handle(
+    requests: &flume::Sender<InspectorRequest>,
+    conn: &mut impl Connection,
+  ) -> Result<(), IoError> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LEN {
+      let len = conn.read(&mut buf)?;
+      if len == 0 {
+        break;
+      }
+      request.extend_from_slice(&buf[..len]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+      (Some("GET"), Some(path)) => match snapshot(requests) {
+        Some(snapshot) => match path.trim_end_matches('/') {
+          "" => ("200 OK", snapshot),
+          section => match snapshot.get(&section[1..]) {
+            Some(section) => ("200 OK", section.clone()),
+            None => (
+              "404 Not Found",
+              serde_json::json!({ "error": "no such section" }),
+            ),
+          },
+        },
+        None => (
+          "503 Service Unavailable",
+          serde_json::json!({ "error": "server is not being driven" }),
+        ),
+      },
+      _ => (
+        "405 Method Not Allowed",
+        serde_json::json!({ "error": "only GET is supported" }),
+      ),
+    };
+
+    let body = serde_json::to_string_pretty(&body).unwrap();
+    write!(
+      conn,
+      "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      status,
+      body.len(),
+      body
+    )?;
+    conn.flush()
+  }
+
+  fn snapshot(requests: &flume::Sender<InspectorRequest>) -> Option<serde_json::Value> {
+    let (reply_sender, reply_receiver) = flume::bounded(1);
+    requests.send(reply_sender).ok()?;
+    reply_receiver.recv_timeout(TIMEOUT).ok()
+  }
+}
@@ -1,14 +1,83 @@
+mod admission;
+mod audit;
 mod buffer_pool;
 mod client;
+mod client_table;
+mod clock;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod config;
+mod crc32c;
 mod crypto;
+mod demux;
+mod driver;
+mod dtls_floor;
+mod error_code;
+mod event;
+mod ffi_event;
+mod inspector;
 mod interval;
+mod loopback;
+mod middleware;
+mod pktinfo;
+mod public_addr;
+mod relay;
+mod ring_queue;
+mod schedule;
 mod sctp;
 mod sdp;
 mod server;
+mod snapshot;
+mod stats;
 mod stun;
+mod thread_tuning;
+mod token;
+mod trace;
 mod util;
+mod version;
+mod watchdog;
 
-pub use client::{MessageType, MAX_MESSAGE_LEN};
+pub use admission::AdmissionRate;
+pub use audit::{AuditEvent, AuditKind, AuditSink};
+pub use bytes::Bytes;
+pub use client::{
+  Delivery, MessageType, MAX_FRAGMENTED_MESSAGE_LEN, MAX_MESSAGE_LEN, MAX_UNACKED_BYTES,
+};
+pub use client_table::{ClientId, ClientState};
+pub use clock::ClockJumpPolicy;
+pub use config::{
+  CandidateFilter, DuplicateUfragPolicy, NegotiatedChannel, ServerConfig, SessionEndpointConfig,
+};
+pub use demux::PacketClass;
+pub use driver::{
+  ControlHandle, DriverStopped, EventStream, MessageSink, MessageStream, ReceivedMessage,
+  ReceiverStream, SenderHandle, ServerHandle,
+};
+pub use dtls_floor::{DowngradeReason, DtlsFloor};
+pub use error_code::{ErrorCode, ErrorMessage};
+pub use event::{DisconnectReason, EventKind, HandshakeDiagnostics, ServerEvent};
+pub use ffi_event::{EventCallback, FfiEventDelivery, FfiEventQueue};
+#[cfg(feature = "inspector")]
+pub use inspector::InspectorAddr;
+pub use loopback::LoopbackClient;
+pub use middleware::{MessageCtx, MiddlewareStats, Verdict};
+pub use public_addr::PublicAddr;
+pub use relay::RelayedMessage;
+pub use ring_queue::OverflowPolicy;
+pub use sdp::{
+  decode_offer, CandidateKind, DataDirection, IceCandidate, OfferDecodeError, SdpAnswer,
+  SdpAnswerFormat, SdpAttribute,
+};
 pub use server::{
-  ErrorMessage, MessageBuffer, MessageResult, SendError, Server, SessionEndpoint, SessionError,
+  ClientInfo, ClientsPage, MessageBuffer, MessageFragment, MessageResult, OwnedMessage, SendError,
+  Server, SessionEndpoint, SessionError, SessionInfo, StreamedMessage, SESSION_EXPIRED_CLOSE_CODE,
+};
+pub use snapshot::{ClientsSnapshot, SnapshotHandle};
+pub use stats::{
+  ChannelTraffic, ConnectionFunnel, DemuxStats, LatencyStats, MemoryUsage, QueueDepth,
+  ReassemblyStats, ServerStats,
 };
+pub use token::TokenKey;
+pub use trace::{ConnectionTrace, TraceDirection, TraceEntry, TraceLayer};
+pub use version::VERSION_MISMATCH_CLOSE_CODE;
+pub use watchdog::DriverPhase;
@@ -0,0 +1,764 @@
+use std::{
+  fmt::Write as _,
+  io::{Error as IoError, ErrorKind as IoErrorKind},
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+  time::{Duration, Instant},
+};
+
+use async_io::{Async, Timer};
+use atone::Vc as VecDeque;
+use futures_util::{pin_mut, select, FutureExt};
+use openssl::{
+  hash::MessageDigest,
+  ssl::{ErrorCode, HandshakeError, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode},
+};
+use rand::{thread_rng, Rng};
+
+use crate::{
+  buffer_pool::BufferPool,
+  client::{
+    data_channel_open_message, send_sctp_packet, ClientSslPackets, Delivery, MessageType,
+    DATA_CHANNEL_MESSAGE_ACK, DATA_CHANNEL_MESSAGE_OPEN, DATA_CHANNEL_PROTO_BINARY,
+    DATA_CHANNEL_PROTO_CONTROL, DATA_CHANNEL_PROTO_STRING, MAX_MESSAGE_LEN, MAX_SCTP_PACKET_SIZE,
+    MAX_UDP_PAYLOAD_SIZE, SCTP_BUFFER_SIZE, SCTP_MAX_CHUNKS,
+  },
+  sctp::{
+    read_sctp_packet, SctpChunk, SctpPacket, StreamResetRequest, RECONFIG_RESULT_PERFORMED,
+    SCTP_FLAG_BEGIN_FRAGMENT, SCTP_FLAG_COMPLETE_UNRELIABLE, SCTP_FLAG_END_FRAGMENT,
+    SCTP_FLAG_UNRELIABLE,
+  },
+  server::SessionEndpoint,
+  stun::{is_stun_success_response, stun_error_response_code, write_stun_binding_request},
+  util::rand_string,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const STUN_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(250);
+const SCTP_PORT: u16 = 5000;
+const CHANNEL_LABEL: &str = "loopback";
+
+/// A minimal native WebRTC data channel client connected to a `Server` through the loopback
+/// interface, created by `Server::connect_loopback`, or to any server with
+/// `LoopbackClient::connect`.
+///
+/// It performs the same STUN, DTLS, SCTP and data channel handshakes a browser would, so it can
+/// be used to check a complete connection and message round trip without a browser.  The server
+/// must still be driven (by `Server::recv`) for messages sent by this client to arrive.
+pub struct LoopbackClient {
+  socket: Async<UdpSocket>,
+  server_addr: SocketAddr,
+  buffer_pool: BufferPool,
+  ssl_stream: SslStream<ClientSslPackets>,
+  association: Association,
+  received: VecDeque<(Vec<u8>, MessageType, u16)>,
+  // The STUN username and password of the client's binding requests.
+  ice_username: String,
+  ice_passwd: String,
+}
+
+// State of the SCTP association from the client's side.
+#[derive(Default)]
+struct Association {
+  local_tag: u32,
+  remote_tag: u32,
+  local_tsn: u32,
+  state_cookie: Option<Vec<u8>>,
+  established: bool,
+  // Streams whose DATA_CHANNEL_OPEN the server has acknowledged.
+  open_channels: Vec<u16>,
+  // The stream of the next channel opened, an even one as the DTLS client (RFC 8832 section 6).
+  next_channel_id: u16,
+  // The sequence number of the next stream reset request, and that of the last one received.
+  reconfig_seq: u32,
+  remote_reconfig_seq: u32,
+  closed: bool,
+  // The message whose fragments are arriving so far, and the TSN of its next fragment.
+  partial: Option<(Vec<u8>, u32)>,
+  // The highest TSN received from the server, which like the server the client acknowledges as
+  // if everything before it had arrived.
+  remote_tsn: Option<u32>,
+}
+
+impl LoopbackClient {
+  /// The local address of this client's UDP socket, which is the client's address as seen by the
+  /// server.
+  pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+    self.socket.get_ref().local_addr()
+  }
+
+  /// Send a connectivity check with this client's ICE credentials from a new socket bound to
+  /// `local_addr`, as a browser checking another candidate pair does, and return that socket's
+  /// address once the server has answered it.
+  ///
+  /// With `nominate` the check carries USE-CANDIDATE, and the client carries on from the new
+  /// socket as a browser switching to the nominated pair would.  The server must be driven
+  /// meanwhile.  A check the server answers with 401 Unauthorized, as under
+  /// `ServerConfig::reauthenticate_on_rebind`, fails with `ErrorKind::PermissionDenied`.
+  pub async fn check_from(
+    &mut self,
+    local_addr: SocketAddr,
+    nominate: bool,
+  ) -> Result<SocketAddr, IoError> {
+    let socket = Async::<UdpSocket>::bind(local_addr)?;
+    check_pair(
+      &socket,
+      self.server_addr,
+      &self.ice_username,
+      &self.ice_passwd,
+      nominate,
+      true,
+      Instant::now() + HANDSHAKE_TIMEOUT,
+    )
+    .await?;
+    let local_addr = socket.get_ref().local_addr()?;
+    if nominate {
+      self.socket = socket;
+    }
+    Ok(local_addr)
+  }
+
+  /// Send a message to the server on the client's data channel.
+  ///
+  /// Messages longer than `MAX_MESSAGE_LEN` are sent in fragments, which the server only accepts
+  /// with `ServerConfig::max_reassembled_len`.
+  pub async fn send(&mut self, message: &[u8], message_type: MessageType) -> Result<(), IoError> {
+    self.send_on(0, message, message_type).await
+  }
+
+  /// Send a message to the server as with `LoopbackClient::send`, on the data channel
+  /// `channel_id`.  Messages are always sent unreliably, whatever the channel's delivery.
+  pub async fn send_on(
+    &mut self,
+    channel_id: u16,
+    message: &[u8],
+    message_type: MessageType,
+  ) -> Result<(), IoError> {
+    let proto_id = match message_type {
+      MessageType::Text => DATA_CHANNEL_PROTO_STRING,
+      MessageType::Binary => DATA_CHANNEL_PROTO_BINARY,
+    };
+    if message.len() <= MAX_MESSAGE_LEN {
+      self.send_data(channel_id, SCTP_FLAG_COMPLETE_UNRELIABLE, proto_id, message)?;
+    } else {
+      let fragments = message.len().div_ceil(MAX_MESSAGE_LEN);
+      for (index, fragment) in message.chunks(MAX_MESSAGE_LEN).enumerate() {
+        let mut chunk_flags = SCTP_FLAG_UNRELIABLE;
+        if index == 0 {
+          chunk_flags |= SCTP_FLAG_BEGIN_FRAGMENT;
+        }
+        if index + 1 == fragments {
+          chunk_flags |= SCTP_FLAG_END_FRAGMENT;
+        }
+        self.send_data(channel_id, chunk_flags, proto_id, fragment)?;
+      }
+    }
+    self.flush().await
+  }
+
+  /// Receive the next message the server sent on any of the client's data channels.
+  pub async fn recv(&mut self) -> Result<(Vec<u8>, MessageType), IoError> {
+    let (message, message_type, _) = self.recv_with_channel().await?;
+    Ok((message, message_type))
+  }
+
+  /// Receive the next message as with `LoopbackClient::recv`, along with the id of the data
+  /// channel it arrived on.
+  pub async fn recv_with_channel(&mut self) -> Result<(Vec<u8>, MessageType, u16), IoError> {
+    loop {
+      if let Some(message) = self.received.pop_front() {
+        return Ok(message);
+      }
+      if self.association.closed {
+        return Err(IoErrorKind::ConnectionAborted.into());
+      }
+      self.receive_packet(None).await?;
+    }
+  }
+
+  /// Open another data channel called `label`, as a browser's `createDataChannel` would, and
+  /// return its id once the server has acknowledged it.
+  ///
+  /// The server sends on the channel as `delivery`, and must be driven meanwhile.
+  pub async fn open_channel(&mut self, label: &str, delivery: Delivery) -> Result<u16, IoError> {
+    let channel_id = self.association.next_channel_id;
+    self.association.next_channel_id = channel_id.wrapping_add(2);
+    self.send_data(
+      channel_id,
+      SCTP_FLAG_COMPLETE_UNRELIABLE,
+      DATA_CHANNEL_PROTO_CONTROL,
+      &data_channel_open_message(label, delivery),
+    )?;
+    self.flush().await?;
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while !self.association.open_channels.contains(&channel_id) {
+      self.receive_packet(Some(deadline)).await?;
+    }
+    Ok(channel_id)
+  }
+
+  /// Close the data channel `channel_id` by resetting its stream, as a browser does when a data
+  /// channel is closed.
+  pub async fn close_channel(&mut self, channel_id: u16) -> Result<(), IoError> {
+    let reconfig_seq = self.association.reconfig_seq;
+    self.association.reconfig_seq = reconfig_seq.wrapping_add(1);
+    self
+      .association
+      .open_channels
+      .retain(|&id| id != channel_id);
+    self.send_chunk(
+      self.association.remote_tag,
+      SctpChunk::ReConfig {
+        reset_request: Some(StreamResetRequest {
+          request_seq: reconfig_seq,
+          response_seq: self.association.remote_reconfig_seq,
+          last_tsn: self.association.local_tsn.wrapping_sub(1),
+          streams: &channel_id.to_be_bytes(),
+        }),
+        response: None,
+      },
+    )?;
+    self.flush().await
+  }
+
+  /// Connect to the server listening at `server_addr` whose sessions are answered by
+  /// `session_endpoint`, for servers driven elsewhere, such as on a `Server::spawn_driver` thread.
+  ///
+  /// The server may run on another host if `session_endpoint` is made with
+  /// `SessionEndpoint::standalone` from the server's certificate fingerprint and its
+  /// `ServerConfig::stun_token_key`, which is how synthetic clients load test a remote server.
+  pub async fn connect(
+    session_endpoint: &mut SessionEndpoint,
+    server_addr: SocketAddr,
+  ) -> Result<LoopbackClient, IoError> {
+    LoopbackClient::connect_with_lifetime(session_endpoint, server_addr, None).await
+  }
+
+  // Connect as with `LoopbackClient::connect`, requesting a session which expires after
+  // `lifetime` as with `SessionEndpoint::session_request_with_lifetime`.
+  pub(crate) async fn connect_with_lifetime(
+    session_endpoint: &mut SessionEndpoint,
+    server_addr: SocketAddr,
+    lifetime: Option<Duration>,
+  ) -> Result<LoopbackClient, IoError> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let local_ip = match (server_addr.is_ipv4(), server_addr.ip().is_loopback()) {
+      (true, true) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+      (true, false) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      (false, true) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+      (false, false) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = Async::<UdpSocket>::bind(SocketAddr::new(local_ip, 0))?;
+
+    let (client_user, server_user, server_passwd, fingerprint) = {
+      let mut rng = thread_rng();
+      let client_user = rand_string(&mut rng, 8);
+      let client_passwd = rand_string(&mut rng, 24);
+      let offer = format!(
+        "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\n\
+         m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n\
+         a=ice-ufrag:{}\r\na=ice-pwd:{}\r\na=setup:actpass\r\na=mid:0\r\na=sctp-port:{}\r\n",
+        rng.gen::<u32>(),
+        client_user,
+        client_passwd,
+        SCTP_PORT,
+      );
+      let response = match lifetime {
+        Some(lifetime) => session_endpoint.session_request_with_lifetime(&offer, lifetime),
+        None => session_endpoint.session_request(&offer),
+      }
+      .map_err(|err| IoError::other(err.to_string()))?;
+      let (server_user, server_passwd, fingerprint) = parse_answer(&response)?;
+      (client_user, server_user, server_passwd, fingerprint)
+    };
+
+    // ICE, as the controlling agent nominating the only candidate pair there is.
+    let ice_username = format!("{}:{}", server_user, client_user);
+    check_pair(
+      &socket,
+      server_addr,
+      &ice_username,
+      &server_passwd,
+      true,
+      false,
+      deadline,
+    )
+    .await?;
+    let mut packet = vec![0; MAX_UDP_PAYLOAD_SIZE];
+
+    // DTLS, as the client since the server's answer always uses `a=setup:passive`.
+    let buffer_pool = BufferPool::new();
+    let mut context = SslContext::builder(SslMethod::dtls()).map_err(IoError::other)?;
+    // The server's certificate is checked against the fingerprint of its answer instead.
+    context.set_verify(SslVerifyMode::NONE);
+    let ssl = Ssl::new(&context.build()).map_err(IoError::other)?;
+    let packets = ClientSslPackets {
+      buffer_pool: buffer_pool.clone(),
+      incoming_udp: VecDeque::new(),
+      outgoing_udp: VecDeque::new(),
+    };
+    let mut mid_handshake = match ssl.connect(packets) {
+      Ok(_) => unreachable!("handshake cannot finish with no incoming packets"),
+      Err(HandshakeError::WouldBlock(mid_handshake)) => mid_handshake,
+      Err(err) => return Err(IoError::other(err.to_string())),
+    };
+    let ssl_stream = loop {
+      flush_packets(&socket, server_addr, &buffer_pool, mid_handshake.get_mut()).await?;
+      let len = recv_until(&socket, server_addr, &mut packet, deadline)
+        .await?
+        .ok_or_else(|| IoError::new(IoErrorKind::TimedOut, "DTLS handshake timed out"))?;
+      if !is_dtls_packet(&packet[..len]) {
+        continue;
+      }
+      let mut incoming = buffer_pool.acquire();
+      incoming.extend_from_slice(&packet[..len]);
+      mid_handshake
+        .get_mut()
+        .incoming_udp
+        .push_back(incoming.into_owned());
+      match mid_handshake.handshake() {
+        Ok(ssl_stream) => break ssl_stream,
+        Err(HandshakeError::WouldBlock(next)) | Err(HandshakeError::Failure(next))
+          if next.error().code() == ErrorCode::WANT_READ =>
+        {
+          mid_handshake = next
+        }
+        Err(err) => return Err(IoError::other(err.to_string())),
+      }
+    };
+
+    let certificate = ssl_stream
+      .ssl()
+      .peer_certificate()
+      .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "server sent no certificate"))?;
+    let digest = certificate
+      .digest(MessageDigest::sha256())
+      .map_err(IoError::other)?;
+    if format_fingerprint(&digest) != fingerprint {
+      return Err(IoError::new(
+        IoErrorKind::InvalidData,
+        "server certificate does not match the answer's fingerprint",
+      ));
+    }
+
+    let local_tsn = thread_rng().gen();
+    let mut client = LoopbackClient {
+      socket,
+      server_addr,
+      buffer_pool,
+      ssl_stream,
+      association: Association {
+        local_tag: thread_rng().gen_range(1..=u32::MAX),
+        local_tsn,
+        next_channel_id: 2,
+        reconfig_seq: local_tsn,
+        ..Association::default()
+      },
+      received: VecDeque::new(),
+      ice_username,
+      ice_passwd: server_passwd,
+    };
+
+    // SCTP association, then the data channel over it.
+    client.send_chunk(
+      0,
+      SctpChunk::Init {
+        initiate_tag: client.association.local_tag,
+        window_credit: SCTP_BUFFER_SIZE,
+        num_outbound_streams: 1024,
+        num_inbound_streams: 1024,
+        initial_tsn: client.association.local_tsn,
+        support_unreliable: true,
+      },
+    )?;
+    client.flush().await?;
+    while client.association.state_cookie.is_none() {
+      client.receive_packet(Some(deadline)).await?;
+    }
+
+    let state_cookie = client.association.state_cookie.take().unwrap();
+    client.send_chunk(
+      client.association.remote_tag,
+      SctpChunk::CookieEcho {
+        state_cookie: &state_cookie,
+      },
+    )?;
+    client.flush().await?;
+    while !client.association.established {
+      client.receive_packet(Some(deadline)).await?;
+    }
+
+    client.send_data(
+      0,
+      SCTP_FLAG_COMPLETE_UNRELIABLE,
+      DATA_CHANNEL_PROTO_CONTROL,
+      &data_channel_open_message(CHANNEL_LABEL, Delivery::Unreliable),
+    )?;
+    client.flush().await?;
+    while !client.association.open_channels.contains(&0) {
+      client.receive_packet(Some(deadline)).await?;
+    }
+
+    Ok(client)
+  }
+
+  fn send_data(
+    &mut self,
+    stream_id: u16,
+    chunk_flags: u8,
+    proto_id: u32,
+    user_data: &[u8],
+  ) -> Result<(), IoError> {
+    let tsn = self.association.local_tsn;
+    self.association.local_tsn = tsn.wrapping_add(1);
+    self.send_chunk(
+      self.association.remote_tag,
+      SctpChunk::Data {
+        chunk_flags,
+        tsn,
+        stream_id,
+        stream_seq: 0,
+        proto_id,
+        user_data,
+      },
+    )
+  }
+
+  fn send_chunk(&mut self, verification_tag: u32, chunk: SctpChunk) -> Result<(), IoError> {
+    send_sctp_packet(
+      &self.buffer_pool,
+      &mut self.ssl_stream,
+      SctpPacket {
+        source_port: SCTP_PORT,
+        dest_port: SCTP_PORT,
+        verification_tag,
+        chunks: &[chunk],
+      },
+    )
+    .map_err(IoError::other)
+  }
+
+  async fn flush(&mut self) -> Result<(), IoError> {
+    flush_packets(
+      &self.socket,
+      self.server_addr,
+      &self.buffer_pool,
+      self.ssl_stream.get_mut(),
+    )
+    .await
+  }
+
+  // Wait for a single packet from the server and handle every SCTP chunk it carries.
+  async fn receive_packet(&mut self, deadline: Option<Instant>) -> Result<(), IoError> {
+    let mut packet = self.buffer_pool.acquire();
+    packet.resize(MAX_UDP_PAYLOAD_SIZE, 0);
+    let len = match deadline {
+      Some(deadline) => recv_until(&self.socket, self.server_addr, &mut packet, deadline)
+        .await?
+        .ok_or_else(|| IoError::new(IoErrorKind::TimedOut, "SCTP handshake timed out"))?,
+      None => loop {
+        let (len, addr) = self.socket.recv_from(&mut packet).await?;
+        if addr == self.server_addr {
+          break len;
+        }
+      },
+    };
+    if !is_dtls_packet(&packet[..len]) {
+      return Ok(());
+    }
+    packet.truncate(len);
+    self
+      .ssl_stream
+      .get_mut()
+      .incoming_udp
+      .push_back(packet.into_owned());
+
+    let mut record = vec![0; MAX_SCTP_PACKET_SIZE];
+    loop {
+      let len = match self.ssl_stream.ssl_read(&mut record) {
+        Ok(len) => len,
+        Err(err) if err.code() == ErrorCode::WANT_READ => break,
+        Err(err) if err.code() == ErrorCode::ZERO_RETURN => {
+          self.association.closed = true;
+          break;
+        }
+        Err(err) => return Err(IoError::other(err.to_string())),
+      };
+
+      let mut chunks = [SctpChunk::Abort; SCTP_MAX_CHUNKS];
+      let sctp_packet = match read_sctp_packet(&record[..len], false, &mut chunks) {
+        Ok(sctp_packet) => sctp_packet,
+        Err(_) => continue,
+      };
+      let mut received_data = false;
+      for chunk in sctp_packet.chunks {
+        // Retransmitted chunks which already arrived are only acknowledged again.
+        if let SctpChunk::Data { tsn, .. } = *chunk {
+          received_data = true;
+          match self.association.remote_tsn {
+            Some(remote_tsn) if tsn.wrapping_sub(remote_tsn) as i32 <= 0 => continue,
+            _ => self.association.remote_tsn = Some(tsn),
+          }
+        }
+        match *chunk {
+          SctpChunk::InitAck {
+            initiate_tag,
+            initial_tsn,
+            state_cookie,
+            ..
+          } => {
+            self.association.remote_tag = initiate_tag;
+            self.association.remote_reconfig_seq = initial_tsn.wrapping_sub(1);
+            self.association.state_cookie = Some(state_cookie.to_vec());
+          }
+          SctpChunk::CookieAck => {
+            self.association.established = true;
+          }
+          SctpChunk::Data {
+            chunk_flags,
+            tsn,
+            stream_id,
+            proto_id,
+            user_data,
+            ..
+          } => match proto_id {
+            DATA_CHANNEL_PROTO_CONTROL if user_data.first() == Some(&DATA_CHANNEL_MESSAGE_ACK) => {
+              self.association.open_channels.push(stream_id);
+            }
+            // Channels the server opens with `Server::open_channel` are accepted right away.
+            DATA_CHANNEL_PROTO_CONTROL if user_data.first() == Some(&DATA_CHANNEL_MESSAGE_OPEN) => {
+              self.send_data(
+                stream_id,
+                SCTP_FLAG_COMPLETE_UNRELIABLE,
+                DATA_CHANNEL_PROTO_CONTROL,
+                &[DATA_CHANNEL_MESSAGE_ACK],
+              )?;
+              if !self.association.open_channels.contains(&stream_id) {
+                self.association.open_channels.push(stream_id);
+              }
+            }
+            DATA_CHANNEL_PROTO_STRING => {
+              self.receive_data(chunk_flags, tsn, stream_id, user_data, MessageType::Text);
+            }
+            DATA_CHANNEL_PROTO_BINARY => {
+              self.receive_data(chunk_flags, tsn, stream_id, user_data, MessageType::Binary);
+            }
+            _ => {}
+          },
+          // The server resets its side of the streams the client closed, which is only answered.
+          SctpChunk::ReConfig {
+            reset_request: Some(request),
+            ..
+          } => {
+            self.association.remote_reconfig_seq = request.request_seq;
+            self.send_chunk(
+              self.association.remote_tag,
+              SctpChunk::ReConfig {
+                reset_request: None,
+                response: Some((request.request_seq, RECONFIG_RESULT_PERFORMED)),
+              },
+            )?;
+          }
+          SctpChunk::Heartbeat { heartbeat_info } => {
+            let heartbeat_info = heartbeat_info.map(|info| info.to_vec());
+            self.send_chunk(
+              self.association.remote_tag,
+              SctpChunk::HeartbeatAck {
+                heartbeat_info: heartbeat_info.as_deref(),
+              },
+            )?;
+          }
+          SctpChunk::Abort | SctpChunk::Shutdown { .. } => {
+            self.association.closed = true;
+          }
+          _ => {}
+        }
+      }
+      if let (true, Some(remote_tsn)) = (received_data, self.association.remote_tsn) {
+        self.send_chunk(
+          self.association.remote_tag,
+          SctpChunk::SAck {
+            cumulative_tsn_ack: remote_tsn,
+            adv_recv_window: SCTP_BUFFER_SIZE,
+            gap_ack_blocks: &[],
+            num_dup_tsn: 0,
+          },
+        )?;
+      }
+    }
+
+    self.flush().await?;
+    if deadline.is_some() && self.association.closed {
+      return Err(IoErrorKind::ConnectionAborted.into());
+    }
+    Ok(())
+  }
+
+  // Queue a received message once its last fragment arrives.  After a lost fragment the rest of
+  // its message is dropped.
+  fn receive_data(
+    &mut self,
+    chunk_flags: u8,
+    tsn: u32,
+    stream_id: u16,
+    user_data: &[u8],
+    message_type: MessageType,
+  ) {
+    if chunk_flags & SCTP_FLAG_BEGIN_FRAGMENT != 0 {
+      self.association.partial = Some((Vec::new(), tsn));
+    }
+    let mut message = match self.association.partial.take() {
+      Some((message, next_tsn)) if next_tsn == tsn => message,
+      _ => return,
+    };
+    message.extend_from_slice(user_data);
+    if chunk_flags & SCTP_FLAG_END_FRAGMENT != 0 {
+      self.received.push_back((message, message_type, stream_id));
+    } else {
+      self.association.partial = Some((message, tsn.wrapping_add(1)));
+    }
+  }
+}
+
+// The address a loopback client should use to reach a server socket bound to `local_addr`.
+pub(crate) fn loopback_addr(local_addr: SocketAddr) -> SocketAddr {
+  match local_addr.ip() {
+    IpAddr::V4(ip) if ip.is_unspecified() => {
+      SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), local_addr.port())
+    }
+    IpAddr::V6(ip) if ip.is_unspecified() => {
+      SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), local_addr.port())
+    }
+    _ => local_addr,
+  }
+}
+
+// Pull the ICE credentials and certificate fingerprint out of the JSON returned by
+// `SessionEndpoint::session_request`.
+pub(crate) fn parse_answer(response: &str) -> Result<(String, String, String), IoError> {
+  let invalid = |msg| IoError::new(IoErrorKind::InvalidData, msg);
+  let response: serde_json::Value =
+    serde_json::from_str(response).map_err(|_| invalid("session response is not JSON"))?;
+  let sdp = response["answer"]["sdp"]
+    .as_str()
+    .ok_or_else(|| invalid("session response has no answer"))?;
+
+  let mut ufrag = None;
+  let mut passwd = None;
+  let mut fingerprint = None;
+  for line in sdp.lines() {
+    if let Some(value) = line.strip_prefix("a=ice-ufrag:") {
+      ufrag = Some(value.to_owned());
+    } else if let Some(value) = line.strip_prefix("a=ice-pwd:") {
+      passwd = Some(value.to_owned());
+    } else if let Some(value) = line.strip_prefix("a=fingerprint:sha-256 ") {
+      fingerprint = Some(value.to_owned());
+    }
+  }
+
+  match (ufrag, passwd, fingerprint) {
+    (Some(ufrag), Some(passwd), Some(fingerprint)) => Ok((ufrag, passwd, fingerprint)),
+    _ => Err(invalid("answer is missing ICE credentials or fingerprint")),
+  }
+}
+
+fn format_fingerprint(digest: &[u8]) -> String {
+  let mut fingerprint = String::new();
+  for (i, b) in digest.iter().enumerate() {
+    if i != 0 {
+      fingerprint.push(':');
+    }
+    write!(fingerprint, "{:02X}", b).unwrap();
+  }
+  fingerprint
+}
+
+// DTLS records start with a content type in the range reserved for them by RFC 7983.
+fn is_dtls_packet(packet: &[u8]) -> bool {
+  matches!(packet.first(), Some(20..=63))
+}
+
+async fn flush_packets(
+  socket: &Async<UdpSocket>,
+  server_addr: SocketAddr,
+  buffer_pool: &BufferPool,
+  packets: &mut ClientSslPackets,
+) -> Result<(), IoError> {
+  while let Some(packet) = packets.outgoing_udp.pop_front() {
+    let packet = buffer_pool.adopt(packet);
+    socket.send_to(&packet, server_addr).await?;
+  }
+  Ok(())
+}
+
+// Send a binding request from `socket` until the server answers it with a success response.  An
+// error response fails the check if `errors_are_final`, and is otherwise retried, as the server may
+// not have taken in the session yet.
+async fn check_pair(
+  socket: &Async<UdpSocket>,
+  server_addr: SocketAddr,
+  username: &str,
+  passwd: &str,
+  use_candidate: bool,
+  errors_are_final: bool,
+  deadline: Instant,
+) -> Result<(), IoError> {
+  let mut packet = vec![0; MAX_UDP_PAYLOAD_SIZE];
+  let transaction_id: [u8; 12] = thread_rng().gen();
+  let request_len = write_stun_binding_request(
+    transaction_id,
+    username.as_bytes(),
+    passwd.as_bytes(),
+    thread_rng().gen(),
+    use_candidate,
+    &mut packet,
+  )
+  .map_err(|err| IoError::other(err.to_string()))?;
+  let request = packet[..request_len].to_vec();
+  loop {
+    socket.send_to(&request, server_addr).await?;
+    let retransmit_at = (Instant::now() + STUN_RETRANSMIT_INTERVAL).min(deadline);
+    while let Some(len) = recv_until(socket, server_addr, &mut packet, retransmit_at).await? {
+      if is_stun_success_response(&packet[..len], transaction_id) {
+        return Ok(());
+      }
+      let error_code = stun_error_response_code(&packet[..len], transaction_id);
+      if let Some(code) = error_code.filter(|_| errors_are_final) {
+        let kind = match code {
+          401 => IoErrorKind::PermissionDenied,
+          _ => IoErrorKind::Other,
+        };
+        return Err(IoError::new(kind, format!("STUN error response {}", code)));
+      }
+    }
+    if Instant::now() >= deadline {
+      return Err(IoError::new(
+        IoErrorKind::TimedOut,
+        "no STUN response from server",
+      ));
+    }
+  }
+}
+
+// Receive the next packet from `server_addr`, or None once `deadline` has passed.
+async fn recv_until(
+  socket: &Async<UdpSocket>,
+  server_addr: SocketAddr,
+  buf: &mut [u8],
+  deadline: Instant,
+) -> Result<Option<usize>, IoError> {
+  let timer = Timer::at(deadline).fuse();
+  pin_mut!(timer);
+  loop {
+    let recv = socket.recv_from(buf).fuse();
+    pin_mut!(recv);
+    select! {
+      res = recv => {
+        let (len, addr) = res?;
+        if addr == server_addr {
+          return Ok(Some(len));
+        }
+      }
+      _ = timer => return Ok(None),
+    }
+  }
+}
@@ -0,0 +1,113 @@
+use std::net::SocketAddr;
+
+use crate::{
+  buffer_pool::{BufferPool, OwnedBuffer},
+  client::MessageType,
+};
+
+/// A message passing through the middleware added with `Server::add_inbound_middleware` or
+/// `Server::add_outbound_middleware`.
+///
+/// Middleware may rewrite the message in place, for example to compress or decompress it, and
+/// change its type accordingly.  `remote_addr` is the client the message was received from or is
+/// being sent to.
+pub struct MessageCtx<'a> {
+  pub remote_addr: SocketAddr,
+  pub message_type: MessageType,
+  /// Subprotocol tag of the data channel a received message arrived on, as in `MessageResult`,
+  /// always `None` for outbound messages.
+  pub subprotocol: Option<u8>,
+  pub message: &'a mut Vec<u8>,
+}
+
+/// What middleware decided to do with a message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+  /// Pass the message on to the next middleware, and after the last one deliver it to
+  /// `Server::recv` or send it.
+  Continue,
+  /// Discard the message.
+  Drop,
+  /// Discard the message and disconnect the client.  Inbound, any other messages received along
+  /// with it are discarded too.
+  Disconnect,
+}
+
+/// How many times one middleware returned each `Verdict`, part of `ServerStats`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MiddlewareStats {
+  /// The name the middleware was added with.
+  pub name: String,
+  pub continued: u64,
+  pub dropped: u64,
+  pub disconnected: u64,
+}
+
+pub(crate) type MessageFilter = Box<dyn FnMut(&mut MessageCtx) -> Verdict + Send>;
+
+// An ordered chain of middleware, for either direction.
+pub struct MiddlewareChain {
+  stages: Vec<(MessageFilter, MiddlewareStats)>,
+}
+
+impl MiddlewareChain {
+  pub fn new() -> MiddlewareChain {
+    MiddlewareChain { stages: Vec::new() }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.stages.is_empty()
+  }
+
+  pub fn push(&mut self, name: String, filter: MessageFilter) {
+    self.stages.push((
+      filter,
+      MiddlewareStats {
+        name,
+        ..Default::default()
+      },
+    ));
+  }
+
+  // Run a message through every stage in order, returning it if it should be delivered or
+  // sent.
+  pub fn run(
+    &mut self,
+    buffer_pool: &BufferPool,
+    remote_addr: SocketAddr,
+    message_type: MessageType,
+    subprotocol: Option<u8>,
+    message: OwnedBuffer,
+  ) -> Result<(MessageType, OwnedBuffer), Verdict> {
+    if self.stages.is_empty() {
+      return Ok((message_type, message));
+    }
+
+    let mut message = buffer_pool.adopt(message);
+    let mut ctx = MessageCtx {
+      remote_addr,
+      message_type,
+      subprotocol,
+      message: &mut message,
+    };
+    for (filter, stats) in &mut self.stages {
+      match filter(&mut ctx) {
+        Verdict::Continue => stats.continued += 1,
+        Verdict::Drop => {
+          stats.dropped += 1;
+          return Err(Verdict::Drop);
+        }
+        Verdict::Disconnect => {
+          stats.disconnected += 1;
+          return Err(Verdict::Disconnect);
+        }
+      }
+    }
+    let message_type = ctx.message_type;
+    Ok((message_type, message.into_owned()))
+  }
+
+  pub fn stats(&self) -> Vec<MiddlewareStats> {
+    self.stages.iter().map(|(_, stats)| stats.clone()).collect()
+  }
+}
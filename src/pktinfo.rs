@@ -0,0 +1,216 @@
+use std::{
+  io::Error as IoError,
+  mem::MaybeUninit,
+  net::{IpAddr, SocketAddr, UdpSocket},
+};
+
+// The local end of a client's path: the index of the listening socket its packets arrive on, and
+// for a socket bound to a wildcard address, the address they were sent to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LocalSocket {
+  pub index: usize,
+  pub ip: Option<IpAddr>,
+}
+
+// Ask the kernel to report the destination address of every datagram received on a socket bound
+// to a wildcard address, so replies can be sent from that same address.  Only supported on Linux,
+// elsewhere replies leave from whatever address the routing table picks.
+#[cfg(target_os = "linux")]
+pub fn enable_pktinfo(socket: &UdpSocket) -> Result<(), IoError> {
+  use std::os::unix::io::AsRawFd;
+
+  let fd = socket.as_raw_fd();
+  let enable = |level, name| -> Result<(), IoError> {
+    let on: libc::c_int = 1;
+    let res = unsafe {
+      libc::setsockopt(
+        fd,
+        level,
+        name,
+        &on as *const libc::c_int as *const libc::c_void,
+        std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+      )
+    };
+    if res == 0 {
+      Ok(())
+    } else {
+      Err(IoError::last_os_error())
+    }
+  };
+
+  if socket.local_addr()?.is_ipv6() {
+    enable(libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)?;
+    // IPv4 datagrams arriving on a dual stack socket report their destination this way.
+    let _ = enable(libc::IPPROTO_IP, libc::IP_PKTINFO);
+    Ok(())
+  } else {
+    enable(libc::IPPROTO_IP, libc::IP_PKTINFO)
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_pktinfo(_socket: &UdpSocket) -> Result<(), IoError> {
+  Ok(())
+}
+
+// Receive a datagram, along with the local address it was sent to if the socket reports it.  The
+// buffer need not be initialized, only the first bytes up to the returned length are once this
+// returns.
+#[cfg(target_os = "linux")]
+pub fn recv_from_to(
+  socket: &UdpSocket,
+  buf: &mut [MaybeUninit<u8>],
+) -> Result<(usize, SocketAddr, Option<IpAddr>), IoError> {
+  use std::{
+    mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
+    os::unix::io::AsRawFd,
+    ptr,
+  };
+
+  let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+  let mut control = [0u64; CONTROL_LEN / 8];
+  let mut iov = libc::iovec {
+    iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+    iov_len: buf.len(),
+  };
+  let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+  msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+  msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+  msg.msg_iov = &mut iov;
+  msg.msg_iovlen = 1;
+  msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+  msg.msg_controllen = CONTROL_LEN as _;
+
+  let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+  if len < 0 {
+    return Err(IoError::last_os_error());
+  }
+
+  let remote_addr = match name.ss_family as libc::c_int {
+    libc::AF_INET => {
+      let addr = unsafe { &*(&name as *const _ as *const libc::sockaddr_in) };
+      SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        u16::from_be(addr.sin_port),
+      ))
+    }
+    libc::AF_INET6 => {
+      let addr = unsafe { &*(&name as *const _ as *const libc::sockaddr_in6) };
+      SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::from(addr.sin6_addr.s6_addr),
+        u16::from_be(addr.sin6_port),
+        addr.sin6_flowinfo,
+        addr.sin6_scope_id,
+      ))
+    }
+    _ => return Err(IoError::other("datagram from unknown address family")),
+  };
+
+  let mut local_ip = None;
+  unsafe {
+    let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+    while !cmsg.is_null() {
+      let header = &*cmsg;
+      if header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_PKTINFO {
+        let info: libc::in_pktinfo = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+        local_ip = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+          info.ipi_addr.s_addr,
+        ))));
+      } else if header.cmsg_level == libc::IPPROTO_IPV6 && header.cmsg_type == libc::IPV6_PKTINFO {
+        let info: libc::in6_pktinfo = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+        local_ip = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+      }
+      cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+    }
+  }
+
+  Ok((len as usize, remote_addr, local_ip))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_from_to(
+  socket: &UdpSocket,
+  buf: &mut [MaybeUninit<u8>],
+) -> Result<(usize, SocketAddr, Option<IpAddr>), IoError> {
+  let (len, remote_addr) = socket2::SockRef::from(socket).recv_from(buf)?;
+  let remote_addr = remote_addr
+    .as_socket()
+    .ok_or_else(|| IoError::other("datagram from unknown address family"))?;
+  Ok((len, remote_addr, None))
+}
+
+// Send a datagram from the local address `local_ip`, which must be one the socket's wildcard
+// address covers.
+#[cfg(target_os = "linux")]
+pub fn send_to_from(
+  socket: &UdpSocket,
+  buf: &[u8],
+  remote_addr: SocketAddr,
+  local_ip: IpAddr,
+) -> Result<usize, IoError> {
+  use std::{mem, os::unix::io::AsRawFd, ptr};
+
+  use socket2::SockAddr;
+
+  let name = SockAddr::from(remote_addr);
+  let mut control = [0u64; CONTROL_LEN / 8];
+  let mut iov = libc::iovec {
+    iov_base: buf.as_ptr() as *mut libc::c_void,
+    iov_len: buf.len(),
+  };
+  let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+  msg.msg_name = name.as_ptr() as *mut libc::c_void;
+  msg.msg_namelen = name.len();
+  msg.msg_iov = &mut iov;
+  msg.msg_iovlen = 1;
+  msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+
+  unsafe {
+    match local_ip {
+      IpAddr::V4(ip) => {
+        let data_len = mem::size_of::<libc::in_pktinfo>() as u32;
+        msg.msg_controllen = libc::CMSG_SPACE(data_len) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+        let mut info: libc::in_pktinfo = mem::zeroed();
+        info.ipi_spec_dst.s_addr = u32::from(ip).to_be();
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, info);
+      }
+      IpAddr::V6(ip) => {
+        let data_len = mem::size_of::<libc::in6_pktinfo>() as u32;
+        msg.msg_controllen = libc::CMSG_SPACE(data_len) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+        (*cmsg).cmsg_type = libc::IPV6_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+        let mut info: libc::in6_pktinfo = mem::zeroed();
+        info.ipi6_addr.s6_addr = ip.octets();
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo, info);
+      }
+    }
+  }
+
+  let len = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+  if len < 0 {
+    Err(IoError::last_os_error())
+  } else {
+    Ok(len as usize)
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_to_from(
+  socket: &UdpSocket,
+  buf: &[u8],
+  remote_addr: SocketAddr,
+  _local_ip: IpAddr,
+) -> Result<usize, IoError> {
+  socket.send_to(buf, remote_addr)
+}
+
+// Room for one IPv4 and one IPv6 packet info control message, in units of `u64` for alignment.
+#[cfg(target_os = "linux")]
+const CONTROL_LEN: usize = 128;
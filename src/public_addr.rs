@@ -0,0 +1,77 @@
+use std::{
+  io::{Error as IoError, ErrorKind as IoErrorKind},
+  net::{SocketAddr, ToSocketAddrs},
+  thread,
+};
+
+/// The address advertised to browsers for one listening socket.
+///
+/// A `PublicAddr::Host` is resolved when the server starts, and then re-resolved in the
+/// background every `ServerConfig::dns_refresh_interval` so that deployments behind dynamic DNS
+/// pick up a new IP without restarting.  Clients that are already connected keep using the
+/// address they connected to, only new session answers advertise the new one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PublicAddr {
+  Addr(SocketAddr),
+  Host(String, u16),
+}
+
+impl PublicAddr {
+  pub fn host(host: impl Into<String>, port: u16) -> PublicAddr {
+    PublicAddr::Host(host.into(), port)
+  }
+}
+
+impl From<SocketAddr> for PublicAddr {
+  fn from(addr: SocketAddr) -> PublicAddr {
+    PublicAddr::Addr(addr)
+  }
+}
+
+// A configured public hostname, along with the index of the listening socket it is advertised
+// for.
+#[derive(Clone, Debug)]
+pub struct PublicHost {
+  pub index: usize,
+  pub host: String,
+  pub port: u16,
+  pub listen_addr: SocketAddr,
+}
+
+impl PublicHost {
+  // Resolve this host with the system resolver, preferring an address of the same family as the
+  // listening socket.
+  pub fn resolve(&self) -> Result<SocketAddr, IoError> {
+    let mut first = None;
+    for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+      if addr.is_ipv4() == self.listen_addr.is_ipv4() {
+        return Ok(addr);
+      }
+      first.get_or_insert(addr);
+    }
+    first.ok_or_else(|| {
+      IoError::new(
+        IoErrorKind::NotFound,
+        format!("public host {} did not resolve to any address", self.host),
+      )
+    })
+  }
+}
+
+// Resolve every host on a background thread, so the (blocking) system resolver never stalls the
+// server, sending each result to `results` as `(index, result)`.
+pub fn spawn_resolve(
+  hosts: Vec<PublicHost>,
+  results: flume::Sender<(usize, Result<SocketAddr, IoError>)>,
+) -> Result<(), IoError> {
+  thread::Builder::new()
+    .name("unreliablertc-dns".to_owned())
+    .spawn(move || {
+      for host in hosts {
+        if results.send((host.index, host.resolve())).is_err() {
+          break;
+        }
+      }
+    })
+    .map(|_| ())
+}
@@ -0,0 +1,17 @@
+use std::{io::Error as IoError, net::SocketAddr};
+
+use crate::client::MessageType;
+
+/// A message received from a client handed to another server with `Server::relay`, after the
+/// inbound middleware has run.
+///
+/// The transport only borrows the message, so it must copy or send it before returning.
+pub struct RelayedMessage<'a> {
+  pub remote_addr: SocketAddr,
+  pub message_type: MessageType,
+  /// Subprotocol tag of the data channel the message arrived on, as in `MessageResult`.
+  pub subprotocol: Option<u8>,
+  pub message: &'a [u8],
+}
+
+pub(crate) type RelayTransport = Box<dyn FnMut(&RelayedMessage) -> Result<(), IoError> + Send>;
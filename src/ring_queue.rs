@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+
+/// What a `RingQueue` does with new items once it holds as many as its capacity.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum OverflowPolicy {
+  /// Discard the item being queued.
+  DropNewest,
+  /// Discard the oldest queued item to make room for the new one.
+  DropOldest,
+  /// Keep every item, reallocating the queue beyond its initial capacity.
+  Grow,
+}
+
+/// FIFO queue backed by a ring buffer which is allocated up front, so that pushing never has to
+/// reallocate while fewer than `capacity` items are queued.
+#[derive(Debug)]
+pub struct RingQueue<T> {
+  items: VecDeque<T>,
+  capacity: usize,
+  overflow: OverflowPolicy,
+  dropped: u64,
+}
+
+impl<T> RingQueue<T> {
+  pub fn new(capacity: usize, overflow: OverflowPolicy) -> RingQueue<T> {
+    RingQueue {
+      items: VecDeque::with_capacity(capacity),
+      capacity,
+      overflow,
+      dropped: 0,
+    }
+  }
+
+  /// Queue an item, returns false if an item had to be dropped because the queue was full.
+  pub fn push_back(&mut self, item: T) -> bool {
+    if self.items.len() < self.capacity {
+      self.items.push_back(item);
+      return true;
+    }
+
+    match self.overflow {
+      OverflowPolicy::DropNewest => {
+        self.dropped += 1;
+        false
+      }
+      OverflowPolicy::DropOldest => {
+        self.items.pop_front();
+        self.items.push_back(item);
+        self.dropped += 1;
+        false
+      }
+      OverflowPolicy::Grow => {
+        self.items.push_back(item);
+        true
+      }
+    }
+  }
+
+  pub fn pop_front(&mut self) -> Option<T> {
+    self.items.pop_front()
+  }
+
+  pub fn front(&self) -> Option<&T> {
+    self.items.front()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    self.items.iter()
+  }
+
+  pub fn clear(&mut self) {
+    self.items.clear();
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  /// The number of items the queue holds before it starts dropping them, or `None` if it grows
+  /// instead.
+  pub fn bounded_capacity(&self) -> Option<usize> {
+    match self.overflow {
+      OverflowPolicy::Grow => None,
+      OverflowPolicy::DropNewest | OverflowPolicy::DropOldest => Some(self.capacity),
+    }
+  }
+
+  /// Total number of items which have been dropped because the queue was full.
+  pub fn dropped(&self) -> u64 {
+    self.dropped
+  }
+}
+
+impl<T> Extend<T> for RingQueue<T> {
+  fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+    for item in iter {
+      self.push_back(item);
+    }
+  }
+}
@@ -0,0 +1,97 @@
+use std::{
+  cmp::{Ordering, Reverse},
+  collections::BinaryHeap,
+  net::SocketAddr,
+  time::Instant,
+};
+
+use crate::{buffer_pool::OwnedBuffer, client::MessageType};
+
+// A message queued with `Server::send_at`.
+pub struct ScheduledSend {
+  pub at: Instant,
+  // Breaks ties between messages due at the same instant, so they go out in the order they were
+  // scheduled.
+  seq: u64,
+  pub message: OwnedBuffer,
+  pub message_type: MessageType,
+  pub remote_addr: SocketAddr,
+}
+
+impl PartialEq for ScheduledSend {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for ScheduledSend {}
+
+impl PartialOrd for ScheduledSend {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for ScheduledSend {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.at, self.seq).cmp(&(other.at, other.seq))
+  }
+}
+
+// Outgoing messages waiting for their transmit time, earliest first.
+#[derive(Default)]
+pub struct SendSchedule {
+  heap: BinaryHeap<Reverse<ScheduledSend>>,
+  next_seq: u64,
+}
+
+impl SendSchedule {
+  pub fn push(
+    &mut self,
+    at: Instant,
+    message: OwnedBuffer,
+    message_type: MessageType,
+    remote_addr: SocketAddr,
+  ) {
+    let seq = self.next_seq;
+    self.next_seq += 1;
+    self.heap.push(Reverse(ScheduledSend {
+      at,
+      seq,
+      message,
+      message_type,
+      remote_addr,
+    }));
+  }
+
+  // When the earliest message is due, if there is one.
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self.heap.peek().map(|Reverse(send)| send.at)
+  }
+
+  // Take the earliest message if it is due by `now`.
+  pub fn pop_due(&mut self, now: Instant) -> Option<ScheduledSend> {
+    if self.next_deadline()? <= now {
+      self.heap.pop().map(|Reverse(send)| send)
+    } else {
+      None
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.heap.len()
+  }
+
+  // Memory held by the waiting messages.
+  pub fn bytes(&self) -> usize {
+    self
+      .heap
+      .iter()
+      .map(|Reverse(send)| send.message.capacity())
+      .sum()
+  }
+
+  pub fn clear(&mut self) {
+    self.heap.clear();
+  }
+}
@@ -1,7 +1,8 @@
 use std::{convert::TryInto, error::Error, fmt};
 
 use byteorder::{ByteOrder, LittleEndian, NetworkEndian};
-use crc::{Crc, CRC_32_ISCSI};
+
+use crate::crc32c::{crc32c, Crc32c};
 
 pub const SCTP_FLAG_END_FRAGMENT: u8 = 0x01;
 pub const SCTP_FLAG_BEGIN_FRAGMENT: u8 = 0x02;
@@ -39,7 +40,9 @@ pub enum SctpChunk<'a> {
   SAck {
     cumulative_tsn_ack: u32,
     adv_recv_window: u32,
-    num_gap_ack_blocks: u16,
+    /// Start and end offsets from `cumulative_tsn_ack` of each block of TSNs received beyond it,
+    /// as pairs of big endian `u16`s.
+    gap_ack_blocks: &'a [u8],
     num_dup_tsn: u16,
   },
   Heartbeat {
@@ -64,16 +67,39 @@ pub enum SctpChunk<'a> {
   ShutdownComplete,
   Auth,
   AsConfAck,
-  ReConfig,
+  ReConfig {
+    /// An Outgoing SSN Reset Request parameter (RFC 6525 section 4.1), asking to reset streams
+    /// the sender will no longer send on.
+    reset_request: Option<StreamResetRequest<'a>>,
+    /// A Re-configuration Response parameter (RFC 6525 section 4.4), as the sequence number of
+    /// the request it answers and its result.
+    response: Option<(u32, u32)>,
+  },
   Pad,
   IData,
   ForwardTsn {
     new_cumulative_tsn: u32,
+    /// Stream id and stream sequence number of the last skipped ordered message of each stream,
+    /// as pairs of big endian `u16`s.
+    streams: &'a [u8],
   },
   AsConf,
   IForwardTsn,
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct StreamResetRequest<'a> {
+  pub request_seq: u32,
+  pub response_seq: u32,
+  /// The last TSN the sender assigned before resetting the streams.
+  pub last_tsn: u32,
+  /// Ids of the streams to reset as big endian `u16`s, all of them if empty.
+  pub streams: &'a [u8],
+}
+
+/// The result of a stream reset which was performed (RFC 6525 section 4.4).
+pub const RECONFIG_RESULT_PERFORMED: u32 = 1;
+
 #[derive(Debug)]
 pub struct SctpPacket<'a> {
   pub source_port: u16,
@@ -116,7 +142,7 @@ pub fn read_sctp_packet<'a>(
   let checksum = LittleEndian::read_u32(&src[8..12]);
 
   if check_crc {
-    let mut digest = CRC.digest();
+    let mut digest = Crc32c::new();
     digest.update(&src[0..8]);
     digest.update(&[0, 0, 0, 0]);
     digest.update(&src[12..]);
@@ -127,7 +153,7 @@ pub fn read_sctp_packet<'a>(
 
   let mut remaining_chunks = &src[12..];
   let mut chunk_count = 0;
-  while remaining_chunks.len() > 4 {
+  while remaining_chunks.len() >= 4 {
     if chunk_count >= chunk_space.len() {
       return Err(SctpReadError::TooManyChunks);
     }
@@ -214,14 +240,17 @@ pub fn read_sctp_packet<'a>(
             support_unreliable,
           };
         } else {
-          let (param_type, param_data) = iter_params(&chunk_data, 16)
-            .next()
-            .ok_or_else(|| SctpReadError::BadPacket)
-            .and_then(|v| v.map_err(|_| SctpReadError::BadPacket))?;
-          // first parameter must be the state cookie
-          if param_type != INIT_ACK_PARAM_STATE_COOKIE {
-            return Err(SctpReadError::BadPacket);
+          // the state cookie is mandatory, but may come after optional parameters such as
+          // forward tsn support
+          let mut state_cookie = None;
+          for param in iter_params(&chunk_data, 16) {
+            let (param_type, param_data) = param.map_err(|_| SctpReadError::BadPacket)?;
+            if param_type == INIT_ACK_PARAM_STATE_COOKIE {
+              state_cookie = Some(param_data);
+              break;
+            }
           }
+          let param_data = state_cookie.ok_or(SctpReadError::BadPacket)?;
 
           *chunk = SctpChunk::InitAck {
             initiate_tag,
@@ -240,13 +269,16 @@ pub fn read_sctp_packet<'a>(
 
         let cumulative_tsn_ack = NetworkEndian::read_u32(&chunk_data[0..4]);
         let adv_recv_window = NetworkEndian::read_u32(&chunk_data[4..8]);
-        let num_gap_ack_blocks = NetworkEndian::read_u16(&chunk_data[8..10]);
+        let num_gap_ack_blocks = NetworkEndian::read_u16(&chunk_data[8..10]) as usize;
         let num_dup_tsn = NetworkEndian::read_u16(&chunk_data[10..12]);
+        let gap_ack_blocks = chunk_data
+          .get(12..12 + num_gap_ack_blocks * 4)
+          .ok_or(SctpReadError::BadPacket)?;
 
         *chunk = SctpChunk::SAck {
           cumulative_tsn_ack,
           adv_recv_window,
-          num_gap_ack_blocks,
+          gap_ack_blocks,
           num_dup_tsn,
         };
       }
@@ -255,7 +287,9 @@ pub fn read_sctp_packet<'a>(
         if chunk_data.len() > 4 {
           let param_type = NetworkEndian::read_u16(&chunk_data[0..2]);
           let param_len = NetworkEndian::read_u16(&chunk_data[2..4]);
-          if param_type == HEARTBEAT_PARAM_INFO && (param_len as usize) < chunk_data.len() {
+          if param_type == HEARTBEAT_PARAM_INFO
+            && (4..=chunk_data.len()).contains(&(param_len as usize))
+          {
             heartbeat_info = Some(&chunk_data[4..param_len as usize]);
           }
         }
@@ -310,7 +344,32 @@ pub fn read_sctp_packet<'a>(
         *chunk = SctpChunk::AsConfAck;
       }
       CHUNK_TYPE_RE_CONFIG => {
-        *chunk = SctpChunk::ReConfig;
+        let mut reset_request = None;
+        let mut response = None;
+        for param in iter_params(chunk_data, 0) {
+          let (param_type, param_data) = param.map_err(|_| SctpReadError::BadPacket)?;
+          match param_type {
+            RECONFIG_PARAM_OUTGOING_RESET if param_data.len() >= 12 => {
+              reset_request = Some(StreamResetRequest {
+                request_seq: NetworkEndian::read_u32(&param_data[0..4]),
+                response_seq: NetworkEndian::read_u32(&param_data[4..8]),
+                last_tsn: NetworkEndian::read_u32(&param_data[8..12]),
+                streams: &param_data[12..param_data.len() & !1],
+              });
+            }
+            RECONFIG_PARAM_RESPONSE if param_data.len() >= 8 => {
+              response = Some((
+                NetworkEndian::read_u32(&param_data[0..4]),
+                NetworkEndian::read_u32(&param_data[4..8]),
+              ));
+            }
+            _ => {}
+          }
+        }
+        *chunk = SctpChunk::ReConfig {
+          reset_request,
+          response,
+        };
       }
       CHUNK_TYPE_PAD => {
         *chunk = SctpChunk::Pad;
@@ -324,7 +383,10 @@ pub fn read_sctp_packet<'a>(
         }
 
         let new_cumulative_tsn = NetworkEndian::read_u32(&chunk_data[0..4]);
-        *chunk = SctpChunk::ForwardTsn { new_cumulative_tsn };
+        *chunk = SctpChunk::ForwardTsn {
+          new_cumulative_tsn,
+          streams: &chunk_data[4..],
+        };
       }
       CHUNK_TYPE_ASCONF => {
         *chunk = SctpChunk::AsConf;
@@ -445,7 +507,7 @@ pub fn write_sctp_packet(dest: &mut [u8], packet: SctpPacket) -> Result<usize, S
         initial_tsn,
         state_cookie,
       } => {
-        let data_len = 24 + state_cookie.len();
+        let data_len = 32 + state_cookie.len();
         if chunk_data.len() < data_len {
           return Err(SctpWriteError::BufferSize);
         }
@@ -459,33 +521,49 @@ pub fn write_sctp_packet(dest: &mut [u8], packet: SctpPacket) -> Result<usize, S
         NetworkEndian::write_u16(&mut chunk_data[16..18], INIT_PARAM_FORWARD_TSN);
         NetworkEndian::write_u16(&mut chunk_data[18..20], 4);
 
-        NetworkEndian::write_u16(&mut chunk_data[20..22], INIT_ACK_PARAM_STATE_COOKIE);
+        // supported extensions parameter, so that peers reset streams to close data channels
+        NetworkEndian::write_u16(&mut chunk_data[20..22], INIT_PARAM_SUPPORTED_EXTENSIONS);
+        NetworkEndian::write_u16(&mut chunk_data[22..24], 6);
+        chunk_data[24..28].copy_from_slice(&[
+          INIT_PARAM_EXT_FORWARD_TSN,
+          CHUNK_TYPE_RE_CONFIG,
+          0,
+          0,
+        ]);
+
+        NetworkEndian::write_u16(&mut chunk_data[28..30], INIT_ACK_PARAM_STATE_COOKIE);
         NetworkEndian::write_u16(
-          &mut chunk_data[22..24],
+          &mut chunk_data[30..32],
           (state_cookie.len() + 4)
             .try_into()
             .map_err(|_| SctpWriteError::OutOfRange)?,
         );
 
-        chunk_data[24..data_len].copy_from_slice(state_cookie);
+        chunk_data[32..data_len].copy_from_slice(state_cookie);
 
         (CHUNK_TYPE_INIT_ACK, 0, data_len)
       }
       SctpChunk::SAck {
         cumulative_tsn_ack,
         adv_recv_window,
-        num_gap_ack_blocks,
+        gap_ack_blocks,
         num_dup_tsn,
       } => {
-        let data_len = 12;
+        let data_len = 12 + gap_ack_blocks.len();
         if chunk_data.len() < data_len {
           return Err(SctpWriteError::BufferSize);
         }
 
         NetworkEndian::write_u32(&mut chunk_data[0..4], cumulative_tsn_ack);
         NetworkEndian::write_u32(&mut chunk_data[4..8], adv_recv_window);
-        NetworkEndian::write_u16(&mut chunk_data[8..10], num_gap_ack_blocks);
+        NetworkEndian::write_u16(
+          &mut chunk_data[8..10],
+          (gap_ack_blocks.len() / 4)
+            .try_into()
+            .map_err(|_| SctpWriteError::OutOfRange)?,
+        );
         NetworkEndian::write_u16(&mut chunk_data[10..12], num_dup_tsn);
+        chunk_data[12..data_len].copy_from_slice(gap_ack_blocks);
 
         (CHUNK_TYPE_SACK, 0, data_len)
       }
@@ -536,14 +614,59 @@ pub fn write_sctp_packet(dest: &mut [u8], packet: SctpPacket) -> Result<usize, S
       }
       SctpChunk::CookieAck => (CHUNK_TYPE_COOKIE_ACK, 0, 0),
       SctpChunk::ShutdownComplete => (CHUNK_TYPE_SHUTDOWN_COMPLETE, 0, 0),
-      SctpChunk::ForwardTsn { new_cumulative_tsn } => {
-        let data_len = 4;
-        if chunk_data.len() < 4 {
+      SctpChunk::ForwardTsn {
+        new_cumulative_tsn,
+        streams,
+      } => {
+        let data_len = 4 + streams.len();
+        if chunk_data.len() < data_len {
           return Err(SctpWriteError::BufferSize);
         }
         NetworkEndian::write_u32(&mut chunk_data[0..4], new_cumulative_tsn);
+        chunk_data[4..data_len].copy_from_slice(streams);
         (CHUNK_TYPE_FORWARD_TSN, 0, data_len)
       }
+      SctpChunk::ReConfig {
+        reset_request,
+        response,
+      } => {
+        let mut data_len = 0;
+        if let Some((response_seq, result)) = response {
+          if chunk_data.len() < data_len + 12 {
+            return Err(SctpWriteError::BufferSize);
+          }
+          let param = &mut chunk_data[data_len..data_len + 12];
+          NetworkEndian::write_u16(&mut param[0..2], RECONFIG_PARAM_RESPONSE);
+          NetworkEndian::write_u16(&mut param[2..4], 12);
+          NetworkEndian::write_u32(&mut param[4..8], response_seq);
+          NetworkEndian::write_u32(&mut param[8..12], result);
+          data_len += 12;
+        }
+        if let Some(request) = reset_request {
+          let param_len = 16 + request.streams.len();
+          let padded_len = next_multiple(param_len, 4);
+          if chunk_data.len() < data_len + padded_len {
+            return Err(SctpWriteError::BufferSize);
+          }
+          let param = &mut chunk_data[data_len..data_len + padded_len];
+          NetworkEndian::write_u16(&mut param[0..2], RECONFIG_PARAM_OUTGOING_RESET);
+          NetworkEndian::write_u16(
+            &mut param[2..4],
+            param_len
+              .try_into()
+              .map_err(|_| SctpWriteError::OutOfRange)?,
+          );
+          NetworkEndian::write_u32(&mut param[4..8], request.request_seq);
+          NetworkEndian::write_u32(&mut param[8..12], request.response_seq);
+          NetworkEndian::write_u32(&mut param[12..16], request.last_tsn);
+          param[16..param_len].copy_from_slice(request.streams);
+          for byte in &mut param[param_len..] {
+            *byte = 0;
+          }
+          data_len += padded_len;
+        }
+        (CHUNK_TYPE_RE_CONFIG, 0, data_len)
+      }
       chunk => unimplemented!("write for SCTP chunk {:?} not implemented", chunk),
     };
 
@@ -568,14 +691,12 @@ pub fn write_sctp_packet(dest: &mut [u8], packet: SctpPacket) -> Result<usize, S
 
   let remainder = rest.len();
   let len = dest.len() - remainder;
-  let crc = CRC.checksum(&dest[0..len]);
+  let crc = crc32c(&dest[0..len]);
   LittleEndian::write_u32(&mut dest[8..12], crc);
 
   Ok(len)
 }
 
-const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
-
 const CHUNK_TYPE_DATA: u8 = 0x00;
 const CHUNK_TYPE_INIT: u8 = 0x01;
 const CHUNK_TYPE_INIT_ACK: u8 = 0x02;
@@ -600,9 +721,11 @@ const CHUNK_TYPE_I_FORWARD_TSN: u8 = 0xc2;
 
 const INIT_ACK_PARAM_STATE_COOKIE: u16 = 0x07;
 const INIT_PARAM_FORWARD_TSN: u16 = 0xc000;
-const HEARTBEAT_PARAM_INFO: u16 = 0x07;
+const HEARTBEAT_PARAM_INFO: u16 = 0x01;
 const INIT_PARAM_SUPPORTED_EXTENSIONS: u16 = 0x8008;
 const INIT_PARAM_EXT_FORWARD_TSN: u8 = 0xc0;
+const RECONFIG_PARAM_OUTGOING_RESET: u16 = 0x0d;
+const RECONFIG_PARAM_RESPONSE: u16 = 0x10;
 enum IterParamsError {
   BufferSize,
 }
@@ -1,18 +1,310 @@
+use openssl::base64;
 use rand::Rng;
-use std::{error, str};
+use serde::{Deserialize, Serialize};
+use std::{
+  error, fmt,
+  net::{IpAddr, SocketAddr},
+  ops::RangeInclusive,
+  str,
+};
 pub type Error = Box<dyn error::Error>;
 
+#[cfg(test)]
+mod answer_format;
+
 #[derive(Debug)]
 pub struct SdpFields {
   pub ice_ufrag: String,
   pub ice_passwd: String,
   pub mid: String,
+  /// Value of `a=max-message-size`, the largest message the offerer can receive.
+  pub max_message_size: Option<usize>,
+  /// The offer's `a=candidate` lines which could be parsed, in order.
+  pub candidates: Vec<IceCandidate>,
+  /// The offer's `a=sendrecv`, `a=sendonly`, `a=recvonly` or `a=inactive`, `SendRecv` if it has
+  /// none.
+  pub direction: DataDirection,
+}
+
+/// Direction of an offer's data channels, from the client's point of view (RFC 8866 section 6.7).
+///
+/// Browsers always offer `a=sendrecv`, but some SDKs mark their application section `a=recvonly`
+/// or `a=inactive`.  The answer carries the matching direction, such as `a=sendonly` for a
+/// `a=recvonly` offer, see `SdpAnswer::set_direction`, and the negotiated direction is returned
+/// by `Server::data_direction`.  The server does not enforce it, it only tells the application
+/// what the client has promised.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DataDirection {
+  #[default]
+  SendRecv,
+  /// The client sends messages but never receives any.
+  SendOnly,
+  /// The client receives messages but never sends any.
+  RecvOnly,
+  /// The client neither sends nor receives messages.
+  Inactive,
+}
+
+impl DataDirection {
+  /// Whether the client may send messages to the server.
+  pub fn client_sends(self) -> bool {
+    matches!(self, DataDirection::SendRecv | DataDirection::SendOnly)
+  }
+
+  /// Whether the client may receive messages from the server.
+  pub fn client_receives(self) -> bool {
+    matches!(self, DataDirection::SendRecv | DataDirection::RecvOnly)
+  }
+
+  /// The same direction from the point of view of the other side, which is what the answer
+  /// carries.
+  pub fn reverse(self) -> DataDirection {
+    match self {
+      DataDirection::SendOnly => DataDirection::RecvOnly,
+      DataDirection::RecvOnly => DataDirection::SendOnly,
+      direction => direction,
+    }
+  }
+
+  /// Name of the SDP attribute for this direction.
+  pub fn attribute(self) -> &'static str {
+    match self {
+      DataDirection::SendRecv => "sendrecv",
+      DataDirection::SendOnly => "sendonly",
+      DataDirection::RecvOnly => "recvonly",
+      DataDirection::Inactive => "inactive",
+    }
+  }
+
+  fn from_attribute(name: &str) -> Option<DataDirection> {
+    match name {
+      "sendrecv" => Some(DataDirection::SendRecv),
+      "sendonly" => Some(DataDirection::SendOnly),
+      "recvonly" => Some(DataDirection::RecvOnly),
+      "inactive" => Some(DataDirection::Inactive),
+      _ => None,
+    }
+  }
+}
+
+/// An ICE candidate from an `a=candidate` line of a client's offer (RFC 8839 section 5.1).
+///
+/// The server is ICE lite and never sends connectivity checks, so these are only kept for
+/// diagnostics, such as noticing a client which only offered relay candidates.  Candidates
+/// trickled after the offer are not known to the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IceCandidate {
+  pub foundation: String,
+  /// 1 for RTP, which is the only component of a data channel.
+  pub component: u32,
+  /// Transport protocol in lowercase, `"udp"` or `"tcp"`.
+  pub transport: String,
+  pub priority: u32,
+  /// An IP address, or an mDNS name ending in `.local` for browsers hiding their host addresses.
+  pub address: String,
+  pub port: u16,
+  pub kind: CandidateKind,
+  /// The `raddr` and `rport` of reflexive and relay candidates, the address they were derived
+  /// from.
+  pub related_address: Option<(String, u16)>,
+}
+
+/// The `typ` of an `IceCandidate`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CandidateKind {
+  Host,
+  ServerReflexive,
+  PeerReflexive,
+  Relay,
+}
+
+// Parse the value of an `a=candidate` line, returning `None` for anything malformed or of an
+// unknown type.
+fn parse_candidate(value: &str) -> Option<IceCandidate> {
+  let mut fields = value.split_ascii_whitespace();
+  let foundation = fields.next()?.to_owned();
+  let component = fields.next()?.parse().ok()?;
+  let transport = fields.next()?.to_ascii_lowercase();
+  let priority = fields.next()?.parse().ok()?;
+  let address = fields.next()?.to_owned();
+  let port = fields.next()?.parse().ok()?;
+  if fields.next()? != "typ" {
+    return None;
+  }
+  let kind = match fields.next()? {
+    "host" => CandidateKind::Host,
+    "srflx" => CandidateKind::ServerReflexive,
+    "prflx" => CandidateKind::PeerReflexive,
+    "relay" => CandidateKind::Relay,
+    _ => return None,
+  };
+
+  // Extensions come as name and value pairs.
+  let mut related_addr = None;
+  let mut related_port = None;
+  while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+    match name {
+      "raddr" => related_addr = Some(value.to_owned()),
+      "rport" => related_port = value.parse().ok(),
+      _ => {}
+    }
+  }
+
+  Some(IceCandidate {
+    foundation,
+    component,
+    transport,
+    priority,
+    address,
+    port,
+    kind,
+    related_address: related_addr.zip(related_port),
+  })
+}
+
+/// Why `decode_offer` could not make an SDP offer out of a signaling payload.
+#[derive(Debug)]
+pub enum OfferDecodeError {
+  /// The payload was a JSON object, but not an `RTCSessionDescription` with `type` and `sdp`
+  /// string fields.
+  Json(serde_json::Error),
+  /// The payload was an `RTCSessionDescription` of another type than `"offer"`, such as an
+  /// answer or a rollback.
+  NotAnOffer(String),
+  /// The payload was neither SDP nor JSON, and not valid base64 either.
+  Base64,
+  /// The payload decoded from base64 was not UTF-8.
+  Utf8(str::Utf8Error),
+  /// The payload decoded from base64 was neither SDP nor JSON.
+  Unrecognized,
+}
+
+impl fmt::Display for OfferDecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      OfferDecodeError::Json(e) => write!(f, "invalid RTCSessionDescription JSON: {}", e),
+      OfferDecodeError::NotAnOffer(ty) => {
+        write!(
+          f,
+          "RTCSessionDescription is of type {:?}, not \"offer\"",
+          ty
+        )
+      }
+      OfferDecodeError::Base64 => write!(f, "payload is not SDP, JSON or base64"),
+      OfferDecodeError::Utf8(e) => write!(f, "base64 payload is not UTF-8: {}", e),
+      OfferDecodeError::Unrecognized => write!(f, "base64 payload is neither SDP nor JSON"),
+    }
+  }
+}
+
+impl error::Error for OfferDecodeError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      OfferDecodeError::Json(e) => Some(e),
+      OfferDecodeError::Utf8(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+/// Extract the SDP offer from a signaling payload in any of the shapes browser code tends to send
+/// it in: the bare SDP of an `application/sdp` body, the JSON of an `RTCSessionDescription` such
+/// as `RTCPeerConnection.localDescription`, or either of those encoded in base64.
+///
+/// Only the shape of the payload is checked, the SDP itself is validated when the offer is
+/// answered.  `SessionEndpoint::session_request_any` answers payloads decoded with this.
+pub fn decode_offer(payload: &str) -> Result<String, OfferDecodeError> {
+  match decode_offer_text(payload)? {
+    Some(sdp) => Ok(sdp),
+    None => {
+      let decoded = decode_base64(payload).ok_or(OfferDecodeError::Base64)?;
+      let decoded =
+        String::from_utf8(decoded).map_err(|e| OfferDecodeError::Utf8(e.utf8_error()))?;
+      decode_offer_text(&decoded)?.ok_or(OfferDecodeError::Unrecognized)
+    }
+  }
+}
+
+// The SDP of a payload which is either bare SDP or `RTCSessionDescription` JSON, `None` if it is
+// neither.
+fn decode_offer_text(payload: &str) -> Result<Option<String>, OfferDecodeError> {
+  #[derive(Deserialize)]
+  struct SessionDescription {
+    #[serde(rename = "type")]
+    ty: String,
+    sdp: String,
+  }
+
+  let trimmed = payload.trim_start();
+  if trimmed.starts_with("v=") {
+    Ok(Some(trimmed.to_owned()))
+  } else if trimmed.starts_with('{') {
+    let description: SessionDescription =
+      serde_json::from_str(trimmed).map_err(OfferDecodeError::Json)?;
+    if description.ty != "offer" {
+      return Err(OfferDecodeError::NotAnOffer(description.ty));
+    }
+    Ok(Some(description.sdp))
+  } else {
+    Ok(None)
+  }
+}
+
+// Decode standard or URL-safe base64, with or without padding and line breaks.
+fn decode_base64(payload: &str) -> Option<Vec<u8>> {
+  let mut encoded: String = payload
+    .chars()
+    .filter(|c| !c.is_ascii_whitespace())
+    .map(|c| match c {
+      '-' => '+',
+      '_' => '/',
+      c => c,
+    })
+    .collect();
+  if encoded.is_empty() {
+    return None;
+  }
+  match encoded.len() % 4 {
+    1 => return None,
+    2 => encoded.push_str("=="),
+    3 => encoded.push('='),
+    _ => {}
+  }
+  base64::decode_block(&encoded).ok()
+}
+
+// The most lines an offer may have, far more than the few dozen a browser sends, so that a huge
+// body of short lines is rejected before it is parsed.
+const MAX_SDP_LINES: usize = 1024;
+
+// Cheaply reject input which is obviously not an SDP session description, before spending any
+// time on allocation in `parse_sdp_fields`.
+pub fn prevalidate_sdp(body: &str) -> Result<(), Error> {
+  let mut lines = body.trim_start().lines();
+  if lines.next().map(str::trim_end) != Some("v=0") {
+    return Err("sdp does not start with v=0".into());
+  }
+  let mut count = 1;
+  for line in lines {
+    count += 1;
+    if count > MAX_SDP_LINES {
+      return Err("too many sdp lines".into());
+    }
+    let line = line.as_bytes();
+    if !line.is_empty() && (line.len() < 2 || !line[0].is_ascii_lowercase() || line[1] != b'=') {
+      return Err("malformed sdp line".into());
+    }
+  }
+  Ok(())
 }
 
 pub fn parse_sdp_fields(body: &str) -> Result<SdpFields, Error> {
   let mut ice_ufrag = String::new();
   let mut ice_passwd = String::new();
   let mut mid = String::new();
+  let mut max_message_size = None;
+  let mut candidates = Vec::new();
+  let mut direction = DataDirection::default();
   let mut lines = body.lines();
   while let Some(line) = lines.next() {
     if line.starts_with("a=ice-ufrag:") {
@@ -21,58 +313,357 @@ pub fn parse_sdp_fields(body: &str) -> Result<SdpFields, Error> {
       ice_passwd = line[10..].to_string();
     } else if line.starts_with("a=mid:") {
       mid = line[6..].to_string();
+    } else if let Some(size) = line.strip_prefix("a=max-message-size:") {
+      max_message_size = Some(
+        size
+          .trim_end()
+          .parse()
+          .map_err(|_| "invalid max-message-size")?,
+      );
+    } else if let Some(candidate) = line.strip_prefix("a=candidate:") {
+      candidates.extend(parse_candidate(candidate));
+    } else if let Some(name) = line.strip_prefix("a=") {
+      // A media level direction comes after any session level one, and takes precedence.
+      if let Some(parsed) = DataDirection::from_attribute(name.trim_end()) {
+        direction = parsed;
+      }
     }
   }
   if ice_ufrag.is_empty() || ice_passwd.is_empty() || mid.is_empty() {
     return Err("missing ice-ufrag, ice-pwd, or mid".into());
   }
+
+  // These values are echoed back in the answer and used as STUN credentials, so anything which
+  // is not valid according to the grammar of RFC 8839 and RFC 8843 is rejected outright.
+  if !(ICE_UFRAG_LEN.contains(&ice_ufrag.len()) && ice_ufrag.bytes().all(is_ice_char)) {
+    return Err("invalid ice-ufrag".into());
+  }
+  if !(ICE_PWD_LEN.contains(&ice_passwd.len()) && ice_passwd.bytes().all(is_ice_char)) {
+    return Err("invalid ice-pwd".into());
+  }
+  if mid.len() > MAX_MID_LEN || !mid.bytes().all(is_token_char) {
+    return Err("invalid mid".into());
+  }
+
   Ok(SdpFields {
     ice_ufrag,
     ice_passwd,
     mid,
+    max_message_size,
+    candidates,
+    direction,
   })
 }
 
-pub fn gen_sdp_response<R: Rng>(
+// The upper bound on ice-ufrag is lower than that of RFC 8839, it is the longest username the
+// STUN parser accepts.
+const ICE_UFRAG_LEN: RangeInclusive<usize> = 4..=128;
+const ICE_PWD_LEN: RangeInclusive<usize> = 22..=256;
+const MAX_MID_LEN: usize = 256;
+
+// ice-char from RFC 8839 section 5.4
+fn is_ice_char(c: u8) -> bool {
+  c.is_ascii_alphanumeric() || c == b'+' || c == b'/'
+}
+
+// token-char from RFC 8866 section 9
+fn is_token_char(c: u8) -> bool {
+  matches!(
+    c,
+    0x21 | 0x23..=0x27 | 0x2a..=0x2b | 0x2d..=0x2e | 0x30..=0x39 | 0x41..=0x5a | 0x5e..=0x7e
+  )
+}
+
+/// A single `a=` line of an SDP description.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SdpAttribute {
+  pub name: String,
+  pub value: Option<String>,
+}
+
+impl SdpAttribute {
+  pub fn new(name: impl Into<String>, value: Option<String>) -> SdpAttribute {
+    SdpAttribute {
+      name: name.into(),
+      value,
+    }
+  }
+}
+
+/// Which attributes describe the SCTP association in generated SDP answers, set with
+/// `SessionEndpointConfig::answer_format`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SdpAnswerFormat {
+  /// A single `a=sctp-port` and `a=max-message-size` as in RFC 8841, which every current browser
+  /// understands.  This is the default.
+  #[default]
+  Modern,
+  /// Also include the `a=sctpmap` attribute of draft-ietf-mmusic-sctp-sdp-05, advertising this
+  /// many streams, for client stacks which predate RFC 8841.  Strict RFC 8841 parsers may reject
+  /// such answers.
+  Legacy { sctpmap_streams: u16 },
+}
+
+/// The SDP answer which is sent back to a browser in response to its offer.
+///
+/// The answer is generated with a single `m=application` section, and may be adjusted before it
+/// is sent with `SessionEndpointConfig::answer_mutator`, for example to add custom attributes or
+/// to work around a picky client stack.
+#[derive(Clone, Debug)]
+pub struct SdpAnswer {
+  /// Session id of the `o=` line.
+  pub session_id: u32,
+  /// Address used in the `o=` and `c=` lines.
+  pub address: IpAddr,
+  /// Port of the `m=application` line.
+  pub port: u16,
+  /// Attributes at session level, before the `m=` line.
+  pub session_attributes: Vec<SdpAttribute>,
+  /// Attributes of the `m=application` section.
+  pub media_attributes: Vec<SdpAttribute>,
+  /// Values of the `a=candidate` lines which end the media section.  The first one is also
+  /// returned separately for the browser to add with `addIceCandidate`.
+  pub candidates: Vec<String>,
+}
+
+impl SdpAnswer {
+  /// Value of the first session level attribute called `name`.
+  pub fn session_attribute(&self, name: &str) -> Option<&str> {
+    find_attribute(&self.session_attributes, name)
+  }
+
+  /// Replace the value of the first session level attribute called `name`, or add it if there is
+  /// no such attribute.
+  pub fn set_session_attribute(&mut self, name: &str, value: Option<String>) {
+    set_attribute(&mut self.session_attributes, name, value);
+  }
+
+  /// Remove every session level attribute called `name`.
+  pub fn remove_session_attribute(&mut self, name: &str) {
+    self.session_attributes.retain(|a| a.name != name);
+  }
+
+  /// Value of the first media level attribute called `name`.
+  pub fn media_attribute(&self, name: &str) -> Option<&str> {
+    find_attribute(&self.media_attributes, name)
+  }
+
+  /// Replace the value of the first media level attribute called `name`, or add it if there is
+  /// no such attribute.
+  pub fn set_media_attribute(&mut self, name: &str, value: Option<String>) {
+    set_attribute(&mut self.media_attributes, name, value);
+  }
+
+  /// Remove every media level attribute called `name`.
+  pub fn remove_media_attribute(&mut self, name: &str) {
+    self.media_attributes.retain(|a| a.name != name);
+  }
+
+  /// Direction of the answer's `m=application` section, `SendRecv` if it has none.
+  pub fn direction(&self) -> DataDirection {
+    self
+      .media_attributes
+      .iter()
+      .find_map(|a| DataDirection::from_attribute(&a.name))
+      .unwrap_or_default()
+  }
+
+  /// Replace the direction attribute of the `m=application` section, which is the server's side
+  /// of the offer's direction.
+  pub fn set_direction(&mut self, direction: DataDirection) {
+    let is_direction = |a: &SdpAttribute| DataDirection::from_attribute(&a.name).is_some();
+    // The new attribute takes the place of the first old one, which is before any others.
+    let index = self
+      .media_attributes
+      .iter()
+      .position(is_direction)
+      .unwrap_or(self.media_attributes.len());
+    self.media_attributes.retain(|a| !is_direction(a));
+    self
+      .media_attributes
+      .insert(index, SdpAttribute::new(direction.attribute(), None));
+  }
+}
+
+impl fmt::Display for SdpAnswer {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    let ipv = if self.address.is_ipv6() { "IP6" } else { "IP4" };
+    write!(f, "v=0\r\n")?;
+    write!(
+      f,
+      "o=FTL {} 1 IN {} {}\r\n",
+      self.session_id, ipv, self.address
+    )?;
+    write!(f, "s=-\r\n")?;
+    write!(f, "c=IN {} {}\r\n", ipv, self.address)?;
+    write!(f, "t=0 0\r\n")?;
+    for attribute in &self.session_attributes {
+      write_attribute(f, attribute)?;
+    }
+    write!(
+      f,
+      "m=application {} UDP/DTLS/SCTP webrtc-datachannel\r\n",
+      self.port
+    )?;
+    for attribute in &self.media_attributes {
+      write_attribute(f, attribute)?;
+    }
+    for candidate in &self.candidates {
+      write!(f, "a=candidate:{}\r\n", candidate)?;
+    }
+    Ok(())
+  }
+}
+
+fn find_attribute<'a>(attributes: &'a [SdpAttribute], name: &str) -> Option<&'a str> {
+  attributes
+    .iter()
+    .find(|a| a.name == name)
+    .map(|a| a.value.as_deref().unwrap_or(""))
+}
+
+fn set_attribute(attributes: &mut Vec<SdpAttribute>, name: &str, value: Option<String>) {
+  match attributes.iter_mut().find(|a| a.name == name) {
+    Some(attribute) => attribute.value = value,
+    None => attributes.push(SdpAttribute::new(name, value)),
+  }
+}
+
+fn write_attribute(f: &mut fmt::Formatter, attribute: &SdpAttribute) -> Result<(), fmt::Error> {
+  match &attribute.value {
+    Some(value) => write!(f, "a={}:{}\r\n", attribute.name, value),
+    None => write!(f, "a={}\r\n", attribute.name),
+  }
+}
+
+pub fn gen_sdp_answer<R: Rng>(
   rng: &mut R,
   cert_fingerprint: &str,
-  server_ip: &str,
-  server_is_ipv6: bool,
-  server_port: u16,
+  public_addrs: &[SocketAddr],
   ufrag: &str,
   pass: &str,
   remote_mid: &str,
-) -> String {
-  format!(
-    "{{\"answer\":{{\"sdp\":\"v=0\\r\\n\
-         o=FTL {rand1} 1 IN {ipv} {ip}\\r\\n\
-         s=-\\r\\n\
-         c=IN {ipv} {ip}\\r\\n\
-         t=0 0\\r\\n\
-         a=ice-lite\\r\\n\
-         a=ice-ufrag:{ufrag}\\r\\n\
-         a=ice-pwd:{pass}\\r\\n\
-         m=application {port} UDP/DTLS/SCTP webrtc-datachannel\\r\\n\
-         a=max-message-size:1160\\r\\n\
-         a=fingerprint:sha-256 {fingerprint}\\r\\n\
-         a=ice-options:trickle\\r\\n\
-         a=setup:passive\\r\\n\
-         a=mid:{mid}\\r\\n\
-		     a=sctpmap:{port} webrtc-datachannel 8000\\r\\n\
-         a=max-message-size:1160\\r\\n\
-         a=sendrecv\\r\\n\
-         a=sctp-port:{port}\\r\\n\",\
-         \"type\":\"answer\"}},\"candidate\":{{\"sdpMLineIndex\":0,\
-         \"sdpMid\":\"{mid}\",\"candidate\":\"candidate:1 1 UDP {rand2} {ip} {port} \
-         typ host\"}}}}",
-    rand1 = rng.gen::<u32>(),
-    rand2 = rng.gen::<u32>(),
-    fingerprint = cert_fingerprint,
-    ip = server_ip,
-    port = server_port,
-    ufrag = ufrag,
-    pass = pass,
-    mid = remote_mid,
-    ipv = if server_is_ipv6 { "IP6" } else { "IP4" },
-  )
+  format: SdpAnswerFormat,
+) -> SdpAnswer {
+  let server_addr = public_addrs[0];
+  let attribute = |name: &str, value: &str| SdpAttribute::new(name, Some(value.to_owned()));
+
+  // Every public address is advertised as its own host candidate, in order of preference.
+  let candidates = public_addrs
+    .iter()
+    .enumerate()
+    .map(|(index, addr)| {
+      format!(
+        "{} 1 UDP {} {} {} typ host",
+        index + 1,
+        host_candidate_priority(index),
+        addr.ip(),
+        addr.port()
+      )
+    })
+    .collect();
+
+  let mut media_attributes = vec![
+    attribute("fingerprint", &format!("sha-256 {}", cert_fingerprint)),
+    attribute("ice-options", "trickle"),
+    attribute("setup", "passive"),
+    attribute("mid", remote_mid),
+  ];
+  if let SdpAnswerFormat::Legacy { sctpmap_streams } = format {
+    media_attributes.push(attribute(
+      "sctpmap",
+      &format!(
+        "{} webrtc-datachannel {}",
+        server_addr.port(),
+        sctpmap_streams
+      ),
+    ));
+  }
+  media_attributes.extend(vec![
+    SdpAttribute::new(DataDirection::SendRecv.attribute(), None),
+    attribute("sctp-port", &server_addr.port().to_string()),
+    attribute("max-message-size", "1160"),
+  ]);
+
+  SdpAnswer {
+    session_id: rng.gen::<u32>(),
+    address: server_addr.ip(),
+    port: server_addr.port(),
+    session_attributes: vec![
+      SdpAttribute::new("ice-lite", None),
+      attribute("ice-ufrag", ufrag),
+      attribute("ice-pwd", pass),
+    ],
+    media_attributes,
+    candidates,
+  }
+}
+
+/// Serialize an answer into the JSON object returned by `SessionEndpoint::session_request`.
+pub fn gen_sdp_response(answer: &SdpAnswer, protocol_versions: &[String]) -> String {
+  #[derive(Serialize)]
+  #[serde(rename_all = "camelCase")]
+  struct Response<'a> {
+    answer: SessionDescription,
+    candidate: IceCandidate<'a>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    protocol_versions: &'a [String],
+  }
+
+  #[derive(Serialize)]
+  struct SessionDescription {
+    sdp: String,
+    #[serde(rename = "type")]
+    ty: &'static str,
+  }
+
+  #[derive(Serialize)]
+  #[serde(rename_all = "camelCase")]
+  struct IceCandidate<'a> {
+    sdp_m_line_index: u32,
+    sdp_mid: &'a str,
+    candidate: String,
+  }
+
+  let response = Response {
+    answer: SessionDescription {
+      sdp: answer.to_string(),
+      ty: "answer",
+    },
+    candidate: IceCandidate {
+      sdp_m_line_index: 0,
+      sdp_mid: answer.media_attribute("mid").unwrap_or(""),
+      candidate: format!(
+        "candidate:{}",
+        answer.candidates.first().map(String::as_str).unwrap_or("")
+      ),
+    },
+    protocol_versions,
+  };
+  serde_json::to_string(&response).expect("SDP answer could not be serialized")
+}
+
+/// Passive TCP host candidates for each of `public_addrs`, following the UDP candidates of
+/// `gen_sdp_answer` in foundation and, below all of them, in priority.
+pub fn tcp_candidate_stubs(public_addrs: &[SocketAddr]) -> impl Iterator<Item = String> + '_ {
+  public_addrs.iter().enumerate().map(move |(index, addr)| {
+    let index = public_addrs.len() + index;
+    format!(
+      "{} 1 TCP {} {} {} typ host tcptype passive",
+      index + 1,
+      host_candidate_priority(index),
+      addr.ip(),
+      addr.port()
+    )
+  })
+}
+
+// ICE priority (RFC 8445 section 5.1.2) of the host candidate at `index`, earlier candidates are
+// preferred.
+fn host_candidate_priority(index: usize) -> u32 {
+  const HOST_TYPE_PREFERENCE: u32 = 126;
+  const COMPONENT_ID: u32 = 1;
+
+  let local_preference = 65535 - index.min(65535) as u32;
+  (HOST_TYPE_PREFERENCE << 24) | (local_preference << 8) | (256 - COMPONENT_ID)
 }
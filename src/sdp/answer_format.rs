@@ -0,0 +1,244 @@
+//! Round trips of generated answers through SDP parsing, for both `SdpAnswerFormat`s and every
+//! `DataDirection`.
+//!
+//! Answers are checked with `parse_sdp_fields`, which the server uses on offers, and by splitting
+//! them into lines the way a strict RFC 8866 parser would, so that duplicated or missing SCTP
+//! attributes show up.
+
+use std::net::SocketAddr;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::*;
+
+const UFRAG: &str = "Ab3/";
+const PWD: &str = "0123456789abcdefghijkl+/";
+const FINGERPRINT: &str = "AB:CD:EF";
+
+fn answer(format: SdpAnswerFormat) -> String {
+  answer_with_direction(format, DataDirection::SendRecv)
+}
+
+fn answer_with_direction(format: SdpAnswerFormat, direction: DataDirection) -> String {
+  let addr: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+  let mut answer = gen_sdp_answer(
+    &mut StdRng::seed_from_u64(7),
+    FINGERPRINT,
+    &[addr],
+    UFRAG,
+    PWD,
+    "0",
+    format,
+  );
+  answer.set_direction(direction.reverse());
+  answer.to_string()
+}
+
+// Every line of `sdp`, failing on anything which is not a `<type>=<value>` line ending in CRLF.
+fn lines(sdp: &str) -> Vec<&str> {
+  let body = sdp.strip_suffix("\r\n").expect("sdp must end with CRLF");
+  let lines: Vec<&str> = body.split("\r\n").collect();
+  for line in &lines {
+    let bytes = line.as_bytes();
+    assert!(
+      bytes.len() >= 2 && bytes[0].is_ascii_lowercase() && bytes[1] == b'=',
+      "malformed line {:?}",
+      line
+    );
+  }
+  lines
+}
+
+// Values of the attribute lines called `name`, `""` for flags.
+fn attribute_values<'a>(lines: &[&'a str], name: &str) -> Vec<&'a str> {
+  lines
+    .iter()
+    .filter_map(|line| line.strip_prefix("a="))
+    .filter_map(|attribute| {
+      let (attribute_name, value) = match attribute.find(':') {
+        Some(colon) => (&attribute[..colon], &attribute[colon + 1..]),
+        None => (attribute, ""),
+      };
+      (attribute_name == name).then_some(value)
+    })
+    .collect()
+}
+
+fn check_common(sdp: &str) {
+  prevalidate_sdp(sdp).unwrap();
+  let fields = parse_sdp_fields(sdp).unwrap();
+  assert_eq!(fields.ice_ufrag, UFRAG);
+  assert_eq!(fields.ice_passwd, PWD);
+  assert_eq!(fields.mid, "0");
+  assert_eq!(fields.max_message_size, Some(1160));
+  assert_eq!(fields.candidates.len(), 1);
+  assert_eq!(fields.candidates[0].address, "192.0.2.1");
+  assert_eq!(fields.candidates[0].port, 4433);
+
+  let lines = lines(sdp);
+  let media_lines: Vec<&str> = lines
+    .iter()
+    .copied()
+    .filter(|line| line.starts_with("m="))
+    .collect();
+  assert_eq!(
+    media_lines,
+    ["m=application 4433 UDP/DTLS/SCTP webrtc-datachannel"]
+  );
+  assert_eq!(attribute_values(&lines, "sctp-port"), ["4433"]);
+  assert_eq!(attribute_values(&lines, "max-message-size"), ["1160"]);
+  assert_eq!(
+    attribute_values(&lines, "fingerprint"),
+    [format!("sha-256 {}", FINGERPRINT)]
+  );
+}
+
+#[test]
+fn modern_answer() {
+  let sdp = answer(SdpAnswerFormat::Modern);
+  check_common(&sdp);
+  assert!(attribute_values(&lines(&sdp), "sctpmap").is_empty());
+}
+
+#[test]
+fn modern_is_default() {
+  assert_eq!(SdpAnswerFormat::default(), SdpAnswerFormat::Modern);
+}
+
+#[test]
+fn legacy_answer() {
+  let sdp = answer(SdpAnswerFormat::Legacy {
+    sctpmap_streams: 1024,
+  });
+  check_common(&sdp);
+  assert_eq!(
+    attribute_values(&lines(&sdp), "sctpmap"),
+    ["4433 webrtc-datachannel 1024"]
+  );
+}
+
+#[test]
+fn mutated_answer() {
+  let addr: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+  let mut answer = gen_sdp_answer(
+    &mut StdRng::seed_from_u64(7),
+    FINGERPRINT,
+    &[addr],
+    UFRAG,
+    PWD,
+    "data",
+    SdpAnswerFormat::Modern,
+  );
+  answer.set_media_attribute("max-message-size", Some("65536".to_owned()));
+  answer.set_session_attribute("tool", Some("test".to_owned()));
+  let sdp = answer.to_string();
+
+  let fields = parse_sdp_fields(&sdp).unwrap();
+  assert_eq!(fields.mid, "data");
+  assert_eq!(fields.max_message_size, Some(65536));
+  let lines = lines(&sdp);
+  assert!(lines.contains(&"c=IN IP6 2001:db8::1"));
+  assert_eq!(attribute_values(&lines, "max-message-size"), ["65536"]);
+  assert_eq!(attribute_values(&lines, "tool"), ["test"]);
+}
+
+// A minimal offer with the given session and media level direction attributes.
+fn offer(session_direction: Option<&str>, media_direction: Option<&str>) -> String {
+  let mut sdp = String::from("v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n");
+  if let Some(direction) = session_direction {
+    sdp.push_str(&format!("a={}\r\n", direction));
+  }
+  sdp.push_str("m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n");
+  sdp.push_str(&format!(
+    "a=ice-ufrag:{}\r\na=ice-pwd:{}\r\na=mid:0\r\n",
+    UFRAG, PWD
+  ));
+  if let Some(direction) = media_direction {
+    sdp.push_str(&format!("a={}\r\n", direction));
+  }
+  sdp.push_str("a=sctp-port:5000\r\n");
+  sdp
+}
+
+#[test]
+fn offer_direction() {
+  let cases = [
+    (None, None, DataDirection::SendRecv),
+    (None, Some("sendrecv"), DataDirection::SendRecv),
+    (None, Some("sendonly"), DataDirection::SendOnly),
+    (None, Some("recvonly"), DataDirection::RecvOnly),
+    (None, Some("inactive"), DataDirection::Inactive),
+    (Some("inactive"), None, DataDirection::Inactive),
+    (Some("inactive"), Some("recvonly"), DataDirection::RecvOnly),
+  ];
+  for (session_direction, media_direction, expected) in cases {
+    let fields = parse_sdp_fields(&offer(session_direction, media_direction)).unwrap();
+    assert_eq!(
+      fields.direction, expected,
+      "{:?} {:?}",
+      session_direction, media_direction
+    );
+  }
+}
+
+#[test]
+fn answer_direction() {
+  let cases = [
+    (DataDirection::SendRecv, "sendrecv"),
+    (DataDirection::SendOnly, "recvonly"),
+    (DataDirection::RecvOnly, "sendonly"),
+    (DataDirection::Inactive, "inactive"),
+  ];
+  for (offered, answered) in cases {
+    let sdp = answer_with_direction(SdpAnswerFormat::Modern, offered);
+    check_common(&sdp);
+    let lines = lines(&sdp);
+    for direction in ["sendrecv", "sendonly", "recvonly", "inactive"] {
+      let expected: &[&str] = if direction == answered { &[""] } else { &[] };
+      assert_eq!(
+        attribute_values(&lines, direction),
+        expected,
+        "{:?}",
+        offered
+      );
+    }
+    // The answer read back as an offer describes the same exchange from the other side.
+    assert_eq!(parse_sdp_fields(&sdp).unwrap().direction, offered.reverse());
+  }
+}
+
+#[test]
+fn direction_capabilities() {
+  assert!(DataDirection::SendRecv.client_sends() && DataDirection::SendRecv.client_receives());
+  assert!(DataDirection::SendOnly.client_sends() && !DataDirection::SendOnly.client_receives());
+  assert!(!DataDirection::RecvOnly.client_sends() && DataDirection::RecvOnly.client_receives());
+  assert!(!DataDirection::Inactive.client_sends() && !DataDirection::Inactive.client_receives());
+}
+
+#[test]
+fn tcp_candidate_stub() {
+  let addrs: [SocketAddr; 2] = [
+    "192.0.2.1:4433".parse().unwrap(),
+    "192.0.2.2:4433".parse().unwrap(),
+  ];
+  let mut answer = gen_sdp_answer(
+    &mut StdRng::seed_from_u64(7),
+    FINGERPRINT,
+    &addrs,
+    UFRAG,
+    PWD,
+    "0",
+    SdpAnswerFormat::Modern,
+  );
+  answer.candidates.extend(tcp_candidate_stubs(&addrs));
+  let sdp = answer.to_string();
+  lines(&sdp);
+
+  let candidates = parse_sdp_fields(&sdp).unwrap().candidates;
+  let transports: Vec<&str> = candidates.iter().map(|c| c.transport.as_str()).collect();
+  assert_eq!(transports, ["udp", "udp", "tcp", "tcp"]);
+  assert!(candidates
+    .windows(2)
+    .all(|pair| pair[0].priority > pair[1].priority));
+  assert!(sdp.contains(" 192.0.2.2 4433 typ host tcptype passive\r\n"));
+}
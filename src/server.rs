@@ -1,38 +1,110 @@
-use atone::Vc as VecDeque;
 use std::{
+  any::Any,
+  cmp::Reverse,
+  collections::{BTreeSet, BinaryHeap, VecDeque},
   convert::AsRef,
   error::Error,
   fmt,
+  future::Future,
+  hash::{BuildHasher, Hash, Hasher},
   io::{Error as IoError, ErrorKind as IoErrorKind},
-  net::{SocketAddr, UdpSocket},
-  ops::Deref,
-  sync::Arc,
-  time::{Duration, Instant},
+  mem::{self, MaybeUninit},
+  net::{IpAddr, SocketAddr, UdpSocket},
+  ops::{Bound, Deref},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, RwLock,
+  },
+  task::{Context, Poll},
+  time::{Duration, Instant, SystemTime},
 };
 
-use async_io::Async;
-use futures_util::{pin_mut, select, FutureExt, StreamExt};
-use hashbrown::hash_map::{Entry as HashMapEntry, HashMap};
+use async_io::{Async, Timer};
+use bytes::Bytes;
+use futures_util::{
+  future::{self, poll_fn},
+  pin_mut, select, FutureExt, StreamExt,
+};
+use hashbrown::hash_map::{HashMap, RawEntryMut};
 use openssl::ssl::SslAcceptor;
-use rand::thread_rng;
+use rand::{thread_rng, RngCore};
 use socket2::{Domain, SockAddr, Socket, Type};
 
+#[cfg(feature = "inspector")]
+use crate::inspector::{spawn_inspector, InspectorAddr};
 use crate::{
-  buffer_pool::{BufferHandle, BufferPool, OwnedBuffer},
-  client::{Client, ClientError, MessageType, MAX_UDP_PAYLOAD_SIZE},
+  admission::TokenBucket,
+  audit::{AuditKind, AuditLog, AuditSink},
+  buffer_pool::{BufferHandle, BufferPool, OwnedBuffer, PooledBuffer},
+  client::{
+    ChannelEvent, Client, ClientError, Delivery, FragmentPosition, MessageType,
+    MAX_UDP_PAYLOAD_SIZE,
+  },
+  client_table::{ClientId, ClientState, ClientTable},
+  clock::{count_gap, ClockJumpPolicy, ClockMonitor},
+  config::{DuplicateUfragPolicy, NegotiatedChannel, ServerConfig, SessionEndpointConfig},
   crypto::Crypto,
+  demux::PacketClass,
+  driver::{
+    run, spawn_driver, spawn_headless, ControlHandle, EventStream, ReceiverStream, SenderHandle,
+    ServerHandle,
+  },
+  dtls_floor::{check_client_hello, DtlsFloor, DTLS_FLOOR},
+  error_code::ErrorCode,
+  event::{DisconnectReason, EventKind, EventQueue, ServerEvent},
+  ffi_event::{EventCallback, FfiEventQueue},
+  inspector::{ErrorLog, InspectorRequest},
   interval::Interval,
-  sdp::{gen_sdp_response, parse_sdp_fields, SdpFields},
-  stun::{parse_stun_binding_request, write_stun_success_response},
-  util::rand_string,
+  loopback::{loopback_addr, LoopbackClient},
+  middleware::{MessageCtx, MiddlewareChain, MiddlewareStats, Verdict},
+  pktinfo::{enable_pktinfo, recv_from_to, send_to_from, LocalSocket},
+  public_addr::{spawn_resolve, PublicAddr, PublicHost},
+  relay::RelayedMessage,
+  ring_queue::{OverflowPolicy, RingQueue},
+  schedule::SendSchedule,
+  sdp::{
+    decode_offer, gen_sdp_answer, gen_sdp_response, parse_sdp_fields, prevalidate_sdp,
+    tcp_candidate_stubs, DataDirection, IceCandidate, OfferDecodeError, SdpFields,
+  },
+  snapshot::{ClientsSnapshot, SnapshotHandle},
+  stats::{
+    ConnectionFunnel, DemuxStats, LatencyHistogram, MemoryUsage, QueueDepth, ReassemblyStats,
+    ServerStats, TrafficCounters,
+  },
+  stun::{
+    parse_stun_binding_request, stun_binding_request_transaction_id, stun_error_response_len,
+    stun_success_response_len, verify_stun_fingerprint, verify_stun_message_integrity,
+    write_stun_error_response, write_stun_success_response, IceRole, StunBindingRequest,
+    StunErrorCode, StunExtraAttributes,
+  },
+  trace::{ConnectionTrace, TraceDirection, TraceLayer, TraceLog},
+  util::{rand_string, Secret},
+  version::{negotiate_version, version_mismatch_message},
+  watchdog::{DriverPhase, Watchdog},
 };
 
+#[cfg(test)]
+mod audit_events;
+#[cfg(test)]
+mod clock_jumps;
+#[cfg(test)]
+mod session_lifetimes;
+#[cfg(test)]
+mod test_support;
+
 #[derive(Debug)]
 pub enum SendError {
   ClientNotConnected,
   IncompleteMessageWrite,
   ClientError(String),
   Io(IoError),
+  /// The client already has `ServerConfig::max_client_egress` packets waiting to be sent, or
+  /// `MAX_UNACKED_BYTES` of reliable messages waiting to be acknowledged, so the message was not
+  /// queued.  Sending may succeed again once the server has written or the client acknowledged
+  /// them.
+  Backpressure,
+  /// The client has no open data channel with the id given to `Server::send_on`.
+  UnknownChannel(u16),
 }
 
 impl fmt::Display for SendError {
@@ -44,6 +116,8 @@ impl fmt::Display for SendError {
       }
       SendError::Io(err) => fmt::Display::fmt(err, f),
       SendError::ClientError(msg) => fmt::Display::fmt(msg, f),
+      SendError::Backpressure => write!(f, "client send queue is full"),
+      SendError::UnknownChannel(channel_id) => write!(f, "no open data channel {}", channel_id),
     }
   }
 }
@@ -62,6 +136,19 @@ pub enum SessionError {
   Disconnected,
   /// An error streaming the SDP descriptor
   ParseError(Box<dyn Error + 'static>),
+  /// The SDP descriptor was longer than the endpoint's configured maximum.
+  OfferTooLarge,
+  /// The endpoint's rate limiter rejected the request.
+  RateLimited,
+  /// The payload passed to `SessionEndpoint::session_request_any` did not contain an offer.
+  Decode(OfferDecodeError),
+  /// The `Server` is draining after `Server::begin_drain` and accepts no new sessions.
+  Draining,
+  /// The endpoint was told to stop accepting new sessions with `SessionEndpoint::set_accepting`.
+  NotAccepting,
+  /// The offer reused the `a=ice-ufrag` of a recent offer, under
+  /// `DuplicateUfragPolicy::Reject`.
+  DuplicateUfrag,
 }
 
 impl fmt::Display for SessionError {
@@ -71,6 +158,12 @@ impl fmt::Display for SessionError {
       SessionError::ParseError(e) => {
         write!(f, "error streaming the incoming SDP descriptor: {}", e)
       }
+      SessionError::OfferTooLarge => write!(f, "incoming SDP descriptor is too large"),
+      SessionError::RateLimited => write!(f, "session request was rate limited"),
+      SessionError::Decode(e) => write!(f, "error decoding the session request: {}", e),
+      SessionError::Draining => write!(f, "server is draining and accepts no new sessions"),
+      SessionError::NotAccepting => write!(f, "session endpoint accepts no new sessions"),
+      SessionError::DuplicateUfrag => write!(f, "ice-ufrag was used by a recent offer"),
     }
   }
 }
@@ -78,51 +171,308 @@ impl fmt::Display for SessionError {
 impl Error for SessionError {
   fn source(&self) -> Option<&(dyn Error + 'static)> {
     match self {
-      SessionError::Disconnected => None,
+      SessionError::Disconnected
+      | SessionError::OfferTooLarge
+      | SessionError::RateLimited
+      | SessionError::Draining
+      | SessionError::NotAccepting
+      | SessionError::DuplicateUfrag => None,
       SessionError::ParseError(e) => Some(e.as_ref()),
+      SessionError::Decode(e) => Some(e),
     }
   }
 }
 
-/// A reference to an internal buffer containing a received message.
-pub struct MessageBuffer<'a>(BufferHandle<'a>);
+/// A received message, backed by one of the server's internal buffers.
+///
+/// Cloning is cheap and does not copy the message, and the buffer goes back to the server once the
+/// last clone is dropped, so messages can be handed to other tasks or threads freely.
+#[derive(Clone, Debug)]
+pub struct MessageBuffer(Bytes);
+
+impl MessageBuffer {
+  /// The message as `Bytes`, without copying it.
+  pub fn into_bytes(self) -> Bytes {
+    self.0
+  }
+}
 
-impl<'a> Deref for MessageBuffer<'a> {
-  type Target = Vec<u8>;
+impl Deref for MessageBuffer {
+  type Target = [u8];
 
-  fn deref(&self) -> &Vec<u8> {
+  fn deref(&self) -> &[u8] {
     &self.0
   }
 }
 
-impl<'a> AsRef<[u8]> for MessageBuffer<'a> {
+impl AsRef<[u8]> for MessageBuffer {
   fn as_ref(&self) -> &[u8] {
     &self.0
   }
 }
 
+impl From<MessageBuffer> for Bytes {
+  fn from(buffer: MessageBuffer) -> Bytes {
+    buffer.0
+  }
+}
+
+/// A received message returned by `Server::recv_owned`, which owns one of the server's internal
+/// buffers outright.
 ///
-/// Struct representing a ErrorMessage
-///
-#[repr(C)]
-pub struct ErrorMessage {
-  pub code: i32,
-  pub message: String,
+/// Unlike the `MessageBuffer` of a `MessageResult`, the message may be modified in place, for
+/// example to decompress or decrypt it, or turned into a `Vec<u8>` without copying.  The buffer
+/// goes back to the server when the message is dropped, from any thread.
+#[derive(Debug)]
+pub struct OwnedMessage {
+  buffer: PooledBuffer,
+  pub message_type: MessageType,
+  /// Tag of the application subprotocol of the data channel, as in `MessageResult`.
+  pub subprotocol: Option<u8>,
+  /// Id of the data channel the message arrived on, as in `MessageResult`.
+  pub channel_id: u16,
+  pub remote_addr: SocketAddr,
+}
+
+impl OwnedMessage {
+  pub fn message(&self) -> &[u8] {
+    &self.buffer
+  }
+
+  pub fn message_mut(&mut self) -> &mut Vec<u8> {
+    &mut self.buffer
+  }
+
+  /// The message as a `Vec<u8>`, whose buffer is then never returned to the server.
+  pub fn into_vec(self) -> Vec<u8> {
+    self.buffer.into_vec()
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct MessageResult {
+  pub message: MessageBuffer,
+  pub message_type: MessageType,
+  /// Tag of the application subprotocol negotiated for the data channel the message arrived on,
+  /// see `ServerConfig::subprotocols`.
+  pub subprotocol: Option<u8>,
+  /// Id of the data channel the message arrived on, as in `ServerEvent::ChannelOpened`.
+  pub channel_id: u16,
+  pub remote_addr: SocketAddr,
 }
-pub struct MessageResult<'a> {
-  pub message: MessageBuffer<'a>,
+
+/// A fragment of a message too large for a single SCTP DATA chunk, returned by
+/// `Server::recv_stream` when `ServerConfig::stream_fragments` is set.
+///
+/// Fragments of a message arrive in order, starting at offset 0.  If a fragment is lost on an
+/// unreliable channel, or abandoned by the client, the rest of its message is never delivered.
+/// The same happens to messages abandoned because of `ServerConfig::max_partial_messages` or
+/// `ServerConfig::max_partial_message_len`, which are counted in `ServerStats::reassembly`.
+#[derive(Clone, Debug)]
+pub struct MessageFragment {
+  pub fragment: MessageBuffer,
   pub message_type: MessageType,
+  /// Tag of the application subprotocol of the data channel, as in `MessageResult`.
+  pub subprotocol: Option<u8>,
+  /// Id of the data channel the message arrived on, as in `MessageResult`.
+  pub channel_id: u16,
+  pub remote_addr: SocketAddr,
+  /// Identifies the message among those in progress from the same client.
+  pub message_id: u32,
+  /// Position of this fragment within its message.
+  pub offset: usize,
+  /// This is the final fragment, so the message is `offset + fragment.len()` bytes long.
+  pub last: bool,
+}
+
+/// Either a whole message or a fragment of one, returned by `Server::recv_stream`.
+#[derive(Clone, Debug)]
+pub enum StreamedMessage {
+  Message(MessageResult),
+  Fragment(MessageFragment),
+}
+
+/// A page of the clients of a `Server`, returned by `Server::clients_page`.
+#[derive(Clone, Debug)]
+pub struct ClientsPage {
+  /// Addresses of the clients on this page, in ascending order.
+  pub clients: Vec<SocketAddr>,
+  /// Cursor to pass to `Server::clients_page` for the next page, `None` on the last page.
+  pub next_cursor: Option<SocketAddr>,
+}
+
+/// A client of a `Server`, returned by `Server::clients`.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
   pub remote_addr: SocketAddr,
+  pub id: ClientId,
+  /// How far along its connection the client is, as with `Server::client_state`.
+  pub state: ClientState,
+  /// When the client first became established, `None` if it has not yet.
+  pub established_at: Option<SystemTime>,
+  /// Messages and bytes sent to and received from the client on all its data channels, counted
+  /// as in `ChannelTraffic`.
+  pub messages_sent: u64,
+  pub bytes_sent: u64,
+  pub messages_received: u64,
+  pub bytes_received: u64,
+}
+
+/// Metadata of a pending WebRTC session, returned by `Server::sessions`.
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+  /// Identifies the session, this is the `a=ice-ufrag` of the answer returned by
+  /// `SessionEndpoint::session_request`.
+  pub id: String,
+  /// The ICE username fragment of the browser's offer.
+  pub remote_user: String,
+  pub created_at: Instant,
+  /// Time left before the session expires, unless it is used again before then.
+  pub ttl_remaining: Duration,
+  /// The ICE candidates listed in the browser's offer which passed the
+  /// `SessionEndpointConfig::candidate_filter`.
+  pub remote_candidates: Arc<[IceCandidate]>,
 }
 
 #[derive(Clone)]
 pub struct SessionEndpoint {
-  public_addr: SocketAddr,
+  public_addrs: Arc<RwLock<Arc<[SocketAddr]>>>,
   cert_fingerprint: Arc<String>,
   session_sender: flume::Sender<IncomingSession>,
+  config: SessionEndpointConfig,
+  answer_cache: Option<Arc<Mutex<AnswerCache>>>,
+  protocol_versions: Arc<[String]>,
+  // Set by `Server::begin_drain`.
+  draining: Arc<AtomicBool>,
+  // Cleared by `SessionEndpoint::set_accepting`.
+  accepting: Arc<AtomicBool>,
+  // Advertised as `a=max-message-size`, from `ServerConfig::max_reassembled_len`.
+  max_message_size: Option<usize>,
+  // Disconnected once the `Server` is dropped, which resolves `SessionEndpoint::closed`.
+  server_closed: flume::Receiver<()>,
+}
+
+// Answers recently returned by a `SessionEndpoint`, keyed by the ice-ufrag of the offer along
+// with the offer itself and when it was answered.
+#[derive(Default)]
+struct AnswerCache {
+  answers: HashMap<String, (String, String, Instant)>,
+}
+
+impl AnswerCache {
+  fn new_shared(config: &SessionEndpointConfig) -> Option<Arc<Mutex<AnswerCache>>> {
+    let finds_duplicates = matches!(
+      config.duplicate_ufrag,
+      DuplicateUfragPolicy::Reject | DuplicateUfragPolicy::KeepFirst
+    );
+    if config.idempotent_offers || finds_duplicates {
+      Some(Arc::default())
+    } else {
+      None
+    }
+  }
+
+  // The offer answered with `ice_ufrag` less than `lifetime` ago and its response, if any.
+  fn get(&mut self, ice_ufrag: &str, lifetime: Duration) -> Option<(&str, &str)> {
+    self
+      .answers
+      .retain(|_, (_, _, answered_at)| answered_at.elapsed() < lifetime);
+    self
+      .answers
+      .get(ice_ufrag)
+      .map(|(offer, response, _)| (offer.as_str(), response.as_str()))
+  }
+
+  fn insert(&mut self, ice_ufrag: String, offer: &str, response: &str) {
+    // When full of unexpired answers, new ones are simply not remembered.
+    if self.answers.len() < MAX_CACHED_ANSWERS || self.answers.contains_key(&ice_ufrag) {
+      self.answers.insert(
+        ice_ufrag,
+        (offer.to_owned(), response.to_owned(), Instant::now()),
+      );
+    }
+  }
 }
 
 impl SessionEndpoint {
+  /// Create an endpoint which is not attached to any `Server`, for answering offers from a web
+  /// tier running separately from the servers handling the connections.
+  ///
+  /// `public_addrs` and `cert_fingerprint` must be those of the server browsers should connect
+  /// to, as reported by its own `SessionEndpoint::public_addrs` and
+  /// `SessionEndpoint::cert_fingerprint`.  The endpoint can only answer offers when `config` has
+  /// `SessionEndpointConfig::stateless_tokens` set, otherwise every request fails with
+  /// `SessionError::Disconnected`.
+  pub fn standalone(
+    public_addrs: Vec<SocketAddr>,
+    cert_fingerprint: impl Into<String>,
+    config: SessionEndpointConfig,
+  ) -> SessionEndpoint {
+    let (session_sender, _) = flume::bounded(0);
+    let (_, server_closed) = flume::bounded(0);
+    SessionEndpoint {
+      public_addrs: Arc::new(RwLock::new(public_addrs.into())),
+      cert_fingerprint: Arc::new(cert_fingerprint.into()),
+      session_sender,
+      answer_cache: AnswerCache::new_shared(&config),
+      protocol_versions: config
+        .protocol_versions
+        .clone()
+        .unwrap_or_else(|| Vec::new().into()),
+      config,
+      draining: Arc::default(),
+      accepting: Arc::new(AtomicBool::new(true)),
+      max_message_size: None,
+      server_closed,
+    }
+  }
+
+  /// Whether the `Server` this endpoint starts sessions on is still there.
+  ///
+  /// Once it has been dropped, every session request fails with `SessionError::Disconnected`.  A
+  /// standalone endpoint is never attached, as it answers offers without a server.
+  pub fn is_attached(&self) -> bool {
+    !self.session_sender.is_disconnected()
+  }
+
+  /// A future which resolves once the `Server` this endpoint starts sessions on has been dropped,
+  /// for example so that a signalling service can report maintenance right away rather than on
+  /// its next session request.  It resolves immediately for a standalone endpoint.
+  ///
+  /// It does not borrow the endpoint, and resolves for every clone of it.
+  pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+    let server_closed = self.server_closed.clone();
+    async move {
+      // Nothing is ever sent, the sender being dropped is the signal.
+      let _ = server_closed.recv_async().await;
+    }
+  }
+
+  /// Stop answering offers with new sessions when `accepting` is false, failing them with
+  /// `SessionError::NotAccepting`, or start again when it is true.
+  ///
+  /// This applies to this endpoint and its clones only, so that one of several endpoints
+  /// starting sessions on the same server, such as that of a single game shard, can stop taking
+  /// new players while the others carry on.  Sessions already answered may still connect.
+  pub fn set_accepting(&self, accepting: bool) {
+    self.accepting.store(accepting, Ordering::Relaxed);
+  }
+
+  /// Whether the endpoint answers offers, see `SessionEndpoint::set_accepting`.
+  pub fn is_accepting(&self) -> bool {
+    self.accepting.load(Ordering::Relaxed)
+  }
+
+  /// The public addresses currently advertised in session answers.
+  pub fn public_addrs(&self) -> Vec<SocketAddr> {
+    self.public_addrs.read().unwrap().to_vec()
+  }
+
+  /// The SHA-256 fingerprint of the server's DTLS certificate, as advertised in session answers.
+  pub fn cert_fingerprint(&self) -> &str {
+    &self.cert_fingerprint
+  }
+
   /// Receives an incoming SDP descriptor of an `RTCSessionDescription` from a browser, informs
   /// the corresponding `Server` of the new WebRTC session, and returns a JSON object containing
   /// objects which can construct an `RTCSessionDescription` and an `RTCIceCandidate` in a
@@ -132,58 +482,282 @@ impl SessionEndpoint {
   /// DTLS, and the browser will ensure that this digest matches before starting a WebRTC
   /// connection.
   pub fn session_request(&mut self, sdp_descriptor: &str) -> Result<String, SessionError> {
+    self.handle_session_request(None, sdp_descriptor, None)
+  }
+
+  /// Handles a session request as with `SessionEndpoint::session_request`, taking the offer in any
+  /// shape `decode_offer` accepts, such as the JSON of the browser's `RTCSessionDescription`.
+  ///
+  /// The endpoint's `SessionEndpointConfig::max_offer_len` applies to the payload before it is
+  /// decoded as well as to the offer.  To also apply the endpoint's rate limiter, decode the
+  /// payload with `decode_offer` and pass the offer to `SessionEndpoint::session_request_from`.
+  pub fn session_request_any(&mut self, payload: &str) -> Result<String, SessionError> {
+    if payload.len() > self.config.max_offer_len {
+      return Err(SessionError::OfferTooLarge);
+    }
+    let sdp_descriptor = decode_offer(payload).map_err(SessionError::Decode)?;
+    self.handle_session_request(None, &sdp_descriptor, None)
+  }
+
+  #[cfg(feature = "compat")]
+  pub(crate) fn max_offer_len(&self) -> usize {
+    self.config.max_offer_len
+  }
+
+  /// Handles a session request as with `SessionEndpoint::session_request`, but first checks the
+  /// address the request came from against the endpoint's rate limiter, if one is configured.
+  pub fn session_request_from(
+    &mut self,
+    source: IpAddr,
+    sdp_descriptor: &str,
+  ) -> Result<String, SessionError> {
+    self.handle_session_request(Some(source), sdp_descriptor, None)
+  }
+
+  /// Handles a session request as with `SessionEndpoint::session_request`, for a session which
+  /// is disconnected once `lifetime` has passed since the answer, for example for guests limited
+  /// to a 10 minute demo.
+  ///
+  /// The client is sent a final text message such as `{"close":4002,"reason":"session
+  /// expired"}` as with `Server::kick`, and the server produces a
+  /// `ServerEvent::SessionLifetimeExpired`.  Lifetimes are checked once a second.  Sessions
+  /// answered with `SessionEndpointConfig::stateless_tokens` are not known to the server until
+  /// they connect, so the lifetime of those is ignored.
+  pub fn session_request_with_lifetime(
+    &mut self,
+    sdp_descriptor: &str,
+    lifetime: Duration,
+  ) -> Result<String, SessionError> {
+    self.handle_session_request(None, sdp_descriptor, Some(lifetime))
+  }
+
+  fn handle_session_request(
+    &mut self,
+    source: Option<IpAddr>,
+    sdp_descriptor: &str,
+    lifetime: Option<Duration>,
+  ) -> Result<String, SessionError> {
     const SERVER_USER_LEN: usize = 12;
     const SERVER_PASSWD_LEN: usize = 24;
 
-    let SdpFields { ice_ufrag, mid, .. } =
-      parse_sdp_fields(sdp_descriptor).map_err(|e| SessionError::ParseError(e.into()))?;
+    if self.draining.load(Ordering::Relaxed) {
+      return Err(SessionError::Draining);
+    }
+    if !self.accepting.load(Ordering::Relaxed) {
+      return Err(SessionError::NotAccepting);
+    }
+    if let (Some(source), Some(rate_limiter)) = (source, &self.config.rate_limiter) {
+      if !rate_limiter(source) {
+        return Err(SessionError::RateLimited);
+      }
+    }
+    if sdp_descriptor.len() > self.config.max_offer_len {
+      return Err(SessionError::OfferTooLarge);
+    }
+    prevalidate_sdp(sdp_descriptor).map_err(SessionError::ParseError)?;
+
+    let SdpFields {
+      ice_ufrag,
+      mid,
+      max_message_size,
+      candidates,
+      direction,
+      ..
+    } = parse_sdp_fields(sdp_descriptor).map_err(|e| SessionError::ParseError(e.into()))?;
+
+    // Held until the answer is remembered, so that concurrent retries of the same offer cannot
+    // both start a session.
+    let mut answer_cache = self
+      .answer_cache
+      .as_ref()
+      .map(|cache| cache.lock().unwrap());
+    let cache_lifetime = match &self.config.stateless_tokens {
+      Some((_, ttl)) => RTC_SESSION_TIMEOUT.min(*ttl),
+      None => RTC_SESSION_TIMEOUT,
+    };
+    if let Some(answer_cache) = &mut answer_cache {
+      if let Some((cached_offer, response)) = answer_cache.get(&ice_ufrag, cache_lifetime) {
+        if self.config.idempotent_offers && cached_offer == sdp_descriptor {
+          return Ok(response.to_owned());
+        }
+        match self.config.duplicate_ufrag {
+          DuplicateUfragPolicy::Reject => return Err(SessionError::DuplicateUfrag),
+          DuplicateUfragPolicy::KeepFirst => return Ok(response.to_owned()),
+          DuplicateUfragPolicy::Allow | DuplicateUfragPolicy::Replace => {}
+        }
+      }
+    }
+    let cache_key = answer_cache.as_ref().map(|_| ice_ufrag.clone());
 
     let (incoming_session, response) = {
-      let mut rng = thread_rng();
-      let server_user = rand_string(&mut rng, SERVER_USER_LEN);
-      let server_passwd = rand_string(&mut rng, SERVER_PASSWD_LEN);
+      let mut shared_rng = self.config.rng.as_ref().map(|rng| rng.lock().unwrap());
+      let mut thread_rng = thread_rng();
+      let mut rng: &mut dyn RngCore = match &mut shared_rng {
+        Some(rng) => &mut **rng,
+        None => &mut thread_rng,
+      };
+      let (server_user, server_passwd) = match &self.config.stateless_tokens {
+        Some((key, ttl)) => key.mint(&mut rng, *ttl),
+        None => (
+          rand_string(&mut rng, SERVER_USER_LEN),
+          Secret::new(rand_string(&mut rng, SERVER_PASSWD_LEN)),
+        ),
+      };
 
+      let offered_candidates = candidates.len();
+      let tcp_only = offered_candidates > 0
+        && candidates
+          .iter()
+          .all(|candidate| candidate.transport == "tcp");
+      let filter = &self.config.candidate_filter;
       let incoming_session = IncomingSession {
         server_user: server_user.clone(),
         server_passwd: server_passwd.clone(),
         remote_user: ice_ufrag,
+        max_message_size,
+        direction,
+        expires_at: lifetime.map(|lifetime| Instant::now() + lifetime),
+        replace: self.config.duplicate_ufrag == DuplicateUfragPolicy::Replace,
+        remote_candidates: candidates
+          .into_iter()
+          .filter(|candidate| filter.keeps(candidate))
+          .collect(),
+        offered_candidates,
+        tcp_only,
       };
 
-      let response = gen_sdp_response(
+      let public_addrs = self.public_addrs.read().unwrap().clone();
+      let mut answer = gen_sdp_answer(
         &mut rng,
         &self.cert_fingerprint,
-        &self.public_addr.ip().to_string(),
-        self.public_addr.ip().is_ipv6(),
-        self.public_addr.port(),
+        &public_addrs,
         &server_user,
         &server_passwd,
         &mid,
+        self.config.answer_format,
       );
+      answer.set_direction(direction.reverse());
+      if self.config.tcp_candidate_stub {
+        answer.candidates.extend(tcp_candidate_stubs(&public_addrs));
+      }
+      if let Some(max_message_size) = self.max_message_size {
+        answer.set_media_attribute("max-message-size", Some(max_message_size.to_string()));
+      }
+      if let Some(answer_mutator) = &self.config.answer_mutator {
+        answer_mutator(&mut answer);
+      }
 
-      (incoming_session, response)
+      (
+        incoming_session,
+        gen_sdp_response(&answer, &self.protocol_versions),
+      )
     };
 
-    let incoming_session = incoming_session;
-    let handler = self.session_sender.send(incoming_session);
-    if handler.is_err() {
-      return Err(SessionError::Disconnected);
+    // A token carries everything the server needs to accept the session by itself.
+    if self.config.stateless_tokens.is_none() {
+      let handler = self.session_sender.send(incoming_session);
+      if handler.is_err() {
+        return Err(SessionError::Disconnected);
+      }
+    }
+
+    if let (Some(answer_cache), Some(cache_key)) = (&mut answer_cache, cache_key) {
+      answer_cache.insert(cache_key, sdp_descriptor, &response);
     }
     Ok(response)
   }
 }
+
+/// Close code sent to a client whose lifetime, set with
+/// `SessionEndpoint::session_request_with_lifetime`, has run out, just before it is disconnected.
+pub const SESSION_EXPIRED_CLOSE_CODE: u16 = 4002;
+
+// Handler set with `Server::set_packet_passthrough`.
+type PacketPassthrough = Box<dyn FnMut(PacketClass, SocketAddr, &[u8]) + Send>;
+
 pub struct Server {
-  udp_socket: Async<UdpSocket>,
+  config: ServerConfig,
+  udp_sockets: Vec<Async<UdpSocket>>,
+  next_socket: usize,
   session_endpoint: SessionEndpoint,
   incoming_session_stream: flume::Receiver<IncomingSession>,
+  // Never sent on, dropped with the server to resolve `SessionEndpoint::closed`.
+  _closed_sender: flume::Sender<()>,
   ssl_acceptor: SslAcceptor,
-  outgoing_udp: VecDeque<(OwnedBuffer, SocketAddr)>,
-  incoming_rtc: VecDeque<(OwnedBuffer, SocketAddr, MessageType)>,
+  outgoing_udp: RingQueue<(OwnedBuffer, SocketAddr, LocalSocket)>,
+  egress_ready: VecDeque<SocketAddr>,
+  // Packets dropped by the egress queues of clients which have been removed, and messages they
+  // dropped while paused.
+  egress_dropped: u64,
+  // Traffic of clients which have been removed.
+  closed_traffic: TrafficCounters,
+  closed_reassembly: ReassemblyStats,
+  stale_source_packets: u64,
+  refused_clients: u64,
+  redirected_clients: u64,
+  relayed_messages: u64,
+  refused_downgrades: u64,
+  demux: DemuxStats,
+  funnel: ConnectionFunnel,
+  packet_passthrough: Option<PacketPassthrough>,
+  audit: AuditLog,
+  // Traces of the last clients to be removed, with the reason each was removed for.
+  closed_traces: VecDeque<(SocketAddr, TraceLog, DisconnectReason)>,
+  ffi_events: FfiEventQueue,
+  scheduled_sends: SendSchedule,
+  // When each client with a session lifetime expires, earliest first.
+  session_expiries: BinaryHeap<Reverse<(Instant, ClientId)>>,
+  admission: Option<TokenBucket>,
+  deferred_handshakes: RingQueue<(OwnedBuffer, SocketAddr, LocalSocket)>,
+  incoming_rtc: RingQueue<(OwnedBuffer, SocketAddr, MessageType, Option<u8>, u16)>,
+  incoming_fragments: RingQueue<(
+    OwnedBuffer,
+    SocketAddr,
+    MessageType,
+    Option<u8>,
+    u16,
+    FragmentPosition,
+  )>,
+  subprotocols: Arc<[String]>,
+  negotiated_channels: Arc<[NegotiatedChannel]>,
+  events: EventQueue,
+  inbound_middleware: MiddlewareChain,
+  outbound_middleware: MiddlewareChain,
+  processing_latency: LatencyHistogram,
   buffer_pool: BufferPool,
   sessions: HashMap<SessionKey, Session>,
-  clients: HashMap<SocketAddr, Client>,
+  // The keys of `sessions` in order, for cleaning them up a slice at a time.
+  session_order: BTreeSet<SessionKey>,
+  clients: ClientTable,
+  // The addresses of `clients` in order, for `Server::clients_page`.
+  client_order: BTreeSet<SocketAddr>,
+  // Dropped once the server has no clients left, which resolves the futures returned by
+  // `Server::drained`.
+  drain_waiters: Vec<flume::Sender<()>>,
+  // Futures returned by `Server::wait_connected`, woken when their client is established.
+  connect_waiters: Vec<(SocketAddr, flume::Sender<()>)>,
   last_generate_periodic: Instant,
-  last_cleanup: Instant,
+  // Where `Server::timeout_clients` carries on checking `session_order` and `client_order`.
+  session_cursor: Option<SessionKey>,
+  client_cursor: Option<SocketAddr>,
+  last_stats_event: Instant,
+  // Published once a second for as long as anyone holds a handle returned by
+  // `Server::client_snapshots`, with the epoch of the next snapshot.
+  client_snapshots: Option<SnapshotHandle>,
+  snapshot_epoch: u64,
   periodic_timer: Interval,
+  clock: ClockMonitor,
+  public_hosts: Vec<PublicHost>,
+  dns_sender: flume::Sender<(usize, Result<SocketAddr, IoError>)>,
+  dns_receiver: flume::Receiver<(usize, Result<SocketAddr, IoError>)>,
+  dns_pending: usize,
+  last_dns_refresh: Instant,
+  errors: ErrorLog,
+  watchdog: Option<Watchdog>,
+  inspector: Option<(
+    flume::Sender<InspectorRequest>,
+    flume::Receiver<InspectorRequest>,
+  )>,
 }
 // unsafe impl Send for Server {}
 
@@ -196,320 +770,2462 @@ impl Server {
   pub fn new(
     listen_addr: SocketAddr,
     public_addr: SocketAddr,
-    cb: Option<extern "C" fn(u32, *mut u8, u32)>,
+    cb: Option<EventCallback>,
+  ) -> Result<Server, IoError> {
+    Server::new_multi([(listen_addr, public_addr)], cb)
+  }
+
+  /// Start a new WebRTC data channel server listening on several UDP ports at once.
+  ///
+  /// Each entry is a `(listen_addr, public_addr)` pair, and every public address is advertised as
+  /// a separate ICE candidate in session answers, so a browser on a network that only passes UDP
+  /// on some ports (such as 3478, 443 or 53) can still reach the server.  A port range can be
+  /// given directly, for example `(3478..=3480).map(|p| (SocketAddr::new(ip, p),
+  /// SocketAddr::new(public_ip, p)))`.
+  ///
+  /// STUN is answered on every port, and clients share a single table keyed only by their remote
+  /// address; outgoing packets for a client leave from the port its traffic last arrived on.  On
+  /// Linux, a socket listening on a wildcard address such as `0.0.0.0` also sends them from the
+  /// address the client's traffic was sent to, so multi-homed hosts answer from the right one.
+  ///
+  /// Public addresses may also be given as a `PublicAddr`, including hostnames with
+  /// `PublicAddr::host`.
+  pub fn new_multi<P: Into<PublicAddr>>(
+    addrs: impl IntoIterator<Item = (SocketAddr, P)>,
+    cb: Option<EventCallback>,
+  ) -> Result<Server, IoError> {
+    Server::with_config(addrs, ServerConfig::default(), cb)
+  }
+
+  /// Start a new WebRTC data channel server listening on the given `(listen_addr, public_addr)`
+  /// pairs, as with `Server::new_multi`, but with non-default behaviour set by `config`.
+  ///
+  /// Hostnames given as public addresses are resolved before this returns, and fail construction
+  /// if they do not resolve.
+  pub fn with_config<P: Into<PublicAddr>>(
+    addrs: impl IntoIterator<Item = (SocketAddr, P)>,
+    config: ServerConfig,
+    cb: Option<EventCallback>,
   ) -> Result<Server, IoError> {
     const SESSION_BUFFER_SIZE: usize = 8;
-    if cb.is_some() {
-      unsafe {
-        EVENT_CB = cb;
+    for (index, channel) in config.negotiated_channels.iter().enumerate() {
+      // Stream 65535 is reserved for data channels (RFC 8831 section 6.5).
+      if channel.id == u16::MAX {
+        return Err(IoError::new(
+          IoErrorKind::InvalidInput,
+          "negotiated channel id 65535 is reserved",
+        ));
+      }
+      if config.negotiated_channels[..index]
+        .iter()
+        .any(|other| other.id == channel.id)
+      {
+        return Err(IoError::new(
+          IoErrorKind::InvalidInput,
+          format!(
+            "negotiated channel id {} is used more than once",
+            channel.id
+          ),
+        ));
       }
     }
+    let ffi_events = FfiEventQueue::new(cb, config.ffi_event_delivery)?;
     let crypto = Crypto::init().expect("WebRTC server could not initialize OpenSSL primitives");
 
-    let inner = Socket::new(Domain::for_address(listen_addr), Type::DGRAM, None).unwrap();
-
-    //This is temporary disable due to probleme with Sessions management.
-    //the sessions should be handled in the Deno side using a single UDP socket and a Map to store each request,
-    //then wait until we get a new UDP connection in Rust side to handle the DTLS part.
-
-    // #[cfg(any(unix))]
-    // inner.set_reuse_port(true).unwrap();
-
-    // inner.set_reuse_address(true).unwrap();
-
-    let address = SockAddr::from(listen_addr);
-    inner.bind(&address)?;
-
-    let sock = inner.into();
+    let mut udp_sockets = Vec::new();
+    let mut public_addrs = Vec::new();
+    let mut public_hosts = Vec::new();
+    for (listen_addr, public_addr) in addrs {
+      let public_addr = match public_addr.into() {
+        PublicAddr::Addr(addr) => addr,
+        PublicAddr::Host(host, port) => {
+          let public_host = PublicHost {
+            index: public_addrs.len(),
+            host,
+            port,
+            listen_addr,
+          };
+          let addr = public_host.resolve()?;
+          public_hosts.push(public_host);
+          addr
+        }
+      };
+      udp_sockets.push(bind_udp_socket(listen_addr)?);
+      public_addrs.push(public_addr);
+    }
+    if udp_sockets.is_empty() {
+      return Err(IoError::new(
+        IoErrorKind::InvalidInput,
+        "at least one listen address is required",
+      ));
+    }
 
-    let udp_socket = Async::new(sock)?;
     let (session_sender, session_receiver) = flume::bounded(SESSION_BUFFER_SIZE);
+    let (closed_sender, server_closed) = flume::bounded(0);
+    let (dns_sender, dns_receiver) = flume::unbounded();
+    let events = EventQueue::new(MAX_QUEUED_EVENTS);
+    let watchdog = match config.watchdog_timeout {
+      Some(timeout) => Some(Watchdog::spawn(timeout, events.shared_subscribers())?),
+      None => None,
+    };
 
     let session_endpoint = SessionEndpoint {
-      public_addr,
+      public_addrs: Arc::new(RwLock::new(public_addrs.into())),
       cert_fingerprint: Arc::new(crypto.fingerprint),
       session_sender,
+      config: SessionEndpointConfig::default(),
+      answer_cache: None,
+      protocol_versions: config.protocol_versions.clone().into(),
+      draining: Arc::default(),
+      accepting: Arc::new(AtomicBool::new(true)),
+      max_message_size: config.max_reassembled_len,
+      server_closed,
     };
 
     Ok(Server {
-      udp_socket,
+      udp_sockets,
+      next_socket: 0,
       session_endpoint,
       incoming_session_stream: session_receiver,
+      _closed_sender: closed_sender,
       ssl_acceptor: crypto.ssl_acceptor,
-      outgoing_udp: VecDeque::new(),
-      incoming_rtc: VecDeque::new(),
+      outgoing_udp: RingQueue::new(config.outgoing_queue_capacity, config.queue_overflow),
+      egress_ready: VecDeque::new(),
+      egress_dropped: 0,
+      closed_traffic: TrafficCounters::default(),
+      closed_reassembly: ReassemblyStats::default(),
+      stale_source_packets: 0,
+      refused_clients: 0,
+      redirected_clients: 0,
+      relayed_messages: 0,
+      refused_downgrades: 0,
+      demux: DemuxStats::default(),
+      funnel: ConnectionFunnel::default(),
+      packet_passthrough: None,
+      audit: AuditLog::new(),
+      closed_traces: VecDeque::new(),
+      ffi_events,
+      scheduled_sends: SendSchedule::default(),
+      session_expiries: BinaryHeap::new(),
+      admission: config.admission_rate.map(TokenBucket::new),
+      deferred_handshakes: RingQueue::new(
+        config.admission_rate.map_or(0, |rate| rate.max_deferred),
+        OverflowPolicy::DropNewest,
+      ),
+      incoming_rtc: RingQueue::new(config.incoming_queue_capacity, config.queue_overflow),
+      incoming_fragments: RingQueue::new(
+        if config.stream_fragments {
+          config.incoming_queue_capacity
+        } else {
+          0
+        },
+        config.queue_overflow,
+      ),
+      subprotocols: config.subprotocols.clone().into(),
+      negotiated_channels: config.negotiated_channels.clone().into(),
+      events,
+      inbound_middleware: MiddlewareChain::new(),
+      outbound_middleware: MiddlewareChain::new(),
+      processing_latency: LatencyHistogram::new(),
       buffer_pool: BufferPool::new(),
       sessions: HashMap::new(),
-      clients: HashMap::new(),
+      session_order: BTreeSet::new(),
+      clients: ClientTable::new(),
+      client_order: BTreeSet::new(),
+      drain_waiters: Vec::new(),
+      connect_waiters: Vec::new(),
       last_generate_periodic: Instant::now(),
-      last_cleanup: Instant::now(),
+      session_cursor: None,
+      client_cursor: None,
+      last_stats_event: Instant::now(),
+      client_snapshots: None,
+      snapshot_epoch: 0,
       periodic_timer: Interval::new(PERIODIC_TIMER_INTERVAL),
+      clock: ClockMonitor::new(),
+      public_hosts,
+      dns_sender,
+      dns_receiver,
+      dns_pending: 0,
+      last_dns_refresh: Instant::now(),
+      errors: ErrorLog::default(),
+      watchdog,
+      inspector: None,
+      config,
     })
   }
-  /// Returns a `SessionEndpoint` which can be used to start new WebRTC sessions.
+
+  /// Move this server onto a dedicated thread which drives it with its own executor, for
+  /// applications that are not async at all.
   ///
-  /// WebRTC connections must be started via an external communication channel from a browser via
-  /// the returned `SessionEndpoint`, and this communication channel will be used to exchange
-  /// session descriptions in SDP format.
+  /// Returns handles to queue outgoing messages, iterate over received messages and
+  /// `ServerEvent`s, and to control or stop the server.  Any `SessionEndpoint` must be taken
+  /// from the server before calling this.
   ///
-  /// The returned `SessionEndpoint` will notify this `Server` of new sessions via a shared async
-  /// channel.  This is done so that the `SessionEndpoint` is easy to use in a separate server
-  /// task (such as a `hyper` HTTP server).
-  pub fn session_endpoint(&self) -> SessionEndpoint {
-    self.session_endpoint.clone()
+  /// The thread is scheduled according to `ServerConfig::driver_realtime_priority` and
+  /// `ServerConfig::driver_cpu_affinity`, and this fails if those cannot be applied.
+  pub fn spawn_driver(
+    self,
+  ) -> Result<(SenderHandle, ReceiverStream, EventStream, ControlHandle), IoError> {
+    spawn_driver(self)
   }
 
-  /// The total count of clients in any active state, whether still starting up, fully
-  /// established, or still shutting down.
-  pub fn active_clients(&self) -> usize {
-    self.clients.values().filter(|c| !c.is_shutdown()).count()
+  /// Split this server into a future which drives it, to be spawned as its own task on any
+  /// executor, and a `ServerHandle` through which other tasks send and receive concurrently.
+  ///
+  /// This is `Server::spawn_driver` without the thread, for applications which already run an
+  /// executor.  The future completes once `ServerHandle::shutdown` is called or every handle has
+  /// been dropped.  Events are only available to subscribers added with `Server::subscribe`
+  /// before the server is split.
+  pub fn run(self) -> (ServerHandle, impl Future<Output = ()> + Send) {
+    run(self)
   }
 
-  /// List all the currently fully established client connections.
-  pub fn connected_clients(&mut self) -> String {
-    self
-      .clients
-      .iter_mut()
-      .filter(|(_, c)| c.is_established())
-      .map(|(addr, _)| addr.to_string() + ",")
-      .collect::<String>()
+  /// Move this server onto a dedicated driver thread as with `Server::spawn_driver`, for servers
+  /// which only ever send, such as broadcast-only telemetry.
+  ///
+  /// The driver answers STUN, completes handshakes and times out clients on its own, while every
+  /// message received from a client is discarded as soon as it arrives, so nothing needs to be
+  /// received for the server to keep working.
+  pub fn spawn_headless(self) -> Result<(SenderHandle, EventStream, ControlHandle), IoError> {
+    spawn_headless(self)
   }
 
-  /// Returns true if the client has a completely established WebRTC data channel connection and
-  /// can send messages back and forth.  Returns false for disconnected clients as well as those
-  /// that are still starting up or are in the process of shutting down.
-  pub fn is_connected(&self, remote_addr: &SocketAddr) -> bool {
-    if let Some(client) = self.clients.get(remote_addr) {
-      client.is_established()
-    } else {
-      false
+  /// Connect a native data channel client to this server over the loopback interface, driving
+  /// the server until the client's data channel is open.
+  ///
+  /// This goes through the same session request, STUN, DTLS, SCTP and data channel handshakes as
+  /// a browser, which makes it useful for smoke testing a server without any browser.  The client
+  /// connects to the first listening socket.
+  pub async fn connect_loopback(&mut self) -> Result<LoopbackClient, IoError> {
+    let server_addr = loopback_addr(self.udp_sockets[0].get_ref().local_addr()?);
+    let mut session_endpoint = self.session_endpoint.clone();
+    let connect = LoopbackClient::connect(&mut session_endpoint, server_addr).fuse();
+    pin_mut!(connect);
+    loop {
+      let process = self.process().fuse();
+      pin_mut!(process);
+      select! {
+        client = connect => return client,
+        res = process => res?,
+      }
     }
   }
 
-  /// Disconect the given client, does nothing if the client is not currently connected.
-  pub async fn disconnect(&mut self, remote_addr: &SocketAddr) -> Result<(), IoError> {
-    if let Some(client) = self.clients.get_mut(remote_addr) {
-      match client.start_shutdown() {
-        Ok(true) => {
-          //   log::info!("starting shutdown for client {}", remote_addr);
-        }
-        Ok(false) => {}
-        Err(_) => {}
-      }
-
-      self
-        .outgoing_udp
-        .extend(client.take_outgoing_packets().map(|p| (p, *remote_addr)));
-      match self.send_outgoing().await {
-        Ok(_) => {}
-        Err(_) => {}
-      }
-    }
+  /// This server's events for the FFI event callback passed to `Server::new`, which are
+  /// delivered with `FfiEventQueue::dispatch` under `FfiEventDelivery::Poll`.
+  ///
+  /// Each server has its own callback and queue, so several servers with different callbacks may
+  /// run in one process.
+  pub fn ffi_event_queue(&self) -> FfiEventQueue {
+    self.ffi_events.clone()
+  }
 
-    Ok(())
+  /// The configuration this server was started with.
+  pub fn config(&self) -> &ServerConfig {
+    &self.config
   }
 
-  /// Send the given message to the given remote client, if they are connected.
+  /// Take the oldest pending `ServerEvent`, if there is one.
   ///
-  /// The given message must be less than `MAX_MESSAGE_LEN`.
-  pub async fn send(
-    &mut self,
-    message: &[u8],
-    message_type: MessageType,
-    remote_addr: &SocketAddr,
-  ) -> Result<(), SendError> {
-    let client = self
-      .clients
-      .get_mut(remote_addr)
-      .ok_or(SendError::ClientNotConnected)?;
+  /// Events are produced while the server is driven by `Server::recv`.  If they are never taken
+  /// only the most recent 1024 are kept.
+  pub fn poll_event(&mut self) -> Option<ServerEvent> {
+    self.events.pop_front()
+  }
 
-    let send_result = client.send_message(message_type, message);
-    match send_result {
-      Err(ClientError::NotConnected) | Err(ClientError::NotEstablished) => {
-        return Err(SendError::ClientNotConnected).into();
-      }
-      Err(ClientError::IncompletePacketWrite) => {
-        return Err(SendError::IncompleteMessageWrite).into();
+  /// Wait for the next `ServerEvent` or received message, whichever comes first, as an
+  /// alternative to calling both `Server::recv` and `Server::poll_event`.
+  ///
+  /// Messages are returned as `ServerEvent::Message`, after any other pending events, so a
+  /// client's `ServerEvent::ClientConnected` always comes before its first message.  Like
+  /// `Server::recv`, this drives the server and must be called continuously.
+  pub async fn next_event(&mut self) -> Result<ServerEvent, IoError> {
+    loop {
+      if let Some(event) = self.events.pop_front() {
+        return Ok(event);
       }
-      Err(err) => {
-        let shutdown = client.start_shutdown();
-        let catcher = match shutdown {
-          Ok(true) => Err(SendError::ClientError(err.to_string())),
-          Ok(false) => Err(SendError::ClientNotConnected),
-          Err(cerror) => Err(SendError::ClientError(cerror.to_string())),
-        };
-        return catcher.into();
+      if !self.incoming_rtc.is_empty() {
+        return self.recv().await.map(ServerEvent::Message);
       }
-      Ok(()) => {}
+      self.process().await?;
     }
+  }
 
-    self
-      .outgoing_udp
-      .extend(client.take_outgoing_packets().map(|p| (p, *remote_addr)));
-    self.send_outgoing().await?;
-    Ok(())
+  /// Subscribe to the `ServerEvent`s of the given kinds, returning a stream which receives each
+  /// of them as it is produced.
+  ///
+  /// Any number of subscribers may each have their own filter, for example one for metrics
+  /// taking only `EventKind::Stats` and one for logging taking only `EventKind::Error`.  They
+  /// receive events independently of each other and of `Server::poll_event`.  Events are dropped
+  /// for a subscriber which falls 1024 events behind, and the subscriber is removed once its
+  /// stream is dropped.  Subscribers keep receiving events after `Server::spawn_driver`.
+  pub fn subscribe(&mut self, kinds: &[EventKind]) -> EventStream {
+    EventStream(self.events.subscribe(kinds, MAX_QUEUED_EVENTS))
   }
 
-  /// Receive a WebRTC data channel message from any connected client.
+  /// Total number of outgoing packets and received messages dropped because their queue was
+  /// full, always 0 under `OverflowPolicy::Grow` unless clients are paused with `Server::pause`.
+  pub fn dropped_packets(&self) -> u64 {
+    self.outgoing_udp.dropped()
+      + self.incoming_rtc.dropped()
+      + self.incoming_fragments.dropped()
+      + self.deferred_handshakes.dropped()
+      + self.egress_dropped
+      + self
+        .clients
+        .values()
+        .map(|c| c.egress_dropped() + c.held_dropped())
+        .sum::<u64>()
+  }
+
+  /// Run every received message through `middleware` before it is returned from `Server::recv`.
   ///
-  /// `Server::recv` *must* be called for proper operation of the server, as it also handles
-  /// background tasks such as responding to STUN packets and timing out existing sessions.
+  /// Middleware runs in the order it was added, after a message has been fully received and
+  /// before it is queued, so it is the place for checks that apply to every message such as
+  /// protocol version checks, decompression or cheap anti-cheat filters.  How often each
+  /// middleware returned each `Verdict` is reported under `name` in `Server::stats`.
+  pub fn add_inbound_middleware(
+    &mut self,
+    name: impl Into<String>,
+    middleware: impl FnMut(&mut MessageCtx) -> Verdict + Send + 'static,
+  ) {
+    let name = name.into();
+    self.audit.record(None, || AuditKind::ConfigChanged {
+      setting: "inbound_middleware".to_owned(),
+      value: name.clone(),
+    });
+    self.inbound_middleware.push(name, Box::new(middleware));
+  }
+
+  /// Run every message passed to `Server::send` through `middleware` before it is sent, the
+  /// outbound counterpart of `Server::add_inbound_middleware`.
   ///
-  /// If the provided buffer is not large enough to hold the received message, the received
-  /// message will be truncated, and the original length will be returned as part of
-  /// `MessageResult`.
-  pub async fn recv(&mut self) -> Result<MessageResult<'_>, IoError> {
-    while self.incoming_rtc.is_empty() {
-      self.process().await?;
-    }
+  /// This is the place for concerns that apply to every message, such as compression, signing
+  /// or sequence headers, without threading them through every call site.  Middleware sees each
+  /// message along with its type and destination, so it can apply itself selectively.  A message
+  /// dropped by middleware is not sent and `Server::send` still succeeds, while
+  /// `Verdict::Disconnect` starts disconnecting the client and fails the send with
+  /// `SendError::ClientNotConnected`.
+  pub fn add_outbound_middleware(
+    &mut self,
+    name: impl Into<String>,
+    middleware: impl FnMut(&mut MessageCtx) -> Verdict + Send + 'static,
+  ) {
+    let name = name.into();
+    self.audit.record(None, || AuditKind::ConfigChanged {
+      setting: "outbound_middleware".to_owned(),
+      value: name.clone(),
+    });
+    self.outbound_middleware.push(name, Box::new(middleware));
+  }
 
-    let (message, remote_addr, message_type) = self.incoming_rtc.pop_front().unwrap();
-    return Ok(MessageResult {
-      message: MessageBuffer(self.buffer_pool.adopt(message)),
-      message_type,
-      remote_addr,
+  /// Show every received datagram which is neither STUN nor DTLS to `passthrough`, before it is
+  /// dropped, for example to forward QUIC arriving on a port shared through a mux to the service
+  /// it is meant for.
+  ///
+  /// The passthrough runs on the thread driving the server, in the middle of handling packets, so
+  /// it should hand datagrams off rather than process them.  Such datagrams are counted in
+  /// `ServerStats::demux` either way.
+  pub fn set_packet_passthrough(
+    &mut self,
+    passthrough: impl FnMut(PacketClass, SocketAddr, &[u8]) + Send + 'static,
+  ) {
+    self.audit.record(None, || AuditKind::ConfigChanged {
+      setting: "packet_passthrough".to_owned(),
+      value: "enabled".to_owned(),
     });
+    self.packet_passthrough = Some(Box::new(passthrough));
   }
-  // Accepts new incoming WebRTC sessions, times out existing WebRTC sessions, sends outgoing UDP
-  // packets, receives incoming UDP packets, and responds to STUN packets.
-  async fn process(&mut self) -> Result<(), IoError> {
-    enum Next {
-      IncomingSession(IncomingSession),
-      IncomingPacket(usize, SocketAddr),
-      PeriodicTimer,
-    }
 
-    let mut packet_buffer = self.buffer_pool.acquire();
-    packet_buffer.resize(MAX_UDP_PAYLOAD_SIZE, 0);
-    let next = {
-      let recv_udp = self.udp_socket.recv_from(&mut packet_buffer).fuse();
+  /// Report security relevant events to `sink`, such as failed STUN authentication, refused
+  /// handshakes, floods of new clients, kicked clients and changes to the configuration of the
+  /// running server, replacing any sink set before.
+  ///
+  /// Events from before the sink is set are not reported, so it is best set right after the
+  /// server is created.
+  pub fn set_audit_sink(&mut self, sink: impl AuditSink + 'static) {
+    self.audit.set_sink(Box::new(sink));
+  }
+
+  /// Current performance counters, including the distribution of packet processing latency.
+  pub fn stats(&self) -> ServerStats {
+    ServerStats {
+      dropped_packets: self.dropped_packets(),
+      processing_latency: self.processing_latency.stats(),
+      inbound_middleware: self.inbound_middleware.stats(),
+      outbound_middleware: self.outbound_middleware.stats(),
+      channel_traffic: {
+        let mut traffic = self.closed_traffic.clone();
+        for client in self.clients.values() {
+          traffic.merge(client.traffic());
+        }
+        traffic.into_channels()
+      },
+      reassembly: {
+        let mut reassembly = self.closed_reassembly;
+        for client in self.clients.values() {
+          reassembly.merge(client.reassembly());
+        }
+        reassembly
+      },
+      stale_source_packets: self.stale_source_packets,
+      refused_clients: self.refused_clients,
+      redirected_clients: self.redirected_clients,
+      relayed_messages: self.relayed_messages,
+      refused_downgrades: self.refused_downgrades,
+      demux: self.demux,
+      funnel: self.funnel,
+    }
+  }
+
+  /// How much memory the server's buffers and queues hold, broken down by where it is held, for
+  /// example to alert well before a container's memory limit is reached.
+  ///
+  /// This walks every client and session, so it is meant to be called every few seconds at most.
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage {
+      buffer_pool: self.buffer_pool.pooled_bytes(),
+      ..Default::default()
+    };
+    for client in self.clients.values() {
+      usage.client_send_queues += client.egress_bytes();
+      usage.client_receive_queues += client.received_bytes();
+      usage.reassembly += client.reassembly_bytes();
+    }
+    usage.received_queue = self
+      .incoming_rtc
+      .iter()
+      .map(|(message, ..)| message.capacity())
+      .chain(
+        self
+          .incoming_fragments
+          .iter()
+          .map(|(fragment, ..)| fragment.capacity()),
+      )
+      .sum();
+    usage.outgoing_queue = self
+      .outgoing_udp
+      .iter()
+      .chain(self.deferred_handshakes.iter())
+      .map(|(packet, ..)| packet.capacity())
+      .sum();
+    usage.scheduled_sends = self.scheduled_sends.bytes();
+    usage.session_table = self
+      .sessions
+      .iter()
+      .map(|(key, session)| session_bytes(key, session))
+      .sum();
+    usage
+  }
+
+  /// Forget every processing latency recorded so far, so that later `Server::stats` describe
+  /// only the packets received from now on.
+  pub fn reset_latency_stats(&mut self) {
+    self.processing_latency.clear();
+  }
+
+  /// Serve JSON dumps of this server's clients, sessions, queues, configuration, stats and
+  /// recent client errors at `addr`, for inspecting a live process.
+  ///
+  /// Any HTTP `GET` is answered, `/` with everything and `/clients`, `/sessions`, `/queues`,
+  /// `/config`, `/stats` or `/errors` with just that section, for example with
+  /// `curl --unix-socket /run/game/inspector.sock http://localhost/clients`.  Dumps are taken
+  /// while the server is being driven, so the inspector answers with `503` if it is not.  The
+  /// inspector stops listening once the server is dropped.
+  ///
+  /// This may be called more than once to listen on several addresses.
+  #[cfg(feature = "inspector")]
+  pub fn enable_inspector(&mut self, addr: InspectorAddr) -> Result<(), IoError> {
+    self.audit.record(None, || AuditKind::ConfigChanged {
+      setting: "inspector".to_owned(),
+      value: format!("{:?}", addr),
+    });
+    let (sender, _) = self.inspector.get_or_insert_with(flume::unbounded);
+    spawn_inspector(addr, sender.clone())
+  }
+
+  /// The local addresses of every UDP socket this server is listening on.
+  pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, IoError> {
+    self
+      .udp_sockets
+      .iter()
+      .map(|socket| socket.get_ref().local_addr())
+      .collect()
+  }
+
+  /// The weakest DTLS protocol version, cipher suites and SRTP profiles this server accepts.
+  pub fn dtls_floor(&self) -> DtlsFloor {
+    DTLS_FLOOR
+  }
+
+  /// Returns a `SessionEndpoint` which can be used to start new WebRTC sessions.
+  ///
+  /// WebRTC connections must be started via an external communication channel from a browser via
+  /// the returned `SessionEndpoint`, and this communication channel will be used to exchange
+  /// session descriptions in SDP format.
+  ///
+  /// The returned `SessionEndpoint` will notify this `Server` of new sessions via a shared async
+  /// channel.  This is done so that the `SessionEndpoint` is easy to use in a separate server
+  /// task (such as a `hyper` HTTP server).
+  pub fn session_endpoint(&self) -> SessionEndpoint {
+    self.session_endpoint.clone()
+  }
+
+  /// Returns a new `SessionEndpoint` as with `Server::session_endpoint`, but whose answers are
+  /// customized by `config`.
+  ///
+  /// Any number of differently configured endpoints may start sessions on the same `Server`.
+  pub fn session_endpoint_with_config(&self, config: SessionEndpointConfig) -> SessionEndpoint {
+    SessionEndpoint {
+      answer_cache: AnswerCache::new_shared(&config),
+      protocol_versions: config
+        .protocol_versions
+        .clone()
+        .unwrap_or_else(|| self.session_endpoint.protocol_versions.clone()),
+      config,
+      accepting: Arc::new(AtomicBool::new(true)),
+      ..self.session_endpoint.clone()
+    }
+  }
+
+  /// Stop accepting new sessions, for example ahead of a rolling restart, while clients already
+  /// connected keep being served.
+  ///
+  /// From now on every `SessionEndpoint` of this server fails with `SessionError::Draining`, and
+  /// STUN binding requests for sessions the server does not know about, including stateless
+  /// tokens, are treated as coming from unknown users.  Sessions already accepted may still
+  /// connect.  Wait for the remaining clients to leave with `Server::drained`.
+  pub fn begin_drain(&mut self) {
+    self.audit.record(None, || AuditKind::ConfigChanged {
+      setting: "draining".to_owned(),
+      value: "true".to_owned(),
+    });
+    self
+      .session_endpoint
+      .draining
+      .store(true, Ordering::Relaxed);
+  }
+
+  /// Whether `Server::begin_drain` has been called.
+  pub fn is_draining(&self) -> bool {
+    self.session_endpoint.draining.load(Ordering::Relaxed)
+  }
+
+  /// A future which resolves once this server has no clients left, or is dropped.
+  ///
+  /// It does not borrow the server, which must keep being driven for the clients to leave, for
+  /// example by awaiting `Server::recv` in another branch of a `select!`.  After
+  /// `Server::begin_drain` this resolves when the last client disconnects.
+  pub fn drained(&mut self) -> impl Future<Output = ()> + Send + 'static {
+    let (sender, receiver) = flume::bounded(0);
+    if !self.clients.is_empty() {
+      self.drain_waiters.push(sender);
+    }
+    async move {
+      // Nothing is ever sent, the sender being dropped is the signal.
+      let _ = receiver.recv_async().await;
+    }
+  }
+
+  /// A future which resolves to true once the client at `remote_addr` is established, as with
+  /// `Server::is_connected`, or to false if `timeout` passes first or the server is dropped.
+  ///
+  /// This lets a signalling server hold its reply until the data channel is actually open.  The
+  /// client need not be known yet when this is called.  As with `Server::drained`, the future
+  /// does not borrow the server, which must keep being driven meanwhile.
+  pub fn wait_connected(
+    &mut self,
+    remote_addr: SocketAddr,
+    timeout: Duration,
+  ) -> impl Future<Output = bool> + Send + 'static {
+    let (sender, receiver) = flume::bounded(1);
+    self.add_connect_waiter(remote_addr, sender);
+    wait_connected(receiver, timeout)
+  }
+
+  // Signal `sender` once the client at `remote_addr` is established, which may be right away.
+  pub(crate) fn add_connect_waiter(&mut self, remote_addr: SocketAddr, sender: flume::Sender<()>) {
+    if self.is_connected(&remote_addr) {
+      let _ = sender.try_send(());
+    } else {
+      // Forget about the futures which were dropped before their client connected.
+      self
+        .connect_waiters
+        .retain(|(_, sender)| !sender.is_disconnected());
+      self.connect_waiters.push((remote_addr, sender));
+    }
+  }
+
+  /// The total count of clients in any active state, whether still starting up, fully
+  /// established, or still shutting down.
+  pub fn active_clients(&self) -> usize {
+    self.clients.values().filter(|c| !c.is_shutdown()).count()
+  }
+
+  /// Every client in any state, in no particular order.
+  pub fn clients(&self) -> impl Iterator<Item = ClientInfo> + '_ {
+    self.clients.iter_ids().map(|(id, client)| {
+      let mut info = ClientInfo {
+        remote_addr: client._remote_addr,
+        id,
+        state: client.state(),
+        established_at: client.established_at,
+        messages_sent: 0,
+        bytes_sent: 0,
+        messages_received: 0,
+        bytes_received: 0,
+      };
+      for channel in client.traffic().channels() {
+        info.messages_sent += channel.messages_sent;
+        info.bytes_sent += channel.bytes_sent;
+        info.messages_received += channel.messages_received;
+        info.bytes_received += channel.bytes_received;
+      }
+      info
+    })
+  }
+
+  /// A handle for reading snapshots of `Server::clients` from other threads, such as a stats
+  /// endpoint, without going through the server, see `SnapshotHandle`.
+  ///
+  /// Every call publishes a fresh snapshot, and handles from earlier calls see it too.
+  pub fn client_snapshots(&mut self) -> SnapshotHandle {
+    let snapshot = self.take_client_snapshot();
+    match &self.client_snapshots {
+      Some(snapshots) => {
+        snapshots.publish(snapshot);
+        snapshots.clone()
+      }
+      None => {
+        let snapshots = SnapshotHandle::new(snapshot);
+        self.client_snapshots = Some(snapshots.clone());
+        snapshots
+      }
+    }
+  }
+
+  /// List all the currently fully established client connections, each followed by a comma.
+  /// `Server::clients` describes them in more detail.
+  pub fn connected_clients(&mut self) -> String {
+    self
+      .clients
+      .iter_mut()
+      .filter(|(_, c)| c.is_established())
+      .map(|(addr, _)| addr.to_string() + ",")
+      .collect::<String>()
+  }
+
+  /// Up to `limit` clients in any state, in ascending order of address, starting after `cursor`
+  /// or from the first client if it is `None`.  A `limit` of 0 is taken as 1.
+  ///
+  /// Paging through every client this way never returns a client twice, even as clients come
+  /// and go in between pages, and each page only costs as much as the clients on it, which
+  /// keeps admin tooling cheap on servers with tens of thousands of clients.
+  pub fn clients_page(&self, cursor: Option<SocketAddr>, limit: usize) -> ClientsPage {
+    let start = match cursor {
+      Some(cursor) => Bound::Excluded(cursor),
+      None => Bound::Unbounded,
+    };
+    let mut remaining = self.client_order.range((start, Bound::Unbounded));
+    let clients: Vec<SocketAddr> = remaining.by_ref().take(limit.max(1)).copied().collect();
+    let next_cursor = match remaining.next() {
+      Some(_) => clients.last().copied(),
+      None => None,
+    };
+    ClientsPage {
+      clients,
+      next_cursor,
+    }
+  }
+
+  #[cfg(feature = "compat")]
+  pub(crate) fn connected_client_addrs(&self) -> impl Iterator<Item = &SocketAddr> + '_ {
+    self
+      .clients
+      .iter()
+      .filter(|(_, c)| c.is_established())
+      .map(|(addr, _)| addr)
+  }
+
+  /// The id of the client currently at the given address, which stays the same when the client
+  /// moves to another address, see `ServerEvent::ClientRebound`.
+  pub fn client_id(&self, remote_addr: &SocketAddr) -> Option<ClientId> {
+    self.clients.id(remote_addr)
+  }
+
+  /// The address the client with the given id is currently at, `None` once it has been removed.
+  pub fn client_addr(&self, client_id: ClientId) -> Option<SocketAddr> {
+    self.clients.addr(client_id)
+  }
+
+  /// Returns true if the client has a completely established WebRTC data channel connection and
+  /// can send messages back and forth.  Returns false for disconnected clients as well as those
+  /// that are still starting up or are in the process of shutting down.
+  pub fn is_connected(&self, remote_addr: &SocketAddr) -> bool {
+    if let Some(client) = self.clients.get(remote_addr) {
+      client.is_established()
+    } else {
+      false
+    }
+  }
+
+  /// The application protocol version the client named in its first message, one of
+  /// `ServerConfig::protocol_versions`.
+  ///
+  /// Returns `None` for unknown clients, clients which have not sent a message yet, and always
+  /// when no versions are configured.
+  pub fn protocol_version(&self, remote_addr: &SocketAddr) -> Option<&str> {
+    let index = self.clients.get(remote_addr)?.protocol_version?;
+    Some(&self.config.protocol_versions[index])
+  }
+
+  /// Stop delivering the messages of the given client to `Server::recv` and
+  /// `Server::recv_stream`, for example while it sits on a loading screen or is being migrated to
+  /// another match.
+  ///
+  /// The connection is kept up as usual, and messages sent to the client still go out.  Its
+  /// received messages are held until `Server::resume`, up to `ServerConfig::max_held_messages`,
+  /// beyond which they are dropped and counted in `Server::dropped_packets`.  Messages on reliable
+  /// channels are never dropped: beyond the limit they go unacknowledged, so the client sends them
+  /// again after `Server::resume`.  Returns false if the client is not known.
+  pub fn pause(&mut self, remote_addr: &SocketAddr) -> bool {
+    match self.clients.get_mut(remote_addr) {
+      Some(client) => {
+        client.pause(self.config.max_held_messages);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Deliver the messages held for a client paused with `Server::pause`, in the order they were
+  /// received, and go back to delivering its messages as they arrive.  Returns false if the
+  /// client is not known.
+  pub fn resume(&mut self, remote_addr: &SocketAddr) -> bool {
+    match self.clients.get_mut(remote_addr) {
+      Some(client) => {
+        client.resume();
+        self.deliver_received(*remote_addr);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Returns true if the client is paused with `Server::pause`.
+  pub fn is_paused(&self, remote_addr: &SocketAddr) -> bool {
+    self
+      .clients
+      .get(remote_addr)
+      .is_some_and(|client| client.is_paused())
+  }
+
+  /// Disconect the given client, does nothing if the client is not currently connected.
+  pub async fn disconnect(&mut self, remote_addr: &SocketAddr) -> Result<(), IoError> {
+    if let Some(client) = self.clients.get_mut(remote_addr) {
+      client.clear_egress();
+      match client.start_shutdown(DisconnectReason::LocalShutdown) {
+        Ok(true) => {
+          //   log::info!("starting shutdown for client {}", remote_addr);
+          self.audit.record(Some(*remote_addr), || AuditKind::Kicked {
+            reason_code: None,
+            reason: "disconnected by the application".to_owned(),
+          });
+        }
+        Ok(false) => {}
+        Err(_) => {}
+      }
+
+      schedule_egress(&mut self.egress_ready, *remote_addr, client);
+      match self.send_outgoing().await {
+        Ok(_) => {}
+        Err(_) => {}
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Disconnect the given client as with `Server::disconnect`, after sending it a final text
+  /// message such as `{"close":4003,"reason":"kicked for cheating"}` and an SCTP SHUTDOWN, so that
+  /// the application in the browser can tell being kicked from losing its connection.
+  ///
+  /// `reason_code` is up to the application, the range 4000 to 4999 mirrors the WebSocket close
+  /// codes private to applications, of which `VERSION_MISMATCH_CLOSE_CODE` and
+  /// `SESSION_EXPIRED_CLOSE_CODE` are already taken.
+  /// Messages still queued for the client are dropped.  Fails with
+  /// `SendError::ClientNotConnected` if the client is not established.
+  pub async fn kick(
+    &mut self,
+    remote_addr: &SocketAddr,
+    reason_code: u16,
+    message: &str,
+  ) -> Result<(), SendError> {
+    let client = match self.clients.get_mut(remote_addr) {
+      Some(client) if client.is_established() => client,
+      _ => return Err(SendError::ClientNotConnected),
+    };
+    client.clear_egress();
+    let close_message = serde_json::json!({
+      "close": reason_code,
+      "reason": message,
+    })
+    .to_string();
+    self.audit.record(Some(*remote_addr), || AuditKind::Kicked {
+      reason_code: Some(reason_code),
+      reason: message.to_owned(),
+    });
+    let goodbye = client.send_goodbye(Some(&close_message));
+    let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+    schedule_egress(&mut self.egress_ready, *remote_addr, client);
+    self.send_outgoing().await?;
+    goodbye.map_err(|err| SendError::ClientError(err.to_string()))
+  }
+
+  /// Attach `data` to the given client, such as the ID of the player it belongs to, replacing
+  /// whatever was attached before.
+  ///
+  /// The data lives exactly as long as the client, so it is dropped along with it however it
+  /// goes away and follows it to a new address when its NAT rebinds it.  Returns `false` if there
+  /// is no such client.
+  pub fn set_client_data<T: Any + Send>(&mut self, remote_addr: &SocketAddr, data: T) -> bool {
+    match self.clients.get_mut(remote_addr) {
+      Some(client) => {
+        client.user_data = Some(Box::new(data));
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// The data attached to the given client with `Server::set_client_data`, if there is any of
+  /// type `T`.
+  pub fn get_client_data<T: Any>(&self, remote_addr: &SocketAddr) -> Option<&T> {
+    self
+      .clients
+      .get(remote_addr)?
+      .user_data
+      .as_ref()?
+      .downcast_ref()
+  }
+
+  /// Mutable access to the data attached to the given client, as with `Server::get_client_data`.
+  pub fn get_client_data_mut<T: Any>(&mut self, remote_addr: &SocketAddr) -> Option<&mut T> {
+    self
+      .clients
+      .get_mut(remote_addr)?
+      .user_data
+      .as_mut()?
+      .downcast_mut()
+  }
+
+  /// Detach and return the data attached to the given client, if there is any of type `T`.
+  pub fn take_client_data<T: Any>(&mut self, remote_addr: &SocketAddr) -> Option<T> {
+    let user_data = &mut self.clients.get_mut(remote_addr)?.user_data;
+    if !user_data.as_ref()?.is::<T>() {
+      return None;
+    }
+    user_data.take()?.downcast().ok().map(|data| *data)
+  }
+
+  /// Hand every message later received from the given client to `transport` instead of
+  /// `Server::recv`, for example to forward a player's traffic to the zone server they moved to
+  /// while their browser stays connected here.
+  ///
+  /// Messages are relayed after the inbound middleware has run, and what the transport does with
+  /// them, along with how the peer server answers, is up to the application, whose answers are
+  /// sent back with `Server::relay_return`.  The transport runs on the thread driving the server,
+  /// in the middle of handling packets, so it should hand messages off rather than process them.
+  /// A failed transport is recorded as a client error and the message is lost.  Fragments
+  /// streamed under `ServerConfig::stream_fragments` are not relayed but discarded.  Returns
+  /// `false` if there is no such client, and replaces any transport it was already relayed with.
+  pub fn relay(
+    &mut self,
+    remote_addr: &SocketAddr,
+    transport: impl FnMut(&RelayedMessage) -> Result<(), IoError> + Send + 'static,
+  ) -> bool {
+    match self.clients.get_mut(remote_addr) {
+      Some(client) => {
+        client.relay = Some(Box::new(transport));
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Stop relaying the given client's messages, which are delivered to `Server::recv` again,
+  /// returning whether it was being relayed.
+  pub fn unrelay(&mut self, remote_addr: &SocketAddr) -> bool {
+    self
+      .clients
+      .get_mut(remote_addr)
+      .and_then(|client| client.relay.take())
+      .is_some()
+  }
+
+  /// Whether the given client's messages are being relayed, see `Server::relay`.
+  pub fn is_relayed(&self, remote_addr: &SocketAddr) -> bool {
+    self
+      .clients
+      .get(remote_addr)
+      .is_some_and(|client| client.relay.is_some())
+  }
+
+  /// Send a message from the server the given client is relayed to, as with `Server::send` but
+  /// without running it through outbound middleware, which the peer server has already applied.
+  ///
+  /// This works whether or not the client is still relayed, so that answers in flight when
+  /// `Server::unrelay` is called are not lost.
+  pub async fn relay_return(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    remote_addr: &SocketAddr,
+  ) -> Result<(), SendError> {
+    let result = self.queue_message(
+      message,
+      message_type,
+      Route::Default(Delivery::Unreliable),
+      remote_addr,
+      false,
+    );
+    self.send_outgoing().await?;
+    result
+  }
+
+  /// Send the given message to the given remote client, if they are connected.
+  ///
+  /// The given message must be no longer than the client's `Server::max_message_len`.
+  pub async fn send(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    remote_addr: &SocketAddr,
+  ) -> Result<(), SendError> {
+    self
+      .send_with(message, message_type, Delivery::Unreliable, remote_addr)
+      .await
+  }
+
+  /// Send the given message as with `Server::send`, but delivered according to `delivery`, for
+  /// example reliably and in order for chat next to unreliable state updates.
+  ///
+  /// Reliable messages are retransmitted by the server while it is driven, and fail with
+  /// `SendError::Backpressure` once the client has more than `MAX_UNACKED_BYTES` of them
+  /// unacknowledged.
+  pub async fn send_with(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    delivery: Delivery,
+    remote_addr: &SocketAddr,
+  ) -> Result<(), SendError> {
+    let result = self.queue_message(
+      message,
+      message_type,
+      Route::Default(delivery),
+      remote_addr,
+      true,
+    );
+    self.send_outgoing().await?;
+    result
+  }
+
+  /// Send the given message as with `Server::send`, but on the data channel `channel_id` which the
+  /// client opened, delivered as the browser negotiated for it.  See `ServerEvent::ChannelOpened`.
+  ///
+  /// This fails with `SendError::UnknownChannel` if the client has not opened such a channel or
+  /// has closed it since.
+  pub async fn send_on(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    channel_id: u16,
+    remote_addr: &SocketAddr,
+  ) -> Result<(), SendError> {
+    let result = self.queue_message(
+      message,
+      message_type,
+      Route::Channel(channel_id),
+      remote_addr,
+      true,
+    );
+    self.send_outgoing().await?;
+    result
+  }
+
+  /// Open a data channel called `label` to the given client, returning its id once the client
+  /// has acknowledged it, after which `Server::send_on` sends to it as `delivery`.
+  ///
+  /// The browser sees the channel in its `ondatachannel` handler.  The server is driven as with
+  /// `Server::recv` while waiting, and messages and events arriving meanwhile are kept for later
+  /// calls, along with a `ServerEvent::ChannelOpened` for the new channel.  This fails with
+  /// `SendError::ClientNotConnected` if the client's data channel is not established yet, or if
+  /// it disconnects before acknowledging, and with `SendError::ClientError` if every stream the
+  /// client's association allows for channels from this side is taken.
+  pub async fn open_channel(
+    &mut self,
+    remote_addr: &SocketAddr,
+    label: &str,
+    delivery: Delivery,
+  ) -> Result<u16, SendError> {
+    let client = self
+      .clients
+      .get_mut(remote_addr)
+      .ok_or(SendError::ClientNotConnected)?;
+    let channel_id = match client.open_channel(label, delivery) {
+      Ok(channel_id) => channel_id,
+      Err(ClientError::NotConnected) | Err(ClientError::NotEstablished) => {
+        return Err(SendError::ClientNotConnected);
+      }
+      Err(ClientError::SendWindowFull) => return Err(SendError::Backpressure),
+      Err(err) => return Err(SendError::ClientError(err.to_string())),
+    };
+    schedule_egress(&mut self.egress_ready, *remote_addr, client);
+    self.send_outgoing().await?;
+
+    loop {
+      match self.clients.get(remote_addr) {
+        Some(client) if client.is_channel_open(channel_id) => return Ok(channel_id),
+        Some(client) if !client.shutdown_started() => {}
+        _ => return Err(SendError::ClientNotConnected),
+      }
+      self.process().await?;
+    }
+  }
+
+  /// Send the given message to every connected client, as with `Server::send`.
+  ///
+  /// The message is queued for every client before any packet is written, so all clients get it
+  /// in one batch of socket writes.  It is still framed and encrypted once per client, as SCTP and
+  /// DTLS require, and passed through outbound middleware for each.  Clients which could not be
+  /// sent the message are returned along with why, for example those whose
+  /// `Server::max_message_len` is too small, while the others still get it.  Only an error
+  /// writing to the socket fails the whole broadcast.
+  pub async fn broadcast(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+  ) -> Result<Vec<(SocketAddr, SendError)>, IoError> {
+    let remote_addrs: Vec<SocketAddr> = self
+      .clients
+      .iter()
+      .filter(|(_, client)| client.is_established())
+      .map(|(remote_addr, _)| *remote_addr)
+      .collect();
+    self
+      .send_to_many(message, message_type, &remote_addrs)
+      .await
+  }
+
+  /// Send the given message to each of the given remote clients, as with `Server::broadcast`.
+  ///
+  /// Addresses of clients which are not connected are returned with
+  /// `SendError::ClientNotConnected`, and an address listed twice is sent the message twice.
+  pub async fn send_to_many(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    remote_addrs: &[SocketAddr],
+  ) -> Result<Vec<(SocketAddr, SendError)>, IoError> {
+    let mut failed = Vec::new();
+    for remote_addr in remote_addrs {
+      if let Err(err) = self.queue_message(
+        message,
+        message_type,
+        Route::Default(Delivery::Unreliable),
+        remote_addr,
+        true,
+      ) {
+        failed.push((*remote_addr, err));
+      }
+    }
+    self.send_outgoing().await?;
+    Ok(failed)
+  }
+
+  /// Send the given message to the given remote client at `at` rather than right away, for
+  /// example to pace snapshots evenly or to align them with a client's interpolation delay.
+  ///
+  /// The message is copied and held by the server until it is due, and then sent as with
+  /// `Server::send`, including outbound middleware.  Messages scheduled for the same instant are
+  /// sent in the order they were scheduled, and a message whose time has already passed is sent
+  /// the next time the server is driven.  Scheduled messages are only sent while the server is
+  /// driven, by `Server::recv`, `Server::poll_io`, `Server::run` or `Server::spawn_driver`, and
+  /// are silently dropped if the client has disconnected by then.
+  ///
+  /// This fails immediately with `SendError::ClientNotConnected` if the client's data channel is
+  /// not established, as while it is still handshaking or once it is shutting down, or with
+  /// `SendError::IncompleteMessageWrite` if the message is longer than its
+  /// `Server::max_message_len`.
+  pub fn send_at(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    remote_addr: &SocketAddr,
+    at: Instant,
+  ) -> Result<(), SendError> {
+    let client = self
+      .clients
+      .get(remote_addr)
+      .filter(|client| client.is_established())
+      .ok_or(SendError::ClientNotConnected)?;
+    if message.len() > client.max_message_len {
+      return Err(SendError::IncompleteMessageWrite);
+    }
+
+    let mut buffer = self.buffer_pool.acquire();
+    buffer.extend_from_slice(message);
+    self
+      .scheduled_sends
+      .push(at, buffer.into_owned(), message_type, *remote_addr);
+    Ok(())
+  }
+
+  // Queue a message on its client's egress queue, after running it through the outbound
+  // middleware unless `outbound_middleware` is false.
+  fn queue_message(
+    &mut self,
+    message: &[u8],
+    message_type: MessageType,
+    route: Route,
+    remote_addr: &SocketAddr,
+    outbound_middleware: bool,
+  ) -> Result<(), SendError> {
+    let client = self
+      .clients
+      .get_mut(remote_addr)
+      .ok_or(SendError::ClientNotConnected)?;
+    if let Some(max_egress) = self.config.max_client_egress {
+      if client.egress_len() >= max_egress {
+        return Err(SendError::Backpressure);
+      }
+    }
+
+    let send_result = if !outbound_middleware || self.outbound_middleware.is_empty() {
+      route.send(client, message_type, message)
+    } else {
+      let mut buffer = self.buffer_pool.acquire();
+      buffer.extend_from_slice(message);
+      match self.outbound_middleware.run(
+        &self.buffer_pool,
+        *remote_addr,
+        message_type,
+        None,
+        buffer.into_owned(),
+      ) {
+        Ok((message_type, message)) => {
+          let message = self.buffer_pool.adopt(message);
+          route.send(client, message_type, &message)
+        }
+        Err(Verdict::Disconnect) => {
+          self.audit.record(Some(*remote_addr), || AuditKind::Kicked {
+            reason_code: None,
+            reason: "disconnected by outbound middleware".to_owned(),
+          });
+          client.clear_egress();
+          let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+          schedule_egress(&mut self.egress_ready, *remote_addr, client);
+          return Err(SendError::ClientNotConnected);
+        }
+        Err(_) => return Ok(()),
+      }
+    };
+    match send_result {
+      Err(ClientError::NotConnected) | Err(ClientError::NotEstablished) => {
+        return Err(SendError::ClientNotConnected).into();
+      }
+      Err(ClientError::IncompletePacketWrite) => {
+        return Err(SendError::IncompleteMessageWrite).into();
+      }
+      Err(ClientError::SendWindowFull) => {
+        return Err(SendError::Backpressure);
+      }
+      Err(ClientError::UnknownChannel(channel_id)) => {
+        return Err(SendError::UnknownChannel(channel_id));
+      }
+      Err(err) => {
+        record_client_error(
+          &mut self.errors,
+          &mut self.events,
+          *remote_addr,
+          ErrorCode::from(&err),
+          format_args!("failed to send: {}", err),
+        );
+        let shutdown = client.start_shutdown(DisconnectReason::DtlsError);
+        let catcher = match shutdown {
+          Ok(true) => Err(SendError::ClientError(err.to_string())),
+          Ok(false) => Err(SendError::ClientNotConnected),
+          Err(cerror) => Err(SendError::ClientError(cerror.to_string())),
+        };
+        return catcher.into();
+      }
+      Ok(()) => {}
+    }
+
+    schedule_egress(&mut self.egress_ready, *remote_addr, client);
+    Ok(())
+  }
+
+  // Queue every message scheduled with `Server::send_at` which is now due.
+  fn queue_scheduled(&mut self) {
+    let now = Instant::now();
+    let buffer_pool = self.buffer_pool.clone();
+    while let Some(scheduled) = self.scheduled_sends.pop_due(now) {
+      let message = buffer_pool.adopt(scheduled.message);
+      let _ = self.queue_message(
+        &message,
+        scheduled.message_type,
+        Route::Default(Delivery::Unreliable),
+        &scheduled.remote_addr,
+        true,
+      );
+    }
+  }
+
+  /// Receive a WebRTC data channel message from any connected client.
+  ///
+  /// `Server::recv` *must* be called for proper operation of the server, as it also handles
+  /// background tasks such as responding to STUN packets and timing out existing sessions.  A
+  /// server which never needs to receive anything can be started with `Server::spawn_headless`
+  /// instead.
+  ///
+  /// If the provided buffer is not large enough to hold the received message, the received
+  /// message will be truncated, and the original length will be returned as part of
+  /// `MessageResult`.
+  pub async fn recv(&mut self) -> Result<MessageResult, IoError> {
+    loop {
+      if let Some(message) = self.try_recv() {
+        return Ok(message);
+      }
+      self.process().await?;
+    }
+  }
+
+  /// Receive a message which has already arrived, as with `Server::recv`, or `None` right away if
+  /// there is none.
+  ///
+  /// This never does any work of its own, so a fixed-tick game loop calls `Server::poll_io` once
+  /// per tick and then `Server::try_recv` until it returns `None`.
+  pub fn try_recv(&mut self) -> Option<MessageResult> {
+    let (message, remote_addr, message_type, subprotocol, channel_id) =
+      self.incoming_rtc.pop_front()?;
+    Some(MessageResult {
+      message: MessageBuffer(self.buffer_pool.freeze(message)),
+      message_type,
+      subprotocol,
+      channel_id,
+      remote_addr,
+    })
+  }
+
+  /// Receive a message as with `Server::recv`, as an `OwnedMessage` which may be modified in
+  /// place.
+  pub async fn recv_owned(&mut self) -> Result<OwnedMessage, IoError> {
+    loop {
+      if let Some(message) = self.try_recv_owned() {
+        return Ok(message);
+      }
+      self.process().await?;
+    }
+  }
+
+  /// Receive a message which has already arrived as an `OwnedMessage`, as with
+  /// `Server::recv_owned`, or `None` right away if there is none.
+  pub fn try_recv_owned(&mut self) -> Option<OwnedMessage> {
+    let (message, remote_addr, message_type, subprotocol, channel_id) =
+      self.incoming_rtc.pop_front()?;
+    Some(OwnedMessage {
+      buffer: self.buffer_pool.detach(message),
+      message_type,
+      subprotocol,
+      channel_id,
+      remote_addr,
+    })
+  }
+
+  /// Receive every message which has arrived, up to `max` of them, waiting for the first one as
+  /// `Server::recv` does.
+  ///
+  /// Once there is a message, the packets already waiting on the socket are handled without
+  /// waiting, as with `Server::poll_io`, so a busy server gets many messages for a single await
+  /// instead of one per `Server::recv`.  Returns no messages at all if `max` is 0.
+  pub async fn recv_batch(&mut self, max: usize) -> Result<Vec<OwnedMessage>, IoError> {
+    if max == 0 {
+      return Ok(Vec::new());
+    }
+    while self.incoming_rtc.is_empty() {
+      self.process().await?;
+    }
+    for _ in 0..MAX_POLL_IO_STEPS {
+      if self.incoming_rtc.len() >= max {
+        break;
+      }
+      match self.process().now_or_never() {
+        Some(res) => res?,
+        None => break,
+      }
+    }
+    let mut batch = Vec::with_capacity(max.min(self.incoming_rtc.len()));
+    while batch.len() < max {
+      match self.try_recv_owned() {
+        Some(message) => batch.push(message),
+        None => break,
+      }
+    }
+    Ok(batch)
+  }
+
+  /// Receive a message as with `Server::recv`, copying it into `buf` instead of returning a
+  /// `MessageBuffer`, for callers such as FFI bindings which already own a buffer to receive into.
+  ///
+  /// Returns the number of bytes copied into `buf`, the original length of the message, which is
+  /// larger if the message was truncated to fit, its type and the client it came from.  Nothing
+  /// borrowed from the server outlives the call, so `Server::send` may be called right away.
+  pub async fn recv_into(
+    &mut self,
+    buf: &mut [u8],
+  ) -> Result<(usize, usize, MessageType, SocketAddr), IoError> {
+    loop {
+      if let Some(received) = self.try_recv_into(buf) {
+        return Ok(received);
+      }
+      self.process().await?;
+    }
+  }
+
+  /// Receive a message which has already arrived into `buf`, as with `Server::recv_into`, or
+  /// `None` right away if there is none.
+  pub fn try_recv_into(
+    &mut self,
+    buf: &mut [u8],
+  ) -> Option<(usize, usize, MessageType, SocketAddr)> {
+    let (message, remote_addr, message_type, ..) = self.incoming_rtc.pop_front()?;
+    let message = self.buffer_pool.adopt(message);
+    let len = message.len().min(buf.len());
+    buf[..len].copy_from_slice(&message[..len]);
+    Some((len, message.len(), message_type, remote_addr))
+  }
+
+  /// Do all the work `Server::recv` would do which is ready right now, such as handling received
+  /// packets, accepting sessions and running timers, without ever waiting.
+  ///
+  /// Received messages are queued for `Server::try_recv`.  At most 1024 pieces of work are done
+  /// per call, so that a flood of packets cannot stall the caller; a loop calling this on every
+  /// tick gets to the rest on the next one.  Outgoing packets the socket cannot take right away are
+  /// left queued for the next call, except for the one being written, which is dropped as UDP
+  /// would under load.
+  pub fn poll_io(&mut self) -> Result<(), IoError> {
+    for _ in 0..MAX_POLL_IO_STEPS {
+      match self.process().now_or_never() {
+        Some(res) => res?,
+        None => break,
+      }
+    }
+    Ok(())
+  }
+
+  /// Receive either a whole message as with `Server::recv`, or a fragment of a message too large
+  /// for a single SCTP DATA chunk as soon as it arrives.
+  ///
+  /// Fragments are only delivered when `ServerConfig::stream_fragments` is set and
+  /// `ServerConfig::max_reassembled_len` is not, otherwise such messages are reassembled or
+  /// discarded.  Streaming them lets large blobs be written to disk as they arrive,
+  /// or refused early, rather than reassembled in memory.
+  pub async fn recv_stream(&mut self) -> Result<StreamedMessage, IoError> {
+    while self.incoming_rtc.is_empty() && self.incoming_fragments.is_empty() {
+      self.process().await?;
+    }
+
+    if let Some((fragment, remote_addr, message_type, subprotocol, channel_id, position)) =
+      self.incoming_fragments.pop_front()
+    {
+      return Ok(StreamedMessage::Fragment(MessageFragment {
+        fragment: MessageBuffer(self.buffer_pool.freeze(fragment)),
+        message_type,
+        subprotocol,
+        channel_id,
+        remote_addr,
+        message_id: position.message_id,
+        offset: position.offset,
+        last: position.last,
+      }));
+    }
+    self.recv().await.map(StreamedMessage::Message)
+  }
+
+  // Accepts new incoming WebRTC sessions, times out existing WebRTC sessions, sends outgoing UDP
+  // packets, receives incoming UDP packets, and responds to STUN packets.
+  async fn process(&mut self) -> Result<(), IoError> {
+    let res = self.process_next().await;
+    self.enter_phase(DriverPhase::Application);
+    res
+  }
+
+  // Tell the watchdog, if there is one, what the server is doing now.
+  fn enter_phase(&self, phase: DriverPhase) {
+    if let Some(watchdog) = &self.watchdog {
+      watchdog.enter(phase);
+    }
+  }
+
+  async fn process_next(&mut self) -> Result<(), IoError> {
+    enum Next {
+      IncomingSession(IncomingSession),
+      IncomingPacket(usize, SocketAddr, LocalSocket, Instant),
+      PeriodicTimer,
+      ScheduledSends,
+      Resolved(usize, Result<SocketAddr, IoError>),
+      Inspect(InspectorRequest),
+    }
+
+    // Datagrams are received straight into the buffer's spare capacity, which pooled buffers
+    // usually already have, rather than zeroing a full datagram's worth of buffer every time.
+    let mut packet_buffer = self.buffer_pool.acquire();
+    packet_buffer.reserve(MAX_UDP_PAYLOAD_SIZE);
+    self.enter_phase(DriverPhase::Idle);
+    let next = {
+      let udp_sockets = &self.udp_sockets;
+      let next_socket = &mut self.next_socket;
+      let spare = &mut packet_buffer.spare_capacity_mut()[..MAX_UDP_PAYLOAD_SIZE];
+      let recv_udp = poll_fn(|cx| poll_recv_from_any(udp_sockets, next_socket, spare, cx)).fuse();
       pin_mut!(recv_udp);
 
       let timer_next = self.periodic_timer.next().fuse();
       pin_mut!(timer_next);
 
+      let scheduled_next = FutureExt::fuse(match self.scheduled_sends.next_deadline() {
+        Some(at) => Timer::at(at),
+        None => Timer::never(),
+      });
+      pin_mut!(scheduled_next);
+
+      let inspector = &self.inspector;
+      let inspect_next = async move {
+        if let Some((_, requests)) = inspector {
+          if let Ok(request) = requests.recv_async().await {
+            return request;
+          }
+        }
+        future::pending().await
+      }
+      .fuse();
+      pin_mut!(inspect_next);
+
       select! {
         incoming_session = self.incoming_session_stream.recv_async().fuse() => {
           Next::IncomingSession(incoming_session.expect("connection to SessionEndpoint has closed"))
         }
         res = recv_udp => {
-          let (len, remote_addr) = res?;
-          Next::IncomingPacket(len, remote_addr)
+          let (len, remote_addr, local_socket) = res?;
+          Next::IncomingPacket(len, remote_addr, local_socket, Instant::now())
         }
         _ = timer_next => {
           Next::PeriodicTimer
         }
+        _ = scheduled_next => {
+          Next::ScheduledSends
+        }
+        resolved = self.dns_receiver.recv_async().fuse() => {
+          let (index, result) = resolved.expect("server holds its own DNS result sender");
+          Next::Resolved(index, result)
+        }
+        request = inspect_next => {
+          Next::Inspect(request)
+        }
+      }
+    };
+
+    self.enter_phase(match next {
+      Next::IncomingSession(_) => DriverPhase::AcceptSession,
+      Next::IncomingPacket(..) => DriverPhase::ReceivePacket,
+      Next::PeriodicTimer => DriverPhase::PeriodicTasks,
+      Next::ScheduledSends => DriverPhase::ScheduledSends,
+      Next::Resolved(..) => DriverPhase::DnsUpdate,
+      Next::Inspect(_) => DriverPhase::Inspect,
+    });
+
+    // Time the host spent suspended is already left out of every `Instant`, so freezing needs
+    // nothing more.
+    if let Some(gap) = self
+      .clock
+      .check(Instant::now(), self.config.clock_jump_threshold)
+    {
+      if self.config.clock_jump_policy == ClockJumpPolicy::Ignore {
+        for client in self.clients.values_mut() {
+          client.count_gap(gap);
+        }
+        for session in self.sessions.values_mut() {
+          count_gap(&mut session.ttl, gap);
+        }
+      }
+      self.events.push_back(ServerEvent::ClockJump { gap });
+    }
+
+    match next {
+      Next::IncomingSession(incoming_session) => {
+        drop(packet_buffer);
+        self.accept_session(incoming_session)
+      }
+      Next::IncomingPacket(len, remote_addr, local_socket, received_at) => {
+        if len > MAX_UDP_PAYLOAD_SIZE {
+          return Err(IoError::new(
+            IoErrorKind::Other,
+            "failed to read entire datagram from socket",
+          ));
+        }
+        // The socket initialized the first `len` bytes of the buffer, which was empty.
+        unsafe {
+          packet_buffer.set_len(len);
+        }
+        let packet_buffer = packet_buffer.into_owned();
+        self.admit_deferred();
+        if self.receive_packet(remote_addr, local_socket, packet_buffer) {
+          self.processing_latency.record(received_at.elapsed());
+        }
+        self.send_outgoing().await?;
+      }
+      Next::PeriodicTimer => {
+        drop(packet_buffer);
+        self.admit_deferred();
+        self.timeout_clients();
+        self.expire_session_lifetimes();
+        self.generate_periodic_packets();
+        self.produce_stats_event();
+        self.audit.report_floods();
+        self.publish_client_snapshot();
+        self.refresh_public_hosts();
+        self.send_outgoing().await?;
+      }
+      Next::ScheduledSends => {
+        drop(packet_buffer);
+        self.queue_scheduled();
+        self.send_outgoing().await?;
+      }
+      Next::Resolved(index, result) => {
+        drop(packet_buffer);
+        self.dns_pending -= 1;
+        // A failed lookup keeps advertising the last address that resolved.
+        if let Ok(new) = result {
+          let mut public_addrs = self.session_endpoint.public_addrs.write().unwrap();
+          let old = public_addrs[index];
+          if old != new {
+            let mut updated = public_addrs.to_vec();
+            updated[index] = new;
+            *public_addrs = updated.into();
+            self
+              .events
+              .push_back(ServerEvent::PublicAddrChanged { old, new });
+          }
+        }
+      }
+      Next::Inspect(reply) => {
+        drop(packet_buffer);
+        let _ = reply.send(self.inspect());
+      }
+    }
+
+    if self.clients.is_empty() {
+      self.drain_waiters.clear();
+    }
+    Ok(())
+  }
+
+  // Send all pending outgoing UDP packets, first the server's own STUN responses and then the
+  // client egress queues, one packet per client at a time so a busy client cannot starve the
+  // others.
+  async fn send_outgoing(&mut self) -> Result<(), IoError> {
+    while let Some((packet, remote_addr, local_socket)) = self.outgoing_udp.pop_front() {
+      let packet = self.buffer_pool.adopt(packet);
+      send_packet(
+        &self.udp_sockets[local_socket.index],
+        &packet,
+        remote_addr,
+        local_socket.ip,
+      )
+      .await?;
+    }
+
+    while let Some(remote_addr) = self.egress_ready.pop_front() {
+      // The client may have been removed or cleared its queue since it was scheduled.
+      let client = match self.clients.get_mut(&remote_addr) {
+        Some(client) => client,
+        None => continue,
+      };
+      let packet = match client.pop_egress() {
+        Some(packet) => packet,
+        None => {
+          client.egress_scheduled = false;
+          continue;
+        }
+      };
+      if client.has_egress() {
+        self.egress_ready.push_back(remote_addr);
+      } else {
+        client.egress_scheduled = false;
+      }
+
+      let packet = self.buffer_pool.adopt(packet);
+      let local_socket = client.local_socket;
+      send_packet(
+        &self.udp_sockets[local_socket.index],
+        &packet,
+        remote_addr,
+        local_socket.ip,
+      )
+      .await?;
+    }
+
+    Ok(())
+  }
+
+  // Handle a single incoming UDP packet, either by responding to it as a STUN binding request or
+  // by handling it as part of an existing WebRTC connection.  Which one is decided by the first
+  // byte as in RFC 7983, and anything else, such as QUIC on a port shared through a mux, is
+  // only counted and shown to the packet passthrough.
+  //
+  // STUN responses always leave from the socket the request arrived on, while a client's
+  // DTLS / SCTP traffic follows whichever socket it most recently used.
+  //
+  // Returns whether the packet produced a response or a received message.
+  fn receive_packet(
+    &mut self,
+    remote_addr: SocketAddr,
+    local_socket: LocalSocket,
+    packet_buffer: OwnedBuffer,
+  ) -> bool {
+    let packet = self.buffer_pool.adopt(packet_buffer);
+    let class = PacketClass::of(&packet);
+    self.demux.record(class);
+    match class {
+      PacketClass::Stun => self.receive_stun(remote_addr, local_socket, packet.into_owned()),
+      PacketClass::Dtls => self.receive_dtls(remote_addr, local_socket, packet.into_owned()),
+      _ => {
+        if let Some(passthrough) = &mut self.packet_passthrough {
+          passthrough(class, remote_addr, &packet);
+        }
+        false
+      }
+    }
+  }
+
+  // Respond to a STUN binding request, admitting a new client if it is the first from its
+  // address.  Requests held back by the admission rate come back here from
+  // `Server::admit_deferred`.
+  fn receive_stun(
+    &mut self,
+    remote_addr: SocketAddr,
+    local_socket: LocalSocket,
+    packet_buffer: OwnedBuffer,
+  ) -> bool {
+    let mut packet_buffer = self.buffer_pool.adopt(packet_buffer);
+    // Binding requests are by far the most frequent packets, so they are answered without
+    // allocating: the request is parsed in place and the response overwrites it in the same buffer.
+    if let Some(stun_binding_request) = parse_stun_binding_request(&packet_buffer[..]) {
+      let StunBindingRequest {
+        transaction_id,
+        use_candidate,
+        ice_role,
+        ..
+      } = stun_binding_request;
+      let session = find_session(
+        &mut self.sessions,
+        stun_binding_request.server_user,
+        stun_binding_request.remote_user,
+      );
+      let draining = self.session_endpoint.draining.load(Ordering::Relaxed);
+      let token_passwd;
+      let server_passwd = match &session {
+        Some(session) => session.server_passwd.as_str(),
+        None => match self
+          .config
+          .stun_token_key
+          .as_ref()
+          .filter(|_| !draining)
+          .and_then(|key| key.verify(stun_binding_request.server_user))
+        {
+          Some(passwd) => {
+            token_passwd = passwd;
+            token_passwd.as_str()
+          }
+          None => {
+            self
+              .audit
+              .record(Some(remote_addr), || AuditKind::UnknownUser {
+                username: stun_binding_request.server_user.to_owned(),
+              });
+            if !self.config.stun_silent_unknown_users
+              && write_stun_error(
+                &mut packet_buffer,
+                transaction_id,
+                StunErrorCode::Unauthorized,
+                None,
+                &self.config,
+              )
+            {
+              self
+                .outgoing_udp
+                .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+              return true;
+            }
+            return false;
+          }
+        },
+      };
+
+      if self.config.stun_require_fingerprint
+        && !verify_stun_fingerprint(&packet_buffer, &stun_binding_request)
+      {
+        // Without a valid FINGERPRINT this is not known to be STUN at all, so it is discarded
+        // without an answer.
+        return false;
+      }
+
+      if !verify_stun_message_integrity(
+        &packet_buffer,
+        &stun_binding_request,
+        server_passwd.as_bytes(),
+      ) {
+        self
+          .audit
+          .record(Some(remote_addr), || AuditKind::IntegrityFailure {
+            username: stun_binding_request.server_user.to_owned(),
+          });
+        if write_stun_error(
+          &mut packet_buffer,
+          transaction_id,
+          StunErrorCode::Unauthorized,
+          None,
+          &self.config,
+        ) {
+          self
+            .outgoing_udp
+            .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+          return true;
+        }
+        return false;
+      }
+
+      // As an ICE lite agent the server always takes the controlled role (RFC 8445 section
+      // 6.1.1).  A peer which also claims the controlled role is told to switch with a 487 Role
+      // Conflict, which is what the tie-breaker comparison of section 7.3.1.1 must conclude for an
+      // agent that is unable to become controlling, whatever the peer's tie-breaker value.
+      if let Some(IceRole::Controlled(_)) = ice_role {
+        if write_stun_error(
+          &mut packet_buffer,
+          transaction_id,
+          StunErrorCode::RoleConflict,
+          Some(server_passwd.as_bytes()),
+          &self.config,
+        ) {
+          self
+            .outgoing_udp
+            .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+          return true;
+        }
+        return false;
+      }
+
+      // New clients are admitted at the configured rate, the others keep their request waiting
+      // for a turn in `Server::admit_deferred`.  A client whose NAT moved it to another port is
+      // not new, and keeps its connection unless it has to authenticate again.
+      //
+      // Browsers check several candidate pairs with the same credentials, so a check from
+      // another address only moves the client once it nominates that pair with USE-CANDIDATE, or
+      // once nothing has been heard from its current address for `REBIND_QUIET_PERIOD`.  Other
+      // checks are answered without moving it or creating another client.
+      let mut ice_users = None;
+      let mut checking_other_pair = false;
+      if !self.clients.contains_key(&remote_addr) {
+        let migrate_across_ips = self.config.migrate_across_ips;
+        let rebound_from = self
+          .clients
+          .iter()
+          .find(|(addr, client)| {
+            (addr.ip() == remote_addr.ip() || migrate_across_ips)
+              && client.has_ice_users(
+                stun_binding_request.server_user,
+                stun_binding_request.remote_user,
+              )
+          })
+          .map(|(addr, client)| (*addr, client.last_heard.elapsed() >= REBIND_QUIET_PERIOD));
+        if let Some((old_addr, quiet)) = rebound_from {
+          if !use_candidate && !quiet {
+            checking_other_pair = true;
+          } else if self.config.reauthenticate_on_rebind {
+            self
+              .audit
+              .record(Some(remote_addr), || AuditKind::RebindRefused {
+                username: stun_binding_request.server_user.to_owned(),
+              });
+            if write_stun_error(
+              &mut packet_buffer,
+              transaction_id,
+              StunErrorCode::Unauthorized,
+              None,
+              &self.config,
+            ) {
+              self
+                .outgoing_udp
+                .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+              return true;
+            }
+            return false;
+          } else {
+            rebind_client(
+              &mut self.clients,
+              &mut self.client_order,
+              &mut self.egress_ready,
+              &mut self.events,
+              old_addr,
+              remote_addr,
+              local_socket,
+            );
+          }
+        } else {
+          ice_users = Some((
+            stun_binding_request.server_user.to_owned(),
+            stun_binding_request.remote_user.to_owned(),
+          ));
+        }
+      }
+      if ice_users.is_some() {
+        let overloaded = match (
+          self.config.try_alternate_beyond,
+          self.config.stun_alternate_server,
+        ) {
+          (Some(max_clients), Some(_)) => self.clients.len() >= max_clients,
+          _ => false,
+        };
+        if overloaded {
+          self.redirected_clients += 1;
+          if write_stun_error(
+            &mut packet_buffer,
+            transaction_id,
+            StunErrorCode::TryAlternate,
+            Some(server_passwd.as_bytes()),
+            &self.config,
+          ) {
+            self
+              .outgoing_udp
+              .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+            return true;
+          }
+          return false;
+        }
+        if !has_room_for_client(&self.config, &self.clients) {
+          self.refused_clients += 1;
+          self.audit.refused_client();
+          return false;
+        }
+        if let Some(admission) = &mut self.admission {
+          if !admission.try_take() {
+            self.audit.deferred_handshake();
+            self.deferred_handshakes.push_back((
+              packet_buffer.into_owned(),
+              remote_addr,
+              local_socket,
+            ));
+            return false;
+          }
+        }
+      }
+
+      let response_origin = if self.config.stun_response_origin {
+        let public_addrs = self.session_endpoint.public_addrs.read().unwrap();
+        public_addrs.get(local_socket.index).copied()
+      } else {
+        None
+      };
+      let extras = StunExtraAttributes {
+        software: self.config.stun_software.as_deref(),
+        alternate_server: None,
+        response_origin,
+      };
+      packet_buffer.resize(stun_success_response_len(remote_addr, &extras), 0);
+      let resp_len = write_stun_success_response(
+        transaction_id,
+        remote_addr,
+        server_passwd.as_bytes(),
+        &extras,
+        &mut packet_buffer,
+      );
+      // Sessions answered with a stateless token are not known here, their offers' limit and
+      // direction are unknown and assumed to be the defaults.
+      let mut max_message_size = None;
+      let mut direction = DataDirection::default();
+      let mut expires_at = None;
+      let mut first_binding = ice_users.is_some();
+      if let Some(session) = session {
+        first_binding = !mem::replace(&mut session.stun_seen, true);
+        session.ttl = Instant::now();
+        max_message_size = session.max_message_size;
+        direction = session.direction;
+        expires_at = session.expires_at;
+      }
+      if first_binding {
+        self.funnel.stun_seen += 1;
+      }
+      match resp_len {
+        Ok(len) => {
+          packet_buffer.truncate(len);
+          self
+            .outgoing_udp
+            .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+
+          let client = if self.clients.contains_key(&remote_addr) {
+            self.clients.get_mut(&remote_addr)
+          } else if checking_other_pair {
+            None
+          } else {
+            let buffer_pool = self.buffer_pool.clone();
+            let client = Client::new(
+              &self.ssl_acceptor,
+              buffer_pool,
+              remote_addr,
+              local_socket,
+              self.config.client_egress_capacity,
+              self.config.queue_overflow,
+              self.ffi_events.clone(),
+            );
+            match client {
+              Ok(mut cl) => {
+                cl.record_trace(
+                  TraceLayer::Stun,
+                  TraceDirection::Received,
+                  "binding request",
+                );
+                cl.record_trace(TraceLayer::Stun, TraceDirection::Sent, "binding success");
+                cl.set_subprotocols(self.subprotocols.clone());
+                cl.set_negotiated_channels(self.negotiated_channels.clone());
+                cl.set_stream_fragments(self.config.stream_fragments);
+                cl.set_reassembly_limits(
+                  self.config.max_partial_messages,
+                  self.config.max_partial_message_len,
+                );
+                cl.set_max_reassembled_len(self.config.max_reassembled_len);
+                cl.set_max_fragment_len(self.config.max_fragment_len);
+                cl.set_max_message_size(max_message_size);
+                cl.data_direction = direction;
+                cl.expires_at = expires_at;
+                cl.ice_users = ice_users;
+                self.client_order.insert(remote_addr);
+                self.clients.insert(cl);
+                if let (Some(expires_at), Some(id)) = (expires_at, self.clients.id(&remote_addr)) {
+                  self.session_expiries.push(Reverse((expires_at, id)));
+                }
+                self.clients.get_mut(&remote_addr)
+              }
+              Err(err) => {
+                record_client_error(
+                  &mut self.errors,
+                  &mut self.events,
+                  remote_addr,
+                  ErrorCode::from(&err),
+                  format_args!("failed to start DTLS: {}", err),
+                );
+                self
+                  .ffi_events
+                  .emit(ErrorCode::from(&err).code(), err.to_string());
+                None
+              }
+            }
+          };
+
+          // The nominated pair is the one the client will use for DTLS, so its traffic should
+          // leave from the socket the nomination arrived on.
+          if let Some(client) = client {
+            client.last_validated = Instant::now();
+            client.last_heard = Instant::now();
+            if use_candidate && !client.nominated {
+              client.record_trace(
+                TraceLayer::Stun,
+                TraceDirection::Received,
+                "binding request with USE-CANDIDATE",
+              );
+              client.nominated = true;
+              client.local_socket = local_socket;
+              self
+                .events
+                .push_back(ServerEvent::PairNominated { remote_addr });
+            }
+          }
+          true
+        }
+        Err(_) => false,
+      }
+    } else if let Some(transaction_id) = stun_binding_request_transaction_id(&packet_buffer) {
+      if write_stun_error(
+        &mut packet_buffer,
+        transaction_id,
+        StunErrorCode::BadRequest,
+        None,
+        &self.config,
+      ) {
+        self
+          .outgoing_udp
+          .push_back((packet_buffer.into_owned(), remote_addr, local_socket));
+        return true;
+      }
+      false
+    } else {
+      false
+    }
+  }
+
+  // Hand a DTLS packet to the client at its source address, if there is one.
+  fn receive_dtls(
+    &mut self,
+    remote_addr: SocketAddr,
+    local_socket: LocalSocket,
+    mut packet_buffer: OwnedBuffer,
+  ) -> bool {
+    if let Some(client) = self.clients.get_mut(&remote_addr) {
+      // A stale validation means the peer may be long gone, and this packet spoofed.  It must
+      // not move the client's traffic to another socket either.
+      if let Some(timeout) = self.config.stun_validation_timeout {
+        if client.last_validated.elapsed() > timeout {
+          self.stale_source_packets += 1;
+          return false;
+        }
+      }
+      let client = client;
+      client.local_socket = local_socket;
+      client.last_heard = Instant::now();
+      if client.is_handshaking() {
+        let packet = self.buffer_pool.adopt(packet_buffer);
+        if let Err(reason) = check_client_hello(&packet) {
+          client.refuse_handshake(reason);
+          self.refused_downgrades += 1;
+          self
+            .audit
+            .record(Some(remote_addr), || AuditKind::DowngradeRefused {
+              reason: reason.to_string(),
+            });
+          self.events.push_back(ServerEvent::DowngradeRefused {
+            remote_addr,
+            reason,
+          });
+          return false;
+        }
+        packet_buffer = packet.into_owned();
+      }
+      let was_handshaking = matches!(
+        client.state(),
+        ClientState::StunBound | ClientState::DtlsHandshaking
+      );
+      let was_established = client.is_established();
+      if let Err(err) = client.receive_incoming_packet(packet_buffer) {
+        client.record_trace(
+          TraceLayer::Server,
+          TraceDirection::Local,
+          format!("failed to receive: {}", err),
+        );
+        record_client_error(
+          &mut self.errors,
+          &mut self.events,
+          remote_addr,
+          ErrorCode::from(&err),
+          format_args!("failed to receive: {}", err),
+        );
+        if !client.shutdown_started() {
+          let _ = client.start_shutdown(DisconnectReason::DtlsError);
+        }
+      }
+      if let Some(error) = client.take_handshake_failure() {
+        self
+          .events
+          .push_back(ServerEvent::HandshakeFailed { remote_addr, error });
       }
-    };
-
-    match next {
-      Next::IncomingSession(incoming_session) => {
-        drop(packet_buffer);
-        self.accept_session(incoming_session)
+      for event in client.take_channel_events() {
+        self.events.push_back(match event {
+          ChannelEvent::Opened {
+            channel_id,
+            label,
+            delivery,
+          } => ServerEvent::ChannelOpened {
+            remote_addr,
+            channel_id,
+            label,
+            delivery,
+          },
+          ChannelEvent::Closed { channel_id } => ServerEvent::ChannelClosed {
+            remote_addr,
+            channel_id,
+          },
+        });
       }
-      Next::IncomingPacket(len, remote_addr) => {
-        if len > MAX_UDP_PAYLOAD_SIZE {
-          return Err(IoError::new(
-            IoErrorKind::Other,
-            "failed to read entire datagram from socket",
-          ));
+      if was_handshaking
+        && matches!(
+          client.state(),
+          ClientState::SctpConnecting | ClientState::Established
+        )
+      {
+        self.funnel.dtls_complete += 1;
+      }
+      if !was_established && client.is_established() {
+        self.funnel.sctp_established += 1;
+        client.established_at = Some(SystemTime::now());
+        self.connect_waiters.retain(|(addr, sender)| {
+          if *addr == remote_addr {
+            let _ = sender.try_send(());
+            return false;
+          }
+          true
+        });
+        if let Some(diagnostics) = client.handshake_diagnostics() {
+          self.events.push_back(ServerEvent::ClientConnected {
+            remote_addr,
+            diagnostics,
+          });
         }
-        packet_buffer.truncate(len);
-        let packet_buffer = packet_buffer.into_owned();
-        self.receive_packet(remote_addr, packet_buffer);
-        self.send_outgoing().await?;
       }
-      Next::PeriodicTimer => {
-        drop(packet_buffer);
-        self.timeout_clients();
-        self.generate_periodic_packets();
-        self.send_outgoing().await?;
+      // A paused client keeps its messages until `Server::resume`, but its acknowledgements
+      // still go out.
+      if client.is_paused() {
+        schedule_egress(&mut self.egress_ready, remote_addr, client);
+        return client.has_egress();
       }
+      self.deliver_received(remote_addr)
+    } else {
+      false
     }
-
-    Ok(())
   }
 
-  // Send all pending outgoing UDP packets
-  async fn send_outgoing(&mut self) -> Result<(), IoError> {
-    while let Some((packet, remote_addr)) = self.outgoing_udp.pop_front() {
-      let packet = self.buffer_pool.adopt(packet);
-      let len = self.udp_socket.send_to(&packet, remote_addr).await?;
-      let packet_len = packet.len();
-      if len != packet_len {
-        return Err(IoError::new(
-          IoErrorKind::Other,
-          "failed to write entire datagram to socket",
-        ));
+  // Hand the messages and fragments a client has received on to `Server::recv` and
+  // `Server::recv_stream`, through version negotiation and inbound middleware, and schedule
+  // whatever it has to send.  Returns whether anything was queued.
+  fn deliver_received(&mut self, remote_addr: SocketAddr) -> bool {
+    let client = match self.clients.get_mut(&remote_addr) {
+      Some(client) => client,
+      None => return false,
+    };
+    let mut delivered = false;
+    let mut disconnect = false;
+    let mut reject_version = false;
+    // Without configured versions every client counts as having agreed on one.
+    let mut negotiated =
+      self.config.protocol_versions.is_empty() || client.protocol_version.is_some();
+    let mut protocol_version = client.protocol_version;
+    // Taken for the duration, as receiving holds on to the client.
+    let mut relay = client.relay.take();
+    for (message_type, subprotocol, channel_id, message) in client.receive_messages() {
+      if disconnect || reject_version {
+        continue;
+      }
+      if !negotiated {
+        let message = self.buffer_pool.adopt(message);
+        match negotiate_version(&self.config.protocol_versions, &message) {
+          Some(index) => {
+            protocol_version = Some(index);
+            negotiated = true;
+          }
+          None => reject_version = true,
+        }
+        continue;
+      }
+      match self.inbound_middleware.run(
+        &self.buffer_pool,
+        remote_addr,
+        message_type,
+        subprotocol,
+        message,
+      ) {
+        Ok((message_type, message)) => {
+          let relay = match &mut relay {
+            Some(relay) => relay,
+            None => {
+              delivered = true;
+              self.incoming_rtc.push_back((
+                message,
+                remote_addr,
+                message_type,
+                subprotocol,
+                channel_id,
+              ));
+              continue;
+            }
+          };
+          let message = self.buffer_pool.adopt(message);
+          let relayed = relay(&RelayedMessage {
+            remote_addr,
+            message_type,
+            subprotocol,
+            message: &message,
+          });
+          match relayed {
+            Ok(()) => self.relayed_messages += 1,
+            Err(err) => record_client_error(
+              &mut self.errors,
+              &mut self.events,
+              remote_addr,
+              ErrorCode::Relay,
+              format_args!("failed to relay: {}", err),
+            ),
+          }
+        }
+        Err(Verdict::Disconnect) => disconnect = true,
+        Err(_) => {}
       }
     }
-    Ok(())
+    for (message_type, subprotocol, channel_id, position, fragment) in client.receive_fragments() {
+      // A version can only be named by a complete message.
+      if disconnect || reject_version || !negotiated || relay.is_some() {
+        continue;
+      }
+      delivered = true;
+      self.incoming_fragments.push_back((
+        fragment,
+        remote_addr,
+        message_type,
+        subprotocol,
+        channel_id,
+        position,
+      ));
+    }
+    client.protocol_version = protocol_version;
+    client.relay = relay;
+    if disconnect {
+      self.audit.record(Some(remote_addr), || AuditKind::Kicked {
+        reason_code: None,
+        reason: "disconnected by inbound middleware".to_owned(),
+      });
+      client.clear_egress();
+      let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+    } else if reject_version {
+      record_client_error(
+        &mut self.errors,
+        &mut self.events,
+        remote_addr,
+        ErrorCode::UnsupportedProtocolVersion,
+        "unsupported protocol version",
+      );
+      let _ = client.send_message(
+        MessageType::Text,
+        version_mismatch_message(&self.config.protocol_versions).as_bytes(),
+      );
+      let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+    }
+    schedule_egress(&mut self.egress_ready, remote_addr, client);
+    delivered || client.has_egress()
   }
 
-  // Handle a single incoming UDP packet, either by responding to it as a STUN binding request or
-  // by handling it as part of an existing WebRTC connection.
-  fn receive_packet(&mut self, remote_addr: SocketAddr, packet_buffer: OwnedBuffer) {
-    let mut packet_buffer = self.buffer_pool.adopt(packet_buffer);
-    if let Some(stun_binding_request) = parse_stun_binding_request(&packet_buffer[..]) {
-      if let Some(session) = self.sessions.get_mut(&SessionKey {
-        server_user: stun_binding_request.server_user,
-        remote_user: stun_binding_request.remote_user,
-      }) {
-        session.ttl = Instant::now();
-        packet_buffer.resize(MAX_UDP_PAYLOAD_SIZE, 0);
-        let resp_len = write_stun_success_response(
-          stun_binding_request.transaction_id,
-          remote_addr,
-          session.server_passwd.as_bytes(),
-          &mut packet_buffer,
-        );
-        match resp_len {
-          Ok(len) => {
-            packet_buffer.truncate(len);
-            self
-              .outgoing_udp
-              .push_back((packet_buffer.into_owned(), remote_addr));
+  // A snapshot of the server's state for the inspector.
+  fn inspect(&self) -> serde_json::Value {
+    use serde_json::json;
 
-            match self.clients.entry(remote_addr) {
-              HashMapEntry::Vacant(vacant) => {
-                let client = Client::new(
-                  &self.ssl_acceptor,
-                  self.buffer_pool.clone(),
-                  remote_addr,
-                  unsafe { EVENT_CB },
-                );
-                match client {
-                  Ok(cl) => {
-                    vacant.insert(cl);
-                  }
-                  Err(err) => unsafe {
-                    let mut msg = err.to_string();
-                    EVENT_CB.as_mut().unwrap()(0, msg.as_mut_ptr(), msg.len() as u32)
-                  },
-                }
-              }
-              HashMapEntry::Occupied(_) => {}
-            }
-          }
-          Err(_) => {}
-        };
-      }
-    } else {
-      if let Some(client) = self.clients.get_mut(&remote_addr) {
-        let client = client;
-        if let Err(_err) = client.receive_incoming_packet(packet_buffer.into_owned()) {
-          if !client.shutdown_started() {
-            let _ = client.start_shutdown();
-          }
+    let clients: Vec<_> = self
+      .clients
+      .iter()
+      .map(|(remote_addr, client)| {
+        json!({
+          "remote_addr": remote_addr.to_string(),
+          "id": self.clients.id(remote_addr).map(|id| id.to_string()),
+          "local_socket": client.local_socket.index,
+          "local_ip": client.local_socket.ip.map(|ip| ip.to_string()),
+          "nominated": client.nominated,
+          "protocol_version": client
+            .protocol_version
+            .map(|index| &self.config.protocol_versions[index]),
+          "max_message_len": client.max_message_len,
+          "relayed": client.relay.is_some(),
+          "state": format!("{:?}", client.state()),
+          "direction": client.data_direction.attribute(),
+          "established": client.is_established(),
+          "shutdown_started": client.shutdown_started(),
+          "disconnect_reason": client.disconnect_reason().map(|reason| format!("{:?}", reason)),
+          "idle_ms": client.last_activity().elapsed().as_millis() as u64,
+          "egress_queued": client.has_egress(),
+          "egress_dropped": client.egress_dropped(),
+          "paused": client.is_paused(),
+          "partial_messages": client.reassembly().partial_messages,
+        })
+      })
+      .collect();
+    let sessions: Vec<_> = self
+      .sessions()
+      .into_iter()
+      .map(|session| {
+        json!({
+          "id": session.id,
+          "remote_user": session.remote_user,
+          "age_ms": session.created_at.elapsed().as_millis() as u64,
+          "ttl_remaining_ms": session.ttl_remaining.as_millis() as u64,
+          "remote_candidates": session
+            .remote_candidates
+            .iter()
+            .map(|candidate| {
+              format!(
+                "{:?} {} {}:{}",
+                candidate.kind, candidate.transport, candidate.address, candidate.port
+              )
+            })
+            .collect::<Vec<_>>(),
+        })
+      })
+      .collect();
+    let stats = self.stats();
+    let memory = self.memory_usage();
+    let latency = |d: Duration| d.as_micros() as u64;
+    let addr_strings =
+      |addrs: &[SocketAddr]| -> Vec<String> { addrs.iter().map(|addr| addr.to_string()).collect() };
+    let middleware_json = |stages: &[MiddlewareStats]| -> Vec<serde_json::Value> {
+      stages
+        .iter()
+        .map(|stage| {
+          json!({
+            "name": stage.name,
+            "continued": stage.continued,
+            "dropped": stage.dropped,
+            "disconnected": stage.disconnected,
+          })
+        })
+        .collect()
+    };
+
+    json!({
+      "clients": clients,
+      "sessions": sessions,
+      "draining": self.is_draining(),
+      "queues": {
+        "outgoing_udp": self.outgoing_udp.len(),
+        "egress_ready": self.egress_ready.len(),
+        "incoming_rtc": self.incoming_rtc.len(),
+        "incoming_fragments": self.incoming_fragments.len(),
+        "events": self.events.len(),
+        "event_subscribers": self.events.subscribers(),
+        "deferred_handshakes": self.deferred_handshakes.len(),
+        "scheduled_sends": self.scheduled_sends.len(),
+        "ffi_events": self.ffi_events.queued(),
+        "ffi_events_dropped": self.ffi_events.dropped(),
+        "memory_bytes": {
+          "buffer_pool": memory.buffer_pool,
+          "client_send_queues": memory.client_send_queues,
+          "client_receive_queues": memory.client_receive_queues,
+          "reassembly": memory.reassembly,
+          "received_queue": memory.received_queue,
+          "outgoing_queue": memory.outgoing_queue,
+          "scheduled_sends": memory.scheduled_sends,
+          "session_table": memory.session_table,
+          "total": memory.total(),
+        },
+      },
+      "config": {
+        "listen_addrs": addr_strings(&self.local_addrs().unwrap_or_default()),
+        "public_addrs": addr_strings(&self.session_endpoint.public_addrs()),
+        "stun_silent_unknown_users": self.config.stun_silent_unknown_users,
+        "stun_require_fingerprint": self.config.stun_require_fingerprint,
+        "outgoing_queue_capacity": self.config.outgoing_queue_capacity,
+        "client_egress_capacity": self.config.client_egress_capacity,
+        "incoming_queue_capacity": self.config.incoming_queue_capacity,
+        "queue_overflow": format!("{:?}", self.config.queue_overflow),
+        "dns_refresh_interval_ms": self.config.dns_refresh_interval.as_millis() as u64,
+        "driver_realtime_priority": self.config.driver_realtime_priority,
+        "driver_cpu_affinity": self.config.driver_cpu_affinity,
+        "stun_token_key": self.config.stun_token_key.is_some(),
+        "admission_rate": self.config.admission_rate.map(|rate| json!({
+          "per_second": rate.per_second,
+          "burst": rate.burst,
+          "max_deferred": rate.max_deferred,
+        })),
+        "clock_jump_policy": format!("{:?}", self.config.clock_jump_policy),
+        "clock_jump_threshold_ms": self.config.clock_jump_threshold.as_millis() as u64,
+        "ffi_event_delivery": format!("{:?}", self.config.ffi_event_delivery),
+        "stun_validation_timeout_ms": self
+          .config
+          .stun_validation_timeout
+          .map(|timeout| timeout.as_millis() as u64),
+        "watchdog_timeout_ms": self
+          .config
+          .watchdog_timeout
+          .map(|timeout| timeout.as_millis() as u64),
+        "stats_event_interval_ms": self
+          .config
+          .stats_event_interval
+          .map(|interval| interval.as_millis() as u64),
+        "stream_fragments": self.config.stream_fragments,
+        "max_partial_messages": self.config.max_partial_messages,
+        "max_partial_message_len": self.config.max_partial_message_len,
+        "max_reassembled_len": self.config.max_reassembled_len,
+        "max_fragment_len": self.config.max_fragment_len,
+        "max_held_messages": self.config.max_held_messages,
+        "reauthenticate_on_rebind": self.config.reauthenticate_on_rebind,
+        "migrate_across_ips": self.config.migrate_across_ips,
+        "max_client_egress": self.config.max_client_egress,
+        "max_clients": self.config.max_clients,
+        "max_pending_handshakes": self.config.max_pending_handshakes,
+        "stun_software": self.config.stun_software,
+        "stun_alternate_server": self.config.stun_alternate_server.map(|addr| addr.to_string()),
+        "stun_response_origin": self.config.stun_response_origin,
+        "try_alternate_beyond": self.config.try_alternate_beyond,
+        "protocol_versions": self.config.protocol_versions,
+      },
+      "stats": {
+        "dropped_packets": stats.dropped_packets,
+        "stale_source_packets": stats.stale_source_packets,
+        "refused_clients": stats.refused_clients,
+        "redirected_clients": stats.redirected_clients,
+        "relayed_messages": stats.relayed_messages,
+        "refused_downgrades": stats.refused_downgrades,
+        "demux": {
+          "stun": stats.demux.stun,
+          "zrtp": stats.demux.zrtp,
+          "dtls": stats.demux.dtls,
+          "quic": stats.demux.quic,
+          "rtp": stats.demux.rtp,
+          "unknown": stats.demux.unknown,
+        },
+        "funnel": {
+          "sessions_created": stats.funnel.sessions_created,
+          "stun_seen": stats.funnel.stun_seen,
+          "dtls_complete": stats.funnel.dtls_complete,
+          "sctp_established": stats.funnel.sctp_established,
+        },
+        "channel_traffic": stats
+          .channel_traffic
+          .iter()
+          .map(|traffic| {
+            json!({
+              "label": traffic.label,
+              "message_type": format!("{:?}", traffic.message_type),
+              "messages_sent": traffic.messages_sent,
+              "bytes_sent": traffic.bytes_sent,
+              "messages_received": traffic.messages_received,
+              "bytes_received": traffic.bytes_received,
+            })
+          })
+          .collect::<Vec<_>>(),
+        "reassembly": {
+          "partial_messages": stats.reassembly.partial_messages,
+          "resets": stats.reassembly.resets,
+          "holes": stats.reassembly.holes,
+        },
+        "processing_latency_us": {
+          "samples": stats.processing_latency.samples,
+          "p50": latency(stats.processing_latency.p50),
+          "p95": latency(stats.processing_latency.p95),
+          "p99": latency(stats.processing_latency.p99),
+          "max": latency(stats.processing_latency.max),
+        },
+        "inbound_middleware": middleware_json(&stats.inbound_middleware),
+        "outbound_middleware": middleware_json(&stats.outbound_middleware),
+      },
+      "errors": self.errors.to_json(),
+    })
+  }
+
+  // Handle STUN requests held back by the admission rate, for as long as it allows.
+  fn admit_deferred(&mut self) {
+    while self
+      .admission
+      .as_mut()
+      .is_some_and(|admission| admission.has_token())
+    {
+      match self.deferred_handshakes.pop_front() {
+        Some((packet, remote_addr, local_socket)) => {
+          self.receive_stun(remote_addr, local_socket, packet);
         }
-        let outgoing_packets = client.take_outgoing_packets();
-        self
-          .outgoing_udp
-          .extend(outgoing_packets.map(|p| (p, remote_addr)));
-        let incoming_messages = client.receive_messages();
-        self.incoming_rtc.extend(
-          incoming_messages.map(|(message_type, message)| (message, remote_addr, message_type)),
-        );
+        None => break,
       }
     }
   }
@@ -519,59 +3235,341 @@ impl Server {
     if self.last_generate_periodic.elapsed() >= PERIODIC_PACKET_INTERVAL {
       self.last_generate_periodic = Instant::now();
 
-      for (remote_addr, client) in &mut self.clients {
-        if let Err(_err) = client.generate_periodic() {
+      for (remote_addr, client) in self.clients.iter_mut() {
+        if let Err(err) = client.generate_periodic() {
+          record_client_error(
+            &mut self.errors,
+            &mut self.events,
+            remote_addr,
+            ErrorCode::from(&err),
+            format_args!("failed to generate periodic packets: {}", err),
+          );
           if !client.shutdown_started() {
-            let _ = client.start_shutdown();
+            let _ = client.start_shutdown(DisconnectReason::DtlsError);
           }
         }
-        self
-          .outgoing_udp
-          .extend(client.take_outgoing_packets().map(|p| (p, *remote_addr)));
+        schedule_egress(&mut self.egress_ready, remote_addr, client);
+      }
+    }
+  }
+
+  // Produce a `ServerEvent::Stats`, if one is configured and we are due to do so.
+  fn produce_stats_event(&mut self) {
+    if let Some(interval) = self.config.stats_event_interval {
+      if self.last_stats_event.elapsed() >= interval {
+        self.last_stats_event = Instant::now();
+        let stats = self.stats();
+        self.events.push_back(ServerEvent::Stats(stats));
+      }
+    }
+  }
+
+  // Replace the snapshot of the clients, if anyone still reads them.
+  fn publish_client_snapshot(&mut self) {
+    if let Some(snapshots) = self.client_snapshots.clone() {
+      if snapshots.has_readers() {
+        snapshots.publish(self.take_client_snapshot());
+      }
+    }
+  }
+
+  fn take_client_snapshot(&mut self) -> ClientsSnapshot {
+    let epoch = self.snapshot_epoch;
+    self.snapshot_epoch += 1;
+    ClientsSnapshot {
+      epoch,
+      taken_at: SystemTime::now(),
+      clients: self.clients().collect(),
+    }
+  }
+
+  // Start re-resolving the public hostnames in the background, if we are due to do so and the
+  // previous lookups have all finished.
+  fn refresh_public_hosts(&mut self) {
+    if !self.public_hosts.is_empty()
+      && self.dns_pending == 0
+      && self.last_dns_refresh.elapsed() >= self.config.dns_refresh_interval
+    {
+      self.last_dns_refresh = Instant::now();
+      if spawn_resolve(self.public_hosts.clone(), self.dns_sender.clone()).is_ok() {
+        self.dns_pending = self.public_hosts.len();
       }
     }
   }
 
-  // Clean up all client sessions / connections, if we are due to do so.
+  // Remove the expired sessions and the timed out or shut down clients among the next slice of
+  // each, so that every one is checked about once per `CLEANUP_INTERVAL` while no single tick
+  // scans more than `MAX_CLEANUP_SLICE` of them, however many there are.
   fn timeout_clients(&mut self) {
-    if self.last_cleanup.elapsed() >= CLEANUP_INTERVAL {
-      self.last_cleanup = Instant::now();
-      self.sessions.retain(|_session_key, session| {
-        if session.ttl.elapsed() < RTC_SESSION_TIMEOUT {
-          true
-        } else {
-          false
-        }
-      });
+    let session_slice = cleanup_slice(self.sessions.len());
+    for key in next_slice(&self.session_order, &mut self.session_cursor, session_slice) {
+      let expired = self
+        .sessions
+        .get(&key)
+        .is_none_or(|session| session.ttl.elapsed() >= RTC_SESSION_TIMEOUT);
+      if expired {
+        self.sessions.remove(&key);
+        self.session_order.remove(&key);
+      }
+    }
 
-      self.clients.retain(|remote_addr, client| {
-        if !client.is_shutdown() && client.last_activity().elapsed() < RTC_CONNECTION_TIMEOUT {
-          true
-        } else {
-          if !client.shutdown_started() {
-            unsafe {
-              let mut msg = format!("{}:{}", remote_addr.ip(), remote_addr.port());
-              EVENT_CB.unwrap()(1002, msg.as_mut_ptr(), msg.len() as u32);
-            }
-          }
-          false
+    let client_slice = cleanup_slice(self.clients.len());
+    for remote_addr in next_slice(&self.client_order, &mut self.client_cursor, client_slice) {
+      match self.clients.get(&remote_addr) {
+        Some(client)
+          if !client.is_shutdown() && client.last_activity().elapsed() < RTC_CONNECTION_TIMEOUT =>
+        {
+          continue
         }
+        _ => {}
+      }
+      self.client_order.remove(&remote_addr);
+      let mut client = match self.clients.remove(&remote_addr) {
+        Some(client) => client,
+        None => continue,
+      };
+      self.egress_dropped += client.egress_dropped() + client.held_dropped();
+      self.closed_traffic.merge(client.traffic());
+      self.closed_reassembly.merge(ReassemblyStats {
+        partial_messages: 0,
+        ..client.reassembly()
+      });
+      let removal = if client.is_shutdown() {
+        "removed after shutdown"
+      } else {
+        "removed after timing out"
+      };
+      // A shutdown the peer never completed keeps the reason it was started for.
+      let reason = client
+        .disconnect_reason()
+        .unwrap_or(DisconnectReason::Timeout);
+      keep_closed_trace(
+        &mut self.closed_traces,
+        remote_addr,
+        client.finish_trace(removal),
+        reason,
+      );
+      self.events.push_back(ServerEvent::ClientDisconnected {
+        remote_addr,
+        reason,
       });
+      if !client.shutdown_started() {
+        self
+          .ffi_events
+          .emit(1002, format!("{}:{}", remote_addr.ip(), remote_addr.port()));
+      }
+    }
+  }
+
+  // Disconnect the clients whose lifetime, set with
+  // `SessionEndpoint::session_request_with_lifetime`, has run out.
+  fn expire_session_lifetimes(&mut self) {
+    let now = Instant::now();
+    while let Some(&Reverse((expires_at, id))) = self.session_expiries.peek() {
+      if expires_at > now {
+        break;
+      }
+      self.session_expiries.pop();
+      // The client may have been removed since.
+      let remote_addr = match self.clients.addr(id) {
+        Some(remote_addr) => remote_addr,
+        None => continue,
+      };
+      let client = match self.clients.get_mut(&remote_addr) {
+        Some(client) if client.expires_at == Some(expires_at) => client,
+        _ => continue,
+      };
+      client.expires_at = None;
+      client.clear_egress();
+      if client.is_established() {
+        let close_message = serde_json::json!({
+          "close": SESSION_EXPIRED_CLOSE_CODE,
+          "reason": "session expired",
+        })
+        .to_string();
+        let _ = client.send_goodbye(Some(&close_message));
+      }
+      let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+      schedule_egress(&mut self.egress_ready, remote_addr, client);
+      self
+        .events
+        .push_back(ServerEvent::SessionLifetimeExpired { remote_addr });
     }
   }
 
+  /// List every session which has not yet expired.
+  ///
+  /// A session stays alive for 30 seconds after the last STUN request authenticated with it, so
+  /// this includes the sessions of connected clients as well as reservations which no client has
+  /// used yet.
+  pub fn sessions(&self) -> Vec<SessionInfo> {
+    self
+      .sessions
+      .iter()
+      .filter_map(|(key, session)| {
+        RTC_SESSION_TIMEOUT
+          .checked_sub(session.ttl.elapsed())
+          .map(|ttl_remaining| SessionInfo {
+            id: key.server_user.clone(),
+            remote_user: key.remote_user.clone(),
+            created_at: session.created_at,
+            ttl_remaining,
+            remote_candidates: session.remote_candidates.clone(),
+          })
+      })
+      .collect()
+  }
+
+  /// Expire the session with the given id immediately, so no new client can connect with it.
+  /// Clients which have already connected are unaffected.  Returns false if no such session
+  /// exists.
+  pub fn expire_session(&mut self, id: &str) -> bool {
+    let before = self.sessions.len();
+    self.sessions.retain(|key, _| key.server_user != id);
+    self.session_order.retain(|key| key.server_user != id);
+    self.sessions.len() != before
+  }
+
   fn accept_session(&mut self, incoming_session: IncomingSession) {
+    // Sessions answered just before the drain began are turned away as well.
+    if self.is_draining() {
+      return;
+    }
+    self.funnel.sessions_created += 1;
+    if incoming_session.offered_candidates > 0 && incoming_session.remote_candidates.is_empty() {
+      self.events.push_back(ServerEvent::NoUsableCandidates {
+        session_id: incoming_session.server_user.clone(),
+        offered: incoming_session.offered_candidates,
+      });
+    }
+    if incoming_session.tcp_only {
+      self.events.push_back(ServerEvent::TcpOnlyOffer {
+        session_id: incoming_session.server_user.clone(),
+        offered: incoming_session.offered_candidates,
+      });
+    }
+    if incoming_session.replace {
+      let events = &mut self.events;
+      let session_order = &mut self.session_order;
+      self.sessions.retain(|key, _| {
+        if key.remote_user != incoming_session.remote_user {
+          return true;
+        }
+        session_order.remove(key);
+        events.push_back(ServerEvent::SessionReplaced {
+          old_session_id: key.server_user.clone(),
+          new_session_id: incoming_session.server_user.clone(),
+        });
+        false
+      });
+    }
+    let key = SessionKey {
+      server_user: incoming_session.server_user,
+      remote_user: incoming_session.remote_user,
+    };
+    self.session_order.insert(key.clone());
     self.sessions.insert(
-      SessionKey {
-        server_user: incoming_session.server_user,
-        remote_user: incoming_session.remote_user,
-      },
+      key,
       Session {
         server_passwd: incoming_session.server_passwd,
+        created_at: Instant::now(),
         ttl: Instant::now(),
+        max_message_size: incoming_session.max_message_size,
+        direction: incoming_session.direction,
+        expires_at: incoming_session.expires_at,
+        stun_seen: false,
+        remote_candidates: incoming_session.remote_candidates.into(),
       },
     );
   }
+  /// The longest message that can be sent to the given client, the smaller of `MAX_MESSAGE_LEN`,
+  /// or `MAX_FRAGMENTED_MESSAGE_LEN` with `ServerConfig::max_fragment_len`, and the
+  /// `a=max-message-size` of the client's offer.  Returns `None` if the client is not
+  /// connected.
+  pub fn max_message_len(&self, remote_addr: &SocketAddr) -> Option<usize> {
+    self
+      .clients
+      .get(remote_addr)
+      .map(|client| client.max_message_len)
+  }
+
+  /// The direction of the data channels negotiated with the given client, for example to skip
+  /// sending to a client which offered `a=sendonly` and will never read anything.  Returns `None`
+  /// if the client is not connected.
+  ///
+  /// Clients answered with stateless session tokens always report `DataDirection::SendRecv`, as
+  /// their offers are not known to the server.
+  pub fn data_direction(&self, remote_addr: &SocketAddr) -> Option<DataDirection> {
+    self
+      .clients
+      .get(remote_addr)
+      .map(|client| client.data_direction)
+  }
+
+  /// The outgoing traffic waiting to be sent to the given client, for example to skip sending a
+  /// new snapshot while the previous one has not left yet.  Returns `None` if the client is not
+  /// known.
+  ///
+  /// This counts what `Server::send` has queued and the server's own SCTP and DTLS traffic, but
+  /// not messages scheduled with `Server::send_at` which are not due yet.
+  pub fn pending_outgoing(&self, remote_addr: &SocketAddr) -> Option<QueueDepth> {
+    self
+      .clients
+      .get(remote_addr)
+      .map(|client| client.egress_depth())
+  }
+
+  /// The lifecycle of the connection with the given client so far, from its first STUN binding
+  /// request through the DTLS handshake, SCTP association and data channel opens.
+  ///
+  /// The traces of the last 64 clients to be removed stay available, ending with their removal,
+  /// so that a connection can still be looked into after it has failed.  Returns `None` for any
+  /// other client.
+  pub fn connection_trace(&self, remote_addr: &SocketAddr) -> Option<ConnectionTrace> {
+    if let Some(client) = self.clients.get(remote_addr) {
+      return Some(client.trace().export(*remote_addr));
+    }
+    self
+      .closed_traces
+      .iter()
+      .rev()
+      .find(|(addr, _, _)| addr == remote_addr)
+      .map(|(addr, trace, _)| trace.export(*addr))
+  }
+
+  /// Why the given client was disconnected, or is being disconnected.
+  ///
+  /// Returns `None` for a client which is connected and not going away.  As with
+  /// `Server::connection_trace`, the reasons of the last 64 clients to be removed stay
+  /// available.
+  pub fn disconnect_reason(&self, remote_addr: &SocketAddr) -> Option<DisconnectReason> {
+    if let Some(client) = self.clients.get(remote_addr) {
+      return client.disconnect_reason();
+    }
+    self
+      .closed_traces
+      .iter()
+      .rev()
+      .find(|(addr, _, _)| addr == remote_addr)
+      .map(|(_, _, reason)| *reason)
+  }
+
+  /// How far along its connection the given client is, for example to tell a client stuck in
+  /// its DTLS handshake from one still waiting for its data channel.
+  ///
+  /// The last 64 clients to be removed are `ClientState::Closed`, and `None` is returned for any
+  /// other unknown address.
+  pub fn client_state(&self, remote_addr: &SocketAddr) -> Option<ClientState> {
+    if let Some(client) = self.clients.get(remote_addr) {
+      return Some(client.state());
+    }
+    self
+      .closed_traces
+      .iter()
+      .any(|(addr, _, _)| addr == remote_addr)
+      .then_some(ClientState::Closed)
+  }
+
   pub fn shutdown_started(&self, remote_addr: &SocketAddr) -> Option<bool> {
     if let Some(client) = self.clients.get(remote_addr) {
       Some(client.shutdown_started())
@@ -590,38 +3588,465 @@ impl Server {
       None
     }
   }
+
+  /// Derive `len` bytes of keying material from the DTLS session of the given client, using the
+  /// exporter of RFC 5705 with `label` and an optional `context`.
+  ///
+  /// The peer's DTLS stack derives the same bytes and nobody else can, so they can serve as keys
+  /// tied to this connection, for example to sign client telemetry or to secure a secondary
+  /// channel.  Browsers do not expose the exporter to JavaScript, so this is mostly useful with
+  /// native clients.  Labels should start with `"EXPERIMENTAL"` unless registered with IANA.
+  /// Returns `None` if the client is not connected or its handshake has not completed.
+  pub fn export_keying_material(
+    &self,
+    remote_addr: &SocketAddr,
+    label: &str,
+    context: Option<&[u8]>,
+    len: usize,
+  ) -> Option<Vec<u8>> {
+    let client = self.clients.get(remote_addr)?;
+    let mut keying_material = vec![0; len];
+    client
+      .export_keying_material(label, context, &mut keying_material)
+      .ok()?;
+    Some(keying_material)
+  }
+
   /// Shutdown the whole server, clear sessions and clients.
   ///
+  /// Every established client is sent an SCTP SHUTDOWN and a DTLS close_notify first, so that the
+  /// browser's data channel `onclose` fires right away instead of after its own timeout.  These
+  /// packets are sent without waiting, any that the socket cannot take immediately are lost.
   pub fn shutdown(&mut self) {
-    for client in self.clients.values_mut() {
-      let _ = client.start_shutdown();
+    self.shutdown_clients(None);
+  }
+
+  /// Shutdown the whole server as with `Server::shutdown`, but first send every established
+  /// client `reason` as a final text message so the application can tell why it was dropped.
+  pub fn shutdown_with_reason(&mut self, reason: &str) {
+    self.shutdown_clients(Some(reason));
+  }
+
+  fn shutdown_clients(&mut self, reason: Option<&str>) {
+    for (remote_addr, client) in self.clients.iter_mut() {
+      client.clear_egress();
+      let _ = client.send_goodbye(reason);
+      let _ = client.start_shutdown(DisconnectReason::LocalShutdown);
+      schedule_egress(&mut self.egress_ready, remote_addr, client);
+    }
+    self.flush_nonblocking();
+
+    self.egress_dropped += self
+      .clients
+      .values()
+      .map(|c| c.egress_dropped() + c.held_dropped())
+      .sum::<u64>();
+    for (remote_addr, client) in self.clients.iter_mut() {
+      self.closed_traffic.merge(client.traffic());
+      self.closed_reassembly.merge(ReassemblyStats {
+        partial_messages: 0,
+        ..client.reassembly()
+      });
+      let trace = client.finish_trace("removed at server shutdown");
+      let reason = client
+        .disconnect_reason()
+        .unwrap_or(DisconnectReason::LocalShutdown);
+      keep_closed_trace(&mut self.closed_traces, remote_addr, trace, reason);
+      self.events.push_back(ServerEvent::ClientDisconnected {
+        remote_addr,
+        reason,
+      });
     }
     self.clients.clear();
+    self.client_order.clear();
+    self.egress_ready.clear();
+    self.deferred_handshakes.clear();
+    self.session_expiries.clear();
+    self.scheduled_sends.clear();
     self.sessions.clear();
-    drop(self.udp_socket.as_ref());
+    self.session_order.clear();
+  }
+
+  // Send as much of the pending outgoing traffic as the sockets will take right now, dropping the
+  // rest.
+  fn flush_nonblocking(&mut self) {
+    while let Some(remote_addr) = self.egress_ready.pop_front() {
+      if let Some(client) = self.clients.get_mut(&remote_addr) {
+        let socket = self.udp_sockets[client.local_socket.index].get_ref();
+        while let Some(packet) = client.pop_egress() {
+          let packet = self.buffer_pool.adopt(packet);
+          let _ = match client.local_socket.ip {
+            Some(local_ip) => send_to_from(socket, &packet, remote_addr, local_ip),
+            None => socket.send_to(&packet, remote_addr),
+          };
+        }
+        client.egress_scheduled = false;
+      }
+    }
+  }
+}
+
+impl Drop for Server {
+  // Best-effort cleanup, so that FFI hosts which free the server without shutting it down first
+  // still see a close event for every established client and browsers are still told goodbye.
+  fn drop(&mut self) {
+    self.shutdown_clients(None);
+  }
+}
+
+// Where a queued message goes: to the client's first data channel as `Server::send_with` sends
+// it, or to a channel the client opened, delivered as negotiated for it.
+#[derive(Clone, Copy)]
+enum Route {
+  Default(Delivery),
+  Channel(u16),
+}
+
+impl Route {
+  fn send(
+    self,
+    client: &mut Client,
+    message_type: MessageType,
+    message: &[u8],
+  ) -> Result<(), ClientError> {
+    match self {
+      Route::Default(delivery) => client.send_message_with(message_type, message, delivery),
+      Route::Channel(channel_id) => client.send_message_on(channel_id, message_type, message),
+    }
+  }
+}
+
+// Move a client's freshly written packets into its egress queue, and schedule it with the
+// round-robin flusher if it is not already waiting there.
+fn schedule_egress(
+  egress_ready: &mut VecDeque<SocketAddr>,
+  remote_addr: SocketAddr,
+  client: &mut Client,
+) {
+  client.queue_outgoing_packets();
+  if client.has_egress() && !client.egress_scheduled {
+    client.egress_scheduled = true;
+    egress_ready.push_back(remote_addr);
+  }
+}
+
+async fn send_packet(
+  socket: &Async<UdpSocket>,
+  packet: &[u8],
+  remote_addr: SocketAddr,
+  local_ip: Option<IpAddr>,
+) -> Result<(), IoError> {
+  let len = match local_ip {
+    Some(local_ip) => {
+      socket
+        .write_with(|socket| send_to_from(socket, packet, remote_addr, local_ip))
+        .await?
+    }
+    None => socket.send_to(packet, remote_addr).await?,
+  };
+  if len != packet.len() {
+    return Err(IoError::new(
+      IoErrorKind::Other,
+      "failed to write entire datagram to socket",
+    ));
+  }
+  Ok(())
+}
+
+// A new client fits within `ServerConfig::max_clients` and
+// `ServerConfig::max_pending_handshakes`.
+fn has_room_for_client(config: &ServerConfig, clients: &ClientTable) -> bool {
+  if let Some(max_clients) = config.max_clients {
+    if clients.len() >= max_clients {
+      return false;
+    }
+  }
+  if let Some(max_pending) = config.max_pending_handshakes {
+    let pending = clients
+      .values()
+      .filter(|client| !client.is_established() && !client.shutdown_started())
+      .count();
+    if pending >= max_pending {
+      return false;
+    }
+  }
+  true
+}
+
+// How many of `len` sessions or clients `Server::timeout_clients` checks per periodic tick, so
+// that all of them are checked once per `CLEANUP_INTERVAL`, or more often when there are few,
+// and less often when there are so many that this would exceed `MAX_CLEANUP_SLICE`.
+fn cleanup_slice(len: usize) -> usize {
+  let ticks = (CLEANUP_INTERVAL.as_millis() / PERIODIC_TIMER_INTERVAL.as_millis()).max(1) as usize;
+  len
+    .div_ceil(ticks)
+    .clamp(MIN_CLEANUP_SLICE, MAX_CLEANUP_SLICE)
+}
+
+// The next `limit` keys of `order` after `cursor`, moving `cursor` past them, or back to the
+// start once the end has been reached.
+fn next_slice<K: Ord + Clone>(order: &BTreeSet<K>, cursor: &mut Option<K>, limit: usize) -> Vec<K> {
+  let start = match cursor.take() {
+    Some(cursor) => Bound::Excluded(cursor),
+    None => Bound::Unbounded,
+  };
+  let slice: Vec<K> = order
+    .range((start, Bound::Unbounded))
+    .take(limit)
+    .cloned()
+    .collect();
+  if slice.len() == limit {
+    *cursor = slice.last().cloned();
+  }
+  slice
+}
+
+// Move the client at `old_addr` to `new_addr`, where its NAT has rebound it.
+fn rebind_client(
+  clients: &mut ClientTable,
+  client_order: &mut BTreeSet<SocketAddr>,
+  egress_ready: &mut VecDeque<SocketAddr>,
+  events: &mut EventQueue,
+  old_addr: SocketAddr,
+  new_addr: SocketAddr,
+  local_socket: LocalSocket,
+) {
+  let client = match clients.rebind(&old_addr, new_addr) {
+    Some(client) => client,
+    None => return,
+  };
+  client.local_socket = local_socket;
+  client.record_trace(
+    TraceLayer::Server,
+    TraceDirection::Local,
+    format!("rebound from {}", old_addr),
+  );
+  // Its place in the egress schedule is under the old address.
+  for scheduled in egress_ready.iter_mut() {
+    if *scheduled == old_addr {
+      *scheduled = new_addr;
+    }
+  }
+  client_order.remove(&old_addr);
+  client_order.insert(new_addr);
+  events.push_back(ServerEvent::ClientRebound { old_addr, new_addr });
+}
+
+// Whether `connected` is signalled before `timeout` passes, see `Server::add_connect_waiter`.
+pub(crate) async fn wait_connected(connected: flume::Receiver<()>, timeout: Duration) -> bool {
+  let connected = connected.recv_async();
+  pin_mut!(connected);
+  matches!(
+    future::select(connected, Timer::after(timeout)).await,
+    future::Either::Left((Ok(()), _))
+  )
+}
+
+// Memory held by an entry of the session table, including the strings it points to.
+fn session_bytes(key: &SessionKey, session: &Session) -> usize {
+  let candidates: usize = session
+    .remote_candidates
+    .iter()
+    .map(|candidate| {
+      mem::size_of::<IceCandidate>()
+        + candidate.foundation.capacity()
+        + candidate.transport.capacity()
+        + candidate.address.capacity()
+        + candidate
+          .related_address
+          .as_ref()
+          .map_or(0, |(address, _)| address.capacity())
+    })
+    .sum();
+  mem::size_of::<(SessionKey, Session)>()
+    + key.server_user.capacity()
+    + key.remote_user.capacity()
+    + session.server_passwd.capacity()
+    + candidates
+}
+
+// Keep the trace of a removed client for `Server::connection_trace`, forgetting the oldest.
+fn keep_closed_trace(
+  closed_traces: &mut VecDeque<(SocketAddr, TraceLog, DisconnectReason)>,
+  remote_addr: SocketAddr,
+  trace: TraceLog,
+  reason: DisconnectReason,
+) {
+  if closed_traces.len() >= MAX_CLOSED_TRACES {
+    closed_traces.pop_front();
+  }
+  closed_traces.push_back((remote_addr, trace, reason));
+}
+
+// Record an error on a client's connection for the inspector, and report it with a
+// `ServerEvent::ClientError`.
+fn record_client_error(
+  errors: &mut ErrorLog,
+  events: &mut EventQueue,
+  remote_addr: SocketAddr,
+  code: ErrorCode,
+  error: impl fmt::Display,
+) {
+  let error = error.to_string();
+  errors.record(code, format_args!("{}: {}", remote_addr, error));
+  events.push_back(ServerEvent::ClientError {
+    remote_addr,
+    code,
+    error,
+  });
+}
+
+// Replace the contents of `packet_buffer` with a STUN error response carrying the attributes
+// `config` asks for, returning false if the response could not be written.
+fn write_stun_error(
+  packet_buffer: &mut BufferHandle,
+  transaction_id: [u8; 12],
+  error_code: StunErrorCode,
+  passwd: Option<&[u8]>,
+  config: &ServerConfig,
+) -> bool {
+  let extras = StunExtraAttributes {
+    software: config.stun_software.as_deref(),
+    alternate_server: match error_code {
+      StunErrorCode::TryAlternate => config.stun_alternate_server,
+      _ => None,
+    },
+    response_origin: None,
+  };
+  packet_buffer.resize(
+    stun_error_response_len(error_code, passwd.is_some(), &extras),
+    0,
+  );
+  match write_stun_error_response(transaction_id, error_code, passwd, &extras, packet_buffer) {
+    Ok(len) => {
+      packet_buffer.truncate(len);
+      true
+    }
+    Err(_) => false,
+  }
+}
+
+// Look up the session of a binding request's usernames without building a `SessionKey`, which
+// hashes the same way.
+fn find_session<'a>(
+  sessions: &'a mut HashMap<SessionKey, Session>,
+  server_user: &str,
+  remote_user: &str,
+) -> Option<&'a mut Session> {
+  let mut hasher = sessions.hasher().build_hasher();
+  server_user.hash(&mut hasher);
+  remote_user.hash(&mut hasher);
+  match sessions.raw_entry_mut().from_hash(hasher.finish(), |key| {
+    key.server_user == server_user && key.remote_user == remote_user
+  }) {
+    RawEntryMut::Occupied(occupied) => Some(occupied.into_mut()),
+    RawEntryMut::Vacant(_) => None,
+  }
+}
+
+fn bind_udp_socket(listen_addr: SocketAddr) -> Result<Async<UdpSocket>, IoError> {
+  let inner = Socket::new(Domain::for_address(listen_addr), Type::DGRAM, None)?;
+
+  //This is temporary disable due to probleme with Sessions management.
+  //the sessions should be handled in the Deno side using a single UDP socket and a Map to store each request,
+  //then wait until we get a new UDP connection in Rust side to handle the DTLS part.
+
+  // #[cfg(any(unix))]
+  // inner.set_reuse_port(true).unwrap();
+
+  // inner.set_reuse_address(true).unwrap();
+
+  let address = SockAddr::from(listen_addr);
+  inner.bind(&address)?;
+
+  // A wildcard socket may receive on several addresses, and replies must leave from the one each
+  // client is talking to or the client will not recognize them.
+  let socket: UdpSocket = inner.into();
+  if listen_addr.ip().is_unspecified() {
+    enable_pktinfo(&socket)?;
+  }
+  Async::new(socket)
+}
+
+// Receive a single datagram from whichever socket is ready first, returning its length, source
+// address and the socket it arrived on.  Polling starts one socket further along on
+// every call, so that a single busy port cannot starve the others.
+fn poll_recv_from_any(
+  udp_sockets: &[Async<UdpSocket>],
+  next_socket: &mut usize,
+  buf: &mut [MaybeUninit<u8>],
+  cx: &mut Context<'_>,
+) -> Poll<Result<(usize, SocketAddr, LocalSocket), IoError>> {
+  let start = *next_socket;
+  *next_socket = (start + 1) % udp_sockets.len();
+  for offset in 0..udp_sockets.len() {
+    let index = (start + offset) % udp_sockets.len();
+    let socket = &udp_sockets[index];
+    // The socket is read before waiting for the reactor to report it readable, as
+    // `Server::poll_io` polls without any reactor running.
+    loop {
+      match recv_from_to(socket.get_ref(), buf) {
+        Ok((len, remote_addr, ip)) => {
+          return Poll::Ready(Ok((len, remote_addr, LocalSocket { index, ip })))
+        }
+        Err(err) if err.kind() == IoErrorKind::WouldBlock => {}
+        Err(err) => return Poll::Ready(Err(err)),
+      }
+      match socket.poll_readable(cx) {
+        Poll::Ready(Ok(())) => {}
+        Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+        Poll::Pending => break,
+      }
+    }
   }
+  Poll::Pending
 }
 
 const RTC_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
 const RTC_SESSION_TIMEOUT: Duration = Duration::from_secs(30);
 const CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+// How long nothing must be heard from a client's address before a binding request from another
+// address moves it there without USE-CANDIDATE.
+const REBIND_QUIET_PERIOD: Duration = Duration::from_secs(3);
+const MIN_CLEANUP_SLICE: usize = 256;
+const MAX_CLEANUP_SLICE: usize = 2048;
 const PERIODIC_PACKET_INTERVAL: Duration = Duration::from_secs(1);
 const PERIODIC_TIMER_INTERVAL: Duration = Duration::from_secs(1);
-pub static mut EVENT_CB: Option<extern "C" fn(u32, *mut u8, u32)> = None;
+const MAX_QUEUED_EVENTS: usize = 1024;
+const MAX_CACHED_ANSWERS: usize = 4096;
+const MAX_CLOSED_TRACES: usize = 64;
+const MAX_POLL_IO_STEPS: usize = 1024;
 
-#[derive(Eq, PartialEq, Hash, Clone, Debug)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Debug)]
 struct SessionKey {
   server_user: String,
   remote_user: String,
 }
 
 struct Session {
-  server_passwd: String,
+  server_passwd: Secret<String>,
+  created_at: Instant,
   ttl: Instant,
+  max_message_size: Option<usize>,
+  direction: DataDirection,
+  expires_at: Option<Instant>,
+  // An authenticated binding request has arrived, counted in `ConnectionFunnel::stun_seen`.
+  stun_seen: bool,
+  remote_candidates: Arc<[IceCandidate]>,
 }
 
 struct IncomingSession {
   pub server_user: String,
-  pub server_passwd: String,
+  pub server_passwd: Secret<String>,
   pub remote_user: String,
+  pub max_message_size: Option<usize>,
+  pub direction: DataDirection,
+  pub expires_at: Option<Instant>,
+  // Other sessions with the same `remote_user` are dropped, see `DuplicateUfragPolicy::Replace`.
+  pub replace: bool,
+  pub remote_candidates: Vec<IceCandidate>,
+  // Number of candidates in the offer, including those filtered out.
+  pub offered_candidates: usize,
+  // Every candidate in the offer is a TCP candidate.
+  pub tcp_only: bool,
 }
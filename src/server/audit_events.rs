@@ -0,0 +1,175 @@
+//! The events a `Server` passes to its `AuditSink`, for STUN driven through the server from a
+//! plain UDP socket and for the calls which change a client or the configuration.
+
+use std::{
+  net::{SocketAddr, UdpSocket},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use async_io::{Async, Timer};
+use rand::{thread_rng, Rng};
+
+use super::{test_support::drive, Server};
+use crate::{
+  audit::{AuditEvent, AuditKind},
+  config::ServerConfig,
+  loopback::parse_answer,
+  middleware::Verdict,
+  stun::write_stun_binding_request,
+  util::rand_string,
+};
+
+// A server on the loopback interface whose audit events are collected in the returned list.
+fn server(config: ServerConfig) -> (Server, Arc<Mutex<Vec<AuditKind>>>) {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+  let events = Arc::new(Mutex::new(Vec::new()));
+  let sink_events = events.clone();
+  server.set_audit_sink(move |event: &AuditEvent| {
+    sink_events.lock().unwrap().push(event.kind.clone());
+  });
+  (server, events)
+}
+
+// Answer an offer with the server, returning the `server_user:client_user` username and the
+// password of the session's binding requests.
+async fn session(server: &mut Server) -> (String, String) {
+  let client_user = rand_string(&mut thread_rng(), 8);
+  let offer = format!(
+    "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\n\
+     m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n\
+     a=ice-ufrag:{}\r\na=ice-pwd:{}\r\na=setup:actpass\r\na=mid:0\r\na=sctp-port:5000\r\n",
+    client_user,
+    rand_string(&mut thread_rng(), 24),
+  );
+  let response = server.session_endpoint().session_request(&offer).unwrap();
+  let (server_user, server_passwd, _) = parse_answer(&response).unwrap();
+  // The server learns of the session while it is driven.
+  drive(server, Timer::after(Duration::from_millis(50))).await;
+  (format!("{}:{}", server_user, client_user), server_passwd)
+}
+
+// Send a binding request to the server, and drive it long enough to handle it.
+async fn binding_request(server: &mut Server, username: &str, passwd: &str) {
+  let server_addr = server.local_addrs().unwrap()[0];
+  let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+  let mut request = [0; 512];
+  let len = write_stun_binding_request(
+    thread_rng().gen(),
+    username.as_bytes(),
+    passwd.as_bytes(),
+    thread_rng().gen(),
+    true,
+    &mut request,
+  )
+  .unwrap();
+  socket.send_to(&request[..len], server_addr).await.unwrap();
+  drive(server, Timer::after(Duration::from_millis(100))).await;
+}
+
+#[test]
+fn unknown_user() {
+  futures::executor::block_on(async {
+    let (mut server, events) = server(ServerConfig::default());
+    binding_request(&mut server, "nosuchuser:client", "password").await;
+    assert_eq!(
+      *events.lock().unwrap(),
+      [AuditKind::UnknownUser {
+        username: "nosuchuser".to_owned()
+      }]
+    );
+  });
+}
+
+#[test]
+fn integrity_failure() {
+  futures::executor::block_on(async {
+    let (mut server, events) = server(ServerConfig::default());
+    let (username, _) = session(&mut server).await;
+    binding_request(&mut server, &username, "not the password").await;
+    let server_user = username.split(':').next().unwrap().to_owned();
+    assert_eq!(
+      *events.lock().unwrap(),
+      [AuditKind::IntegrityFailure {
+        username: server_user
+      }]
+    );
+  });
+}
+
+#[test]
+fn flood_summary() {
+  futures::executor::block_on(async {
+    let (mut server, events) = server(ServerConfig {
+      max_clients: Some(0),
+      ..ServerConfig::default()
+    });
+    let (username, passwd) = session(&mut server).await;
+    binding_request(&mut server, &username, &passwd).await;
+    binding_request(&mut server, &username, &passwd).await;
+    // Refused clients are summarized about once a second.
+    drive(&mut server, Timer::after(Duration::from_millis(1500))).await;
+    assert_eq!(
+      *events.lock().unwrap(),
+      [AuditKind::Flood {
+        refused_clients: 2,
+        deferred_handshakes: 0,
+      }]
+    );
+  });
+}
+
+#[test]
+fn kicks() {
+  futures::executor::block_on(async {
+    let (mut server, events) = server(ServerConfig::default());
+    let kicked = server.connect_loopback().await.unwrap();
+    let disconnected = server.connect_loopback().await.unwrap();
+    let kicked_addr = kicked.local_addr().unwrap();
+    let disconnected_addr = disconnected.local_addr().unwrap();
+    // A loopback client's first binding requests may arrive ahead of its session.
+    events.lock().unwrap().clear();
+
+    server
+      .kick(&kicked_addr, 4003, "kicked for cheating")
+      .await
+      .unwrap();
+    server.disconnect(&disconnected_addr).await.unwrap();
+    // Already shut down, so not disconnected again.
+    server.disconnect(&disconnected_addr).await.unwrap();
+    assert_eq!(
+      *events.lock().unwrap(),
+      [
+        AuditKind::Kicked {
+          reason_code: Some(4003),
+          reason: "kicked for cheating".to_owned(),
+        },
+        AuditKind::Kicked {
+          reason_code: None,
+          reason: "disconnected by the application".to_owned(),
+        },
+      ]
+    );
+  });
+}
+
+#[test]
+fn config_changes() {
+  let (mut server, events) = server(ServerConfig::default());
+  server.add_inbound_middleware("inbound", |_| Verdict::Continue);
+  server.begin_drain();
+  assert_eq!(
+    *events.lock().unwrap(),
+    [
+      AuditKind::ConfigChanged {
+        setting: "inbound_middleware".to_owned(),
+        value: "inbound".to_owned(),
+      },
+      AuditKind::ConfigChanged {
+        setting: "draining".to_owned(),
+        value: "true".to_owned(),
+      },
+    ]
+  );
+}
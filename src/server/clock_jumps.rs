@@ -0,0 +1,96 @@
+//! Time the host spends suspended, which `Instant` leaves out, handled under either
+//! `ClockJumpPolicy`.
+
+use std::{
+  cell::Cell,
+  net::SocketAddr,
+  thread,
+  time::{Duration, Instant},
+};
+
+use super::{Server, ServerEvent};
+use crate::{
+  clock::{suspend_aware_now, ClockJumpPolicy, ClockMonitor},
+  config::ServerConfig,
+};
+
+thread_local! {
+  // How long the test pretends the host has been suspended for.
+  static SUSPENDED: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+fn reference() -> Duration {
+  suspend_aware_now() + SUSPENDED.with(Cell::get)
+}
+
+fn server(config: ServerConfig) -> Server {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+  server.clock = ClockMonitor::with_reference(reference);
+  server
+}
+
+// Answer an offer, and drive the server until it has taken in the session.
+fn add_session(server: &mut Server, ufrag: &str) {
+  let offer = format!(
+    "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n\
+     m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n\
+     a=ice-ufrag:{}\r\na=ice-pwd:0123456789abcdefghijklmn\r\na=mid:0\r\na=sctp-port:5000\r\n",
+    ufrag
+  );
+  server.session_endpoint().session_request(&offer).unwrap();
+  server.poll_io().unwrap();
+}
+
+fn clock_jumps(server: &mut Server) -> Vec<Duration> {
+  std::iter::from_fn(|| server.poll_event())
+    .filter_map(|event| match event {
+      ServerEvent::ClockJump { gap } => Some(gap),
+      _ => None,
+    })
+    .collect()
+}
+
+// Suspend for a minute, twice the session timeout, between taking in two sessions, returning how
+// many are left and the jumps reported.
+fn suspend(policy: ClockJumpPolicy) -> (usize, Vec<Duration>) {
+  let mut server = server(ServerConfig {
+    clock_jump_policy: policy,
+    ..Default::default()
+  });
+  add_session(&mut server, "before");
+  SUSPENDED.with(|suspended| suspended.set(Duration::from_secs(60)));
+  add_session(&mut server, "after");
+  SUSPENDED.with(|suspended| suspended.set(Duration::ZERO));
+  (server.sessions().len(), clock_jumps(&mut server))
+}
+
+#[test]
+fn freeze_leaves_the_gap_out() {
+  let (sessions, jumps) = suspend(ClockJumpPolicy::Freeze);
+  assert_eq!(sessions, 2);
+  assert_eq!(jumps.len(), 1);
+  assert!(jumps[0] >= Duration::from_secs(59));
+}
+
+#[test]
+fn ignore_counts_the_gap() {
+  let (sessions, jumps) = suspend(ClockJumpPolicy::Ignore);
+  assert_eq!(sessions, 1);
+  assert_eq!(jumps.len(), 1);
+  assert!(jumps[0] >= Duration::from_secs(59));
+}
+
+#[test]
+fn undriven_server_is_not_a_jump() {
+  let mut server = server(ServerConfig {
+    clock_jump_threshold: Duration::from_millis(100),
+    ..Default::default()
+  });
+  add_session(&mut server, "before");
+  let paused_at = Instant::now();
+  thread::sleep(Duration::from_millis(1500));
+  add_session(&mut server, "after");
+  assert!(paused_at.elapsed() >= Duration::from_millis(1500));
+  assert_eq!(clock_jumps(&mut server), vec![]);
+}
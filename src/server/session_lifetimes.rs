@@ -0,0 +1,47 @@
+//! Clients disconnected once the lifetime of their session, set with
+//! `SessionEndpoint::session_request_with_lifetime`, has run out.
+
+use std::{net::SocketAddr, time::Duration};
+
+use super::{test_support::drive, Server, ServerEvent, SESSION_EXPIRED_CLOSE_CODE};
+use crate::{
+  client::MessageType,
+  loopback::{loopback_addr, LoopbackClient},
+};
+
+#[test]
+fn expired_session_is_closed() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+    let server_addr = loopback_addr(server.local_addrs().unwrap()[0]);
+    let mut endpoint = server.session_endpoint();
+    let connect = LoopbackClient::connect_with_lifetime(
+      &mut endpoint,
+      server_addr,
+      Some(Duration::from_millis(500)),
+    );
+    let mut client = drive(&mut server, connect).await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    // A second client without a lifetime stays connected.
+    let other = server.connect_loopback().await.unwrap();
+    let other_addr = other.local_addr().unwrap();
+
+    // Lifetimes are checked on the server's once a second tick.
+    let (message, message_type) = drive(&mut server, client.recv()).await.unwrap();
+    let message: serde_json::Value = serde_json::from_slice(&message).unwrap();
+    assert_eq!(message_type, MessageType::Text);
+    assert_eq!(message["close"], SESSION_EXPIRED_CLOSE_CODE);
+    assert_eq!(message["reason"], "session expired");
+
+    let mut expired = Vec::new();
+    while let Some(event) = server.poll_event() {
+      if let ServerEvent::SessionLifetimeExpired { remote_addr } = event {
+        expired.push(remote_addr);
+      }
+    }
+    assert_eq!(expired, vec![client_addr]);
+    assert!(!server.is_connected(&client_addr));
+    assert!(server.is_connected(&other_addr));
+  });
+}
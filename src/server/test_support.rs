@@ -0,0 +1,20 @@
+//! Helpers shared by the server's in-crate tests.
+
+use std::future::Future;
+
+use futures_util::future::{select, Either};
+
+use super::Server;
+
+// Run `future` while driving `server`, dropping any message the server receives meanwhile.
+pub(super) async fn drive<T>(server: &mut Server, future: impl Future<Output = T>) -> T {
+  let recv = Box::pin(async {
+    loop {
+      let _ = server.recv().await;
+    }
+  });
+  match select(recv, Box::pin(future)).await {
+    Either::Left(_) => unreachable!(),
+    Either::Right((output, _)) => output,
+  }
+}
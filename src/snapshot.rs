@@ -0,0 +1,50 @@
+use std::{
+  sync::{Arc, RwLock},
+  time::SystemTime,
+};
+
+use crate::server::ClientInfo;
+
+/// The clients of a `Server` as of one moment, loaded from `SnapshotHandle`.
+#[derive(Clone, Debug)]
+pub struct ClientsSnapshot {
+  /// Counts the snapshots published by the server, starting from 0, so readers can tell whether
+  /// anything was published since they last looked.
+  pub epoch: u64,
+  /// When the snapshot was taken.
+  pub taken_at: SystemTime,
+  /// Every client, as returned by `Server::clients` when the snapshot was taken.
+  pub clients: Vec<ClientInfo>,
+}
+
+/// Read-mostly view of a server's clients, returned by `Server::client_snapshots`, for stats
+/// endpoints and dashboards on other threads.
+///
+/// The server replaces the snapshot once a second for as long as any clone of this handle is
+/// alive, whether it is driven directly or through `Server::run` or `Server::spawn_driver`.
+/// Loading takes a lock only long enough to clone an `Arc`, so readers never wait for the server
+/// to handle packets and the server never waits for readers looking through a snapshot.
+#[derive(Clone)]
+pub struct SnapshotHandle(Arc<RwLock<Arc<ClientsSnapshot>>>);
+
+impl SnapshotHandle {
+  pub(crate) fn new(snapshot: ClientsSnapshot) -> SnapshotHandle {
+    SnapshotHandle(Arc::new(RwLock::new(Arc::new(snapshot))))
+  }
+
+  /// The latest snapshot, which stays valid however long it is held.
+  pub fn load(&self) -> Arc<ClientsSnapshot> {
+    self.0.read().unwrap().clone()
+  }
+
+  pub(crate) fn publish(&self, snapshot: ClientsSnapshot) {
+    let snapshot = Arc::new(snapshot);
+    // The previous snapshot is dropped after the lock is released.
+    let _previous = std::mem::replace(&mut *self.0.write().unwrap(), snapshot);
+  }
+
+  // Whether anyone besides the server holds a handle.
+  pub(crate) fn has_readers(&self) -> bool {
+    Arc::strong_count(&self.0) > 1
+  }
+}
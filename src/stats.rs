@@ -0,0 +1,364 @@
+use std::{convert::TryFrom, time::Duration};
+
+use crate::{client::MessageType, demux::PacketClass, middleware::MiddlewareStats};
+
+// Each power of two range of microseconds is split into this many linear buckets, which keeps
+// every recorded latency within about 6% of its true value.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (SUB_BUCKETS + (64 - SUB_BUCKET_BITS as u64) * SUB_BUCKETS) as usize;
+
+/// Counters describing how a `Server` has been performing, retrieved with `Server::stats`.
+#[derive(Clone, Debug)]
+pub struct ServerStats {
+  /// Total number of outgoing packets and received messages dropped because their queue was
+  /// full, as with `Server::dropped_packets`.
+  pub dropped_packets: u64,
+  /// Time from a datagram being read off the socket until the message it carried was queued for
+  /// `Server::recv`, or until the response to it was queued to be sent.
+  pub processing_latency: LatencyStats,
+  /// Verdict counters of each middleware added with `Server::add_inbound_middleware`, in order.
+  pub inbound_middleware: Vec<MiddlewareStats>,
+  /// Verdict counters of each middleware added with `Server::add_outbound_middleware`, in order.
+  pub outbound_middleware: Vec<MiddlewareStats>,
+  /// Messages and bytes carried by data channels, for every channel label and message type seen
+  /// since the server started, including on clients which have since disconnected.
+  pub channel_traffic: Vec<ChannelTraffic>,
+  /// Reassembly of fragmented messages, with abandoned messages counted since the server started.
+  pub reassembly: ReassemblyStats,
+  /// Packets discarded because their source address had not been validated by STUN recently
+  /// enough, see `ServerConfig::stun_validation_timeout`.
+  pub stale_source_packets: u64,
+  /// STUN binding requests of new clients left unanswered because of `ServerConfig::max_clients`
+  /// or `ServerConfig::max_pending_handshakes`.
+  pub refused_clients: u64,
+  /// STUN binding requests of new clients answered with 300 Try Alternate because of
+  /// `ServerConfig::try_alternate_beyond`.
+  pub redirected_clients: u64,
+  /// Messages received from clients and handed to their transport, see `Server::relay`.
+  pub relayed_messages: u64,
+  /// ClientHellos refused for asking for weaker DTLS than `Server::dtls_floor`.
+  pub refused_downgrades: u64,
+  /// Received datagrams by what they are, as told by their first byte.
+  pub demux: DemuxStats,
+  /// How far connections got since the server started, from session to SCTP association.
+  pub funnel: ConnectionFunnel,
+}
+
+/// Number of connections which reached each stage of connecting, part of `ServerStats`.
+///
+/// Comparing consecutive stages tells where connections fail: sessions never followed by STUN
+/// point at signalling or NAT traversal, STUN without a DTLS handshake at firewalls or
+/// certificates, and DTLS without an SCTP association at the data channel setup.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ConnectionFunnel {
+  /// Sessions answered by one of the server's `SessionEndpoint`s and taken in by the server.
+  /// Sessions answered with stateless tokens are only counted from the next stage on.
+  pub sessions_created: u64,
+  /// Sessions whose client sent an authenticated STUN binding request, counted once per session.
+  pub stun_seen: u64,
+  /// Clients which completed their DTLS handshake.
+  pub dtls_complete: u64,
+  /// Clients whose SCTP association was established, ready for messages.
+  pub sctp_established: u64,
+}
+
+/// Number of datagrams received of each `PacketClass`, part of `ServerStats`.
+///
+/// Only STUN and DTLS are handled by the server, anything else is dropped after being shown to
+/// the handler set with `Server::set_packet_passthrough`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DemuxStats {
+  pub stun: u64,
+  pub zrtp: u64,
+  pub dtls: u64,
+  pub quic: u64,
+  pub rtp: u64,
+  pub unknown: u64,
+}
+
+impl DemuxStats {
+  pub(crate) fn record(&mut self, class: PacketClass) {
+    let count = match class {
+      PacketClass::Stun => &mut self.stun,
+      PacketClass::Zrtp => &mut self.zrtp,
+      PacketClass::Dtls => &mut self.dtls,
+      PacketClass::Quic => &mut self.quic,
+      PacketClass::Rtp => &mut self.rtp,
+      PacketClass::Unknown => &mut self.unknown,
+    };
+    *count += 1;
+  }
+}
+
+/// Memory held by a server's buffers and queues, in bytes, returned by `Server::memory_usage`.
+///
+/// Buffers are counted by their capacity, which is what they occupy.  The TLS and SCTP state of
+/// each client and the server's own bookkeeping are not counted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryUsage {
+  /// Buffers waiting in the server's buffer pool to be reused.
+  pub buffer_pool: usize,
+  /// Packets waiting in clients' egress queues, which hold their SCTP DATA and control chunks.
+  pub client_send_queues: usize,
+  /// Received messages and fragments waiting in clients, such as while they are paused.
+  pub client_receive_queues: usize,
+  /// Reassembly of fragmented messages across all clients.
+  pub reassembly: usize,
+  /// Messages and fragments waiting for `Server::recv` and `Server::recv_stream`.
+  pub received_queue: usize,
+  /// Packets waiting to be written to the sockets, or for an admission turn under
+  /// `ServerConfig::admission_rate`.
+  pub outgoing_queue: usize,
+  /// Messages waiting to be sent by `Server::send_at`.
+  pub scheduled_sends: usize,
+  /// Sessions waiting for their client's first binding request, including their ICE candidates.
+  pub session_table: usize,
+}
+
+impl MemoryUsage {
+  /// The sum of all the above.
+  pub fn total(&self) -> usize {
+    self.buffer_pool
+      + self.client_send_queues
+      + self.client_receive_queues
+      + self.reassembly
+      + self.received_queue
+      + self.outgoing_queue
+      + self.scheduled_sends
+      + self.session_table
+  }
+}
+
+/// Outgoing traffic waiting to be sent to a client, returned by `Server::pending_outgoing`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QueueDepth {
+  /// UDP packets waiting to be sent, each carrying one or more DTLS records.
+  pub packets: usize,
+  /// Total size of the waiting packets, including DTLS and SCTP overhead.
+  pub bytes: usize,
+  /// How long the oldest waiting packet has been queued, `None` if nothing is waiting.
+  pub oldest_age: Option<Duration>,
+}
+
+/// State of the reassembly of fragmented messages received with `ServerConfig::stream_fragments`,
+/// part of `ServerStats`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReassemblyStats {
+  /// Fragmented messages currently waiting for their final fragment, at most one per data channel
+  /// of each client.
+  pub partial_messages: usize,
+  /// Partial messages abandoned because of `ServerConfig::max_partial_messages` or
+  /// `ServerConfig::max_partial_message_len`.
+  pub resets: u64,
+  /// Partial messages abandoned because one of their fragments never arrived, noticed when a
+  /// later fragment of the same message did.
+  pub holes: u64,
+}
+
+impl ReassemblyStats {
+  pub(crate) fn merge(&mut self, other: ReassemblyStats) {
+    self.partial_messages += other.partial_messages;
+    self.resets += other.resets;
+    self.holes += other.holes;
+  }
+}
+
+/// Traffic on all data channels with one label, of one message type, part of `ServerStats`.
+///
+/// Byte counts are of message payloads as carried by SCTP, so they are after compression by
+/// outbound middleware and before decompression by inbound middleware, and do not include
+/// protocol overhead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelTraffic {
+  /// The label the channel was opened with, empty for channels opened without the data channel
+  /// establishment protocol.  Messages sent by the server count towards the label of the
+  /// client's first channel, which they are sent on.  Labels are chosen by clients, so only the
+  /// first 32 seen are counted apart, and the traffic of any others is counted under `other`.
+  pub label: String,
+  pub message_type: MessageType,
+  pub messages_sent: u64,
+  pub bytes_sent: u64,
+  pub messages_received: u64,
+  pub bytes_received: u64,
+}
+
+// Number of distinct labels traffic is counted under, beyond which it is counted under
+// `OTHER_TRAFFIC_LABEL`, so that clients cannot grow the counters without bound.
+const MAX_TRAFFIC_LABELS: usize = 32;
+const OTHER_TRAFFIC_LABEL: &str = "other";
+
+// Per label and message type traffic counters, of one client or of every client that has
+// disconnected.  There are rarely more than a handful of labels, so they are kept in a list.
+#[derive(Clone, Default)]
+pub struct TrafficCounters {
+  channels: Vec<ChannelTraffic>,
+}
+
+impl TrafficCounters {
+  pub fn record_sent(&mut self, label: &str, message_type: MessageType, bytes: usize) {
+    let channel = self.channel(label, message_type);
+    channel.messages_sent += 1;
+    channel.bytes_sent += bytes as u64;
+  }
+
+  // Record `bytes` received, and a message if `complete`, which is false for every fragment of a
+  // fragmented message but its last.
+  pub fn record_received(
+    &mut self,
+    label: &str,
+    message_type: MessageType,
+    bytes: usize,
+    complete: bool,
+  ) {
+    let channel = self.channel(label, message_type);
+    if complete {
+      channel.messages_received += 1;
+    }
+    channel.bytes_received += bytes as u64;
+  }
+
+  pub fn merge(&mut self, other: &TrafficCounters) {
+    for traffic in &other.channels {
+      let channel = self.channel(&traffic.label, traffic.message_type);
+      channel.messages_sent += traffic.messages_sent;
+      channel.bytes_sent += traffic.bytes_sent;
+      channel.messages_received += traffic.messages_received;
+      channel.bytes_received += traffic.bytes_received;
+    }
+  }
+
+  pub fn channels(&self) -> &[ChannelTraffic] {
+    &self.channels
+  }
+
+  pub fn into_channels(self) -> Vec<ChannelTraffic> {
+    self.channels
+  }
+
+  fn channel(&mut self, label: &str, message_type: MessageType) -> &mut ChannelTraffic {
+    let channels = &self.channels;
+    let known = channels.iter().any(|c| c.label == label);
+    let labels = channels
+      .iter()
+      .enumerate()
+      .filter(|&(index, c)| {
+        c.label != OTHER_TRAFFIC_LABEL && channels[..index].iter().all(|d| d.label != c.label)
+      })
+      .count();
+    let label = if known || labels < MAX_TRAFFIC_LABELS {
+      label
+    } else {
+      OTHER_TRAFFIC_LABEL
+    };
+    let index = match self
+      .channels
+      .iter()
+      .position(|c| c.label == label && c.message_type == message_type)
+    {
+      Some(index) => index,
+      None => {
+        self.channels.push(ChannelTraffic {
+          label: label.to_owned(),
+          message_type,
+          messages_sent: 0,
+          bytes_sent: 0,
+          messages_received: 0,
+          bytes_received: 0,
+        });
+        self.channels.len() - 1
+      }
+    };
+    &mut self.channels[index]
+  }
+}
+
+/// Percentiles of a latency distribution, each accurate to within about 6%.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LatencyStats {
+  /// Number of latencies recorded, the percentiles are all zero if this is.
+  pub samples: u64,
+  pub p50: Duration,
+  pub p95: Duration,
+  pub p99: Duration,
+  pub max: Duration,
+}
+
+// A histogram of durations in microseconds with log-linear buckets, in the style of an HDR
+// histogram, so recording is constant time and memory does not grow with the number of samples.
+pub struct LatencyHistogram {
+  counts: Box<[u64]>,
+  samples: u64,
+  max: u64,
+}
+
+impl LatencyHistogram {
+  pub fn new() -> LatencyHistogram {
+    LatencyHistogram {
+      counts: vec![0; BUCKETS].into_boxed_slice(),
+      samples: 0,
+      max: 0,
+    }
+  }
+
+  pub fn record(&mut self, latency: Duration) {
+    let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+    self.counts[bucket_index(micros)] += 1;
+    self.samples += 1;
+    self.max = self.max.max(micros);
+  }
+
+  pub fn clear(&mut self) {
+    self.counts.iter_mut().for_each(|c| *c = 0);
+    self.samples = 0;
+    self.max = 0;
+  }
+
+  pub fn stats(&self) -> LatencyStats {
+    LatencyStats {
+      samples: self.samples,
+      p50: self.percentile(50),
+      p95: self.percentile(95),
+      p99: self.percentile(99),
+      max: Duration::from_micros(self.max),
+    }
+  }
+
+  // The highest value in the bucket holding the sample at the given percentile, never more than
+  // the largest value recorded.
+  fn percentile(&self, percentile: u64) -> Duration {
+    if self.samples == 0 {
+      return Duration::ZERO;
+    }
+
+    let rank = (self.samples * percentile).div_ceil(100).max(1);
+    let mut seen = 0;
+    for (index, &count) in self.counts.iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        return Duration::from_micros(bucket_high(index).min(self.max));
+      }
+    }
+    Duration::from_micros(self.max)
+  }
+}
+
+fn bucket_index(micros: u64) -> usize {
+  if micros < SUB_BUCKETS {
+    return micros as usize;
+  }
+  let magnitude = 63 - micros.leading_zeros();
+  let shift = magnitude - SUB_BUCKET_BITS;
+  let sub_bucket = (micros >> shift) - SUB_BUCKETS;
+  (SUB_BUCKETS * (1 + shift as u64) + sub_bucket) as usize
+}
+
+fn bucket_high(index: usize) -> u64 {
+  let index = index as u64;
+  if index < SUB_BUCKETS {
+    return index;
+  }
+  let shift = index / SUB_BUCKETS - 1;
+  let sub_bucket = index % SUB_BUCKETS;
+  ((SUB_BUCKETS + sub_bucket) << shift) + ((1 << shift) - 1)
+}
@@ -2,39 +2,50 @@ use std::{error, net::SocketAddr, str};
 
 use byteorder::{ByteOrder, NetworkEndian};
 use crc::{Crc, CRC_32_ISO_HDLC};
-use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+
+#[cfg(test)]
+mod conformance;
 
 pub type Error = Box<dyn error::Error + Send + Sync>;
 
+// A binding request parsed in place, borrowing the usernames from the packet so that answering it
+// allocates nothing.
 #[derive(Debug)]
-pub struct StunBindingRequest {
+pub struct StunBindingRequest<'a> {
   pub transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
-  pub remote_user: String,
-  pub server_user: String,
+  pub remote_user: &'a str,
+  pub server_user: &'a str,
+  /// Offset of the MESSAGE-INTEGRITY attribute header within the request, if present.
+  pub integrity_offset: Option<usize>,
+  /// Offset of the FINGERPRINT attribute header, if present directly after MESSAGE-INTEGRITY.
+  pub fingerprint_offset: Option<usize>,
+  /// The ICE role the remote agent claims, if it sent ICE-CONTROLLING or ICE-CONTROLLED.
+  pub ice_role: Option<IceRole>,
+  /// The request carried USE-CANDIDATE, nominating this candidate pair.
+  pub use_candidate: bool,
 }
 
-pub fn parse_stun_binding_request(bytes: &[u8]) -> Option<StunBindingRequest> {
-  if bytes.len() < STUN_HEADER_LEN {
-    return None;
-  }
+/// The role asserted by the sender of a binding request, along with its tie-breaker value.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum IceRole {
+  Controlling(u64),
+  Controlled(u64),
+}
 
-  let stun_type = NetworkEndian::read_u16(&bytes[0..2]);
-  if stun_type != StunType::BindingRequest as u16 {
-    return None;
-  }
+pub fn parse_stun_binding_request(bytes: &[u8]) -> Option<StunBindingRequest<'_>> {
+  let transaction_id = stun_binding_request_transaction_id(bytes)?;
 
   let length = NetworkEndian::read_u16(&bytes[2..4]) as usize;
   if length < 4 || STUN_HEADER_LEN + length > bytes.len() {
     return None;
   }
 
-  if bytes[4..8] != STUN_COOKIE {
-    return None;
-  }
-
-  let mut transaction_id = [0; STUN_TRANSACTION_ID_LEN];
-  transaction_id.copy_from_slice(&bytes[8..STUN_HEADER_LEN]);
-
+  let mut users = None;
+  let mut integrity_offset = None;
+  let mut fingerprint_offset = None;
+  let mut ice_role = None;
+  let mut use_candidate = false;
   let mut offset = STUN_HEADER_LEN;
   while offset < STUN_HEADER_LEN + length - 4 {
     let payload_type = NetworkEndian::read_u16(&bytes[offset..offset + 2]);
@@ -53,24 +64,221 @@ pub fn parse_stun_binding_request(bytes: &[u8]) -> Option<StunBindingRequest> {
       {
         return None;
       }
-      let server_user = str::from_utf8(server_user).ok()?.to_owned();
-      let remote_user = str::from_utf8(remote_user).ok()?.to_owned();
-
-      return Some(StunBindingRequest {
-        transaction_id,
-        remote_user,
-        server_user,
-      });
+      let server_user = str::from_utf8(server_user).ok()?;
+      let remote_user = str::from_utf8(remote_user).ok()?;
+      users = Some((server_user, remote_user));
+    } else if payload_type == StunAttributeType::IceControlling as u16
+      || payload_type == StunAttributeType::IceControlled as u16
+    {
+      if payload_len != STUN_TIE_BREAKER_LEN {
+        return None;
+      }
+      let tie_breaker = NetworkEndian::read_u64(&bytes[offset..offset + payload_len]);
+      ice_role = Some(
+        if payload_type == StunAttributeType::IceControlling as u16 {
+          IceRole::Controlling(tie_breaker)
+        } else {
+          IceRole::Controlled(tie_breaker)
+        },
+      );
+    } else if payload_type == StunAttributeType::UseCandidate as u16 {
+      use_candidate = true;
+    } else if payload_type == StunAttributeType::MessageIntegrity as u16 {
+      if payload_len != STUN_INTEGRITY_LEN {
+        return None;
+      }
+      // Everything after MESSAGE-INTEGRITY other than FINGERPRINT must be ignored.
+      integrity_offset = Some(offset - 4);
+      let next = offset + padded_len;
+      if next + 4 + STUN_FINGERPRINT_LEN == STUN_HEADER_LEN + length
+        && NetworkEndian::read_u16(&bytes[next..next + 2]) == StunAttributeType::Fingerprint as u16
+        && NetworkEndian::read_u16(&bytes[next + 2..next + 4]) as usize == STUN_FINGERPRINT_LEN
+      {
+        fingerprint_offset = Some(next);
+      }
+      break;
     }
     offset += padded_len;
   }
-  None
+
+  let (server_user, remote_user) = users?;
+  Some(StunBindingRequest {
+    transaction_id,
+    remote_user,
+    server_user,
+    integrity_offset,
+    fingerprint_offset,
+    ice_role,
+    use_candidate,
+  })
+}
+
+/// Returns the transaction id of anything that has the header of a STUN binding request, whether
+/// or not the rest of the request is well formed.
+///
+/// Used to answer malformed binding requests with an error rather than passing them on as DTLS.
+pub fn stun_binding_request_transaction_id(bytes: &[u8]) -> Option<[u8; STUN_TRANSACTION_ID_LEN]> {
+  if bytes.len() < STUN_HEADER_LEN {
+    return None;
+  }
+
+  let stun_type = NetworkEndian::read_u16(&bytes[0..2]);
+  if stun_type != StunType::BindingRequest as u16 {
+    return None;
+  }
+
+  if bytes[4..8] != STUN_COOKIE {
+    return None;
+  }
+
+  let mut transaction_id = [0; STUN_TRANSACTION_ID_LEN];
+  transaction_id.copy_from_slice(&bytes[8..STUN_HEADER_LEN]);
+  Some(transaction_id)
+}
+
+/// Check the MESSAGE-INTEGRITY attribute of a binding request parsed from `bytes` against the
+/// short-term credential `passwd`.  Requests without MESSAGE-INTEGRITY never pass.
+pub fn verify_stun_message_integrity(
+  bytes: &[u8],
+  request: &StunBindingRequest,
+  passwd: &[u8],
+) -> bool {
+  let integrity_offset = match request.integrity_offset {
+    Some(integrity_offset) => integrity_offset,
+    None => return false,
+  };
+  let integrity_end = integrity_offset + 4 + STUN_INTEGRITY_LEN;
+
+  // The HMAC is calculated with the message length adjusted to end at MESSAGE-INTEGRITY.
+  let mut header = [0; STUN_HEADER_LEN];
+  header.copy_from_slice(&bytes[0..STUN_HEADER_LEN]);
+  NetworkEndian::write_u16(&mut header[2..4], (integrity_end - STUN_HEADER_LEN) as u16);
+
+  let hmac = || -> Result<Vec<u8>, Error> {
+    let key = PKey::hmac(passwd)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(&header)?;
+    signer.update(&bytes[STUN_HEADER_LEN..integrity_offset])?;
+    Ok(signer.sign_to_vec()?)
+  };
+  match hmac() {
+    Ok(hmac) => memcmp::eq(&hmac, &bytes[integrity_offset + 4..integrity_end]),
+    Err(_) => false,
+  }
+}
+
+/// Check the FINGERPRINT attribute of a binding request parsed from `bytes`.  Requests without
+/// FINGERPRINT never pass.
+pub fn verify_stun_fingerprint(bytes: &[u8], request: &StunBindingRequest) -> bool {
+  const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+  match request.fingerprint_offset {
+    Some(offset) => {
+      let value = &bytes[offset + 4..offset + 4 + STUN_FINGERPRINT_LEN];
+      CRC.checksum(&bytes[..offset]) ^ STUN_CRC_XOR == NetworkEndian::read_u32(value)
+    }
+    None => false,
+  }
+}
+
+/// Optional attributes of a STUN response, written after the attributes every response has and
+/// before MESSAGE-INTEGRITY, so that they are authenticated along with the rest of the response.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StunExtraAttributes<'a> {
+  /// SOFTWARE (RFC 8489 section 14.14), of which only the first 127 characters are written.
+  pub software: Option<&'a str>,
+  /// ALTERNATE-SERVER (RFC 8489 section 14.15), the server a client should try instead.
+  pub alternate_server: Option<SocketAddr>,
+  /// RESPONSE-ORIGIN (RFC 5780 section 7.3), the address the response is sent from.
+  pub response_origin: Option<SocketAddr>,
+}
+
+impl StunExtraAttributes<'_> {
+  /// Number of bytes `StunExtraAttributes::write` writes.
+  pub fn len(&self) -> usize {
+    let addr_attribute_len = |addr: SocketAddr| 4 + if addr.is_ipv4() { 8 } else { 20 };
+    self.software().map_or(0, |software| {
+      4 + software.len().div_ceil(STUN_ALIGNMENT) * STUN_ALIGNMENT
+    }) + self.alternate_server.map_or(0, addr_attribute_len)
+      + self.response_origin.map_or(0, addr_attribute_len)
+  }
+
+  // Write the attributes into `out`, which must be exactly `StunExtraAttributes::len` long.
+  fn write(&self, out: &mut [u8]) {
+    let mut out = out;
+    if let Some(software) = self.software() {
+      let padded_len = software.len().div_ceil(STUN_ALIGNMENT) * STUN_ALIGNMENT;
+      let (attribute, rest) = out.split_at_mut(4 + padded_len);
+      NetworkEndian::write_u16(&mut attribute[0..2], StunAttributeType::Software as u16);
+      NetworkEndian::write_u16(&mut attribute[2..4], software.len() as u16);
+      attribute[4..4 + software.len()].copy_from_slice(software);
+      for b in &mut attribute[4 + software.len()..] {
+        *b = 0;
+      }
+      out = rest;
+    }
+    for (ty, addr) in [
+      (StunAttributeType::AlternateServer, self.alternate_server),
+      (StunAttributeType::ResponseOrigin, self.response_origin),
+    ] {
+      if let Some(addr) = addr {
+        let (attribute, rest) = out.split_at_mut(4 + if addr.is_ipv4() { 8 } else { 20 });
+        write_address_attribute(ty, addr, attribute);
+        out = rest;
+      }
+    }
+  }
+
+  fn software(&self) -> Option<&[u8]> {
+    let software = self.software?;
+    let software = match software.char_indices().nth(STUN_MAX_SOFTWARE_CHARS) {
+      Some((end, _)) => &software[..end],
+      None => software,
+    };
+    Some(software.as_bytes())
+  }
+}
+
+// Write an attribute with the MAPPED-ADDRESS encoding of RFC 8489 section 14.1, which is not
+// XORed, into `out`, which must be exactly long enough for it.
+fn write_address_attribute(ty: StunAttributeType, addr: SocketAddr, out: &mut [u8]) {
+  NetworkEndian::write_u16(&mut out[0..2], ty as u16);
+  let value_len = out.len() - 4;
+  NetworkEndian::write_u16(&mut out[2..4], value_len as u16);
+  out[4] = 0;
+  NetworkEndian::write_u16(&mut out[6..8], addr.port());
+  match addr {
+    SocketAddr::V4(addr) => {
+      out[5] = StunAddressFamily::IPV4 as u8;
+      out[8..12].copy_from_slice(&addr.ip().octets());
+    }
+    SocketAddr::V6(addr) => {
+      out[5] = StunAddressFamily::IPV6 as u8;
+      out[8..24].copy_from_slice(&addr.ip().octets());
+    }
+  }
+}
+
+/// Length of the response `write_stun_success_response` writes for `remote_addr` and `extras`,
+/// so that the output buffer can be sized exactly.
+pub fn stun_success_response_len(remote_addr: SocketAddr, extras: &StunExtraAttributes) -> usize {
+  // XOR-MAPPED-ADDRESS, MESSAGE-INTEGRITY and FINGERPRINT, each behind a 4 byte marker.
+  let addr_attribute_len = if remote_addr.is_ipv4() { 8 } else { 20 };
+  STUN_HEADER_LEN
+    + 4
+    + addr_attribute_len
+    + extras.len()
+    + 4
+    + STUN_INTEGRITY_LEN
+    + 4
+    + STUN_FINGERPRINT_LEN
 }
 
 pub fn write_stun_success_response(
   transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
   remote_addr: SocketAddr,
   passwd: &[u8],
+  extras: &StunExtraAttributes,
   out: &mut [u8],
 ) -> Result<usize, Error> {
   const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
@@ -78,7 +286,7 @@ pub fn write_stun_success_response(
   const ATTRIBUTE_MARKER_LEN: usize = 4;
   const IPV4_ADDR_ATTRIBUTE_LEN: usize = 8;
   const IPV6_ADDR_ATTRIBUTE_LEN: usize = 20;
-  const INTEGRITY_ATTRIBUTE_LEN: usize = 20;
+  const INTEGRITY_ATTRIBUTE_LEN: usize = STUN_INTEGRITY_LEN;
   const FINGERPRINT_ATTRIBUTE_LEN: usize = 4;
 
   let addr_attribute_len = if remote_addr.is_ipv4() {
@@ -86,8 +294,9 @@ pub fn write_stun_success_response(
   } else {
     IPV6_ADDR_ATTRIBUTE_LEN
   };
+  let extras_len = extras.len();
   let content_len_integrity =
-    ATTRIBUTE_MARKER_LEN * 2 + addr_attribute_len + INTEGRITY_ATTRIBUTE_LEN;
+    ATTRIBUTE_MARKER_LEN * 2 + addr_attribute_len + extras_len + INTEGRITY_ATTRIBUTE_LEN;
   let content_len = content_len_integrity + ATTRIBUTE_MARKER_LEN + FINGERPRINT_ATTRIBUTE_LEN;
 
   if STUN_HEADER_LEN + content_len > out.len() {
@@ -96,6 +305,7 @@ pub fn write_stun_success_response(
 
   let (header, rest) = out.split_at_mut(STUN_HEADER_LEN);
   let (addr_attribute, rest) = rest.split_at_mut(ATTRIBUTE_MARKER_LEN + addr_attribute_len);
+  let (extra_attributes, rest) = rest.split_at_mut(extras_len);
   let (integrity_attribute, fingerprint_attribute) =
     rest.split_at_mut(ATTRIBUTE_MARKER_LEN + INTEGRITY_ATTRIBUTE_LEN);
 
@@ -128,11 +338,13 @@ pub fn write_stun_success_response(
       xor_range(&mut addr_attribute[12..24], &transaction_id);
     }
   }
+  extras.write(extra_attributes);
 
   let key = PKey::hmac(passwd)?;
   let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
   signer.update(header)?;
   signer.update(addr_attribute)?;
+  signer.update(extra_attributes)?;
   let mut hmac = [0; INTEGRITY_ATTRIBUTE_LEN];
   signer.sign(&mut hmac)?;
 
@@ -151,6 +363,7 @@ pub fn write_stun_success_response(
   let mut digest = CRC.digest();
   digest.update(&header);
   digest.update(&addr_attribute);
+  digest.update(extra_attributes);
   digest.update(&integrity_attribute);
   let crc = digest.finalize();
 
@@ -167,16 +380,317 @@ pub fn write_stun_success_response(
   Ok(STUN_HEADER_LEN + content_len)
 }
 
+/// STUN error codes (RFC 8489 section 14.8) which may be sent in a binding error response.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StunErrorCode {
+  /// The client should contact the server named in the response's ALTERNATE-SERVER attribute
+  /// instead.
+  TryAlternate = 300,
+  /// The request was malformed.
+  BadRequest = 400,
+  /// The request did not contain the correct credentials.
+  Unauthorized = 401,
+  /// The client asserted an ICE role which conflicts with ours (RFC 8445 section 7.3.1.1).
+  RoleConflict = 487,
+}
+
+impl StunErrorCode {
+  pub fn reason_phrase(self) -> &'static str {
+    match self {
+      StunErrorCode::TryAlternate => "Try Alternate",
+      StunErrorCode::BadRequest => "Bad Request",
+      StunErrorCode::Unauthorized => "Unauthorized",
+      StunErrorCode::RoleConflict => "Role Conflict",
+    }
+  }
+}
+
+/// Length of the response `write_stun_error_response` writes for `error_code` and `extras`, with
+/// or without MESSAGE-INTEGRITY.
+pub fn stun_error_response_len(
+  error_code: StunErrorCode,
+  authenticated: bool,
+  extras: &StunExtraAttributes,
+) -> usize {
+  let error_attribute_len = 4 + error_code.reason_phrase().len();
+  let error_padded_len = (error_attribute_len + STUN_ALIGNMENT - 1) & !(STUN_ALIGNMENT - 1);
+  let integrity_len = if authenticated {
+    4 + STUN_INTEGRITY_LEN
+  } else {
+    0
+  };
+  STUN_HEADER_LEN + 4 + error_padded_len + extras.len() + integrity_len + 4 + STUN_FINGERPRINT_LEN
+}
+
+/// Write a binding error response carrying an ERROR-CODE attribute.
+///
+/// If `passwd` is given the response is authenticated with MESSAGE-INTEGRITY; this must only be
+/// done when the request itself was authenticated, so responses to malformed or unauthorized
+/// requests should pass `None`.  A FINGERPRINT attribute is always appended.
+pub fn write_stun_error_response(
+  transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
+  error_code: StunErrorCode,
+  passwd: Option<&[u8]>,
+  extras: &StunExtraAttributes,
+  out: &mut [u8],
+) -> Result<usize, Error> {
+  const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+  const ATTRIBUTE_MARKER_LEN: usize = 4;
+  const ERROR_CODE_HEADER_LEN: usize = 4;
+  const INTEGRITY_ATTRIBUTE_LEN: usize = STUN_INTEGRITY_LEN;
+  const FINGERPRINT_ATTRIBUTE_LEN: usize = 4;
+
+  let reason = error_code.reason_phrase().as_bytes();
+  let error_attribute_len = ERROR_CODE_HEADER_LEN + reason.len();
+  let error_padded_len = (error_attribute_len + STUN_ALIGNMENT - 1) & !(STUN_ALIGNMENT - 1);
+  let integrity_len = if passwd.is_some() {
+    ATTRIBUTE_MARKER_LEN + INTEGRITY_ATTRIBUTE_LEN
+  } else {
+    0
+  };
+  let extras_len = extras.len();
+  let content_len_integrity = ATTRIBUTE_MARKER_LEN + error_padded_len + extras_len + integrity_len;
+  let content_len = content_len_integrity + ATTRIBUTE_MARKER_LEN + FINGERPRINT_ATTRIBUTE_LEN;
+
+  if STUN_HEADER_LEN + content_len > out.len() {
+    return Err("output buffer too small for STUN response".into());
+  }
+
+  let (header, rest) = out.split_at_mut(STUN_HEADER_LEN);
+  let (error_attribute, rest) = rest.split_at_mut(ATTRIBUTE_MARKER_LEN + error_padded_len);
+  let (extra_attributes, rest) = rest.split_at_mut(extras_len);
+  let (integrity_attribute, rest) = rest.split_at_mut(integrity_len);
+  let fingerprint_attribute = &mut rest[..ATTRIBUTE_MARKER_LEN + FINGERPRINT_ATTRIBUTE_LEN];
+
+  NetworkEndian::write_u16(&mut header[0..2], StunType::ErrorResponse as u16);
+  NetworkEndian::write_u16(&mut header[2..4], content_len_integrity as u16);
+  header[4..8].copy_from_slice(&STUN_COOKIE);
+  header[8..20].copy_from_slice(&transaction_id);
+
+  let code = error_code as u16;
+  NetworkEndian::write_u16(
+    &mut error_attribute[0..2],
+    StunAttributeType::ErrorCode as u16,
+  );
+  NetworkEndian::write_u16(&mut error_attribute[2..4], error_attribute_len as u16);
+  error_attribute[4] = 0;
+  error_attribute[5] = 0;
+  error_attribute[6] = (code / 100) as u8;
+  error_attribute[7] = (code % 100) as u8;
+  error_attribute[8..8 + reason.len()].copy_from_slice(reason);
+  for b in &mut error_attribute[8 + reason.len()..] {
+    *b = 0;
+  }
+  extras.write(extra_attributes);
+
+  if let Some(passwd) = passwd {
+    let key = PKey::hmac(passwd)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(header)?;
+    signer.update(error_attribute)?;
+    signer.update(extra_attributes)?;
+    let mut hmac = [0; INTEGRITY_ATTRIBUTE_LEN];
+    signer.sign(&mut hmac)?;
+
+    NetworkEndian::write_u16(
+      &mut integrity_attribute[0..2],
+      StunAttributeType::MessageIntegrity as u16,
+    );
+    NetworkEndian::write_u16(
+      &mut integrity_attribute[2..4],
+      INTEGRITY_ATTRIBUTE_LEN as u16,
+    );
+    integrity_attribute[4..].copy_from_slice(&hmac);
+  }
+
+  NetworkEndian::write_u16(&mut header[2..4], content_len as u16);
+
+  let mut digest = CRC.digest();
+  digest.update(header);
+  digest.update(error_attribute);
+  digest.update(extra_attributes);
+  digest.update(integrity_attribute);
+  let crc = digest.finalize();
+
+  NetworkEndian::write_u16(
+    &mut fingerprint_attribute[0..2],
+    StunAttributeType::Fingerprint as u16,
+  );
+  NetworkEndian::write_u16(
+    &mut fingerprint_attribute[2..4],
+    FINGERPRINT_ATTRIBUTE_LEN as u16,
+  );
+  NetworkEndian::write_u32(&mut fingerprint_attribute[4..8], crc ^ STUN_CRC_XOR);
+
+  Ok(STUN_HEADER_LEN + content_len)
+}
+
+// Write a binding request as sent by a controlling ICE agent, for the native loopback client.
+pub fn write_stun_binding_request(
+  transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
+  username: &[u8],
+  passwd: &[u8],
+  tie_breaker: u64,
+  use_candidate: bool,
+  out: &mut [u8],
+) -> Result<usize, Error> {
+  const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+  const ATTRIBUTE_MARKER_LEN: usize = 4;
+  const FINGERPRINT_ATTRIBUTE_LEN: usize = 4;
+
+  if username.len() > STUN_MAX_IDENTIFIER_LEN * 2 + 1 {
+    return Err("username too long for STUN request".into());
+  }
+
+  let username_len = username.len().div_ceil(STUN_ALIGNMENT) * STUN_ALIGNMENT;
+  let attributes_len = ATTRIBUTE_MARKER_LEN
+    + username_len
+    + ATTRIBUTE_MARKER_LEN
+    + STUN_TIE_BREAKER_LEN
+    + if use_candidate {
+      ATTRIBUTE_MARKER_LEN
+    } else {
+      0
+    };
+  let content_len_integrity = attributes_len + ATTRIBUTE_MARKER_LEN + STUN_INTEGRITY_LEN;
+  let content_len = content_len_integrity + ATTRIBUTE_MARKER_LEN + FINGERPRINT_ATTRIBUTE_LEN;
+
+  if STUN_HEADER_LEN + content_len > out.len() {
+    return Err("output buffer too small for STUN request".into());
+  }
+
+  let (header, rest) = out.split_at_mut(STUN_HEADER_LEN);
+  let (attributes, rest) = rest.split_at_mut(attributes_len);
+  let (integrity_attribute, rest) = rest.split_at_mut(ATTRIBUTE_MARKER_LEN + STUN_INTEGRITY_LEN);
+  let fingerprint_attribute = &mut rest[..ATTRIBUTE_MARKER_LEN + FINGERPRINT_ATTRIBUTE_LEN];
+
+  NetworkEndian::write_u16(&mut header[0..2], StunType::BindingRequest as u16);
+  NetworkEndian::write_u16(&mut header[2..4], content_len_integrity as u16);
+  header[4..8].copy_from_slice(&STUN_COOKIE);
+  header[8..20].copy_from_slice(&transaction_id);
+
+  let (username_attribute, rest) = attributes.split_at_mut(ATTRIBUTE_MARKER_LEN + username_len);
+  NetworkEndian::write_u16(
+    &mut username_attribute[0..2],
+    StunAttributeType::User as u16,
+  );
+  NetworkEndian::write_u16(&mut username_attribute[2..4], username.len() as u16);
+  username_attribute[4..4 + username.len()].copy_from_slice(username);
+  for b in &mut username_attribute[4 + username.len()..] {
+    *b = 0;
+  }
+
+  let (role_attribute, use_candidate_attribute) =
+    rest.split_at_mut(ATTRIBUTE_MARKER_LEN + STUN_TIE_BREAKER_LEN);
+  NetworkEndian::write_u16(
+    &mut role_attribute[0..2],
+    StunAttributeType::IceControlling as u16,
+  );
+  NetworkEndian::write_u16(&mut role_attribute[2..4], STUN_TIE_BREAKER_LEN as u16);
+  NetworkEndian::write_u64(&mut role_attribute[4..12], tie_breaker);
+
+  if use_candidate {
+    NetworkEndian::write_u16(
+      &mut use_candidate_attribute[0..2],
+      StunAttributeType::UseCandidate as u16,
+    );
+    NetworkEndian::write_u16(&mut use_candidate_attribute[2..4], 0);
+  }
+
+  let key = PKey::hmac(passwd)?;
+  let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+  signer.update(header)?;
+  signer.update(attributes)?;
+  let mut hmac = [0; STUN_INTEGRITY_LEN];
+  signer.sign(&mut hmac)?;
+
+  NetworkEndian::write_u16(
+    &mut integrity_attribute[0..2],
+    StunAttributeType::MessageIntegrity as u16,
+  );
+  NetworkEndian::write_u16(&mut integrity_attribute[2..4], STUN_INTEGRITY_LEN as u16);
+  integrity_attribute[4..].copy_from_slice(&hmac);
+
+  NetworkEndian::write_u16(&mut header[2..4], content_len as u16);
+
+  let mut digest = CRC.digest();
+  digest.update(header);
+  digest.update(attributes);
+  digest.update(integrity_attribute);
+  let crc = digest.finalize();
+
+  NetworkEndian::write_u16(
+    &mut fingerprint_attribute[0..2],
+    StunAttributeType::Fingerprint as u16,
+  );
+  NetworkEndian::write_u16(
+    &mut fingerprint_attribute[2..4],
+    FINGERPRINT_ATTRIBUTE_LEN as u16,
+  );
+  NetworkEndian::write_u32(&mut fingerprint_attribute[4..8], crc ^ STUN_CRC_XOR);
+
+  Ok(STUN_HEADER_LEN + content_len)
+}
+
+// Returns true if `bytes` is a binding success response to the request with the given
+// transaction id.
+pub fn is_stun_success_response(
+  bytes: &[u8],
+  transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
+) -> bool {
+  bytes.len() >= STUN_HEADER_LEN
+    && NetworkEndian::read_u16(&bytes[0..2]) == StunType::SuccessResponse as u16
+    && bytes[4..8] == STUN_COOKIE
+    && bytes[8..20] == transaction_id
+}
+
+// The error code of `bytes`, if it is a binding error response to the request with the given
+// transaction id.
+pub fn stun_error_response_code(
+  bytes: &[u8],
+  transaction_id: [u8; STUN_TRANSACTION_ID_LEN],
+) -> Option<u16> {
+  if bytes.len() < STUN_HEADER_LEN
+    || NetworkEndian::read_u16(&bytes[0..2]) != StunType::ErrorResponse as u16
+    || bytes[4..8] != STUN_COOKIE
+    || bytes[8..20] != transaction_id
+  {
+    return None;
+  }
+  let mut attributes = &bytes[STUN_HEADER_LEN..];
+  while attributes.len() >= 4 {
+    let attribute_type = NetworkEndian::read_u16(&attributes[0..2]);
+    let attribute_len = NetworkEndian::read_u16(&attributes[2..4]) as usize;
+    let value = attributes.get(4..4 + attribute_len)?;
+    if attribute_type == StunAttributeType::ErrorCode as u16 && value.len() >= 4 {
+      return Some(u16::from(value[2] & 0x07) * 100 + u16::from(value[3]));
+    }
+    let padded_len = (attribute_len + STUN_ALIGNMENT - 1) & !(STUN_ALIGNMENT - 1);
+    attributes = attributes.get(4 + padded_len..)?;
+  }
+  None
+}
+
 enum StunType {
   BindingRequest = 0x0001,
   SuccessResponse = 0x0101,
+  ErrorResponse = 0x0111,
 }
 
 enum StunAttributeType {
   User = 0x06,
   MessageIntegrity = 0x08,
+  ErrorCode = 0x09,
   XorMappedAddress = 0x20,
+  UseCandidate = 0x25,
+  Software = 0x8022,
+  AlternateServer = 0x8023,
+  IceControlled = 0x8029,
+  IceControlling = 0x802a,
   Fingerprint = 0x8028,
+  ResponseOrigin = 0x802b,
 }
 
 enum StunAddressFamily {
@@ -187,6 +701,10 @@ enum StunAddressFamily {
 const STUN_TRANSACTION_ID_LEN: usize = 12;
 const STUN_MAX_IDENTIFIER_LEN: usize = 128;
 const STUN_HEADER_LEN: usize = 20;
+const STUN_INTEGRITY_LEN: usize = 20;
+const STUN_FINGERPRINT_LEN: usize = 4;
+const STUN_TIE_BREAKER_LEN: usize = 8;
+const STUN_MAX_SOFTWARE_CHARS: usize = 127;
 const STUN_ALIGNMENT: usize = 4;
 const STUN_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
 const STUN_CRC_XOR: u32 = 0x5354554e;
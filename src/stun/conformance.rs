@@ -0,0 +1,421 @@
+//! Conformance tests for the STUN parser and writers, using the sample messages from RFC 5769
+//! ("Test Vectors for Session Traversal Utilities for NAT").
+//!
+//! The RFC samples put their SOFTWARE attribute first, where the writers put it after the
+//! XOR-MAPPED-ADDRESS, so responses are checked attribute by attribute: the XOR-MAPPED-ADDRESS
+//! encoding must match the samples exactly, and MESSAGE-INTEGRITY / FINGERPRINT are checked with
+//! an independent verifier which is itself validated against the RFC sample request.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use byteorder::{ByteOrder, NetworkEndian};
+use crc::{Crc, CRC_32_ISO_HDLC};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+
+use super::*;
+
+const PASSWORD: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+
+const TRANSACTION_ID: [u8; STUN_TRANSACTION_ID_LEN] = [
+  0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+];
+
+// RFC 5769 section 2.1
+const SAMPLE_REQUEST: &[u8] = &[
+  0x00, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86,
+  0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x10, 0x53, 0x54, 0x55, 0x4e, 0x20, 0x74, 0x65, 0x73,
+  0x74, 0x20, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x00, 0x24, 0x00, 0x04, 0x6e, 0x00, 0x01, 0xff,
+  0x80, 0x29, 0x00, 0x08, 0x93, 0x2f, 0xf9, 0xb1, 0x51, 0x26, 0x3b, 0x36, 0x00, 0x06, 0x00, 0x09,
+  0x65, 0x76, 0x74, 0x6a, 0x3a, 0x68, 0x36, 0x76, 0x59, 0x20, 0x20, 0x20, 0x00, 0x08, 0x00, 0x14,
+  0x9a, 0xea, 0xa7, 0x0c, 0xbf, 0xd8, 0xcb, 0x56, 0x78, 0x1e, 0xf2, 0xb5, 0xb2, 0xd3, 0xf2, 0x49,
+  0xc1, 0xb5, 0x71, 0xa2, 0x80, 0x28, 0x00, 0x04, 0xe5, 0x7a, 0x3b, 0xcf,
+];
+
+// XOR-MAPPED-ADDRESS attribute of RFC 5769 section 2.2, for 192.0.2.1:32853
+const SAMPLE_IPV4_MAPPED_ADDRESS: &[u8] = &[
+  0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+];
+
+// XOR-MAPPED-ADDRESS attribute of RFC 5769 section 2.3, for
+// [2001:db8:1234:5678:11:2233:4455:6677]:32853
+const SAMPLE_IPV6_MAPPED_ADDRESS: &[u8] = &[
+  0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79,
+  0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+];
+
+fn sample_ipv4_addr() -> SocketAddr {
+  SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 32853)
+}
+
+fn sample_ipv6_addr() -> SocketAddr {
+  SocketAddr::new(
+    Ipv6Addr::new(0x2001, 0xdb8, 0x1234, 0x5678, 0x11, 0x2233, 0x4455, 0x6677).into(),
+    32853,
+  )
+}
+
+// Returns the offset and full contents (including the attribute header) of every attribute in a
+// STUN message.
+fn attributes(message: &[u8]) -> Vec<(usize, &[u8])> {
+  let length = NetworkEndian::read_u16(&message[2..4]) as usize;
+  assert_eq!(message.len(), STUN_HEADER_LEN + length);
+
+  let mut attributes = Vec::new();
+  let mut offset = STUN_HEADER_LEN;
+  while offset < message.len() {
+    let attribute_len = NetworkEndian::read_u16(&message[offset + 2..offset + 4]) as usize;
+    let padded_len = (attribute_len + STUN_ALIGNMENT - 1) & !(STUN_ALIGNMENT - 1);
+    attributes.push((offset, &message[offset..offset + 4 + attribute_len]));
+    offset += 4 + padded_len;
+  }
+  assert_eq!(offset, message.len());
+  attributes
+}
+
+fn find_attribute(message: &[u8], ty: u16) -> Option<(usize, &[u8])> {
+  attributes(message)
+    .into_iter()
+    .find(|(_, attribute)| NetworkEndian::read_u16(&attribute[0..2]) == ty)
+}
+
+// Check MESSAGE-INTEGRITY as described in RFC 8489 section 14.5, with the message length adjusted
+// to end just after the integrity attribute.
+fn verify_integrity(message: &[u8], passwd: &[u8]) -> bool {
+  let (offset, attribute) =
+    find_attribute(message, StunAttributeType::MessageIntegrity as u16).unwrap();
+  let mut header = [0; STUN_HEADER_LEN];
+  header.copy_from_slice(&message[..STUN_HEADER_LEN]);
+  NetworkEndian::write_u16(
+    &mut header[2..4],
+    (offset + attribute.len() - STUN_HEADER_LEN) as u16,
+  );
+
+  let key = PKey::hmac(passwd).unwrap();
+  let mut signer = Signer::new(MessageDigest::sha1(), &key).unwrap();
+  signer.update(&header).unwrap();
+  signer.update(&message[STUN_HEADER_LEN..offset]).unwrap();
+  signer.sign_to_vec().unwrap() == attribute[4..]
+}
+
+// Check FINGERPRINT as described in RFC 8489 section 14.7, it must be the last attribute.
+fn verify_fingerprint(message: &[u8]) -> bool {
+  const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+  let (offset, attribute) = find_attribute(message, StunAttributeType::Fingerprint as u16).unwrap();
+  assert_eq!(offset + attribute.len(), message.len());
+  CRC.checksum(&message[..offset]) ^ STUN_CRC_XOR == NetworkEndian::read_u32(&attribute[4..8])
+}
+
+#[test]
+fn verifier_accepts_sample_request() {
+  assert!(verify_integrity(SAMPLE_REQUEST, PASSWORD));
+  assert!(!verify_integrity(SAMPLE_REQUEST, b"wrong password"));
+  assert!(verify_fingerprint(SAMPLE_REQUEST));
+
+  let mut corrupted = SAMPLE_REQUEST.to_vec();
+  corrupted[30] ^= 1;
+  assert!(!verify_integrity(&corrupted, PASSWORD));
+  assert!(!verify_fingerprint(&corrupted));
+}
+
+#[test]
+fn parse_sample_request() {
+  let request = parse_stun_binding_request(SAMPLE_REQUEST).unwrap();
+  assert_eq!(request.transaction_id, TRANSACTION_ID);
+  assert_eq!(request.server_user, "evtj");
+  assert_eq!(request.remote_user, "h6vY");
+  assert_eq!(request.integrity_offset, Some(76));
+  assert_eq!(request.fingerprint_offset, Some(100));
+  assert_eq!(
+    request.ice_role,
+    Some(IceRole::Controlled(0x932f_f9b1_5126_3b36))
+  );
+  assert!(!request.use_candidate);
+}
+
+#[test]
+fn verify_sample_request_integrity() {
+  let request = parse_stun_binding_request(SAMPLE_REQUEST).unwrap();
+  assert!(verify_stun_message_integrity(
+    SAMPLE_REQUEST,
+    &request,
+    PASSWORD
+  ));
+  assert!(!verify_stun_message_integrity(
+    SAMPLE_REQUEST,
+    &request,
+    b"wrong password"
+  ));
+
+  let mut corrupted = SAMPLE_REQUEST.to_vec();
+  corrupted[30] ^= 1;
+  let request = parse_stun_binding_request(&corrupted).unwrap();
+  assert!(!verify_stun_message_integrity(
+    &corrupted, &request, PASSWORD
+  ));
+}
+
+#[test]
+fn verify_sample_request_fingerprint() {
+  let request = parse_stun_binding_request(SAMPLE_REQUEST).unwrap();
+  assert!(verify_stun_fingerprint(SAMPLE_REQUEST, &request));
+
+  let mut corrupted = SAMPLE_REQUEST.to_vec();
+  corrupted[30] ^= 1;
+  let request = parse_stun_binding_request(&corrupted).unwrap();
+  assert!(!verify_stun_fingerprint(&corrupted, &request));
+
+  // Without FINGERPRINT, ending the message at MESSAGE-INTEGRITY.
+  let mut unfingerprinted = SAMPLE_REQUEST[..100].to_vec();
+  NetworkEndian::write_u16(&mut unfingerprinted[2..4], 80);
+  let request = parse_stun_binding_request(&unfingerprinted).unwrap();
+  assert_eq!(request.fingerprint_offset, None);
+  assert!(!verify_stun_fingerprint(&unfingerprinted, &request));
+}
+
+#[test]
+fn malformed_request_transaction_id() {
+  let mut no_colon = SAMPLE_REQUEST.to_vec();
+  no_colon[68] = b'_';
+  assert!(parse_stun_binding_request(&no_colon).is_none());
+  assert_eq!(
+    stun_binding_request_transaction_id(&no_colon),
+    Some(TRANSACTION_ID)
+  );
+
+  let mut bad_cookie = SAMPLE_REQUEST.to_vec();
+  bad_cookie[4] ^= 1;
+  assert_eq!(stun_binding_request_transaction_id(&bad_cookie), None);
+}
+
+#[test]
+fn parse_rejects_malformed_requests() {
+  assert!(parse_stun_binding_request(&SAMPLE_REQUEST[..STUN_HEADER_LEN - 1]).is_none());
+
+  let mut bad_cookie = SAMPLE_REQUEST.to_vec();
+  bad_cookie[4] ^= 1;
+  assert!(parse_stun_binding_request(&bad_cookie).is_none());
+
+  let mut not_request = SAMPLE_REQUEST.to_vec();
+  not_request[1] = 0x11;
+  assert!(parse_stun_binding_request(&not_request).is_none());
+
+  let mut truncated = SAMPLE_REQUEST.to_vec();
+  truncated.truncate(60);
+  assert!(parse_stun_binding_request(&truncated).is_none());
+
+  let mut no_colon = SAMPLE_REQUEST.to_vec();
+  no_colon[68] = b'_';
+  assert!(parse_stun_binding_request(&no_colon).is_none());
+
+  let mut short_tie_breaker = SAMPLE_REQUEST.to_vec();
+  short_tie_breaker[51] = 4;
+  assert!(parse_stun_binding_request(&short_tie_breaker).is_none());
+}
+
+fn check_success_response(
+  remote_addr: SocketAddr,
+  extras: &StunExtraAttributes,
+  expected_mapped_address: &[u8],
+) -> Vec<u8> {
+  let mut out = [0; 512];
+  let len =
+    write_stun_success_response(TRANSACTION_ID, remote_addr, PASSWORD, extras, &mut out).unwrap();
+  assert_eq!(len, stun_success_response_len(remote_addr, extras));
+  let response = &out[..len];
+
+  assert_eq!(
+    NetworkEndian::read_u16(&response[0..2]),
+    StunType::SuccessResponse as u16
+  );
+  assert_eq!(response[4..8], STUN_COOKIE);
+  assert_eq!(response[8..20], TRANSACTION_ID);
+
+  let (_, mapped_address) =
+    find_attribute(response, StunAttributeType::XorMappedAddress as u16).unwrap();
+  assert_eq!(mapped_address, expected_mapped_address);
+
+  assert!(verify_integrity(response, PASSWORD));
+  assert!(verify_fingerprint(response));
+  response.to_vec()
+}
+
+#[test]
+fn success_response_ipv4() {
+  let response = check_success_response(
+    sample_ipv4_addr(),
+    &StunExtraAttributes::default(),
+    SAMPLE_IPV4_MAPPED_ADDRESS,
+  );
+  assert_eq!(attributes(&response).len(), 3);
+}
+
+#[test]
+fn success_response_ipv6() {
+  check_success_response(
+    sample_ipv6_addr(),
+    &StunExtraAttributes::default(),
+    SAMPLE_IPV6_MAPPED_ADDRESS,
+  );
+}
+
+#[test]
+fn success_response_extras() {
+  let extras = StunExtraAttributes {
+    software: Some("test vector"),
+    alternate_server: None,
+    response_origin: Some(sample_ipv6_addr()),
+  };
+  let response = check_success_response(sample_ipv4_addr(), &extras, SAMPLE_IPV4_MAPPED_ADDRESS);
+
+  // SOFTWARE of RFC 5769 section 2.2, whose value is padded from 11 to 12 bytes.
+  let (offset, software) = find_attribute(&response, StunAttributeType::Software as u16).unwrap();
+  assert_eq!(software, b"\x80\x22\x00\x0btest vector");
+  assert_eq!(response[offset + software.len()], 0);
+
+  let (_, origin) = find_attribute(&response, StunAttributeType::ResponseOrigin as u16).unwrap();
+  assert_eq!(
+    origin[..8],
+    [0x80, 0x2b, 0x00, 0x14, 0x00, 0x02, 0x80, 0x55]
+  );
+  assert_eq!(
+    origin[8..],
+    Ipv6Addr::new(0x2001, 0xdb8, 0x1234, 0x5678, 0x11, 0x2233, 0x4455, 0x6677).octets()
+  );
+  assert!(find_attribute(&response, StunAttributeType::AlternateServer as u16).is_none());
+}
+
+#[test]
+fn software_is_truncated() {
+  let software = "é".repeat(200);
+  let extras = StunExtraAttributes {
+    software: Some(&software),
+    ..Default::default()
+  };
+  let response = check_success_response(sample_ipv4_addr(), &extras, SAMPLE_IPV4_MAPPED_ADDRESS);
+  let (_, attribute) = find_attribute(&response, StunAttributeType::Software as u16).unwrap();
+  assert_eq!(&attribute[4..], "é".repeat(127).as_bytes());
+}
+
+#[test]
+fn success_response_buffer_too_small() {
+  let mut out = [0; 40];
+  assert!(write_stun_success_response(
+    TRANSACTION_ID,
+    sample_ipv6_addr(),
+    PASSWORD,
+    &StunExtraAttributes::default(),
+    &mut out
+  )
+  .is_err());
+}
+
+fn check_error_response(
+  error_code: StunErrorCode,
+  passwd: Option<&[u8]>,
+  extras: &StunExtraAttributes,
+) -> Vec<u8> {
+  let mut out = [0; 512];
+  let len =
+    write_stun_error_response(TRANSACTION_ID, error_code, passwd, extras, &mut out).unwrap();
+  assert_eq!(
+    len,
+    stun_error_response_len(error_code, passwd.is_some(), extras)
+  );
+  let response = &out[..len];
+
+  assert_eq!(
+    NetworkEndian::read_u16(&response[0..2]),
+    StunType::ErrorResponse as u16
+  );
+  assert_eq!(response[4..8], STUN_COOKIE);
+  assert_eq!(response[8..20], TRANSACTION_ID);
+
+  let (_, error_attribute) = find_attribute(response, StunAttributeType::ErrorCode as u16).unwrap();
+  let code = error_code as u16;
+  assert_eq!(error_attribute[4..6], [0, 0]);
+  assert_eq!(error_attribute[6] as u16, code / 100);
+  assert_eq!(error_attribute[7] as u16, code % 100);
+  assert_eq!(&error_attribute[8..], error_code.reason_phrase().as_bytes());
+
+  match passwd {
+    Some(passwd) => assert!(verify_integrity(response, passwd)),
+    None => {
+      assert!(find_attribute(response, StunAttributeType::MessageIntegrity as u16).is_none())
+    }
+  }
+  assert!(verify_fingerprint(response));
+  response.to_vec()
+}
+
+#[test]
+fn error_response_bad_request() {
+  check_error_response(
+    StunErrorCode::BadRequest,
+    None,
+    &StunExtraAttributes::default(),
+  );
+}
+
+#[test]
+fn error_response_unauthorized() {
+  check_error_response(
+    StunErrorCode::Unauthorized,
+    None,
+    &StunExtraAttributes::default(),
+  );
+}
+
+#[test]
+fn error_response_role_conflict() {
+  check_error_response(
+    StunErrorCode::RoleConflict,
+    Some(PASSWORD),
+    &StunExtraAttributes::default(),
+  );
+}
+
+#[test]
+fn error_response_alternate_server() {
+  let extras = StunExtraAttributes {
+    software: Some("unreliablertc"),
+    alternate_server: Some(sample_ipv4_addr()),
+    response_origin: None,
+  };
+  for passwd in [None, Some(PASSWORD)] {
+    let response = check_error_response(StunErrorCode::Unauthorized, passwd, &extras);
+    let (_, alternate) =
+      find_attribute(&response, StunAttributeType::AlternateServer as u16).unwrap();
+    assert_eq!(
+      alternate,
+      [0x80, 0x23, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 192, 0, 2, 1]
+    );
+    let (_, software) = find_attribute(&response, StunAttributeType::Software as u16).unwrap();
+    assert_eq!(&software[4..], b"unreliablertc");
+  }
+}
+
+#[test]
+fn error_response_try_alternate() {
+  let extras = StunExtraAttributes {
+    alternate_server: Some(sample_ipv6_addr()),
+    ..Default::default()
+  };
+  let response = check_error_response(StunErrorCode::TryAlternate, Some(PASSWORD), &extras);
+  let (_, alternate) =
+    find_attribute(&response, StunAttributeType::AlternateServer as u16).unwrap();
+  assert_eq!(alternate[4..8], [0x00, 0x02, 0x80, 0x55]);
+}
+
+#[test]
+fn error_response_buffer_too_small() {
+  let mut out = [0; 40];
+  assert!(write_stun_error_response(
+    TRANSACTION_ID,
+    StunErrorCode::BadRequest,
+    None,
+    &StunExtraAttributes::default(),
+    &mut out
+  )
+  .is_err());
+}
@@ -0,0 +1,77 @@
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+// Apply the scheduling options of `ServerConfig` to the calling thread.
+pub fn tune_current_thread(
+  realtime_priority: Option<i32>,
+  cpu_affinity: Option<&[usize]>,
+) -> Result<(), IoError> {
+  if let Some(cpus) = cpu_affinity {
+    set_cpu_affinity(cpus)?;
+  }
+  if let Some(priority) = realtime_priority {
+    set_realtime_priority(priority)?;
+  }
+  Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> Result<(), IoError> {
+  unsafe {
+    let mut set: libc::cpu_set_t = std::mem::zeroed();
+    for &cpu in cpus {
+      if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(IoError::new(
+          IoErrorKind::InvalidInput,
+          format!("CPU index {} is out of range", cpu),
+        ));
+      }
+      libc::CPU_SET(cpu, &mut set);
+    }
+    // A pid of zero is the calling thread.
+    if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+      return Err(IoError::last_os_error());
+    }
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cpus: &[usize]) -> Result<(), IoError> {
+  Err(IoError::new(
+    IoErrorKind::Unsupported,
+    "setting CPU affinity is only supported on Linux",
+  ))
+}
+
+#[cfg(unix)]
+fn set_realtime_priority(priority: i32) -> Result<(), IoError> {
+  unsafe {
+    let min = libc::sched_get_priority_min(libc::SCHED_FIFO);
+    let max = libc::sched_get_priority_max(libc::SCHED_FIFO);
+    if priority < min || priority > max {
+      return Err(IoError::new(
+        IoErrorKind::InvalidInput,
+        format!(
+          "realtime priority {} is outside of the supported range {}..={}",
+          priority, min, max
+        ),
+      ));
+    }
+
+    let param = libc::sched_param {
+      sched_priority: priority,
+    };
+    match libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) {
+      0 => Ok(()),
+      err => Err(IoError::from_raw_os_error(err)),
+    }
+  }
+}
+
+#[cfg(not(unix))]
+fn set_realtime_priority(_priority: i32) -> Result<(), IoError> {
+  Err(IoError::new(
+    IoErrorKind::Unsupported,
+    "setting a realtime priority is only supported on Unix",
+  ))
+}
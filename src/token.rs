@@ -0,0 +1,103 @@
+use std::{
+  fmt::{self, Write as _},
+  sync::Arc,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use rand::Rng;
+
+use crate::util::{rand_string, Secret};
+
+// A token ufrag is the expiry as 8 hex digits of UNIX seconds, an 8 character nonce and a 16 hex
+// digit truncated HMAC of both.
+const EXPIRY_LEN: usize = 8;
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+const TOKEN_LEN: usize = EXPIRY_LEN + NONCE_LEN + TAG_LEN;
+// 32 hex digits, comfortably above the 22 characters ICE requires of a password.
+const PASSWD_BYTES: usize = 16;
+
+/// A secret shared by every `SessionEndpoint` minting session tokens and every `Server`
+/// accepting them, set with `SessionEndpointConfig::stateless_tokens` and
+/// `ServerConfig::stun_token_key`.
+///
+/// The key should be at least 32 random bytes, anybody holding it can make a server accept STUN
+/// from any address.
+#[derive(Clone)]
+pub struct TokenKey(Arc<Secret<Vec<u8>>>);
+
+impl TokenKey {
+  pub fn new(secret: impl Into<Vec<u8>>) -> TokenKey {
+    TokenKey(Arc::new(Secret::new(secret.into())))
+  }
+
+  // Mint a session ufrag valid for `ttl`, returning it along with its ICE password.
+  pub(crate) fn mint<R: Rng>(&self, rng: &mut R, ttl: Duration) -> (String, Secret<String>) {
+    let expiry = (unix_now() + ttl.as_secs()).min(u32::MAX as u64) as u32;
+    let mut ufrag = format!("{:08x}", expiry);
+    ufrag.push_str(&rand_string(rng, NONCE_LEN));
+    let tag = self.tag(&ufrag);
+    ufrag.push_str(&tag);
+    let passwd = self.passwd(&ufrag);
+    (ufrag, passwd)
+  }
+
+  // The ICE password for a session ufrag, if it was minted with this key and has not expired.
+  pub(crate) fn verify(&self, ufrag: &str) -> Option<Secret<String>> {
+    if ufrag.len() != TOKEN_LEN || !ufrag.is_ascii() {
+      return None;
+    }
+    let (signed, tag) = ufrag.split_at(EXPIRY_LEN + NONCE_LEN);
+    if !memcmp::eq(self.tag(signed).as_bytes(), tag.as_bytes()) {
+      return None;
+    }
+    let expiry = u32::from_str_radix(&signed[..EXPIRY_LEN], 16).ok()?;
+    if unix_now() > expiry as u64 {
+      return None;
+    }
+    Some(self.passwd(ufrag))
+  }
+
+  fn tag(&self, signed: &str) -> String {
+    let mut tag = to_hex(&self.hmac(b"ufrag", signed));
+    tag.truncate(TAG_LEN);
+    tag
+  }
+
+  fn passwd(&self, ufrag: &str) -> Secret<String> {
+    Secret::new(to_hex(&self.hmac(b"passwd", ufrag)[..PASSWD_BYTES]))
+  }
+
+  fn hmac(&self, purpose: &[u8], data: &str) -> Secret<Vec<u8>> {
+    let sign = || -> Result<Vec<u8>, openssl::error::ErrorStack> {
+      let key = PKey::hmac(&self.0[..])?;
+      let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+      signer.update(purpose)?;
+      signer.update(data.as_bytes())?;
+      signer.sign_to_vec()
+    };
+    Secret::new(sign().expect("HMAC-SHA256 is always available"))
+  }
+}
+
+impl fmt::Debug for TokenKey {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    f.write_str("TokenKey(..)")
+  }
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  let mut hex = String::with_capacity(bytes.len() * 2);
+  for b in bytes {
+    write!(hex, "{:02x}", b).unwrap();
+  }
+  hex
+}
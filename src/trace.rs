@@ -0,0 +1,156 @@
+use std::{
+  net::SocketAddr,
+  time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Protocol layer of a `TraceEntry`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceLayer {
+  Stun,
+  Dtls,
+  Sctp,
+  DataChannel,
+  /// Decisions of the server itself, such as timing a client out.
+  Server,
+}
+
+/// Which way a `TraceEntry` went, as seen from the server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceDirection {
+  /// Sent by the client to the server.
+  Received,
+  /// Sent by the server to the client.
+  Sent,
+  /// Happened on the server without any packet.
+  Local,
+}
+
+/// A single step in the life of a connection.
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceEntry {
+  /// Time since the connection's first STUN binding request, in microseconds.
+  pub at_us: u64,
+  pub layer: TraceLayer,
+  pub direction: TraceDirection,
+  /// What happened, such as `"INIT"` or `"ClientHello"`.
+  pub event: String,
+}
+
+/// Timeline of a single connection, from its first STUN binding request to its removal, returned
+/// by `Server::connection_trace`.
+///
+/// This is meant for tooling that turns it into a sequence diagram, for example when attaching
+/// it to a support ticket.  Only the first 256 steps are kept, plus the connection's removal, and
+/// any others are counted in `dropped`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionTrace {
+  pub remote_addr: SocketAddr,
+  /// Wall clock time of the first entry, in milliseconds since the Unix epoch.
+  pub started_unix_ms: u64,
+  pub entries: Vec<TraceEntry>,
+  pub dropped: u64,
+}
+
+impl ConnectionTrace {
+  /// The trace as a JSON object with the fields of this struct.
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).expect("connection trace is always serializable")
+  }
+}
+
+const MAX_TRACE_ENTRIES: usize = 256;
+
+// The trace being recorded for a connection.
+pub struct TraceLog {
+  started: Instant,
+  started_unix_ms: u64,
+  entries: Vec<TraceEntry>,
+  dropped: u64,
+}
+
+impl TraceLog {
+  pub fn new() -> TraceLog {
+    TraceLog {
+      started: Instant::now(),
+      started_unix_ms: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64,
+      entries: Vec::new(),
+      dropped: 0,
+    }
+  }
+
+  pub fn record(&mut self, layer: TraceLayer, direction: TraceDirection, event: impl Into<String>) {
+    if self.entries.len() >= MAX_TRACE_ENTRIES {
+      self.dropped += 1;
+      return;
+    }
+    self.push(layer, direction, event.into());
+  }
+
+  // Record the end of the connection, even if the trace is full.
+  pub fn finish(&mut self, event: impl Into<String>) {
+    self.push(TraceLayer::Server, TraceDirection::Local, event.into());
+  }
+
+  pub fn export(&self, remote_addr: SocketAddr) -> ConnectionTrace {
+    ConnectionTrace {
+      remote_addr,
+      started_unix_ms: self.started_unix_ms,
+      entries: self.entries.clone(),
+      dropped: self.dropped,
+    }
+  }
+
+  fn push(&mut self, layer: TraceLayer, direction: TraceDirection, event: String) {
+    self.entries.push(TraceEntry {
+      at_us: self.started.elapsed().as_micros() as u64,
+      layer,
+      direction,
+      event,
+    });
+  }
+}
+
+// Name the DTLS records in a datagram, such as `"ServerHello, Certificate, ServerHelloDone"`.
+// Handshake messages after ChangeCipherSpec are encrypted, and are assumed to be Finished.
+pub fn describe_dtls_datagram(mut datagram: &[u8]) -> String {
+  const HEADER_LEN: usize = 13;
+
+  let mut names = Vec::new();
+  while datagram.len() >= HEADER_LEN {
+    let content_type = datagram[0];
+    let epoch = u16::from_be_bytes([datagram[3], datagram[4]]);
+    let len = usize::from(u16::from_be_bytes([datagram[11], datagram[12]]));
+    let body = &datagram[HEADER_LEN..(HEADER_LEN + len).min(datagram.len())];
+    names.push(match (content_type, epoch) {
+      (20, _) => "ChangeCipherSpec",
+      (21, 0) if body.len() >= 2 && body[1] == 0 => "Alert(close_notify)",
+      (21, _) => "Alert",
+      (22, 0) => match body.first() {
+        Some(0) => "HelloRequest",
+        Some(1) => "ClientHello",
+        Some(2) => "ServerHello",
+        Some(3) => "HelloVerifyRequest",
+        Some(4) => "NewSessionTicket",
+        Some(11) => "Certificate",
+        Some(12) => "ServerKeyExchange",
+        Some(13) => "CertificateRequest",
+        Some(14) => "ServerHelloDone",
+        Some(15) => "CertificateVerify",
+        Some(16) => "ClientKeyExchange",
+        _ => "Handshake",
+      },
+      (22, _) => "Finished",
+      (23, _) => "ApplicationData",
+      _ => "Unknown",
+    });
+    datagram = &datagram[(HEADER_LEN + len).min(datagram.len())..];
+  }
+  names.join(", ")
+}
@@ -11,3 +11,27 @@ pub fn rand_string<R: Rng>(rng: &mut R, size: usize) -> String {
   }
   s
 }
+
+// A credential which is wiped from memory when dropped under the `paranoid` feature.
+#[cfg(feature = "paranoid")]
+pub type Secret<T> = zeroize::Zeroizing<T>;
+
+#[cfg(not(feature = "paranoid"))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Secret<T>(T);
+
+#[cfg(not(feature = "paranoid"))]
+impl<T> Secret<T> {
+  pub fn new(value: T) -> Secret<T> {
+    Secret(value)
+  }
+}
+
+#[cfg(not(feature = "paranoid"))]
+impl<T> std::ops::Deref for Secret<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
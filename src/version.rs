@@ -0,0 +1,23 @@
+/// Close code sent to a client whose first message names an application protocol version not in
+/// `ServerConfig::protocol_versions`, just before it is disconnected.
+///
+/// It is in the range WebSocket reserves for applications, so browser code can handle it the same
+/// way as a WebSocket close code.
+pub const VERSION_MISMATCH_CLOSE_CODE: u16 = 4001;
+
+// Find the version a client's first message names, as an index into `versions`.  Either message
+// type is accepted, as long as the payload is exactly the version string.
+pub fn negotiate_version(versions: &[String], message: &[u8]) -> Option<usize> {
+  versions.iter().position(|v| v.as_bytes() == message)
+}
+
+// The text message telling a client why it is being disconnected, for example
+// `{"close":4001,"reason":"unsupported protocol version","supported":["v2","v3"]}`.
+pub fn version_mismatch_message(versions: &[String]) -> String {
+  serde_json::json!({
+    "close": VERSION_MISMATCH_CLOSE_CODE,
+    "reason": "unsupported protocol version",
+    "supported": versions,
+  })
+  .to_string()
+}
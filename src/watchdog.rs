@@ -0,0 +1,134 @@
+use std::{
+  fmt,
+  io::Error as IoError,
+  sync::{
+    atomic::{AtomicU64, AtomicU8, Ordering},
+    Arc, Weak,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use crate::event::{ServerEvent, Subscribers};
+
+/// What a `Server` was doing when its watchdog found it stalled, reported with
+/// `ServerEvent::DriverStalled`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DriverPhase {
+  /// Waiting for packets, sessions or timers, which is never a stall.
+  Idle,
+  /// Registering a session from a `SessionEndpoint`.
+  AcceptSession,
+  /// Handling a received packet, which includes running middleware and the FFI event callback.
+  ReceivePacket,
+  /// The once a second work of timing out clients and sessions and sending heartbeats.
+  PeriodicTasks,
+  /// Sending messages scheduled with `Server::send_at`.
+  ScheduledSends,
+  /// Updating public addresses after a DNS lookup.
+  DnsUpdate,
+  /// Answering an inspector request.
+  Inspect,
+  /// Returned from `Server::recv` to the application, which has not called it again.
+  Application,
+}
+
+impl DriverPhase {
+  const ALL: [DriverPhase; 8] = [
+    DriverPhase::Idle,
+    DriverPhase::AcceptSession,
+    DriverPhase::ReceivePacket,
+    DriverPhase::PeriodicTasks,
+    DriverPhase::ScheduledSends,
+    DriverPhase::DnsUpdate,
+    DriverPhase::Inspect,
+    DriverPhase::Application,
+  ];
+}
+
+impl fmt::Display for DriverPhase {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      DriverPhase::Idle => "idle",
+      DriverPhase::AcceptSession => "accepting a session",
+      DriverPhase::ReceivePacket => "handling a packet",
+      DriverPhase::PeriodicTasks => "running periodic tasks",
+      DriverPhase::ScheduledSends => "sending scheduled messages",
+      DriverPhase::DnsUpdate => "updating public addresses",
+      DriverPhase::Inspect => "answering the inspector",
+      DriverPhase::Application => "in the application",
+    };
+    f.write_str(name)
+  }
+}
+
+// The phase the server is in, written by the server and read by the watchdog thread.
+struct Heartbeat {
+  started: Instant,
+  phase: AtomicU8,
+  // Nanoseconds from `started` to when the current phase was entered.
+  entered_at: AtomicU64,
+  // Bumped on every phase change, so that each stall is only reported once.
+  generation: AtomicU64,
+}
+
+// The server's end of the watchdog, whose thread stops once this is dropped.
+pub struct Watchdog {
+  heartbeat: Arc<Heartbeat>,
+}
+
+impl Watchdog {
+  // Start a thread reporting to `subscribers` whenever the server stays in a phase other than
+  // `DriverPhase::Idle` for longer than `timeout`.
+  pub fn spawn(timeout: Duration, subscribers: Subscribers) -> Result<Watchdog, IoError> {
+    let heartbeat = Arc::new(Heartbeat {
+      started: Instant::now(),
+      phase: AtomicU8::new(DriverPhase::Idle as u8),
+      entered_at: AtomicU64::new(0),
+      generation: AtomicU64::new(0),
+    });
+    let weak = Arc::downgrade(&heartbeat);
+    let check_interval = (timeout / 4).max(Duration::from_millis(1));
+    thread::Builder::new()
+      .name("unreliablertc-watchdog".to_owned())
+      .spawn(move || watch(weak, timeout, check_interval, subscribers))?;
+    Ok(Watchdog { heartbeat })
+  }
+
+  pub fn enter(&self, phase: DriverPhase) {
+    let heartbeat = &*self.heartbeat;
+    let elapsed = heartbeat.started.elapsed().as_nanos() as u64;
+    heartbeat.entered_at.store(elapsed, Ordering::Relaxed);
+    heartbeat.phase.store(phase as u8, Ordering::Relaxed);
+    heartbeat.generation.fetch_add(1, Ordering::Release);
+  }
+}
+
+fn watch(
+  heartbeat: Weak<Heartbeat>,
+  timeout: Duration,
+  check_interval: Duration,
+  subscribers: Subscribers,
+) {
+  let mut reported = None;
+  loop {
+    thread::sleep(check_interval);
+    let heartbeat = match heartbeat.upgrade() {
+      Some(heartbeat) => heartbeat,
+      None => return,
+    };
+
+    let generation = heartbeat.generation.load(Ordering::Acquire);
+    let phase = DriverPhase::ALL[heartbeat.phase.load(Ordering::Relaxed) as usize];
+    let entered_at = Duration::from_nanos(heartbeat.entered_at.load(Ordering::Relaxed));
+    if phase == DriverPhase::Idle || reported == Some(generation) {
+      continue;
+    }
+    let stalled_for = heartbeat.started.elapsed().saturating_sub(entered_at);
+    // A phase change between the loads above is caught by the generation having moved on.
+    if stalled_for >= timeout && heartbeat.generation.load(Ordering::Acquire) == generation {
+      reported = Some(generation);
+      subscribers.publish(&ServerEvent::DriverStalled { phase, stalled_for });
+    }
+  }
+}
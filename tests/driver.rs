@@ -0,0 +1,87 @@
+use std::{future::Future, net::SocketAddr};
+
+use futures::{
+  future::{select, Either},
+  stream, SinkExt, StreamExt,
+};
+use unreliablertc::{Bytes, LoopbackClient, MessageType, SendError, Server, ServerHandle};
+
+// A server on the loopback interface with a client connected to it, split with `Server::run`.
+async fn connected() -> (
+  ServerHandle,
+  impl Future<Output = ()> + Send,
+  LoopbackClient,
+) {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+  let client = server.connect_loopback().await.unwrap();
+  let (handle, driver) = server.run();
+  (handle, driver, client)
+}
+
+// Run `future` while `driver` drives the server.
+async fn driven<T>(driver: impl Future<Output = ()>, future: impl Future<Output = T>) -> T {
+  match select(Box::pin(driver), Box::pin(future)).await {
+    Either::Left(_) => panic!("driver stopped"),
+    Either::Right((output, _)) => output,
+  }
+}
+
+#[test]
+fn message_stream() {
+  futures::executor::block_on(async {
+    let (handle, driver, mut client) = connected().await;
+    let client_addr = client.local_addr().unwrap();
+    let mut messages = handle.stream();
+    driven(driver, async {
+      client.send(b"hello", MessageType::Text).await.unwrap();
+      client.send(&[1, 2, 3], MessageType::Binary).await.unwrap();
+      let (message, remote_addr) = messages.next().await.unwrap();
+      assert_eq!(message.message(), b"hello");
+      assert_eq!(message.message_type, MessageType::Text);
+      assert_eq!(remote_addr, client_addr);
+      let (message, remote_addr) = messages.next().await.unwrap();
+      assert_eq!(message.into_vec(), [1, 2, 3]);
+      assert_eq!(remote_addr, client_addr);
+
+      handle.shutdown().unwrap();
+    })
+    .await;
+  });
+}
+
+#[test]
+fn message_sink() {
+  futures::executor::block_on(async {
+    let (handle, driver, mut client) = connected().await;
+    let client_addr = client.local_addr().unwrap();
+    let mut sink = handle.sink();
+    driven(driver, async {
+      // More messages than may be in flight at once.
+      let messages =
+        (0..100u8).map(|n| Ok((Bytes::from(vec![n]), MessageType::Binary, client_addr)));
+      sink.send_all(&mut stream::iter(messages)).await.unwrap();
+      for n in 0..100u8 {
+        assert_eq!(client.recv().await.unwrap(), (vec![n], MessageType::Binary));
+      }
+
+      // A failed send is reported, and the sink carries on.
+      let unknown_addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+      assert!(matches!(
+        sink
+          .send((Bytes::from_static(b"lost"), MessageType::Text, unknown_addr))
+          .await,
+        Err(SendError::ClientNotConnected)
+      ));
+      sink
+        .send((Bytes::from_static(b"after"), MessageType::Text, client_addr))
+        .await
+        .unwrap();
+      assert_eq!(
+        client.recv().await.unwrap(),
+        (b"after".to_vec(), MessageType::Text)
+      );
+    })
+    .await;
+  });
+}
@@ -0,0 +1,135 @@
+use std::net::SocketAddr;
+
+use unreliablertc::{
+  DuplicateUfragPolicy, Server, ServerEvent, SessionEndpoint, SessionEndpointConfig, SessionError,
+};
+
+fn offer(ufrag: &str, mid: &str) -> String {
+  format!(
+    "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n\
+     m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n\
+     a=ice-ufrag:{}\r\na=ice-pwd:0123456789abcdefghijklmn\r\na=mid:{}\r\na=sctp-port:5000\r\n",
+    ufrag, mid
+  )
+}
+
+fn server() -> Server {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  Server::new(listen_addr, listen_addr, None).unwrap()
+}
+
+fn endpoint(server: &Server, policy: DuplicateUfragPolicy) -> SessionEndpoint {
+  server
+    .session_endpoint_with_config(SessionEndpointConfig::default().duplicate_ufrag_policy(policy))
+}
+
+// Ids of the server's sessions, once it has taken in those answered so far.
+fn session_ids(server: &mut Server) -> Vec<String> {
+  server.poll_io().unwrap();
+  let mut ids: Vec<String> = server.sessions().into_iter().map(|s| s.id).collect();
+  ids.sort();
+  ids
+}
+
+fn replaced_sessions(server: &mut Server) -> Vec<(String, String)> {
+  let mut replaced = Vec::new();
+  while let Some(event) = server.poll_event() {
+    if let ServerEvent::SessionReplaced {
+      old_session_id,
+      new_session_id,
+    } = event
+    {
+      replaced.push((old_session_id, new_session_id));
+    }
+  }
+  replaced
+}
+
+#[test]
+fn allow_keeps_both_sessions() {
+  let mut server = server();
+  let mut endpoint = endpoint(&server, DuplicateUfragPolicy::Allow);
+  let first = endpoint.session_request(&offer("dupe", "0")).unwrap();
+  let second = endpoint.session_request(&offer("dupe", "1")).unwrap();
+  assert_ne!(first, second);
+  assert_eq!(session_ids(&mut server).len(), 2);
+  assert!(replaced_sessions(&mut server).is_empty());
+}
+
+#[test]
+fn reject_fails_the_duplicate() {
+  let mut server = server();
+  let mut endpoint = endpoint(&server, DuplicateUfragPolicy::Reject);
+  endpoint.session_request(&offer("dupe", "0")).unwrap();
+  let sessions = session_ids(&mut server);
+
+  // Identical offers are duplicates too, unless the endpoint has idempotent offers.
+  for mid in ["0", "1"] {
+    assert!(matches!(
+      endpoint.session_request(&offer("dupe", mid)),
+      Err(SessionError::DuplicateUfrag)
+    ));
+  }
+  // Clones of the endpoint remember the same offers.
+  assert!(matches!(
+    endpoint.clone().session_request(&offer("dupe", "1")),
+    Err(SessionError::DuplicateUfrag)
+  ));
+  assert_eq!(session_ids(&mut server), sessions);
+
+  endpoint.session_request(&offer("other", "0")).unwrap();
+  assert_eq!(session_ids(&mut server).len(), 2);
+}
+
+#[test]
+fn reject_with_idempotent_offers_answers_retries() {
+  let mut server = server();
+  let mut endpoint = server.session_endpoint_with_config(
+    SessionEndpointConfig::default()
+      .duplicate_ufrag_policy(DuplicateUfragPolicy::Reject)
+      .idempotent_offers(true),
+  );
+  let first = endpoint.session_request(&offer("dupe", "0")).unwrap();
+  assert_eq!(
+    endpoint.session_request(&offer("dupe", "0")).unwrap(),
+    first
+  );
+  assert!(matches!(
+    endpoint.session_request(&offer("dupe", "1")),
+    Err(SessionError::DuplicateUfrag)
+  ));
+  assert_eq!(session_ids(&mut server).len(), 1);
+}
+
+#[test]
+fn replace_drops_the_earlier_session() {
+  let mut server = server();
+  let mut endpoint = endpoint(&server, DuplicateUfragPolicy::Replace);
+  endpoint.session_request(&offer("dupe", "0")).unwrap();
+  endpoint.session_request(&offer("other", "0")).unwrap();
+  let before = session_ids(&mut server);
+  assert_eq!(before.len(), 2);
+  assert!(replaced_sessions(&mut server).is_empty());
+
+  endpoint.session_request(&offer("dupe", "1")).unwrap();
+  let after = session_ids(&mut server);
+  assert_eq!(after.len(), 2);
+  let replaced = replaced_sessions(&mut server);
+  assert_eq!(replaced.len(), 1);
+  let (old_session_id, new_session_id) = &replaced[0];
+  assert!(before.contains(old_session_id) && !after.contains(old_session_id));
+  assert!(after.contains(new_session_id) && !before.contains(new_session_id));
+}
+
+#[test]
+fn keep_first_answers_with_the_first_answer() {
+  let mut server = server();
+  let mut endpoint = endpoint(&server, DuplicateUfragPolicy::KeepFirst);
+  let first = endpoint.session_request(&offer("dupe", "0")).unwrap();
+  assert_eq!(
+    endpoint.session_request(&offer("dupe", "1")).unwrap(),
+    first
+  );
+  assert_eq!(session_ids(&mut server).len(), 1);
+  assert!(replaced_sessions(&mut server).is_empty());
+}
@@ -0,0 +1,33 @@
+use std::net::SocketAddr;
+
+use futures::FutureExt;
+use unreliablertc::{Server, SessionEndpoint, SessionEndpointConfig};
+
+#[test]
+fn endpoint_detaches_when_server_is_dropped() {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  let server = Server::new(listen_addr, listen_addr, None).unwrap();
+  let endpoint = server.session_endpoint();
+  let clone = endpoint.clone();
+  let closed = endpoint.closed();
+  assert!(endpoint.is_attached());
+  assert!(endpoint.closed().now_or_never().is_none());
+
+  drop(server);
+  assert!(!endpoint.is_attached());
+  assert!(!clone.is_attached());
+  // Futures made before the server was dropped resolve as well as those made after.
+  futures::executor::block_on(closed);
+  assert!(clone.closed().now_or_never().is_some());
+}
+
+#[test]
+fn standalone_endpoint_is_detached() {
+  let endpoint = SessionEndpoint::standalone(
+    vec!["127.0.0.1:5000".parse().unwrap()],
+    "sha-256 00",
+    SessionEndpointConfig::default(),
+  );
+  assert!(!endpoint.is_attached());
+  assert!(endpoint.closed().now_or_never().is_some());
+}
@@ -0,0 +1,434 @@
+use std::{
+  future::Future,
+  net::SocketAddr,
+  time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures::future::{select, Either};
+use unreliablertc::{
+  Delivery, LoopbackClient, MessageType, NegotiatedChannel, OverflowPolicy, SendError, Server,
+  ServerConfig, ServerEvent,
+};
+
+// Run `future` while driving `server`, dropping any message the server receives meanwhile.
+async fn drive<T>(server: &mut Server, future: impl Future<Output = T>) -> T {
+  let recv = Box::pin(async {
+    loop {
+      let _ = server.recv().await;
+    }
+  });
+  match select(recv, Box::pin(future)).await {
+    Either::Left(_) => unreachable!(),
+    Either::Right((output, _)) => output,
+  }
+}
+
+#[test]
+fn loopback_handshake_and_echo() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    assert!(server.is_connected(&client_addr));
+
+    client.send(b"hello", MessageType::Text).await.unwrap();
+    let (message, message_type, remote_addr) = {
+      let received = server.recv().await.unwrap();
+      (
+        received.message.to_vec(),
+        received.message_type,
+        received.remote_addr,
+      )
+    };
+    assert_eq!(message, b"hello");
+    assert_eq!(message_type, MessageType::Text);
+    assert_eq!(remote_addr, client_addr);
+
+    server
+      .send(&[0, 1, 2, 3], MessageType::Binary, &remote_addr)
+      .await
+      .unwrap();
+    let (reply, reply_type) = client.recv().await.unwrap();
+    assert_eq!(reply, [0, 1, 2, 3]);
+    assert_eq!(reply_type, MessageType::Binary);
+  });
+}
+
+#[test]
+fn loopback_multiple_channels() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    let chat = drive(
+      &mut server,
+      client.open_channel("chat", Delivery::ReliableOrdered),
+    )
+    .await
+    .unwrap();
+    let mut opened = Vec::new();
+    while let Some(event) = server.poll_event() {
+      if let ServerEvent::ChannelOpened {
+        channel_id,
+        label,
+        delivery,
+        ..
+      } = event
+      {
+        opened.push((channel_id, label, delivery));
+      }
+    }
+    assert_eq!(
+      opened,
+      [
+        (0, "loopback".to_owned(), Delivery::Unreliable),
+        (chat, "chat".to_owned(), Delivery::ReliableOrdered),
+      ]
+    );
+
+    client
+      .send_on(chat, b"hello", MessageType::Text)
+      .await
+      .unwrap();
+    let received = server.recv().await.unwrap();
+    assert_eq!(received.channel_id, chat);
+    assert_eq!(&received.message[..], b"hello");
+
+    server
+      .send_on(b"welcome", MessageType::Text, chat, &client_addr)
+      .await
+      .unwrap();
+    let (reply, _, channel_id) = client.recv_with_channel().await.unwrap();
+    assert_eq!((&reply[..], channel_id), (&b"welcome"[..], chat));
+
+    client.close_channel(chat).await.unwrap();
+    drive(&mut server, Timer::after(Duration::from_millis(200))).await;
+    let closed: Vec<u16> = std::iter::from_fn(|| server.poll_event())
+      .filter_map(|event| match event {
+        ServerEvent::ChannelClosed { channel_id, .. } => Some(channel_id),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(closed, [chat]);
+    assert!(matches!(
+      server
+        .send_on(b"gone", MessageType::Text, chat, &client_addr)
+        .await,
+      Err(SendError::UnknownChannel(id)) if id == chat
+    ));
+  });
+}
+
+#[test]
+fn loopback_server_opened_channel() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // The loopback client acknowledges the channel while it is receiving.
+    let client_recv = Box::pin(async {
+      loop {
+        let _ = client.recv().await;
+      }
+    });
+    let open = Box::pin(server.open_channel(&client_addr, "state", Delivery::Unreliable));
+    let channel_id = match select(client_recv, open).await {
+      Either::Left(_) => unreachable!(),
+      Either::Right((channel_id, _)) => channel_id.unwrap(),
+    };
+    assert_eq!(channel_id % 2, 1);
+    assert!(std::iter::from_fn(|| server.poll_event()).any(|event| matches!(
+      event,
+      ServerEvent::ChannelOpened { channel_id: id, label, .. } if id == channel_id && label == "state"
+    )));
+
+    server
+      .send_on(b"snapshot", MessageType::Binary, channel_id, &client_addr)
+      .await
+      .unwrap();
+    let (message, _, received_on) = client.recv_with_channel().await.unwrap();
+    assert_eq!((&message[..], received_on), (&b"snapshot"[..], channel_id));
+  });
+}
+
+#[test]
+fn loopback_negotiated_channel() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      negotiated_channels: vec![NegotiatedChannel {
+        id: 8,
+        label: "input".to_owned(),
+        delivery: Delivery::ReliableOrdered,
+      }],
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    assert!(
+      std::iter::from_fn(|| server.poll_event()).any(|event| matches!(
+        event,
+        ServerEvent::ChannelOpened {
+          remote_addr,
+          channel_id: 8,
+          label,
+          delivery: Delivery::ReliableOrdered,
+        } if remote_addr == client_addr && label == "input"
+      ))
+    );
+
+    // Neither side sends a DATA_CHANNEL_OPEN before using the channel.
+    client.send_on(8, b"jump", MessageType::Text).await.unwrap();
+    let received = server.recv().await.unwrap();
+    assert_eq!(
+      (&received.message[..], received.channel_id),
+      (&b"jump"[..], 8)
+    );
+
+    server
+      .send_on(b"ok", MessageType::Text, 8, &client_addr)
+      .await
+      .unwrap();
+    let (reply, _, channel_id) = client.recv_with_channel().await.unwrap();
+    assert_eq!((&reply[..], channel_id), (&b"ok"[..], 8));
+  });
+}
+
+#[test]
+fn negotiated_channels_are_checked() {
+  let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+  let channel = |id| NegotiatedChannel {
+    id,
+    label: "input".to_owned(),
+    delivery: Delivery::ReliableOrdered,
+  };
+  for channels in [vec![channel(8), channel(8)], vec![channel(u16::MAX)]] {
+    let config = ServerConfig {
+      negotiated_channels: channels,
+      ..Default::default()
+    };
+    let err = Server::with_config([(listen_addr, listen_addr)], config, None)
+      .err()
+      .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+  }
+}
+
+#[test]
+fn loopback_send_at_needs_an_established_client() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut server = Server::new(listen_addr, listen_addr, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    server
+      .send_at(b"due", MessageType::Text, &client_addr, Instant::now())
+      .unwrap();
+    let (message, _) = drive(&mut server, client.recv()).await.unwrap();
+    assert_eq!(message, b"due");
+
+    // A client which is shutting down is still known, but no longer takes messages.
+    server.disconnect(&client_addr).await.unwrap();
+    assert!(server.client_state(&client_addr).is_some());
+    assert!(matches!(
+      server.send_at(b"late", MessageType::Text, &client_addr, Instant::now()),
+      Err(SendError::ClientNotConnected)
+    ));
+  });
+}
+
+// Sends `message` from `client` and returns the address the server received it from.
+async fn received_from(
+  server: &mut Server,
+  client: &mut LoopbackClient,
+  message: &[u8],
+) -> SocketAddr {
+  client.send(message, MessageType::Binary).await.unwrap();
+  let received = server.recv().await.unwrap();
+  assert_eq!(&received.message[..], message);
+  received.remote_addr
+}
+
+fn rebinds(server: &mut Server) -> Vec<(SocketAddr, SocketAddr)> {
+  std::iter::from_fn(|| server.poll_event())
+    .filter_map(|event| match event {
+      ServerEvent::ClientRebound { old_addr, new_addr } => Some((old_addr, new_addr)),
+      _ => None,
+    })
+    .collect()
+}
+
+#[test]
+fn loopback_checks_from_another_port() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      reauthenticate_on_rebind: false,
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // A check of another pair is answered, without moving the client or adding another.
+    let other_addr = drive(&mut server, client.check_from(listen_addr, false))
+      .await
+      .unwrap();
+    assert_ne!(other_addr, client_addr);
+    assert!(server.client_state(&other_addr).is_none());
+    assert!(server.is_connected(&client_addr));
+    assert_eq!(
+      received_from(&mut server, &mut client, b"a").await,
+      client_addr
+    );
+    assert!(rebinds(&mut server).is_empty());
+
+    // Nominating it moves the client there.
+    let nominated_addr = drive(&mut server, client.check_from(listen_addr, true))
+      .await
+      .unwrap();
+    assert_eq!(rebinds(&mut server), [(client_addr, nominated_addr)]);
+    assert!(server.client_state(&client_addr).is_none());
+    assert_eq!(
+      received_from(&mut server, &mut client, b"b").await,
+      nominated_addr
+    );
+  });
+}
+
+#[test]
+fn loopback_rebind_reauthenticates() {
+  assert_eq!(
+    ServerConfig::default().reauthenticate_on_rebind,
+    cfg!(feature = "paranoid")
+  );
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      reauthenticate_on_rebind: true,
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // Checks of another pair are still answered, but nominating it is refused with 401.
+    drive(&mut server, client.check_from(listen_addr, false))
+      .await
+      .unwrap();
+    let err = drive(&mut server, client.check_from(listen_addr, true))
+      .await
+      .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(rebinds(&mut server).is_empty());
+    assert!(server.is_connected(&client_addr));
+    assert_eq!(
+      received_from(&mut server, &mut client, b"a").await,
+      client_addr
+    );
+  });
+}
+
+#[test]
+fn loopback_checks_from_another_ip() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      migrate_across_ips: true,
+      reauthenticate_on_rebind: false,
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    let other_ip: SocketAddr = "127.0.0.2:0".parse().unwrap();
+
+    let other_addr = drive(&mut server, client.check_from(other_ip, false))
+      .await
+      .unwrap();
+    assert!(server.client_state(&other_addr).is_none());
+    assert_eq!(
+      received_from(&mut server, &mut client, b"a").await,
+      client_addr
+    );
+    assert!(rebinds(&mut server).is_empty());
+
+    let nominated_addr = drive(&mut server, client.check_from(other_ip, true))
+      .await
+      .unwrap();
+    assert_eq!(rebinds(&mut server), [(client_addr, nominated_addr)]);
+    assert_eq!(
+      received_from(&mut server, &mut client, b"b").await,
+      nominated_addr
+    );
+  });
+}
+
+#[test]
+fn loopback_fragmented_messages() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      max_reassembled_len: Some(64 * 1024),
+      max_fragment_len: Some(4096),
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    let message: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+
+    client.send(&message, MessageType::Binary).await.unwrap();
+    let received = server.recv().await.unwrap();
+    assert_eq!(&received.message[..], &message[..]);
+
+    // Fragments are capped at what fits in a packet, whatever `max_fragment_len` says.
+    server
+      .send(&message, MessageType::Binary, &client_addr)
+      .await
+      .unwrap();
+    let (reply, _) = client.recv().await.unwrap();
+    assert_eq!(reply, message);
+  });
+}
+
+#[test]
+fn loopback_fragmented_message_larger_than_egress_queue() {
+  futures::executor::block_on(async {
+    let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let config = ServerConfig {
+      max_fragment_len: Some(1000),
+      client_egress_capacity: 16,
+      queue_overflow: OverflowPolicy::DropNewest,
+      ..Default::default()
+    };
+    let mut server = Server::with_config([(listen_addr, listen_addr)], config, None).unwrap();
+    let mut client = server.connect_loopback().await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    assert_eq!(server.max_message_len(&client_addr), Some(16000));
+
+    assert!(matches!(
+      server
+        .send(&[0; 16001], MessageType::Binary, &client_addr)
+        .await,
+      Err(SendError::IncompleteMessageWrite)
+    ));
+    let message = vec![7; 16000];
+    server
+      .send(&message, MessageType::Binary, &client_addr)
+      .await
+      .unwrap();
+    let (reply, _) = client.recv().await.unwrap();
+    assert_eq!(reply, message);
+  });
+}